@@ -0,0 +1,42 @@
+// Returned by 'start_recording', what the take is encoded with, see
+// rust/src/domain/encoder_profile.rs
+class EncoderProfile {
+  // av1 is much smaller but encodes slowly, a take may go on saving for a while
+  static const codecs = ['h264', 'av1'];
+  static const qualities = ['low', 'standard', 'high', 'archival'];
+  static const frameRates = [15, 24, 30, 60];
+
+  final String codec;
+  final String quality;
+  final int bitrate; // bps
+  final String rateControl;
+  final int keyframeInterval; // frames
+  final int encoderThreads;
+  final int fps;
+
+  const EncoderProfile({
+    required this.codec,
+    required this.quality,
+    required this.bitrate,
+    required this.rateControl,
+    required this.keyframeInterval,
+    required this.encoderThreads,
+    required this.fps,
+  });
+
+  factory EncoderProfile.fromMap(Map<dynamic, dynamic> map) {
+    return EncoderProfile(
+      codec: map['codec'],
+      quality: map['quality'],
+      bitrate: map['bitrate'],
+      rateControl: map['rate_control'],
+      keyframeInterval: map['keyframe_interval'],
+      encoderThreads: map['encoder_threads'],
+      fps: map['fps'],
+    );
+  }
+
+  String describe() {
+    return '$codec $quality, ${(bitrate / 1000).round()} kbps at $fps fps';
+  }
+}
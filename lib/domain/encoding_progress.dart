@@ -0,0 +1,38 @@
+// Sent by rust/src/domain/progress.rs through 'mark_progress'
+class EncodingProgress {
+  final String phase; // 'Encoding' or 'Muxing'
+  final int framesQueued;
+  final int framesEncoded;
+  final int bytesWritten;
+  final double fps;
+  final double? etaSeconds; // unknown until the recording has stopped
+
+  const EncodingProgress({
+    required this.phase,
+    required this.framesQueued,
+    required this.framesEncoded,
+    required this.bytesWritten,
+    required this.fps,
+    this.etaSeconds,
+  });
+
+  factory EncodingProgress.fromMap(Map<dynamic, dynamic> map) {
+    return EncodingProgress(
+      phase: map['phase'],
+      framesQueued: map['frames_queued'],
+      framesEncoded: map['frames_encoded'],
+      bytesWritten: map['bytes_written'],
+      fps: map['fps'],
+      etaSeconds: map['eta_seconds'],
+    );
+  }
+
+  String describe() {
+    if (phase == 'Muxing') {
+      return 'Saving $framesEncoded frames';
+    }
+    final eta = etaSeconds == null ? '' : ', ${etaSeconds!.ceil()}s left';
+    return 'Encoding $framesEncoded/$framesQueued frames'
+        ' (${fps.toStringAsFixed(0)} fps$eta)';
+  }
+}
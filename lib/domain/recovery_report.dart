@@ -0,0 +1,46 @@
+// Returned by 'recover_sessions', one per take found in rust/src/domain/recovery.rs
+class RecoveryReport {
+  final String session; // the scratch directory of the take
+  final String? fileName; // the entry written, null if nothing was salvaged
+  final int frames;
+  final double videoSeconds;
+  final double audioSeconds;
+  final bool thumbnail;
+  final String? wavFile; // the audio as a WAV, also kept when no video survived
+  final String? error;
+
+  const RecoveryReport({
+    required this.session,
+    this.fileName,
+    required this.frames,
+    required this.videoSeconds,
+    required this.audioSeconds,
+    required this.thumbnail,
+    this.wavFile,
+    this.error,
+  });
+
+  factory RecoveryReport.fromMap(Map<dynamic, dynamic> map) {
+    return RecoveryReport(
+      session: map['session'],
+      fileName: map['file_name'],
+      frames: map['frames'],
+      videoSeconds: map['video_seconds'],
+      audioSeconds: map['audio_seconds'],
+      thumbnail: map['thumbnail'],
+      wavFile: map['wav_file'],
+      error: map['error'],
+    );
+  }
+
+  String describe() {
+    if (fileName == null && wavFile != null) {
+      return 'Kept the audio of $session as $wavFile';
+    }
+    if (fileName == null) {
+      return 'Could not recover $session: $error';
+    }
+    return 'Recovered ${videoSeconds.toStringAsFixed(1)}s of video'
+        ' and ${audioSeconds.toStringAsFixed(1)}s of audio';
+  }
+}
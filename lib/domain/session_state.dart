@@ -0,0 +1,52 @@
+import 'writing_state.dart';
+
+// Mirrors SessionState in rust/src/domain/session.rs
+enum SessionState {
+  idle,
+  previewing,
+  recording,
+  paused,
+  encoding,
+  saving,
+  failed;
+
+  static SessionState fromName(String name) {
+    switch (name) {
+      case 'Idle':
+        return SessionState.idle;
+      case 'Previewing':
+        return SessionState.previewing;
+      case 'Recording':
+        return SessionState.recording;
+      case 'Paused':
+        return SessionState.paused;
+      case 'Encoding':
+        return SessionState.encoding;
+      case 'Saving':
+        return SessionState.saving;
+      case 'Failed':
+        return SessionState.failed;
+      default:
+        throw Exception('Unknown SessionState: $name');
+    }
+  }
+
+  // what the widgets watching the recording and writing state see
+  bool get isRecording =>
+      this == SessionState.recording || this == SessionState.paused;
+
+  bool get isPaused => this == SessionState.paused;
+
+  WritingState get writingState {
+    switch (this) {
+      case SessionState.recording:
+      case SessionState.paused:
+        return WritingState.encoding;
+      case SessionState.encoding:
+      case SessionState.saving:
+        return WritingState.saving;
+      default:
+        return WritingState.idle;
+    }
+  }
+}
@@ -66,6 +66,9 @@ Future<void> setUp() async {
       .init(); // Native init process **MUST NOT** be delayed by other init.
   await Setting().load();
   await DatabaseService().init();
+  if (Native().orphanedSessions.isNotEmpty) {
+    Native().recoverSessions(); // in the background, the entries show up once it's done
+  }
   MediaKit.ensureInitialized();
   version = await getAppVersion();
   await setUpLast();
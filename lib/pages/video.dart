@@ -17,6 +17,7 @@ import 'package:video_diary/widgets/metadata_widget.dart';
 import 'package:video_diary/widgets/tip_content.dart';
 
 import '../domain/app.dart';
+import '../domain/encoding_progress.dart';
 import '../domain/error.dart';
 import '../domain/event.dart';
 import '../domain/metadata.dart';
@@ -175,6 +176,8 @@ class _VideoPageState extends State<VideoPage> {
   void startTimter() {
     _recordingTimer?.cancel();
     _recordingTimer = Timer.periodic(const Duration(seconds: 1), (timer) {
+      // paused time is not part of the entry
+      if (context.read<Native>().paused) return;
       setState(() {
         recordingTime = recordingTime + const Duration(seconds: 1);
       });
@@ -264,7 +267,9 @@ class _VideoPageState extends State<VideoPage> {
                   child: fileCommandWidget(selectedFileTimetamps),
                 ),
               writingStateMessage(
-                  writingState: writingState, rendering: rendering),
+                  writingState: writingState,
+                  progress: native.encodingProgress,
+                  rendering: rendering),
               if (showTipContent && setting.tip)
                 Positioned(
                   top: 82,
@@ -451,7 +456,9 @@ class _VideoPageState extends State<VideoPage> {
   }
 
   Widget writingStateMessage(
-      {required WritingState writingState, required bool rendering}) {
+      {required WritingState writingState,
+      required EncodingProgress? progress,
+      required bool rendering}) {
     bool showMessage = writingState != WritingState.idle && !rendering;
     return AnimatedSwitcher(
         duration: const Duration(milliseconds: 500),
@@ -462,7 +469,8 @@ class _VideoPageState extends State<VideoPage> {
           );
         },
         child: showMessage
-            ? messageWidget(writingState.toName(), true, true)
+            ? messageWidget(
+                progress?.describe() ?? writingState.toName(), true, true)
             : const SizedBox());
   }
 
@@ -79,9 +79,23 @@ class DatabaseService with ChangeNotifier, DiagnosticableTreeMixin {
     store.box<Metadata>().put(metadata);
   }
 
+  // A recovered take keeps its file name, its record may have been cleared in the meantime.
+  // An entry without a record, like one copied back in, gets it from the metadata in its file.
+  Future<void> ensureRecord(String fileName) async {
+    if (findByOsFileName(fileName) is Success) return;
+    final timestamp = int.parse(fileName.split('_').last);
+    final metadata = await Native().readMetadata(fileName) ??
+        Metadata(
+            title: '', timestamp: timestamp, note: '', tags: '', thumbnail: '');
+    store.box<Metadata>().put(metadata);
+  }
+
   Future<List<Metadata>> getEntries() async {
     Native native = Native();
     await native.checkFileDirectoryAndSetFiles();
+    for (final fileName in native.files) {
+      await ensureRecord(fileName);
+    }
     List<Metadata> result = native.files
         .map((el) => findByOsFileName(el))
         .whereType<Success>()
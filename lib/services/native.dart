@@ -5,11 +5,18 @@ import 'package:flutter/cupertino.dart';
 import 'package:flutter/foundation.dart';
 import 'package:irondash_engine_context/irondash_engine_context.dart';
 import 'package:irondash_message_channel/irondash_message_channel.dart';
+import 'package:path_provider/path_provider.dart';
 import 'package:rxdart/rxdart.dart';
 import 'package:video_diary/services/database.dart';
 import 'package:wakelock/wakelock.dart';
 
+import '../domain/encoder_profile.dart';
+import '../domain/encoding_progress.dart';
+import '../domain/metadata.dart';
+import '../domain/recovery_report.dart';
+import '../domain/session_state.dart';
 import '../domain/writing_state.dart';
+import '../tools/time.dart';
 import 'setting.dart';
 
 class Native with ChangeNotifier, DiagnosticableTreeMixin {
@@ -19,10 +26,15 @@ class Native with ChangeNotifier, DiagnosticableTreeMixin {
     return _instance;
   }
 
+  SessionState sessionState =
+      SessionState.idle; // the state of the native capture session
   WritingState writingState = WritingState
       .idle; // whether the recorded video data is being written to the file
 
   bool recording = false; // whether the video is being recorded
+  bool paused = false; // whether the recording is paused
+  EncodingProgress?
+      encodingProgress; // the progress of the take being encoded or saved
   bool rendering = false; // whether the video is being rendered
 
   bool cameraHealthCheck =
@@ -44,6 +56,17 @@ class Native with ChangeNotifier, DiagnosticableTreeMixin {
   bool recordingHealthCheck =
       true; // whether the recording is ok (os permission for writing file etc.)
 
+  List<String> orphanedSessions =
+      []; // takes left behind by a crash or a failed save, found on start up
+  List<RecoveryReport> recoveredTakes =
+      []; // what the last 'recoverSessions' salvaged
+  EncoderProfile? encoderProfile; // what the current or last take is encoded with
+
+  String lastErrorCode = ''; // the code of the last background failure
+  String lastErrorMessage = ''; // the message of the last background failure
+  String lastStopReason =
+      ''; // why the last take stopped itself: 'max_duration', 'max_size' or 'disk_low'
+
   static const String rustLibraryName = 'rust';
 
   final dylib = DynamicLibrary.open("$rustLibraryName.dll");
@@ -67,12 +90,32 @@ class Native with ChangeNotifier, DiagnosticableTreeMixin {
   String fileName = '';
   List<String> files = [];
 
+  static const entryExtensions = ['.mp4', '.mkv', '.m3u8', '.m4a'];
+
+  // the mp4 or mkv of an entry, the playlist of a segmented one, or the m4a of a voice memo
+  String entryPath(String fileName) {
+    for (final extension in entryExtensions.take(3)) {
+      final videoPath = '$filePathPrefix\\$fileName$extension';
+      if (File(videoPath).existsSync()) return videoPath;
+    }
+    return '$filePathPrefix\\$fileName.m4a';
+  }
+
   Future<void> deleteFile(int timestamp) async {
     final fileName = osFileName(timestamp);
-    File file = File('$filePathPrefix\\$fileName.mp4');
+    File file = File(entryPath(fileName));
     file.deleteSync();
     File thumbnailFile = File('$filePathPrefix\\thumbnails\\$fileName.png');
     thumbnailFile.deleteSync();
+    File wavFile = File('$filePathPrefix\\$fileName.wav');
+    if (wavFile.existsSync()) {
+      wavFile.deleteSync();
+    }
+    // the files a segmented entry's playlist points to
+    Directory segments = Directory('$filePathPrefix\\$fileName');
+    if (segments.existsSync()) {
+      segments.deleteSync(recursive: true);
+    }
     var db = DatabaseService();
     await db.sync();
     // The db record will be deleted by the db function 'clearOutdatedRecords'
@@ -80,11 +123,19 @@ class Native with ChangeNotifier, DiagnosticableTreeMixin {
 
   Future<void> sendFileToDesktop(int timestamp) async {
     final fileName = osFileName(timestamp);
-    final filePath = '$filePathPrefix\\$fileName.mp4';
-    File file = File(filePath);
+    File file = File(entryPath(fileName));
     if (file.existsSync()) {
       String desktopDir = '${Platform.environment['USERPROFILE']}\\Desktop';
       file.copySync('$desktopDir\\${file.path.split('\\').last}');
+      // a playlist needs its segments next to it
+      Directory segments = Directory('$filePathPrefix\\$fileName');
+      if (segments.existsSync()) {
+        final copy = Directory('$desktopDir\\$fileName')
+          ..createSync(recursive: true);
+        for (final segment in segments.listSync().whereType<File>()) {
+          segment.copySync('${copy.path}\\${segment.path.split('\\').last}');
+        }
+      }
     } else {
       debugPrint('File does not exist.');
     }
@@ -119,9 +170,12 @@ class Native with ChangeNotifier, DiagnosticableTreeMixin {
         files.clear();
         for (FileSystemEntity file in files_) {
           if (file is File) {
-            if (!file.path.endsWith('.mp4')) continue;
+            final extension = entryExtensions
+                .where((extension) => file.path.endsWith(extension));
+            if (extension.isEmpty) continue;
             // remove the file extension and the path
-            String fileName = file.path.split('\\').last.split('.mp4').first;
+            String fileName =
+                file.path.split('\\').last.split(extension.first).first;
             files.add(fileName);
           }
         }
@@ -176,6 +230,9 @@ class Native with ChangeNotifier, DiagnosticableTreeMixin {
         'rendering_channel_background_thread',
         context: nativeContext);
     setChannelHandlers();
+    await protocolHandshake();
+    await setAppDataDir();
+    await findOrphanedSessions();
     await checkFileDirectoryAndSetFiles();
     await queryDevices();
     listenUiEventDispatcher();
@@ -185,26 +242,57 @@ class Native with ChangeNotifier, DiagnosticableTreeMixin {
   void setChannelHandlers() {
     recordingChannel.setMethodCallHandler((call) async {
       switch (call.method) {
-        case 'mark_writing_state':
-          writingState = WritingState.fromName(call.arguments);
-          if (writingState == WritingState.saving) {
+        case 'mark_session_state':
+          final previous = sessionState;
+          sessionState = SessionState.fromName(call.arguments);
+          recording = sessionState.isRecording;
+          paused = sessionState.isPaused;
+          writingState = sessionState.writingState;
+          if (writingState == WritingState.idle) {
+            encodingProgress = null;
+          }
+          // the session stops the camera itself once the recording stops
+          if (sessionState == SessionState.encoding) {
             stopRendering();
-            await stopCameraStream();
           }
           notifyListeners();
-          debugPrint('writingState: $writingState');
-          if (writingState == WritingState.idle) {
-            if (!rendering) {
-              DatabaseService().sync();
-              await startCamera();
-            }
+          debugPrint('sessionState: $sessionState');
+          // a cancelled voice memo goes straight from recording or encoding to idle
+          final takeEnded = ((previous == SessionState.saving ||
+                      previous == SessionState.encoding ||
+                      previous.isRecording) &&
+                  sessionState == SessionState.idle) ||
+              sessionState == SessionState.failed;
+          if (takeEnded && !rendering) {
+            DatabaseService().sync();
+            await startCamera();
           }
+          // a take cancelled while encoding is back in the preview, the session has
+          // opened the camera again and only the texture is left to restart
+          if (previous == SessionState.encoding &&
+              sessionState == SessionState.previewing &&
+              !rendering) {
+            openTextureStream();
+            await startRendering();
+          }
+          return null;
+
+        case 'mark_progress':
+          encodingProgress = EncodingProgress.fromMap(call.arguments);
+          notifyListeners();
           return null;
 
-        case 'mark_recording_state':
-          recording = call.arguments;
+        case 'mark_limit_reached':
+          lastStopReason = call.arguments;
           notifyListeners();
-          debugPrint('recording: $recording');
+          debugPrint('stopped at a limit: $lastStopReason');
+          return null;
+
+        case 'mark_error':
+          lastErrorCode = call.arguments['code'];
+          lastErrorMessage = call.arguments['message'];
+          notifyListeners();
+          debugPrint('error: $lastErrorCode $lastErrorMessage');
           return null;
         default:
           debugPrint('Unknown method ${call.method} ');
@@ -248,6 +336,52 @@ class Native with ChangeNotifier, DiagnosticableTreeMixin {
     textureId = function(handle);
   }
 
+  // must match PROTOCOL_VERSION in rust/src/message_channel/protocol.rs
+  static const int protocolVersion = 18;
+
+  Future<void> protocolHandshake() async {
+    final res = await cameraChannel.invokeMethod('protocol_handshake', {
+      'protocol_version': protocolVersion.toString(),
+    });
+    if (res['compatible'] != true) {
+      throw StateError(
+          'native library protocol ${res['protocol_version']} (${res['library_version']}) '
+          'does not match $protocolVersion');
+    }
+  }
+
+  // the working files of a take go under the app support directory, not the install folder
+  Future<void> setAppDataDir() async {
+    final appSupportDir = await getApplicationSupportDirectory();
+    final res = await recordingChannel.invokeMethod('set_app_data_dir', {
+      'path': appSupportDir.path,
+    });
+    _showResult(res);
+  }
+
+  Future<void> findOrphanedSessions() async {
+    final res = await recordingChannel.invokeMethod('orphaned_sessions', {});
+    orphanedSessions = res.cast<String>();
+  }
+
+  // Salvages the orphaned takes into entries, the database must be open.
+  Future<void> recoverSessions() async {
+    final res = await recordingChannel.invokeMethod('recover_sessions', {});
+    recoveredTakes = (res as List)
+        .map((el) => RecoveryReport.fromMap(el as Map<dynamic, dynamic>))
+        .toList();
+    for (var report in recoveredTakes) {
+      debugPrint(report.describe());
+    }
+    orphanedSessions = [];
+    final db = DatabaseService();
+    for (var report in recoveredTakes) {
+      if (report.fileName != null) await db.ensureRecord(report.fileName!);
+    }
+    await db.sync();
+    notifyListeners();
+  }
+
   void _showResult(Object res) {
     // const encoder = JsonEncoder.withIndent('  ');
     // final text = encoder.convert(res);
@@ -262,25 +396,107 @@ class Native with ChangeNotifier, DiagnosticableTreeMixin {
   }
 
   void startRecording() async {
-    _stopAudioStream();
-    _openAudioStream(currentAudioDevice);
-    final res = await recordingChannel.invokeMethod('start_recording', {});
-    _showResult(res);
-    _startEncoding();
+    final int timestamp = DateTime.now().millisecondsSinceEpoch;
+
+    final fileName = osFileName(timestamp);
+
+    DatabaseService().insert(timestamp);
+    final res = await recordingChannel.invokeMethod('start_recording', {
+      'file_path_prefix': filePathPrefix,
+      'file_name': fileName,
+      'resolution': currentResolution,
+      'container': Setting().container,
+      'layout': Setting().fragmentedRecording ? 'fragmented' : 'progressive',
+      'codec': Setting().encoderCodec,
+      'quality': Setting().encoderQuality,
+      'fps': Setting().frameRate.toString(),
+      if (Setting().segmentMinutes > 0)
+        'segment_minutes': Setting().segmentMinutes.toString(),
+      'wav_master': Setting().wavMaster.toString(),
+      ...recordingLimits(),
+      'created': isoTimestamp(timestamp),
+    });
+    encoderProfile = EncoderProfile.fromMap(res as Map<dynamic, dynamic>);
+    debugPrint('encoding with ${encoderProfile!.describe()}');
+  }
+
+  // the session stops a take on its own at these, and reports why with 'mark_limit_reached'
+  Map<String, String> recordingLimits() {
+    return {
+      if (Setting().maxMinutes > 0)
+        'max_minutes': Setting().maxMinutes.toString(),
+      if (Setting().minFreeMb > 0) 'min_free_mb': Setting().minFreeMb.toString(),
+    };
   }
 
-  void _startEncoding() async {
+  // records the microphone alone into an m4a, the session stops the camera for it
+  void startVoiceMemo() async {
     final int timestamp = DateTime.now().millisecondsSinceEpoch;
 
     final fileName = osFileName(timestamp);
 
+    // the avatar is a picture the user drops next to the entries
+    final avatarPath = '$filePathPrefix\\avatar.png';
+    var visual = Setting().memoVisual;
+    if (visual == 'avatar' && !File(avatarPath).existsSync()) {
+      visual = 'audiogram';
+    }
+
     DatabaseService().insert(timestamp);
-    final res = await recordingChannel.invokeMethod('start_encording', {
+    stopRendering();
+    final res = await recordingChannel.invokeMethod('start_voice_memo', {
       'file_path_prefix': filePathPrefix,
       'file_name': fileName,
-      'resolution': currentResolution,
+      'visual': visual,
+      'avatar_path': avatarPath,
+      'wav_master': Setting().wavMaster.toString(),
+      ...recordingLimits(),
+      'created': isoTimestamp(timestamp),
     });
     _showResult(res);
+    encoderProfile = null;
+  }
+
+  // The title, note and tags go into the entry's file too, so a copy of it keeps them and
+  // the library can be rebuilt from the files. mkv entries go without.
+  Future<void> writeMetadata(String fileName, Metadata metadata) async {
+    final path = entryPath(fileName);
+    if (path.endsWith('.mkv') || !File(path).existsSync()) return;
+    try {
+      await recordingChannel.invokeMethod('write_metadata', {
+        'path': path,
+        'title': metadata.title,
+        'created': isoTimestamp(metadata.timestamp),
+        'note': metadata.note ?? '',
+        'tags': (metadata.tags ?? '')
+            .split(',')
+            .map((tag) => tag.trim())
+            .where((tag) => tag.isNotEmpty)
+            .join('\n'),
+      });
+    } catch (e) {
+      debugPrint('Failed to write the metadata of $fileName: $e');
+    }
+  }
+
+  // what the entry's file says about it, null for an mkv or a file that can't be read
+  Future<Metadata?> readMetadata(String fileName) async {
+    final path = entryPath(fileName);
+    if (path.endsWith('.mkv')) return null;
+    try {
+      final res = await recordingChannel.invokeMethod('read_metadata', {
+        'path': path,
+      }) as Map<dynamic, dynamic>;
+      return Metadata(
+          title: res['title'] ?? '',
+          timestamp: int.parse(fileName.split('_').last),
+          note: res['note'] ?? '',
+          tags: (res['tags'] as List).cast<String>().join(', '),
+          thumbnail: '');
+    } catch (e) {
+      debugPrint('Failed to read the metadata of $fileName: $e');
+      return null;
+    }
   }
 
   void stopRecording() async {
@@ -288,6 +504,22 @@ class Native with ChangeNotifier, DiagnosticableTreeMixin {
     _showResult(res);
   }
 
+  void pauseRecording() async {
+    final res = await recordingChannel.invokeMethod('pause_recording', {});
+    _showResult(res);
+  }
+
+  void resumeRecording() async {
+    final res = await recordingChannel.invokeMethod('resume_recording', {});
+    _showResult(res);
+  }
+
+  // throws the take away, the preview keeps running
+  void cancelRecording() async {
+    final res = await recordingChannel.invokeMethod('cancel_recording', {});
+    _showResult(res);
+  }
+
   // for rust to communicate each other(rust)
   void listenUiEventDispatcher() async {
     final res =
@@ -14,12 +14,46 @@ class Setting with ChangeNotifier, DiagnosticableTreeMixin {
   String lastPreferredResolution = '';
   bool thumbnailView = true;
   bool tip = true;
+  // fragmented mp4 stays playable up to the last fragment if the app dies mid-take
+  bool fragmentedRecording = false;
+  // 'h264' or 'av1'
+  String encoderCodec = 'h264';
+  // encoder preset, the bitrate is derived from it and the resolution
+  String encoderQuality = 'standard';
+  // 15, 24, 30 or 60, the camera has to keep up
+  int frameRate = 24;
+  // 'mp4' or 'matroska', an mkv plays up to where it was cut off without any recovery
+  String container = 'mp4';
+  static const containers = ['mp4', 'matroska'];
+  // a long take starts a new file every this many minutes, 0 keeps it in one
+  int segmentMinutes = 0;
+  static const segmentLengths = [0, 10, 30, 60];
+  // a take stops itself after this many minutes, 0 lets it go on
+  int maxMinutes = 0;
+  static const maxLengths = [0, 15, 30, 60, 120];
+  // a take doesn't start, or stops, with less than this left on the disk
+  int minFreeMb = 1024;
+  // lossless copy of the audio next to the video, for editing it on its own
+  bool wavMaster = false;
+  // what a voice memo shows: 'none' for an m4a, 'audiogram' or 'avatar' for an mp4
+  String memoVisual = 'none';
+  static const memoVisuals = ['none', 'audiogram', 'avatar'];
 
   Map<String, dynamic> _toJson() {
     final Map<String, dynamic> data = <String, dynamic>{};
     data['lastPreferredResolution'] = lastPreferredResolution;
     data['thumbnailView'] = thumbnailView;
     data['tip'] = tip;
+    data['fragmentedRecording'] = fragmentedRecording;
+    data['encoderCodec'] = encoderCodec;
+    data['encoderQuality'] = encoderQuality;
+    data['frameRate'] = frameRate;
+    data['container'] = container;
+    data['segmentMinutes'] = segmentMinutes;
+    data['maxMinutes'] = maxMinutes;
+    data['minFreeMb'] = minFreeMb;
+    data['wavMaster'] = wavMaster;
+    data['memoVisual'] = memoVisual;
     return data;
   }
 
@@ -28,6 +62,41 @@ class Setting with ChangeNotifier, DiagnosticableTreeMixin {
     save();
   }
 
+  void setEncoderCodec(String codec) {
+    encoderCodec = codec;
+    save();
+  }
+
+  void setEncoderQuality(String quality) {
+    encoderQuality = quality;
+    save();
+  }
+
+  void setFrameRate(int fps) {
+    frameRate = fps;
+    save();
+  }
+
+  void setContainer(String value) {
+    container = value;
+    save();
+  }
+
+  void setSegmentMinutes(int minutes) {
+    segmentMinutes = minutes;
+    save();
+  }
+
+  void setMaxMinutes(int minutes) {
+    maxMinutes = minutes;
+    save();
+  }
+
+  void setMemoVisual(String visual) {
+    memoVisual = visual;
+    save();
+  }
+
   void save() {
     String jsonString = jsonEncode(_toJson());
     File file = File(fileName);
@@ -53,6 +122,16 @@ class Setting with ChangeNotifier, DiagnosticableTreeMixin {
     lastPreferredResolution = data['lastPreferredResolution'] as String? ?? '';
     thumbnailView = data['thumbnailView'] as bool? ?? true;
     tip = data['tip'] as bool? ?? true;
+    fragmentedRecording = data['fragmentedRecording'] as bool? ?? false;
+    encoderCodec = data['encoderCodec'] as String? ?? 'h264';
+    encoderQuality = data['encoderQuality'] as String? ?? 'standard';
+    frameRate = data['frameRate'] as int? ?? 24;
+    container = data['container'] as String? ?? 'mp4';
+    segmentMinutes = data['segmentMinutes'] as int? ?? 0;
+    maxMinutes = data['maxMinutes'] as int? ?? 0;
+    minFreeMb = data['minFreeMb'] as int? ?? 1024;
+    wavMaster = data['wavMaster'] as bool? ?? false;
+    memoVisual = data['memoVisual'] as String? ?? 'none';
 
     return;
   }
@@ -9,6 +9,16 @@ String formatTimestamp({int? timestamp, String? format}) {
   return formattedOffsetTime;
 }
 
+// "2026-10-18T09:30:00+02:00", the local time with the offset it had then
+String isoTimestamp(int timestamp) {
+  final dateTime = DateTime.fromMillisecondsSinceEpoch(timestamp);
+  final offset = dateTime.timeZoneOffset;
+  final minutes = offset.inMinutes.abs();
+  return "${DateFormat("yyyy-MM-dd'T'HH:mm:ss").format(dateTime)}"
+      "${offset.isNegative ? '-' : '+'}"
+      "${formatInt(minutes ~/ 60)}:${formatInt(minutes % 60)}";
+}
+
 String formatDuration(Duration duration) {
   int seconds = duration.inSeconds % 60;
   int totalMinutes = duration.inMinutes;
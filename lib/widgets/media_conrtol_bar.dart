@@ -3,6 +3,7 @@ import 'package:provider/provider.dart';
 import 'package:video_diary/widgets/button.dart';
 
 import '../domain/assets.dart';
+import '../domain/writing_state.dart';
 import '../services/native.dart';
 
 Widget mediaControlButton(
@@ -17,18 +18,53 @@ Widget mediaControlButton(
 
   bool showMediaControlButton =
       currentCameraDevice.isNotEmpty && rendering && !recording;
+  // a voice memo only needs the microphone
+  bool showVoiceMemoButton = native.currentAudioDevice.isNotEmpty &&
+      !recording &&
+      native.writingState == WritingState.idle;
 
-  if (showMediaControlButton) {
-    return customButton(customSky, customBlack, 'REC', () {
-      onRecordStart();
-      Native().startRecording();
-    });
+  if (showMediaControlButton || showVoiceMemoButton) {
+    return Row(
+      mainAxisSize: MainAxisSize.min,
+      children: [
+        if (showVoiceMemoButton)
+          customButton(customBlack, Colors.white, 'MEMO', () {
+            onRecordStart();
+            Native().startVoiceMemo();
+          }),
+        if (showMediaControlButton && showVoiceMemoButton)
+          const SizedBox(width: 8),
+        if (showMediaControlButton)
+          customButton(customSky, customBlack, 'REC', () {
+            onRecordStart();
+            Native().startRecording();
+          }),
+      ],
+    );
   }
   if (recording) {
-    return customButton(customOrange, Colors.white, 'STOP', () {
-      onRecordStop();
-      Native().stopRecording();
-    });
+    return Row(
+      mainAxisSize: MainAxisSize.min,
+      children: [
+        native.paused
+            ? customButton(customSky, customBlack, 'RESUME', () {
+                Native().resumeRecording();
+              })
+            : customButton(customSky, customBlack, 'PAUSE', () {
+                Native().pauseRecording();
+              }),
+        const SizedBox(width: 8),
+        customButton(customOrange, Colors.white, 'STOP', () {
+          onRecordStop();
+          Native().stopRecording();
+        }),
+        const SizedBox(width: 8),
+        customButton(customBlack, Colors.white, 'DISCARD', () {
+          onRecordStop();
+          Native().cancelRecording();
+        }),
+      ],
+    );
   }
 
   return const SizedBox();
@@ -263,13 +263,13 @@ class MetadataModel {
   }
 
   void flush() {
-    DatabaseService().update(
-        _data.timestamp,
-        Metadata(
-            title: title,
-            timestamp: timestamp,
-            note: note,
-            tags: tags,
-            thumbnail: thumbnail));
+    final updated = Metadata(
+        title: title,
+        timestamp: timestamp,
+        note: note,
+        tags: tags,
+        thumbnail: thumbnail);
+    DatabaseService().update(_data.timestamp, updated);
+    Native().writeMetadata(osFileName(timestamp), updated);
   }
 }
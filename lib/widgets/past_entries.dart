@@ -229,7 +229,7 @@ class PastEntriesState extends State<PastEntries>
     List<Metadata> entries = db.uiStatePastEntries;
     int timestamp = entries[selectedIndex].timestamp;
     String fileName = osFileName(timestamp);
-    String filePath = "${native.filePathPrefix}/$fileName.mp4";
+    String filePath = native.entryPath(fileName);
     focusNode.unfocus();
     Navigator.push(
       context,
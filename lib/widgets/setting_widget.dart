@@ -6,6 +6,7 @@ import 'package:video_diary/widgets/waveform.dart';
 import 'package:window_manager/window_manager.dart';
 
 import '../domain/assets.dart';
+import '../domain/encoder_profile.dart';
 import '../services/native.dart';
 import '../services/setting.dart';
 import 'dropdown.dart';
@@ -110,6 +111,100 @@ Widget settings(BuildContext context) {
                                     color: color),
                                 textColor: color),
                           spacer,
+                          dropdown(
+                              value: setting.encoderCodec,
+                              items: EncoderProfile.codecs,
+                              onChanged: (codec) {
+                                setting.setEncoderCodec(codec);
+                              },
+                              icon: const Icon(Icons.compress, color: color),
+                              textOnEmpty: "No codec available",
+                              iconOnEmpty: const Icon(Icons.do_not_disturb,
+                                  color: color),
+                              textColor: color),
+                          spacer,
+                          dropdown(
+                              value: setting.encoderQuality,
+                              items: EncoderProfile.qualities,
+                              onChanged: (quality) {
+                                setting.setEncoderQuality(quality);
+                              },
+                              icon: const Icon(Icons.high_quality, color: color),
+                              textOnEmpty: "No quality available",
+                              iconOnEmpty: const Icon(Icons.do_not_disturb,
+                                  color: color),
+                              textColor: color),
+                          spacer,
+                          dropdown(
+                              value: '${setting.frameRate} fps',
+                              items: EncoderProfile.frameRates.map((fps) => '$fps fps').toList(),
+                              onChanged: (value) {
+                                setting.setFrameRate(
+                                    int.parse(value.split(' ').first));
+                              },
+                              icon: const Icon(Icons.speed, color: color),
+                              textOnEmpty: "No frame rate available",
+                              iconOnEmpty: const Icon(Icons.do_not_disturb,
+                                  color: color),
+                              textColor: color),
+                          spacer,
+                          dropdown(
+                              value: setting.container,
+                              items: Setting.containers,
+                              onChanged: (value) {
+                                setting.setContainer(value);
+                              },
+                              icon: const Icon(Icons.movie, color: color),
+                              textOnEmpty: "No container available",
+                              iconOnEmpty: const Icon(Icons.do_not_disturb,
+                                  color: color),
+                              textColor: color),
+                          spacer,
+                          dropdown(
+                              value: segmentLabel(setting.segmentMinutes),
+                              items: Setting.segmentLengths
+                                  .map(segmentLabel)
+                                  .toList(),
+                              onChanged: (value) {
+                                setting.setSegmentMinutes(Setting
+                                    .segmentLengths
+                                    .firstWhere((minutes) =>
+                                        segmentLabel(minutes) == value));
+                              },
+                              icon: const Icon(Icons.content_cut, color: color),
+                              textOnEmpty: "No segment length available",
+                              iconOnEmpty: const Icon(Icons.do_not_disturb,
+                                  color: color),
+                              textColor: color),
+                          spacer,
+                          dropdown(
+                              value: maxLengthLabel(setting.maxMinutes),
+                              items: Setting.maxLengths
+                                  .map(maxLengthLabel)
+                                  .toList(),
+                              onChanged: (value) {
+                                setting.setMaxMinutes(Setting.maxLengths
+                                    .firstWhere((minutes) =>
+                                        maxLengthLabel(minutes) == value));
+                              },
+                              icon: const Icon(Icons.timer, color: color),
+                              textOnEmpty: "No maximum length available",
+                              iconOnEmpty: const Icon(Icons.do_not_disturb,
+                                  color: color),
+                              textColor: color),
+                          spacer,
+                          dropdown(
+                              value: setting.memoVisual,
+                              items: Setting.memoVisuals,
+                              onChanged: (visual) {
+                                setting.setMemoVisual(visual);
+                              },
+                              icon: const Icon(Icons.graphic_eq, color: color),
+                              textOnEmpty: "No memo visual available",
+                              iconOnEmpty: const Icon(Icons.do_not_disturb,
+                                  color: color),
+                              textColor: color),
+                          spacer,
                           Padding(
                             padding:
                                 const EdgeInsets.only(left: 16.0, right: 8.0),
@@ -193,9 +288,64 @@ Widget settings(BuildContext context) {
                                   ],
                             ),
                           ),
+                          const SizedBox(
+                            height: 16,
+                          ),
+                          Padding(
+                              padding:
+                                  const EdgeInsets.only(left: 16.0, right: 8.0),
+                              child: Row(
+                                children: [
+                                  Text("Crash-safe recording",
+                                      style: TextStyle(
+                                          color: color,
+                                          fontSize: 16,
+                                          fontFamily: mainFont)),
+                                  const Spacer(),
+                                  Switch(
+                                    value: setting.fragmentedRecording,
+                                    onChanged: (value) {
+                                      setting.fragmentedRecording = value;
+                                      setting.save();
+                                    },
+                                    activeTrackColor:
+                                        customSky.withOpacity(0.6),
+                                    activeColor: Colors.white,
+                                  ),
+                                ],
+                              )),
+                          Padding(
+                              padding:
+                                  const EdgeInsets.only(left: 16.0, right: 8.0),
+                              child: Row(
+                                children: [
+                                  Text("WAV master",
+                                      style: TextStyle(
+                                          color: color,
+                                          fontSize: 16,
+                                          fontFamily: mainFont)),
+                                  const Spacer(),
+                                  Switch(
+                                    value: setting.wavMaster,
+                                    onChanged: (value) {
+                                      setting.wavMaster = value;
+                                      setting.save();
+                                    },
+                                    activeTrackColor:
+                                        customSky.withOpacity(0.6),
+                                    activeColor: Colors.white,
+                                  ),
+                                ],
+                              )),
                           const SizedBox(
                             height: 32,
                           ),
                         ],
                       ))))));
 }
+
+String segmentLabel(int minutes) =>
+    minutes == 0 ? 'one file' : 'new file every $minutes min';
+
+String maxLengthLabel(int minutes) =>
+    minutes == 0 ? 'no time limit' : 'stop after $minutes min';
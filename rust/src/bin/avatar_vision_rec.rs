@@ -0,0 +1,659 @@
+// Runs the same start_recording -> stop_recording cycle the Flutter app
+// drives through `recording_message_channel`, from the command line.
+//
+//   avatar-vision-rec --camera synthetic --audio tone --resolution 1280x720 --duration 10 --output ./data
+//
+// The output layout matches the app: `<output>/<name>.mp4` and `<output>/thumbnails/<name>.png`,
+// `<output>/<name>.mkv` with `--container matroska`, `<output>/<name>.m4a` for a voice memo,
+// `<output>/<name>.m3u8` and the segments in `<output>/<name>/` for a segmented take.
+
+use std::{
+    env,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    process,
+    sync::{atomic::Ordering, Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use cpal::traits::DeviceTrait;
+use kanal::Receiver;
+use log::{debug, info};
+use nokhwa::{query, utils::ApiBackend};
+use rust::{
+    domain::{
+        audio_source::{AudioSource, TONE_GENERATOR_NAME},
+        camera::CameraSelection,
+        channel::{ChannelService, UiEvent},
+        encoder_profile::{EncoderSettings, QualityPreset, RateControl, VideoCodec},
+        frame_source::{parse_frame_format, SyntheticConfig, SYNTHETIC_CAMERA_NAME},
+        limits::RecordingLimits,
+        metadata::{parse_created, read_metadata, write_metadata, EntryMetadata},
+        mkv::scan_clusters,
+        mp4::{scan_fragments, Mp4Layout, VIDEO_TIMESCALE},
+        pipeline::THUMBNAIL_DIR_NAME,
+        recording::Container,
+        resolution::ResolutionService,
+        segments::{Segmenting, PLAYLIST_EXTENSION},
+        session::{CaptureSession, RecordingTarget, SessionState, VoiceMemoTarget},
+        visual_track::VisualTrack,
+    },
+    message_channel::{audio_message_channel::cpal_available_inputs, protocol::parse_resolution},
+    tools::log_::init_logging,
+};
+
+// how often `record_for` looks whether a limit stopped the take
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const USAGE: &str = "usage: avatar-vision-rec [options]
+
+  --camera <name>          camera device name, or 'synthetic' (default: synthetic)
+  --audio <name>           audio device name, 'tone' or 'wav:<path>' (default: tone)
+  --resolution <WxH>       requested resolution (default: 1280x720)
+  --fps <n>                synthetic camera frame rate (default: 24)
+  --record-fps <n>         take frame rate: 15, 24, 30 or 60 (default: the camera's, up to 24)
+  --frame-format <format>  synthetic camera format: MJPEG, YUYV, NV12, GRAY, RAWRGB (default: YUYV)
+  --duration <seconds>     recording length (default: 5)
+  --max-minutes <n>        stop the take after n minutes, before --duration is up
+  --max-mb <n>             stop the take once it's estimated at n megabytes
+  --min-free-mb <n>        stop, or don't start, the take below n megabytes of free disk
+  --output <dir>           output directory (default: .)
+  --name <file name>       output file name without extension (default: unix timestamp in ms)
+  --scratch <dir>          app-data root for the working files of the take (default: system temp)
+  --container <container>  file format: mp4 or matroska (default: mp4)
+  --layout <layout>        mp4 layout: progressive or fragmented (default: progressive)
+  --fragment <seconds>     fragment length of the fragmented layout (default: 2)
+  --segment-minutes <n>    start a new file every n minutes (default: one file)
+  --segment-mb <n>         start a new file every n megabytes (default: one file)
+  --codec <codec>          video codec: h264 or av1, av1 encodes much slower (default: h264)
+  --quality <preset>       encoder preset: low, standard, high, archival (default: standard)
+  --bitrate <bps>          encoder bitrate, overrides the preset
+  --rate-control <mode>    quality, bitrate, buffer, timestamp or off (default: bitrate)
+  --keyframe-interval <n>  frames between keyframes at most (default: 2 seconds worth)
+  --encoder-threads <n>    encoder threads, 0 for automatic (default: by resolution)
+  --wav                    also write the audio as a WAV next to the mp4
+  --voice-memo             record the audio input alone into an m4a, no camera
+  --visual <visual>        video drawn for a voice memo: none, audiogram or avatar (default: none)
+  --avatar <png>           the picture of --visual avatar
+  --title <text>           title written into the mp4 or m4a
+  --created <time>         creation time, ISO 8601 with time zone (default: the start, in UTC)
+  --note <text>            note written into the mp4 or m4a
+  --tag <text>             a tag written into the mp4 or m4a, repeat for more
+  --verify <file>          check the fragments of a fragmented mp4, or the clusters of an mkv, and exit
+  --read-metadata <file>   print the title, creation time, note and tags of an entry and exit
+  --write-metadata <file>  replace them with --title, --created, --note and --tag and exit
+  --recover                salvage takes left behind in the scratch root and exit
+  --list-devices           print available cameras and audio inputs and exit
+  --help                   print this message";
+
+struct Args {
+    camera: String,
+    audio: String,
+    resolution: String,
+    fps: u32,
+    record_fps: Option<u32>,
+    frame_format: String,
+    duration: Duration,
+    limits: RecordingLimits,
+    output: PathBuf,
+    name: String,
+    scratch: Option<PathBuf>,
+    container: String,
+    layout: String,
+    fragment: Duration,
+    segment_length: Option<Duration>,
+    segment_bytes: Option<u64>,
+    verify: Option<PathBuf>,
+    encoder: EncoderSettings,
+    wav_master: bool,
+    voice_memo: bool,
+    visual: String,
+    avatar: Option<PathBuf>,
+    metadata: EntryMetadata,
+    read_metadata: Option<PathBuf>,
+    write_metadata: Option<PathBuf>,
+    recover: bool,
+    list_devices: bool,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, anyhow::Error> {
+        let mut parsed = Args {
+            camera: "synthetic".to_string(),
+            audio: "tone".to_string(),
+            resolution: "1280x720".to_string(),
+            fps: 24,
+            record_fps: None,
+            frame_format: "YUYV".to_string(),
+            duration: Duration::from_secs(5),
+            limits: RecordingLimits::default(),
+            output: PathBuf::from("."),
+            name: SystemTime::now()
+                .duration_since(UNIX_EPOCH)?
+                .as_millis()
+                .to_string(),
+            scratch: None,
+            container: "mp4".to_string(),
+            layout: "progressive".to_string(),
+            fragment: Mp4Layout::DEFAULT_FRAGMENT,
+            segment_length: None,
+            segment_bytes: None,
+            verify: None,
+            encoder: EncoderSettings::default(),
+            wav_master: false,
+            voice_memo: false,
+            visual: "none".to_string(),
+            avatar: None,
+            metadata: EntryMetadata::default(),
+            read_metadata: None,
+            write_metadata: None,
+            recover: false,
+            list_devices: false,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| anyhow!("missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--camera" => parsed.camera = value()?,
+                "--audio" => parsed.audio = value()?,
+                "--resolution" => parsed.resolution = value()?,
+                "--fps" => parsed.fps = value()?.parse()?,
+                "--record-fps" => parsed.record_fps = Some(value()?.parse()?),
+                "--frame-format" => parsed.frame_format = value()?,
+                "--duration" => parsed.duration = Duration::from_secs_f64(value()?.parse()?),
+                "--max-minutes" => {
+                    let minutes: f64 = value()?.parse()?;
+                    parsed.limits.max_duration = Some(Duration::from_secs_f64(minutes * 60.0))
+                }
+                "--max-mb" => {
+                    parsed.limits.max_bytes = Some(value()?.parse::<u64>()? * 1024 * 1024)
+                }
+                "--min-free-mb" => {
+                    parsed.limits.min_free_bytes = Some(value()?.parse::<u64>()? * 1024 * 1024)
+                }
+                "--output" => parsed.output = PathBuf::from(value()?),
+                "--name" => parsed.name = value()?,
+                "--scratch" => parsed.scratch = Some(PathBuf::from(value()?)),
+                "--container" => parsed.container = value()?,
+                "--layout" => parsed.layout = value()?,
+                "--fragment" => parsed.fragment = Duration::from_secs_f64(value()?.parse()?),
+                "--segment-minutes" => {
+                    let minutes: f64 = value()?.parse()?;
+                    parsed.segment_length = Some(Duration::from_secs_f64(minutes * 60.0))
+                }
+                "--segment-mb" => {
+                    parsed.segment_bytes = Some(value()?.parse::<u64>()? * 1024 * 1024)
+                }
+                "--codec" => {
+                    let name = value()?;
+                    parsed.encoder.codec = Some(
+                        VideoCodec::parse(&name)
+                            .ok_or_else(|| anyhow!("unknown codec: {}", name))?,
+                    )
+                }
+                "--quality" => {
+                    let name = value()?;
+                    parsed.encoder.preset = Some(
+                        QualityPreset::parse(&name)
+                            .ok_or_else(|| anyhow!("unknown quality: {}", name))?,
+                    )
+                }
+                "--bitrate" => parsed.encoder.bitrate_bps = Some(value()?.parse()?),
+                "--rate-control" => {
+                    let name = value()?;
+                    parsed.encoder.rate_control = Some(
+                        RateControl::parse(&name)
+                            .ok_or_else(|| anyhow!("unknown rate control: {}", name))?,
+                    )
+                }
+                "--keyframe-interval" => parsed.encoder.keyframe_interval = Some(value()?.parse()?),
+                "--encoder-threads" => parsed.encoder.threads = Some(value()?.parse()?),
+                "--wav" => parsed.wav_master = true,
+                "--voice-memo" => parsed.voice_memo = true,
+                "--visual" => parsed.visual = value()?,
+                "--avatar" => parsed.avatar = Some(PathBuf::from(value()?)),
+                "--title" => parsed.metadata.title = Some(value()?),
+                "--created" => {
+                    let created = value()?;
+                    if parse_created(&created).is_none() {
+                        return Err(anyhow!("not ISO 8601 with a time zone: {}", created));
+                    }
+                    parsed.metadata.created = Some(created)
+                }
+                "--note" => parsed.metadata.note = Some(value()?),
+                "--tag" => parsed.metadata.tags.push(value()?),
+                "--read-metadata" => parsed.read_metadata = Some(PathBuf::from(value()?)),
+                "--write-metadata" => parsed.write_metadata = Some(PathBuf::from(value()?)),
+                "--verify" => parsed.verify = Some(PathBuf::from(value()?)),
+                "--recover" => parsed.recover = true,
+                "--list-devices" => parsed.list_devices = true,
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                _ => return Err(anyhow!("unknown argument: {}", arg)),
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn camera_selection(&self) -> Result<CameraSelection, anyhow::Error> {
+        if self.camera == "synthetic" || self.camera == SYNTHETIC_CAMERA_NAME {
+            let frame_format = parse_frame_format(&self.frame_format)
+                .ok_or_else(|| anyhow!("unknown frame format: {}", self.frame_format))?;
+            return Ok(CameraSelection::Synthetic(SyntheticConfig {
+                fps: self.fps,
+                frame_format,
+                ..SyntheticConfig::default()
+            }));
+        }
+        let cameras = query(ApiBackend::Auto).map_err(|e| anyhow!("{:?}", e))?;
+        cameras
+            .into_iter()
+            .find(|c| c.human_name() == self.camera)
+            .map(CameraSelection::Device)
+            .ok_or_else(|| anyhow!("camera not found: {}", self.camera))
+    }
+
+    fn container(&self) -> Result<Container, anyhow::Error> {
+        let container = Container::parse(&self.container)
+            .ok_or_else(|| anyhow!("unknown container: {}", self.container))?;
+        if container == Container::Matroska && !self.metadata.is_empty() {
+            return Err(anyhow!(
+                "--title, --created, --note and --tag only go into an mp4"
+            ));
+        }
+        Ok(container)
+    }
+
+    fn layout(&self) -> Result<Mp4Layout, anyhow::Error> {
+        Mp4Layout::parse(&self.layout, self.fragment)
+            .ok_or_else(|| anyhow!("unknown layout: {}", self.layout))
+    }
+
+    fn segmenting(&self) -> Option<Segmenting> {
+        Segmenting::new(self.segment_length, self.segment_bytes)
+    }
+
+    fn visual(&self) -> Result<Option<VisualTrack>, anyhow::Error> {
+        if self.visual == "none" {
+            return Ok(None);
+        }
+        VisualTrack::parse(&self.visual, self.avatar.as_deref())
+            .map(Some)
+            .ok_or_else(|| anyhow!("unknown visual or no --avatar: {}", self.visual))
+    }
+
+    fn audio_source(&self) -> AudioSource {
+        if self.audio == "tone" {
+            AudioSource::from_name(TONE_GENERATOR_NAME)
+        } else {
+            AudioSource::from_name(&self.audio)
+        }
+    }
+}
+
+fn main() {
+    init_logging();
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if args.list_devices {
+        list_devices();
+        return;
+    }
+
+    if let Some(path) = &args.verify {
+        if let Err(e) = verify(path) {
+            eprintln!("error: {:?}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(path) = &args.write_metadata {
+        if let Err(e) = write_metadata(path, &args.metadata) {
+            eprintln!("error: {:?}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(path) = &args.read_metadata {
+        if let Err(e) = print_metadata(path) {
+            eprintln!("error: {:?}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.recover {
+        if let Err(e) = recover(args) {
+            eprintln!("error: {:?}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.voice_memo {
+        if let Err(e) = run_voice_memo(args) {
+            eprintln!("error: {:?}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Err(e) = run(args) {
+        eprintln!("error: {:?}", e);
+        process::exit(1);
+    }
+}
+
+fn list_devices() {
+    println!("cameras:");
+    println!("  {}", SYNTHETIC_CAMERA_NAME);
+    if let Ok(cameras) = query(ApiBackend::Auto) {
+        for camera in cameras {
+            println!("  {}", camera.human_name());
+        }
+    }
+    println!("audio inputs:");
+    println!("  {}", TONE_GENERATOR_NAME);
+    for device in cpal_available_inputs() {
+        if let Ok(name) = device.name() {
+            println!("  {}", name);
+        }
+    }
+}
+
+// Each take is written back to where it was meant to go, `--output` doesn't apply.
+fn recover(args: Args) -> Result<(), anyhow::Error> {
+    let channel_handler = Arc::new(Mutex::new(ChannelService::new()));
+    let session = CaptureSession::new(channel_handler, Arc::new(ResolutionService::new()));
+    if let Some(scratch) = &args.scratch {
+        session.set_app_data_root(scratch)?;
+    }
+    let reports = session.recover_sessions();
+    if reports.is_empty() {
+        println!("nothing to recover in {:?}", session.app_data_root());
+    }
+    for report in reports {
+        match (&report.file_name, &report.error) {
+            (Some(file_name), _) => println!(
+                "{}: {} frames ({:.1}s), {:.1}s of audio -> {}{}",
+                report.session,
+                report.frames,
+                report.video_seconds,
+                report.audio_seconds,
+                file_name,
+                if report.thumbnail {
+                    ""
+                } else {
+                    " (no thumbnail)"
+                }
+            ),
+            (None, error) => println!(
+                "{}: nothing salvaged, {}",
+                report.session,
+                error.as_deref().unwrap_or("no reason given")
+            ),
+        }
+        if let Some(wav_file) = &report.wav_file {
+            println!("{}: audio kept as {}", report.session, wav_file);
+        }
+    }
+    Ok(())
+}
+
+// What a player gets out of the file, e.g. after killing a recording with `--layout fragmented`.
+fn verify(path: &Path) -> Result<(), anyhow::Error> {
+    if path
+        .extension()
+        .map_or(false, |extension| extension == "mkv")
+    {
+        let scan = scan_clusters(BufReader::new(File::open(path)?))?;
+        println!(
+            "{} clusters, {} video blocks ({:.1}s), {} audio blocks, {} of {} bytes playable{}",
+            scan.clusters,
+            scan.video_blocks,
+            scan.video_end as f64 / 1000.0,
+            scan.audio_blocks,
+            scan.playable_len,
+            scan.file_len,
+            if scan.is_complete() {
+                ""
+            } else {
+                " (incomplete)"
+            }
+        );
+        return Ok(());
+    }
+    let scan = scan_fragments(BufReader::new(File::open(path)?))?;
+    println!(
+        "{} fragments, {} video samples ({:.1}s), {} audio samples, {} of {} bytes playable{}",
+        scan.fragments,
+        scan.video_samples,
+        scan.video_duration as f64 / VIDEO_TIMESCALE as f64,
+        scan.audio_samples,
+        scan.playable_len,
+        scan.file_len,
+        if scan.is_complete() {
+            ""
+        } else {
+            " (incomplete)"
+        }
+    );
+    Ok(())
+}
+
+// What the app rebuilds its library entry from.
+fn print_metadata(path: &Path) -> Result<(), anyhow::Error> {
+    let metadata = read_metadata(path)?;
+    println!("title: {}", metadata.title.as_deref().unwrap_or(""));
+    println!("created: {}", metadata.created.as_deref().unwrap_or(""));
+    println!("note: {}", metadata.note.as_deref().unwrap_or(""));
+    println!("tags: {}", metadata.tags.join(", "));
+    Ok(())
+}
+
+fn run(args: Args) -> Result<(), anyhow::Error> {
+    std::fs::create_dir_all(args.output.join(THUMBNAIL_DIR_NAME))?;
+    let file_path_prefix = args
+        .output
+        .to_str()
+        .ok_or_else(|| anyhow!("output path is not valid unicode"))?
+        .to_string();
+
+    let channel_handler = Arc::new(Mutex::new(ChannelService::new()));
+    let session = CaptureSession::new(channel_handler.clone(), Arc::new(ResolutionService::new()));
+    let ui_event = channel_handler.lock().unwrap().ui_event.1.clone();
+    if let Some(scratch) = &args.scratch {
+        session.set_app_data_root(scratch)?;
+    }
+
+    // camera and audio, the same as 'open_camera_stream' and 'open_audio_stream'
+    session.start_preview(
+        args.camera_selection()?,
+        Some(parse_resolution(&args.resolution)?),
+    )?;
+    session.open_audio(args.audio_source())?;
+
+    let resolution = session
+        .camera
+        .lock()
+        .unwrap()
+        .resolution_service
+        .get_current_resolution();
+    let resolution = parse_resolution(&resolution)?;
+    info!("camera resolution {}", resolution);
+
+    // frames go to the recording channel only while recording, as `TextureHandler` does
+    let (rendering_receiver, recording_sender) = {
+        let channel_handler = channel_handler.lock().unwrap();
+        (
+            channel_handler.rendering.1.clone(),
+            channel_handler.recording.0.clone(),
+        )
+    };
+    let forwarding = session.recording.clone();
+    let forwarder = thread::spawn(move || {
+        while let Ok((buf, timestamp)) = rendering_receiver.recv() {
+            if forwarding.load(Ordering::Relaxed) {
+                recording_sender
+                    .send((buf, timestamp))
+                    .unwrap_or_else(|e| debug!("Error sending to recording channel: {}", e));
+            }
+        }
+    });
+
+    let encoder = session.start_recording(RecordingTarget {
+        file_path_prefix,
+        file_name: args.name.clone(),
+        resolution,
+        container: args.container()?,
+        layout: args.layout()?,
+        segmenting: args.segmenting(),
+        encoder: args.encoder,
+        fps: args.record_fps,
+        wav_master: args.wav_master,
+        limits: args.limits,
+        metadata: args.metadata.clone(),
+    })?;
+    info!(
+        "encoding {} {} fps, {} at {} bps, {} rate control, keyframe every {} frames, {} threads",
+        encoder.codec.to_str(),
+        encoder.fps,
+        encoder.preset.to_str(),
+        encoder.bitrate_bps,
+        encoder.rate_control.to_str(),
+        encoder.keyframe_interval,
+        encoder.threads
+    );
+    info!("recording for {:?}", args.duration);
+    let time_elapsed = record_for(&session, args.duration)?;
+    wait_until_saved(&ui_event)?;
+
+    session.close_audio()?;
+    let _ = forwarder.join();
+
+    let mut video_path = args.output.join(&args.name);
+    match args.segmenting() {
+        Some(_) => video_path.set_extension(PLAYLIST_EXTENSION),
+        None => video_path.set_extension(args.container()?.extension()),
+    };
+    let mut thumbnail_path = args.output.join(THUMBNAIL_DIR_NAME).join(&args.name);
+    thumbnail_path.set_extension("png");
+    println!(
+        "recorded {:.1}s to {} (thumbnail {})",
+        time_elapsed,
+        video_path.display(),
+        thumbnail_path.display()
+    );
+    if args.wav_master {
+        println!(
+            "audio master {}",
+            video_path.with_extension("wav").display()
+        );
+    }
+    Ok(())
+}
+
+// The same take without the camera, as 'start_voice_memo' records it.
+fn run_voice_memo(args: Args) -> Result<(), anyhow::Error> {
+    std::fs::create_dir_all(args.output.join(THUMBNAIL_DIR_NAME))?;
+    let file_path_prefix = args
+        .output
+        .to_str()
+        .ok_or_else(|| anyhow!("output path is not valid unicode"))?
+        .to_string();
+
+    let channel_handler = Arc::new(Mutex::new(ChannelService::new()));
+    let session = CaptureSession::new(channel_handler.clone(), Arc::new(ResolutionService::new()));
+    let ui_event = channel_handler.lock().unwrap().ui_event.1.clone();
+    if let Some(scratch) = &args.scratch {
+        session.set_app_data_root(scratch)?;
+    }
+    session.open_audio(args.audio_source())?;
+
+    session.start_voice_memo(VoiceMemoTarget {
+        file_path_prefix,
+        file_name: args.name.clone(),
+        visual: args.visual()?,
+        wav_master: args.wav_master,
+        limits: args.limits,
+        metadata: args.metadata.clone(),
+    })?;
+    info!("recording a voice memo for {:?}", args.duration);
+    let time_elapsed = record_for(&session, args.duration)?;
+    wait_until_saved(&ui_event)?;
+    session.close_audio()?;
+
+    let mut audio_path = args.output.join(&args.name);
+    audio_path.set_extension(if args.visual()?.is_some() {
+        "mp4"
+    } else {
+        "m4a"
+    });
+    println!("recorded {:.1}s to {}", time_elapsed, audio_path.display());
+    if args.wav_master {
+        println!(
+            "audio master {}",
+            audio_path.with_extension("wav").display()
+        );
+    }
+    Ok(())
+}
+
+// Stops the take after `duration` unless a limit stopped it first. Returns the recorded length.
+fn record_for(session: &CaptureSession, duration: Duration) -> Result<f64, anyhow::Error> {
+    let until = Instant::now() + duration;
+    while Instant::now() < until {
+        if session.state() != SessionState::Recording {
+            return Ok(session.time_elapsed());
+        }
+        thread::sleep(
+            until
+                .saturating_duration_since(Instant::now())
+                .min(POLL_INTERVAL),
+        );
+    }
+    Ok(session.stop_recording()?)
+}
+
+// Follows the take through encoding and saving, the app gets these through
+// 'listen_ui_event_dispatcher'.
+fn wait_until_saved(ui_event: &Receiver<UiEvent>) -> Result<(), anyhow::Error> {
+    let mut failure = None;
+    while let Ok(event) = ui_event.recv() {
+        match event {
+            UiEvent::SessionState(SessionState::Idle) => break,
+            UiEvent::SessionState(SessionState::Failed) => {
+                return Err(failure.unwrap_or_else(|| anyhow!("recording failed")));
+            }
+            UiEvent::SessionState(state) => info!("{}", state.to_str()),
+            UiEvent::Error(e) => failure = Some(e.into()),
+            UiEvent::LimitReached(reason) => info!("stopped at a limit: {}", reason.to_str()),
+            UiEvent::Progress(progress) => info!(
+                "{} {}/{} frames, {} bytes, {:.1} fps, eta {:?}",
+                progress.phase.to_str(),
+                progress.frames_encoded,
+                progress.frames_queued,
+                progress.bytes_written,
+                progress.fps,
+                progress.eta
+            ),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use fdk_aac::enc::{AudioObjectType, BitRate, ChannelMode, Encoder, EncoderParams, Transport};
+
+use super::{error::DomainError, mp4::AacConfig};
+
+// AAC-LC through fdk-aac, one access unit per call once the encoder is primed.
+pub struct AacEncoder {
+    encoder: Encoder,
+    config: AacConfig,
+    frame_length: u32,
+    channels: usize,
+    output: Vec<u8>,
+}
+
+impl AacEncoder {
+    pub fn new(sample_rate: u32, channels: u16, bit_rate: u32) -> Result<Self, DomainError> {
+        let channel_mode = match channels {
+            1 => ChannelMode::Mono,
+            2 => ChannelMode::Stereo,
+            n => {
+                return Err(DomainError::Encoding(format!(
+                    "aac: {} channels are not supported",
+                    n
+                )))
+            }
+        };
+        let encoder = Encoder::new(EncoderParams {
+            bit_rate: BitRate::Cbr(bit_rate),
+            sample_rate,
+            transport: Transport::Raw,
+            channels: channel_mode,
+            audio_object_type: AudioObjectType::Mpeg4LowComplexity,
+        })
+        .map_err(|e| DomainError::Encoding(format!("aac: {:?}", e)))?;
+        let info = encoder
+            .info()
+            .map_err(|e| DomainError::Encoding(format!("aac: {:?}", e)))?;
+
+        Ok(Self {
+            encoder,
+            config: AacConfig {
+                sample_rate,
+                channels,
+                bit_rate,
+                decoder_config: info.confBuf[..info.confSize as usize].to_vec(),
+                priming: info.nDelay,
+            },
+            frame_length: info.frameLength,
+            channels: channels as usize,
+            output: vec![0; (info.maxOutBufBytes as usize).max(8192)],
+        })
+    }
+
+    pub fn config(&self) -> &AacConfig {
+        &self.config
+    }
+
+    // per channel, also the duration of every access unit
+    pub fn frame_length(&self) -> u32 {
+        self.frame_length
+    }
+
+    // interleaved samples `encode` expects per call
+    pub fn frame_samples(&self) -> usize {
+        self.frame_length as usize * self.channels
+    }
+
+    pub fn encode(&mut self, samples: &[i16]) -> Result<Option<&[u8]>, DomainError> {
+        let info = self
+            .encoder
+            .encode(samples, &mut self.output)
+            .map_err(|e| DomainError::Encoding(format!("aac: {:?}", e)))?;
+        if info.output_size == 0 {
+            return Ok(None);
+        }
+        Ok(Some(&self.output[..info.output_size]))
+    }
+}
@@ -1,50 +1,143 @@
 use cpal::traits::{DeviceTrait, StreamTrait};
 use cpal::Stream;
-use log::debug;
+use log::{debug, error};
 
 use std::fs::File;
-use std::io::{self, Write};
+use std::io;
 use std::path::Path;
 use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crate::message_channel::audio_message_channel::{cpal_available_inputs, Pcm};
 
+use super::{
+    audio_source::{
+        AudioSource, GeneratorInput, PcmSink, SampleGenerator, ToneGenerator, WavFileGenerator,
+    },
+    av_sync::AudioClock,
+    error::DomainError,
+};
+
+// Above this a chunk of i16 samples is taken for a voice rather than background noise.
+pub const ACTIVE_AUDIO_AMPLITUDE: f32 = 10000.0;
+
+// The loudest sample of a chunk, what the stream and the visual track go by.
+pub fn amplitude(data: &[i16]) -> f32 {
+    data.iter()
+        .fold(0.0, |max: f32, &sample| max.max(f32::abs(sample as f32)))
+}
+
 pub struct AudioService {
-    pub stream: SendableStream,
+    pub input: AudioInput,
     pub pcm: Pcm,
 }
+
+pub enum AudioInput {
+    Stream(SendableStream),
+    Generator(GeneratorInput),
+}
+
 pub struct SendableStream(Stream);
 
 unsafe impl Sync for SendableStream {}
 unsafe impl Send for SendableStream {}
 
 impl AudioService {
-    pub fn play(&self) -> Result<(), anyhow::Error> {
-        self.stream.0.play()?;
+    pub fn play(&self) -> Result<(), DomainError> {
+        match &self.input {
+            AudioInput::Stream(stream) => stream.0.play().map_err(DomainError::audio)?,
+            AudioInput::Generator(generator) => generator.play(),
+        }
         Ok(())
     }
 
-    pub fn stop(&self) {
-        drop(&self.stream);
+    pub fn stop(self) {
+        drop(self.input);
         debug!("audio stream dropped");
     }
 }
 
+// What a take needs besides the stream: where its samples go and the time spent paused,
+// which the capture times are shifted by like the video frames are.
+pub struct TakeAudio<'a> {
+    pub pcm_path: &'a Path,
+    pub paused_total: Arc<Mutex<Duration>>,
+}
+
+fn open_pcm_sink(
+    buffer: Arc<Mutex<Vec<u8>>>,
+    recording: Arc<AtomicBool>,
+    take: Option<&TakeAudio>,
+    sample_rate: u32,
+    channels: u16,
+) -> Result<(PcmSink, Option<AudioClock>), DomainError> {
+    let file = take
+        .map(|take| File::create(take.pcm_path).map(io::BufWriter::new))
+        .transpose()?;
+    let clock = take.map(|take| AudioClock::new(sample_rate, channels, take.paused_total.clone()));
+    let sink = PcmSink::new(buffer, file, recording, clock.clone(), channels);
+    Ok((sink, clock))
+}
+
+// Samples are written to the take's pcm file while `recording` is set. Without a take the
+// stream only feeds the buffer for the UI, as it does while previewing.
 pub fn open_audio_stream(
+    source: &AudioSource,
+    recording: Arc<AtomicBool>,
+    take: Option<&TakeAudio>,
+) -> Result<AudioService, DomainError> {
+    match source {
+        AudioSource::Device(device_name) => open_device_stream(device_name, recording, take),
+        AudioSource::Tone(config) => {
+            open_generator_stream(ToneGenerator::new(*config), recording, take)
+        }
+        AudioSource::WavFile(path) => {
+            open_generator_stream(WavFileGenerator::open(path)?, recording, take)
+        }
+    }
+}
+
+fn open_generator_stream<G: SampleGenerator>(
+    generator: G,
+    recording: Arc<AtomicBool>,
+    take: Option<&TakeAudio>,
+) -> Result<AudioService, DomainError> {
+    let buffer: Arc<Mutex<Vec<u8>>> = Arc::new(Mutex::new(Vec::new()));
+    let sample_rate = generator.sample_rate();
+    let channels = generator.channels();
+    let (sink, clock) = open_pcm_sink(buffer.clone(), recording, take, sample_rate, channels)?;
+    debug!(
+        "Generated input: sample_rate {}, channels {}",
+        sample_rate, channels
+    );
+
+    Ok(AudioService {
+        input: AudioInput::Generator(GeneratorInput::spawn(generator, sink)?),
+        pcm: Pcm {
+            data: buffer,
+            sample_rate,
+            channels,
+            bit_rate: 128000,
+            path: take.map(|take| take.pcm_path.to_path_buf()),
+            clock,
+        },
+    })
+}
+
+fn open_device_stream(
     device_name: &str,
     recording: Arc<AtomicBool>,
-) -> Result<AudioService, anyhow::Error> {
+    take: Option<&TakeAudio>,
+) -> Result<AudioService, DomainError> {
     let devices = cpal_available_inputs();
     let device = devices
         .iter()
-        .find(|d| d.name().unwrap() == device_name)
-        .unwrap();
+        .find(|d| d.name().map_or(false, |name| name == device_name))
+        .ok_or_else(|| DomainError::Audio(format!("Audio device not found: {}", device_name)))?;
 
-    debug!("Input device: {}", device.name()?);
-    let config = device
-        .default_input_config()
-        .expect("Failed to get default input config");
+    debug!("Input device: {}", device_name);
+    let config = device.default_input_config().map_err(DomainError::audio)?;
 
     // let config = cpal::SupportedStreamConfig::new(
     //     config.channels(),
@@ -56,106 +149,81 @@ pub fn open_audio_stream(
     debug!("Default input config: {:?}", config);
     let buffer: Arc<Mutex<Vec<u8>>> = Arc::new(Mutex::new(Vec::new()));
 
-    let buffer_clone = Arc::clone(&buffer);
-    let buffer_file_name = "temp.pcm";
-
-    if Path::new(buffer_file_name).exists() {
-        std::fs::remove_file(buffer_file_name).unwrap();
-    }
-    let file = File::create(buffer_file_name).unwrap();
-    let mut buffered_file = io::BufWriter::new(file);
-
-    let mut last_recording_state = false;
+    let (mut sink, clock) = open_pcm_sink(
+        Arc::clone(&buffer),
+        recording,
+        take,
+        config.sample_rate().0,
+        config.channels(),
+    )?;
 
     let stream = match config.sample_format() {
-        cpal::SampleFormat::I16 => device.build_input_stream(
-            &config.config(),
-            move |data: &[i16], _: &cpal::InputCallbackInfo| {
-                let mut buffer = buffer_clone.lock().unwrap();
-                // trying to distinguish between silence and human voice for a wavy pattern UI in the 'setting' tab
-                // only when it's not recording
-                const ACTIVE_AUDIO_AMPLITUDE: f32 = 10000.0;
-                let amplitude = data
-                    .iter()
-                    .fold(0.0, |max: f32, &sample| max.max(f32::abs(sample as f32)));
-                // debug!("amplitude: {}", amplitude);
-
-                for &sample in data.iter() {
-                    let sample = sample.to_le_bytes();
-                    if recording.load(std::sync::atomic::Ordering::Relaxed) {
-                        buffer.push(sample[0]);
-                        buffer.push(sample[1]);
-                        if buffer.len() >= 100000 {
-                            buffered_file.write_all(&buffer).unwrap();
-                            buffer.clear();
-                        }
-                        last_recording_state = true;
-                    } else {
-                        if last_recording_state {
-                            buffered_file.write_all(&buffer).unwrap();
-                            buffer.clear();
-                            last_recording_state = false;
-                        }
-                        if amplitude > ACTIVE_AUDIO_AMPLITUDE {
-                            buffer.push(sample[0]);
-                            buffer.push(sample[1]);
-                        }
-                    }
-                }
-            },
-            move |err| eprintln!("an error occurred on stream: {}", err),
-            None,
-        )?,
-        cpal::SampleFormat::F32 => device.build_input_stream(
-            &config.config(),
-            move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                let mut buffer = buffer_clone.lock().unwrap();
-                const ACTIVE_AUDIO_AMPLITUDE: f32 = 0.03;
-                let amplitude = data
-                    .iter()
-                    .fold(0.0, |max: f32, &sample| max.max(f32::abs(sample)));
-                // debug!("amplitude: {}", amplitude);
-
-                for &sample in data.iter() {
-                    let i16_sample = (sample * i16::MAX as f32) as i16;
-                    let sample = i16_sample.to_le_bytes();
-                    if recording.load(std::sync::atomic::Ordering::Relaxed) {
-                        buffer.push(sample[0]);
-                        buffer.push(sample[1]);
-                        if buffer.len() >= 100000 {
-                            buffered_file.write_all(&buffer).unwrap();
-                            buffer.clear();
-                        }
-                        last_recording_state = true;
-                    } else {
-                        if last_recording_state {
-                            buffered_file.write_all(&buffer).unwrap();
-                            buffer.clear();
-                            last_recording_state = false;
-                        }
-                        if amplitude > ACTIVE_AUDIO_AMPLITUDE {
-                            buffer.push(sample[0]);
-                            buffer.push(sample[1]);
-                        }
-                    }
-                }
-
-                // debug!("audio buffer size: {}", buffer.len());
-            },
-            move |err| eprintln!("an error occurred on stream: {}", err),
-            None,
-        )?,
+        cpal::SampleFormat::I16 => device
+            .build_input_stream(
+                &config.config(),
+                move |data: &[i16], info: &cpal::InputCallbackInfo| {
+                    // trying to distinguish between silence and human voice for a wavy pattern UI in the 'setting' tab
+                    // only when it's not recording
+                    let amplitude = amplitude(data);
+                    // debug!("amplitude: {}", amplitude);
+
+                    sink.push(
+                        data.iter().copied(),
+                        amplitude > ACTIVE_AUDIO_AMPLITUDE,
+                        capture_time(info),
+                    );
+                },
+                move |err| error!("an error occurred on stream: {}", err),
+                None,
+            )
+            .map_err(DomainError::audio)?,
+        cpal::SampleFormat::F32 => device
+            .build_input_stream(
+                &config.config(),
+                move |data: &[f32], info: &cpal::InputCallbackInfo| {
+                    const ACTIVE_AUDIO_AMPLITUDE: f32 = 0.03;
+                    let amplitude = data
+                        .iter()
+                        .fold(0.0, |max: f32, &sample| max.max(f32::abs(sample)));
+                    // debug!("amplitude: {}", amplitude);
+
+                    sink.push(
+                        data.iter().map(|&sample| (sample * i16::MAX as f32) as i16),
+                        amplitude > ACTIVE_AUDIO_AMPLITUDE,
+                        capture_time(info),
+                    );
+
+                    // debug!("audio buffer size: {}", buffer.len());
+                },
+                move |err| error!("an error occurred on stream: {}", err),
+                None,
+            )
+            .map_err(DomainError::audio)?,
         _ => {
-            return Err(anyhow::anyhow!("Unsupported sample format"));
+            return Err(DomainError::Audio("Unsupported sample format".to_string()));
         }
     };
     Ok(AudioService {
-        stream: SendableStream(stream),
+        input: AudioInput::Stream(SendableStream(stream)),
         pcm: Pcm {
             data: Arc::clone(&buffer),
             sample_rate: config.sample_rate().0,
             channels: config.channels(),
             bit_rate: 128000,
+            path: take.map(|take| take.pcm_path.to_path_buf()),
+            clock,
         },
     })
 }
+
+// The callback runs a buffer's worth after the first sample of it was captured, cpal knows
+// by how much on most hosts.
+fn capture_time(info: &cpal::InputCallbackInfo) -> Instant {
+    let now = Instant::now();
+    let timestamp = info.timestamp();
+    let latency = timestamp
+        .callback
+        .duration_since(&timestamp.capture)
+        .unwrap_or_default();
+    now.checked_sub(latency).unwrap_or(now)
+}
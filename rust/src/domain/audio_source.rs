@@ -0,0 +1,440 @@
+use std::{
+    f32::consts::PI,
+    fs::File,
+    io::{self, BufReader, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use hound::{SampleFormat, WavReader};
+use log::{debug, error};
+
+use super::{
+    audio::{amplitude, ACTIVE_AUDIO_AMPLITUDE},
+    av_sync::AudioClock,
+    error::DomainError,
+};
+
+pub const TONE_GENERATOR_NAME: &str = "Tone Generator";
+pub const WAV_FILE_PREFIX: &str = "wav:";
+
+// What the audio service captures from. Everything but `Device` is generated in-process
+// and pushed through the same `PcmSink` the cpal callbacks use.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioSource {
+    Device(String),
+    Tone(ToneConfig),
+    WavFile(PathBuf),
+}
+
+impl AudioSource {
+    pub fn from_name(name: &str) -> Self {
+        if name == TONE_GENERATOR_NAME {
+            AudioSource::Tone(ToneConfig::default())
+        } else if let Some(path) = name.strip_prefix(WAV_FILE_PREFIX) {
+            AudioSource::WavFile(PathBuf::from(path))
+        } else {
+            AudioSource::Device(name.to_string())
+        }
+    }
+
+    pub fn is_generated(&self) -> bool {
+        !matches!(self, AudioSource::Device(_))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneConfig {
+    pub frequency: f32,
+    pub amplitude: f32,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl Default for ToneConfig {
+    fn default() -> Self {
+        Self {
+            frequency: 440.0,
+            amplitude: 0.5,
+            sample_rate: 48000,
+            channels: 2,
+        }
+    }
+}
+
+// Receives interleaved i16 samples from any source.
+// While recording, samples go to the pcm file; otherwise (paused included) only loud chunks
+// are kept in the buffer for the wavy pattern UI in the 'setting' tab.
+// Each chunk that makes it into the file is stamped on `clock` with when it was captured.
+pub struct PcmSink {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    buffered_file: Option<io::BufWriter<File>>,
+    recording: Arc<AtomicBool>,
+    last_recording_state: bool,
+    clock: Option<AudioClock>,
+    // samples that went to the file so far
+    written: u64,
+    channels: u16,
+}
+
+impl PcmSink {
+    pub fn new(
+        buffer: Arc<Mutex<Vec<u8>>>,
+        buffered_file: Option<io::BufWriter<File>>,
+        recording: Arc<AtomicBool>,
+        clock: Option<AudioClock>,
+        channels: u16,
+    ) -> Self {
+        Self {
+            buffer,
+            buffered_file,
+            recording,
+            last_recording_state: false,
+            clock,
+            written: 0,
+            channels,
+        }
+    }
+
+    // `captured` is when the first of `samples` was captured
+    pub fn push(&mut self, samples: impl Iterator<Item = i16>, active: bool, captured: Instant) {
+        let mut buffer = self.buffer.lock().unwrap();
+        let mut stamped = false;
+        for (index, sample) in samples.enumerate() {
+            let sample = sample.to_le_bytes();
+            if self.recording.load(Ordering::Relaxed) {
+                if !self.last_recording_state {
+                    // started or resumed, drop what was kept for the wave
+                    buffer.clear();
+                    self.last_recording_state = true;
+                }
+                // once per chunk, again if it resumed halfway through
+                if let Some(clock) = self.clock.as_ref().filter(|_| !stamped) {
+                    clock.stamp(self.written / self.channels.max(1) as u64, captured, index);
+                    stamped = true;
+                }
+                self.written += 1;
+                buffer.push(sample[0]);
+                buffer.push(sample[1]);
+                if buffer.len() >= 100000 {
+                    // this runs on the audio callback, never panic here
+                    if let Some(file) = self.buffered_file.as_mut() {
+                        if let Err(e) = file.write_all(&buffer) {
+                            error!("Failed to write pcm: {:?}", e);
+                        }
+                    }
+                    buffer.clear();
+                }
+            } else {
+                if self.last_recording_state {
+                    // the muxer reads the file right after the recording stops
+                    flush(&mut self.buffered_file, &mut buffer);
+                    self.last_recording_state = false;
+                }
+                stamped = false;
+                if active {
+                    buffer.push(sample[0]);
+                    buffer.push(sample[1]);
+                }
+            }
+        }
+    }
+}
+
+// The stream is replaced as soon as a take stops, what is still buffered must not get lost.
+impl Drop for PcmSink {
+    fn drop(&mut self) {
+        if self.last_recording_state {
+            let buffer = self.buffer.clone();
+            flush(&mut self.buffered_file, &mut buffer.lock().unwrap());
+        }
+        // the encoder waits for this before it reads the end of the file
+        if let Some(clock) = &self.clock {
+            clock.close();
+        }
+    }
+}
+
+fn flush(buffered_file: &mut Option<io::BufWriter<File>>, buffer: &mut Vec<u8>) {
+    if let Some(file) = buffered_file.as_mut() {
+        if let Err(e) = file.write_all(buffer).and_then(|_| file.flush()) {
+            error!("Failed to write pcm: {:?}", e);
+        }
+    }
+    buffer.clear();
+}
+
+pub trait SampleGenerator: Send + 'static {
+    fn sample_rate(&self) -> u32;
+    fn channels(&self) -> u16;
+    // appends `frames` interleaved frames to `out`
+    fn next_samples(&mut self, frames: usize, out: &mut Vec<i16>);
+}
+
+// Deterministic sine wave, the same sample index always produces the same value.
+pub struct ToneGenerator {
+    config: ToneConfig,
+    position: u64,
+}
+
+impl ToneGenerator {
+    pub fn new(config: ToneConfig) -> Self {
+        Self {
+            config,
+            position: 0,
+        }
+    }
+}
+
+impl SampleGenerator for ToneGenerator {
+    fn sample_rate(&self) -> u32 {
+        self.config.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.config.channels
+    }
+
+    fn next_samples(&mut self, frames: usize, out: &mut Vec<i16>) {
+        let rate = self.config.sample_rate as u64;
+        let frequency = self.config.frequency as f64;
+        for _ in 0..frames {
+            // whole seconds and the rest apart, so a long take keeps the precision of its start
+            let seconds = (self.position / rate) as f64 * frequency;
+            let within = (self.position % rate) as f64 * frequency / rate as f64;
+            let phase = (seconds.fract() + within).fract() as f32;
+            let value = (2.0 * PI * phase).sin() * self.config.amplitude * i16::MAX as f32;
+            for _ in 0..self.config.channels {
+                out.push(value as i16);
+            }
+            self.position += 1;
+        }
+    }
+}
+
+// Plays a WAV file in a loop, converting whatever it contains to i16.
+pub struct WavFileGenerator {
+    samples: Vec<i16>,
+    sample_rate: u32,
+    channels: u16,
+    position: usize,
+}
+
+impl WavFileGenerator {
+    pub fn open(path: &PathBuf) -> Result<Self, DomainError> {
+        let reader =
+            WavReader::new(BufReader::new(File::open(path)?)).map_err(DomainError::audio)?;
+        let spec = reader.spec();
+        debug!("wav file {:?}: {:?}", path, spec);
+
+        let samples: Vec<i16> = match spec.sample_format {
+            SampleFormat::Float => reader
+                .into_samples::<f32>()
+                .map(|s| s.map(|s| (s * i16::MAX as f32) as i16))
+                .collect::<Result<_, _>>()
+                .map_err(DomainError::audio)?,
+            SampleFormat::Int => {
+                let shift = spec.bits_per_sample as i32 - 16;
+                reader
+                    .into_samples::<i32>()
+                    .map(|s| {
+                        s.map(|s| {
+                            if shift >= 0 {
+                                (s >> shift) as i16
+                            } else {
+                                (s << -shift) as i16
+                            }
+                        })
+                    })
+                    .collect::<Result<_, _>>()
+                    .map_err(DomainError::audio)?
+            }
+        };
+        if samples.is_empty() {
+            return Err(DomainError::Audio(format!(
+                "WAV file has no samples: {:?}",
+                path
+            )));
+        }
+
+        Ok(Self {
+            samples,
+            sample_rate: spec.sample_rate,
+            channels: spec.channels,
+            position: 0,
+        })
+    }
+}
+
+impl SampleGenerator for WavFileGenerator {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn next_samples(&mut self, frames: usize, out: &mut Vec<i16>) {
+        for _ in 0..frames * self.channels as usize {
+            out.push(self.samples[self.position]);
+            self.position = (self.position + 1) % self.samples.len();
+        }
+    }
+}
+
+// Drives a `SampleGenerator` in real time on its own thread, 10ms at a time, like an
+// audio device callback would.
+pub struct GeneratorInput {
+    playing: Arc<AtomicBool>,
+    alive: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl GeneratorInput {
+    pub fn spawn<G: SampleGenerator>(
+        mut generator: G,
+        mut sink: PcmSink,
+    ) -> Result<Self, DomainError> {
+        let playing = Arc::new(AtomicBool::new(false));
+        let alive = Arc::new(AtomicBool::new(true));
+        let playing_ = playing.clone();
+        let alive_ = alive.clone();
+
+        let worker = thread::Builder::new()
+            .name("audio_generator".to_string())
+            .spawn(move || {
+                let chunk_interval = Duration::from_millis(10);
+                let frames = (generator.sample_rate() / 100) as usize;
+                let mut samples = Vec::with_capacity(frames * generator.channels() as usize);
+                let mut next_tick = Instant::now();
+
+                while alive_.load(Ordering::Relaxed) {
+                    if !playing_.load(Ordering::Relaxed) {
+                        thread::sleep(chunk_interval);
+                        next_tick = Instant::now();
+                        continue;
+                    }
+                    samples.clear();
+                    generator.next_samples(frames, &mut samples);
+                    let active = amplitude(&samples) > ACTIVE_AUDIO_AMPLITUDE;
+                    sink.push(samples.iter().copied(), active, Instant::now());
+
+                    next_tick += chunk_interval;
+                    let now = Instant::now();
+                    if next_tick > now {
+                        thread::sleep(next_tick - now);
+                    } else {
+                        next_tick = now;
+                    }
+                }
+                debug!("audio generator exits");
+            })
+            .map_err(|e| {
+                error!("Failed to spawn audio generator: {:?}", e);
+                e
+            })?;
+
+        Ok(Self {
+            playing,
+            alive,
+            worker: Some(worker),
+        })
+    }
+
+    pub fn play(&self) {
+        self.playing.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for GeneratorInput {
+    fn drop(&mut self) {
+        self.alive.store(false, Ordering::Relaxed);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expected(config: &ToneConfig, position: u64) -> i16 {
+        let t = position as f64 / config.sample_rate as f64;
+        let value = (2.0 * std::f64::consts::PI * config.frequency as f64 * t).sin();
+        (value * config.amplitude as f64 * i16::MAX as f64) as i16
+    }
+
+    fn assert_sine(config: ToneConfig, start: u64, frames: usize) {
+        let mut tone = ToneGenerator::new(config);
+        tone.position = start;
+        let mut out = vec![];
+        tone.next_samples(frames, &mut out);
+        assert_eq!(out.len(), frames * config.channels as usize);
+        for (i, frame) in out.chunks_exact(config.channels as usize).enumerate() {
+            let want = expected(&config, start + i as u64);
+            for &sample in frame {
+                assert!(
+                    (sample as i32 - want as i32).abs() <= 1,
+                    "sample {} is {}, not {}",
+                    start + i as u64,
+                    sample,
+                    want
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tone_is_a_sine() {
+        assert_sine(ToneConfig::default(), 0, 4800);
+    }
+
+    #[test]
+    fn tone_stays_a_sine_past_f32_precision() {
+        // an hour in, well past the 2^24 samples an f32 counts exactly
+        assert_sine(ToneConfig::default(), 48000 * 3600 + 17, 4800);
+        let odd = ToneConfig {
+            frequency: 1234.5,
+            sample_rate: 44100,
+            channels: 1,
+            ..ToneConfig::default()
+        };
+        assert_sine(odd, (1 << 24) + 3, 4410);
+    }
+
+    #[test]
+    fn tone_depends_on_the_position_alone() {
+        let mut whole = ToneGenerator::new(ToneConfig::default());
+        let mut all = vec![];
+        whole.next_samples(1000, &mut all);
+        let mut parts = ToneGenerator::new(ToneConfig::default());
+        let mut pieces = vec![];
+        for _ in 0..10 {
+            parts.next_samples(100, &mut pieces);
+        }
+        assert_eq!(all, pieces);
+    }
+
+    #[test]
+    fn dropped_sink_closes_the_take() {
+        let clock = AudioClock::new(48000, 1, Arc::new(Mutex::new(Duration::ZERO)));
+        let mut sink = PcmSink::new(
+            Arc::new(Mutex::new(vec![])),
+            None,
+            Arc::new(AtomicBool::new(true)),
+            Some(clock.clone()),
+            1,
+        );
+        sink.push([1, 2, 3].into_iter(), false, Instant::now());
+        assert!(!clock.wait_closed(Duration::ZERO));
+        drop(sink);
+        assert!(clock.wait_closed(Duration::ZERO));
+    }
+}
@@ -0,0 +1,401 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+// The estimate sticks to the nominal rate until the stamps span this long, callback jitter
+// outweighs the drift before that.
+const MIN_FIT_SECONDS: f64 = 10.0;
+// Anything further off is a broken timestamp, not a slow crystal.
+const MAX_DRIFT_PPM: f64 = 5000.0;
+// How far the resampler may stray from 1:1 to catch up with the capture clock, 0.5% is
+// well below what can be heard as a pitch change.
+const MAX_CORRECTION: f64 = 0.005;
+// How long the encoder waits for the audio stream to finish the pcm file of a take.
+pub const PCM_CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Ties the samples of a take to the clock the video frames are stamped with. Every chunk
+// the audio callback writes is stamped with its capture time, shifted back by the time
+// spent paused the way frames are, and a line fitted through the stamps tells when any
+// sample was captured and how fast the device really runs.
+#[derive(Debug, Clone)]
+pub struct AudioClock {
+    sample_rate: u32,
+    channels: u16,
+    paused_total: Arc<Mutex<Duration>>,
+    fit: Arc<Mutex<LineFit>>,
+    // the pcm file has all of the take's samples
+    closed: Arc<AtomicBool>,
+}
+
+// Running least squares of capture time (y, seconds since `origin`) over frame index (x),
+// kept as means and co-moments so an hour of stamps doesn't lose precision.
+#[derive(Debug, Default)]
+struct LineFit {
+    origin: Option<Instant>,
+    count: f64,
+    mean_x: f64,
+    mean_y: f64,
+    m2_x: f64,
+    c_xy: f64,
+    first_x: f64,
+    last_x: f64,
+}
+
+impl AudioClock {
+    pub fn new(sample_rate: u32, channels: u16, paused_total: Arc<Mutex<Duration>>) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            paused_total,
+            fit: Arc::new(Mutex::new(LineFit::default())),
+            closed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // `frame` is the index in the take of the first sample written out of a chunk captured
+    // at `captured`, `skipped` the samples of the chunk in front of it that weren't.
+    pub fn stamp(&self, frame: u64, captured: Instant, skipped: usize) {
+        let skipped = skipped as f64 / self.channels.max(1) as f64 / self.sample_rate as f64;
+        let paused = *self.paused_total.lock().unwrap();
+        let captured = captured.checked_sub(paused).unwrap_or(captured);
+        let mut fit = self.fit.lock().unwrap();
+        let origin = *fit.origin.get_or_insert(captured);
+        fit.add(frame as f64, seconds_between(origin, captured) + skipped);
+    }
+
+    // Called by whoever writes the pcm file once the last of it is on disk.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+    }
+
+    // Waits up to `timeout` for `close`, false if it didn't come.
+    pub fn wait_closed(&self, timeout: Duration) -> bool {
+        let until = Instant::now() + timeout;
+        while !self.closed.load(Ordering::Acquire) {
+            if Instant::now() >= until {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    // None until the first chunk has been written.
+    pub fn estimate(&self) -> Option<ClockEstimate> {
+        let fit = self.fit.lock().unwrap();
+        let origin = fit.origin?;
+        let nominal = 1.0 / self.sample_rate as f64;
+        let mut seconds_per_frame = nominal;
+        if (fit.last_x - fit.first_x) * nominal >= MIN_FIT_SECONDS && fit.m2_x > 0.0 {
+            let fitted = fit.c_xy / fit.m2_x;
+            if ((fitted / nominal - 1.0) * 1e6).abs() <= MAX_DRIFT_PPM {
+                seconds_per_frame = fitted;
+            }
+        }
+        Some(ClockEstimate {
+            origin,
+            mean_x: fit.mean_x,
+            mean_y: fit.mean_y,
+            seconds_per_frame,
+            nominal,
+        })
+    }
+}
+
+impl LineFit {
+    fn add(&mut self, x: f64, y: f64) {
+        if self.count == 0.0 {
+            self.first_x = x;
+        }
+        self.last_x = x;
+        self.count += 1.0;
+        let dx = x - self.mean_x;
+        self.mean_x += dx / self.count;
+        self.mean_y += (y - self.mean_y) / self.count;
+        self.m2_x += dx * (x - self.mean_x);
+        self.c_xy += dx * (y - self.mean_y);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ClockEstimate {
+    origin: Instant,
+    mean_x: f64,
+    mean_y: f64,
+    seconds_per_frame: f64,
+    nominal: f64,
+}
+
+impl ClockEstimate {
+    // The (fractional) frame captured `offset` seconds after `start`, negative if that was
+    // before the first one.
+    pub fn frame_at(&self, start: Instant, offset: f64) -> f64 {
+        let y = seconds_between(self.origin, start) + offset;
+        self.mean_x + (y - self.mean_y) / self.seconds_per_frame
+    }
+
+    // positive when the device delivers more samples than it claims
+    pub fn drift_ppm(&self) -> f64 {
+        (self.nominal / self.seconds_per_frame - 1.0) * 1e6
+    }
+}
+
+// Takes the samples of a take onto the video's timeline: the first output frame is the one
+// captured with the first video frame, silence if the audio started later, and the rate
+// follows what the device really delivered so the two don't drift apart over a long take.
+// Without a clock the samples are taken as they are.
+pub struct Resampler {
+    channels: usize,
+    sample_rate: u32,
+    clock: Option<AudioClock>,
+    video_start: Option<Instant>,
+    // whole samples taken in so far, the first one is frame `input_start`
+    input: Vec<i16>,
+    input_start: u64,
+    // where in the input the next output frame is taken from, and how far it then moves
+    position: Option<f64>,
+    step: f64,
+    // interleaved samples of the next output frame, `frame_samples` of them
+    frame: Vec<i16>,
+    frame_samples: usize,
+    // frames out so far
+    produced: u64,
+}
+
+impl Resampler {
+    pub fn new(
+        sample_rate: u32,
+        channels: u16,
+        clock: Option<AudioClock>,
+        frame_samples: usize,
+    ) -> Self {
+        Self {
+            channels: channels.max(1) as usize,
+            sample_rate,
+            clock,
+            video_start: None,
+            input: vec![],
+            input_start: 0,
+            position: None,
+            step: 1.0,
+            frame: Vec::with_capacity(frame_samples),
+            frame_samples,
+            produced: 0,
+        }
+    }
+
+    // Capture time of the first video frame, the audio is lined up against it.
+    pub fn start_video(&mut self, at: Instant) {
+        self.video_start.get_or_insert(at);
+    }
+
+    // Whether there's nothing to line the input up with yet.
+    pub fn waiting_for_video(&self) -> bool {
+        self.clock.is_some() && self.video_start.is_none()
+    }
+
+    pub fn push(&mut self, samples: impl IntoIterator<Item = i16>) {
+        self.input.extend(samples);
+    }
+
+    pub fn produced(&self) -> u64 {
+        self.produced
+    }
+
+    // Whether all of the input is on its way, waiting up to `timeout` for the pcm file to
+    // be closed. Without a clock nobody is writing to it anymore.
+    pub fn wait_for_input(&self, timeout: Duration) -> bool {
+        self.clock
+            .as_ref()
+            .is_none_or(|clock| clock.wait_closed(timeout))
+    }
+
+    // the input frame the next output frame is taken from
+    pub fn input_position(&self) -> u64 {
+        self.position
+            .map_or(0, |position| position.max(0.0).floor() as u64)
+    }
+
+    // The offset of the audio against the video and its drift, once there is an estimate.
+    pub fn clock_report(&self) -> Option<(f64, f64)> {
+        let estimate = self.clock.as_ref()?.estimate()?;
+        let video_start = self.video_start?;
+        let offset = -estimate.frame_at(video_start, 0.0) / self.sample_rate as f64;
+        Some((offset, estimate.drift_ppm()))
+    }
+
+    // The input frame that belongs `produced` frames into the output, going by the clock.
+    fn target(&self, produced: u64) -> Option<f64> {
+        let estimate = self.clock.as_ref()?.estimate()?;
+        let offset = produced as f64 / self.sample_rate as f64;
+        Some(estimate.frame_at(self.video_start?, offset))
+    }
+
+    // The next whole output frame, linearly interpolated between neighbouring input frames.
+    // None where the input runs out, or where it ends with `finishing`, and never past
+    // `until` frames into the output. What was taken of a frame by then stays for the next
+    // call.
+    pub fn next_frame(&mut self, finishing: bool, until: Option<u64>) -> Option<Vec<i16>> {
+        let channels = self.channels;
+        let mut position = match self.position.or_else(|| self.target(0)) {
+            Some(position) => position,
+            // nothing stamped yet, there may be nothing to take
+            None if self.clock.is_some() && !finishing => return None,
+            None => 0.0,
+        };
+        let complete = loop {
+            if self.frame.is_empty() {
+                // aim at where the clock wants the input a second from now
+                let ahead = self.sample_rate as u64;
+                if let Some(target) = self.target(self.produced + ahead) {
+                    self.step = ((target - position) / ahead as f64)
+                        .clamp(1.0 - MAX_CORRECTION, 1.0 + MAX_CORRECTION);
+                }
+            }
+            let index = position.floor();
+            let fraction = position - index;
+            let at = index as i64 - self.input_start as i64;
+            let available = (self.input.len() / channels) as i64;
+            if at >= available || (at + 1 >= available && !finishing) {
+                break false;
+            }
+            if until.is_some_and(|until| self.produced >= until) {
+                break false;
+            }
+            for channel in 0..channels {
+                let sample = |frame: i64| match frame {
+                    frame if frame < 0 || frame >= available => 0.0,
+                    frame => self.input[frame as usize * channels + channel] as f64,
+                };
+                let (a, b) = (sample(at), sample(at + 1));
+                self.frame.push((a + (b - a) * fraction).round() as i16);
+            }
+            position += self.step;
+            self.produced += 1;
+            if self.frame.len() == self.frame_samples {
+                break true;
+            }
+        };
+        self.position = Some(position);
+
+        // what is behind the position won't be looked at again
+        let behind = (position.floor() as i64 - self.input_start as i64 - 1)
+            .clamp(0, (self.input.len() / channels) as i64) as usize;
+        if behind > 0 {
+            self.input.drain(..behind * channels);
+            self.input_start += behind as u64;
+        }
+        complete.then(|| std::mem::replace(&mut self.frame, Vec::with_capacity(self.frame_samples)))
+    }
+
+    // What was taken of a frame that isn't whole.
+    pub fn partial_frame(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.frame)
+    }
+}
+
+fn seconds_between(from: Instant, to: Instant) -> f64 {
+    match to.checked_duration_since(from) {
+        Some(after) => after.as_secs_f64(),
+        None => -from.duration_since(to).as_secs_f64(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 1000;
+
+    // A clock stamped once a second for `seconds`, by a device that runs `ppm` fast and
+    // started `offset` seconds after `start`.
+    fn stamped(start: Instant, offset: f64, ppm: f64, seconds: u64) -> AudioClock {
+        let clock = AudioClock::new(RATE, 1, Arc::new(Mutex::new(Duration::ZERO)));
+        let real_rate = RATE as f64 * (1.0 + ppm / 1e6);
+        for second in 0..=seconds {
+            let frame = second * RATE as u64;
+            let captured = offset + frame as f64 / real_rate;
+            clock.stamp(frame, start + Duration::from_secs_f64(captured), 0);
+        }
+        clock
+    }
+
+    #[test]
+    fn fit_finds_the_drift_and_the_start() {
+        let start = Instant::now();
+        for ppm in [-300.0, 0.0, 120.0, 4000.0] {
+            let estimate = stamped(start, 0.25, ppm, 60).estimate().unwrap();
+            assert!((estimate.drift_ppm() - ppm).abs() < 0.01, "{} ppm", ppm);
+            // the video started a quarter of a second before the first sample
+            let frame = estimate.frame_at(start, 0.0);
+            assert!(
+                (frame + 250.0 * (1.0 + ppm / 1e6)).abs() < 0.01,
+                "{}",
+                frame
+            );
+            let frame = estimate.frame_at(start, 30.25);
+            assert!((frame - 30.0 * RATE as f64 * (1.0 + ppm / 1e6)).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn short_or_broken_stamps_keep_the_nominal_rate() {
+        let start = Instant::now();
+        let short = stamped(start, 0.0, 300.0, MIN_FIT_SECONDS as u64 - 1);
+        assert_eq!(short.estimate().unwrap().drift_ppm(), 0.0);
+        let long = stamped(start, 0.0, 300.0, MIN_FIT_SECONDS as u64);
+        assert!((long.estimate().unwrap().drift_ppm() - 300.0).abs() < 0.01);
+        let broken = stamped(start, 0.0, MAX_DRIFT_PPM + 100.0, 60);
+        assert_eq!(broken.estimate().unwrap().drift_ppm(), 0.0);
+    }
+
+    #[test]
+    fn resampler_catches_up_no_faster_than_the_limit() {
+        let start = Instant::now();
+        for (lag, step) in [
+            (100.0, 1.0 + MAX_CORRECTION),
+            (-100.0, 1.0 - MAX_CORRECTION),
+        ] {
+            let mut resampler = Resampler::new(RATE, 1, Some(stamped(start, 0.0, 0.0, 60)), 100);
+            resampler.start_video(start);
+            resampler.push(vec![0; 60 * RATE as usize]);
+            resampler.next_frame(false, None).unwrap();
+            assert!((resampler.step - 1.0).abs() < 1e-9);
+
+            // fallen behind or run ahead of the clock by a tenth of a second
+            resampler.position = resampler.position.map(|position| position - lag);
+            resampler.next_frame(false, None).unwrap();
+            assert_eq!(resampler.step, step);
+        }
+    }
+
+    #[test]
+    fn resampled_take_keeps_the_video_length() {
+        let start = Instant::now();
+        let seconds = 30 * 60;
+        for ppm in [-200.0, 200.0] {
+            let clock = stamped(start, 0.0, ppm, seconds);
+            let mut resampler = Resampler::new(RATE, 1, Some(clock), 1024);
+            resampler.start_video(start);
+            // everything the device delivered in that time
+            let delivered = (seconds as f64 * RATE as f64 * (1.0 + ppm / 1e6)).round();
+            resampler.push(vec![0; delivered as usize]);
+            while resampler.next_frame(true, None).is_some() {}
+
+            let wanted = seconds * RATE as u64;
+            let produced = resampler.produced() as i64;
+            assert!(
+                (produced - wanted as i64).abs() <= 1,
+                "{} ppm: {}",
+                ppm,
+                produced
+            );
+            assert!((delivered as i64 - wanted as i64).abs() >= 300);
+        }
+    }
+}
@@ -6,16 +6,34 @@ use nokhwa::pixel_format::RgbAFormat;
 use nokhwa::utils::{CameraIndex, CameraInfo, RequestedFormat, RequestedFormatType, Resolution};
 use nokhwa::{Buffer, CallbackCamera};
 
-use std::fmt::Error;
 use std::time::Instant;
 
 use super::channel::ChannelService;
+use super::error::DomainError;
+use super::frame_source::{
+    FrameSource, NokhwaFrameSource, SyntheticConfig, SyntheticFrameSource, SYNTHETIC_CAMERA_NAME,
+};
 use super::resolution::ResolutionService;
 
+#[derive(Debug, Clone)]
+pub enum CameraSelection {
+    Device(CameraInfo),
+    Synthetic(SyntheticConfig),
+}
+
+impl CameraSelection {
+    pub fn human_name(&self) -> String {
+        match self {
+            CameraSelection::Device(camera_info) => camera_info.human_name(),
+            CameraSelection::Synthetic(_) => SYNTHETIC_CAMERA_NAME.to_string(),
+        }
+    }
+}
+
 pub struct CameraService {
     pub channel_handler: Arc<Mutex<ChannelService>>,
-    pub camera: Option<CallbackCamera>,
-    pub current_camera_info: Arc<Mutex<Option<CameraInfo>>>,
+    pub frame_source: Option<Box<dyn FrameSource>>,
+    pub current_camera_info: Arc<Mutex<Option<CameraSelection>>>,
     pub resolution_service: Arc<ResolutionService>,
 }
 
@@ -26,12 +44,16 @@ impl CameraService {
     ) -> Self {
         Self {
             channel_handler,
-            camera: None,
+            frame_source: None,
             current_camera_info: Arc::new(Mutex::new(None)),
             resolution_service,
         }
     }
-    pub fn infate_camera(&mut self, index: CameraIndex, resolution: Option<&String>) {
+    pub fn infate_camera(
+        &mut self,
+        selection: CameraSelection,
+        resolution: Option<Resolution>,
+    ) -> Result<(), DomainError> {
         let mut channel_handler = self.channel_handler.lock().unwrap();
         let mut rendering_sender = channel_handler.rendering.0.clone();
 
@@ -40,41 +62,72 @@ impl CameraService {
             rendering_sender = channel_handler.rendering.0.clone();
         }
 
-        let resolution_service = self.resolution_service.clone();
+        let frame_source: Box<dyn FrameSource> = match selection {
+            CameraSelection::Device(camera_info) => {
+                match inflate_camera_conection(
+                    camera_info.index().clone(),
+                    rendering_sender,
+                    resolution,
+                ) {
+                    Ok(frame_source) => Box::new(frame_source),
+                    Err(e) => {
+                        debug!("Failed to inflate camera");
+                        return Err(DomainError::Camera(format!(
+                            "Failed to inflate camera {}: {}",
+                            camera_info.human_name(),
+                            e
+                        )));
+                    }
+                }
+            }
+            CameraSelection::Synthetic(mut config) => {
+                if let Some(res) = resolution {
+                    config.resolution = res;
+                }
+                Box::new(SyntheticFrameSource::new(config, rendering_sender))
+            }
+        };
+
+        self.resolution_service
+            .set_available_resolutions(&frame_source.available_resolutions());
+        self.resolution_service
+            .set_current_resolution(&frame_source.resolution().to_string());
+        self.frame_source = Some(frame_source);
+        Ok(())
+    }
 
-        if let Ok(camera) =
-            inflate_camera_conection(index, rendering_sender, resolution, resolution_service)
-        {
-            self.camera = Some(camera);
-        } else {
-            debug!("Failed to inflate camera");
-        }
+    // None without a camera or when it doesn't tell
+    pub fn frame_rate(&self) -> Option<u32> {
+        self.frame_source
+            .as_ref()
+            .map(|frame_source| frame_source.frame_rate())
+            .filter(|fps| *fps > 0)
     }
 
     pub fn health_check(&mut self) -> (bool, String) {
-        let camera = self.camera.as_mut().unwrap();
-        match camera.poll_frame() {
-            Ok(_) => (true, "".to_string()),
-            Err(e) => (false, e.to_string()),
+        match self.frame_source.as_mut() {
+            Some(frame_source) => frame_source.health_check(),
+            None => (false, "No camera".to_string()),
         }
     }
 
-    pub fn open_camera_stream(&mut self) {
-        if let Some(mut camera) = self.camera.take() {
-            if let Err(_) = camera.open_stream() {
-                error!("Failed to open camera");
-            } else {
-                debug!("camera opened");
+    pub fn open_camera_stream(&mut self) -> Result<(), DomainError> {
+        if let Some(frame_source) = self.frame_source.as_mut() {
+            if let Err(e) = frame_source.open_stream() {
+                error!("Failed to open camera: {:?}", e);
+                return Err(e);
             }
-            self.camera = Some(camera);
+            debug!("camera opened");
+            Ok(())
         } else {
             debug!("Failed to open camera");
+            Err(DomainError::Camera("No camera to open".to_string()))
         }
     }
 
     pub fn stop_camera_stream(&mut self) {
-        if let Some(camera) = self.camera.take() {
-            drop(camera);
+        if let Some(frame_source) = self.frame_source.take() {
+            drop(frame_source);
             self.channel_handler.lock().unwrap().rendering.0.close();
         } else {
             debug!("No camera to stop");
@@ -86,21 +139,13 @@ impl CameraService {
 fn inflate_camera_conection(
     index: CameraIndex,
     rendering_sender: Sender<(Buffer, Instant)>,
-    requested_resolution: Option<&String>,
-    resolution_service: Arc<ResolutionService>,
-) -> Result<CallbackCamera, Error> {
+    requested_resolution: Option<Resolution>,
+) -> Result<NokhwaFrameSource, DomainError> {
     let mut requested: Option<RequestedFormat> = None;
     if let Some(res) = requested_resolution {
-        if res.len() != 0 {
-            let res = res.split('x').collect::<Vec<&str>>();
-            let res = Resolution::new(
-                res[0].parse::<u32>().unwrap(),
-                res[1].parse::<u32>().unwrap(),
-            );
-            requested = Some(RequestedFormat::new::<RgbAFormat>(
-                RequestedFormatType::HighestResolution(res),
-            ));
-        }
+        requested = Some(RequestedFormat::new::<RgbAFormat>(
+            RequestedFormatType::HighestResolution(res),
+        ));
     }
 
     if requested.is_none() {
@@ -120,26 +165,31 @@ fn inflate_camera_conection(
     })
     .map_err(|why| {
         error!("Error opening camera: {:?}", why);
-        Error
+        DomainError::camera(why)
     })?;
     let format = camera.camera_format().map_err(|why| {
         error!("Error reading camera format: {:?}", why);
-        Error
+        DomainError::camera(why)
     })?;
     let camera_info = camera.info().clone();
 
     debug!("format :{}", format);
     debug!("camera_info :{}", camera_info);
 
-    let frame_format = camera.frame_format().unwrap();
-    let resolutions = camera.compatible_list_by_resolution(frame_format).unwrap();
+    let frame_format = camera.frame_format().map_err(DomainError::camera)?;
+    let resolutions = camera
+        .compatible_list_by_resolution(frame_format)
+        .map_err(DomainError::camera)?;
     let resolutions: Vec<String> = resolutions
         .iter()
         .map(|r| format!("{}x{}", r.0.width(), r.0.height()))
         .collect();
-    resolution_service.set_available_resolutions(&resolutions);
-    resolution_service.set_current_resolution(&format.resolution().to_string());
-    Ok(camera)
+    Ok(NokhwaFrameSource::new(
+        camera,
+        format.resolution(),
+        format.frame_rate(),
+        resolutions,
+    ))
 }
 
 #[cfg(debug_assertions)]
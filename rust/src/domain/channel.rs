@@ -3,10 +3,26 @@ use std::time::Instant;
 use kanal::{Receiver, Sender};
 use nokhwa::Buffer;
 
+use super::{
+    error::DomainError, limits::StopReason, progress::EncodingProgress, session::SessionState,
+};
+
+// Events from background threads, forwarded to Dart by 'listen_ui_event_dispatcher'.
+#[derive(Debug)]
+pub enum UiEvent {
+    SessionState(SessionState),
+    Error(DomainError),
+    Progress(EncodingProgress),
+    // the take stopped on its own, it's being saved
+    LimitReached(StopReason),
+}
+
 pub struct ChannelService {
     pub rendering: (Sender<(Buffer, Instant)>, Receiver<(Buffer, Instant)>),
     pub recording: (Sender<(Buffer, Instant)>, Receiver<(Buffer, Instant)>),
-    pub encoding: (Sender<Buffer>, Receiver<Buffer>),
+    pub encoding: (Sender<(Buffer, Instant)>, Receiver<(Buffer, Instant)>),
+    // lives as long as the app, `reset` leaves it alone
+    pub ui_event: (Sender<UiEvent>, Receiver<UiEvent>),
 }
 
 impl ChannelService {
@@ -24,6 +40,7 @@ impl ChannelService {
             rendering: (rendering_sender, rendering_receiver),
             recording: (recording_sender, recording_receiver),
             encoding: (encoding_sender, encoding_receiver),
+            ui_event: kanal::unbounded(),
         }
     }
 
@@ -0,0 +1,315 @@
+use std::thread;
+
+use openh264::encoder::{EncoderConfig, RateControlMode};
+use rav1e::{config::SpeedSettings, data::Rational};
+
+// The codec of a take's video. AV1 is encoded on the cpu at a fraction of openh264's speed, a
+// take may go on encoding for a while after it's stopped, but it comes out about half the size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+    H264,
+    Av1,
+}
+
+impl VideoCodec {
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "h264",
+            VideoCodec::Av1 => "av1",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "h264" => Some(VideoCodec::H264),
+            "av1" => Some(VideoCodec::Av1),
+            _ => None,
+        }
+    }
+
+    // share of h264's bitrate that gets the same picture
+    fn bitrate_share(&self) -> f64 {
+        match self {
+            VideoCodec::H264 => 1.0,
+            VideoCodec::Av1 => 0.5,
+        }
+    }
+}
+
+// Bits per pixel per frame at which the preset is aimed, 0.08 comes out at about 1.8 Mbps
+// for 720p at 24fps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QualityPreset {
+    Low,
+    Standard,
+    High,
+    Archival,
+}
+
+impl QualityPreset {
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            QualityPreset::Low => "low",
+            QualityPreset::Standard => "standard",
+            QualityPreset::High => "high",
+            QualityPreset::Archival => "archival",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "low" => Some(QualityPreset::Low),
+            "standard" => Some(QualityPreset::Standard),
+            "high" => Some(QualityPreset::High),
+            "archival" => Some(QualityPreset::Archival),
+            _ => None,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> f64 {
+        match self {
+            QualityPreset::Low => 0.04,
+            QualityPreset::Standard => 0.08,
+            QualityPreset::High => 0.14,
+            QualityPreset::Archival => 0.25,
+        }
+    }
+
+    // rav1e's speed from 0 to 10, the slow end doesn't keep up with a camera at any size
+    fn av1_speed(&self) -> u8 {
+        match self {
+            QualityPreset::Low => 10,
+            QualityPreset::Standard => 9,
+            QualityPreset::High => 8,
+            QualityPreset::Archival => 7,
+        }
+    }
+
+    // rav1e's quantizer from 0 to 255 for the modes without a bitrate
+    fn av1_quantizer(&self) -> usize {
+        match self {
+            QualityPreset::Low => 160,
+            QualityPreset::Standard => 120,
+            QualityPreset::High => 90,
+            QualityPreset::Archival => 60,
+        }
+    }
+}
+
+// openh264's rate control modes, by the names the channel uses. rav1e only knows a bitrate
+// or a quantizer, 'quality' and 'off' encode AV1 at the preset's quantizer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateControl {
+    Quality,
+    Bitrate,
+    Buffer,
+    Timestamp,
+    Off,
+}
+
+impl RateControl {
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            RateControl::Quality => "quality",
+            RateControl::Bitrate => "bitrate",
+            RateControl::Buffer => "buffer",
+            RateControl::Timestamp => "timestamp",
+            RateControl::Off => "off",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "quality" => Some(RateControl::Quality),
+            "bitrate" => Some(RateControl::Bitrate),
+            "buffer" => Some(RateControl::Buffer),
+            "timestamp" => Some(RateControl::Timestamp),
+            "off" => Some(RateControl::Off),
+            _ => None,
+        }
+    }
+
+    fn mode(&self) -> RateControlMode {
+        match self {
+            RateControl::Quality => RateControlMode::Quality,
+            RateControl::Bitrate => RateControlMode::Bitrate,
+            RateControl::Buffer => RateControlMode::Bufferbased,
+            RateControl::Timestamp => RateControlMode::Timestamp,
+            RateControl::Off => RateControlMode::Off,
+        }
+    }
+}
+
+// What the caller asked for, anything left out is filled in by `resolve`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EncoderSettings {
+    pub codec: Option<VideoCodec>,
+    pub preset: Option<QualityPreset>,
+    pub bitrate_bps: Option<u32>,
+    pub rate_control: Option<RateControl>,
+    // in frames
+    pub keyframe_interval: Option<u32>,
+    // 0 lets the encoder decide
+    pub threads: Option<u16>,
+}
+
+// The settings a take is actually encoded with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncoderProfile {
+    pub codec: VideoCodec,
+    pub preset: QualityPreset,
+    pub bitrate_bps: u32,
+    pub rate_control: RateControl,
+    pub keyframe_interval: u32,
+    pub threads: u16,
+    pub fps: u32,
+}
+
+const MIN_BITRATE_BPS: u32 = 100_000;
+// seeking and the fragments of a fragmented take don't have to go back further than this
+const KEYFRAME_SECONDS: u32 = 2;
+const MAX_THREADS: usize = 4;
+
+impl EncoderSettings {
+    pub fn resolve(&self, width: usize, height: usize, fps: u32) -> EncoderProfile {
+        let codec = self.codec.unwrap_or(VideoCodec::H264);
+        let preset = self.preset.unwrap_or(QualityPreset::Standard);
+        let bitrate_bps = self.bitrate_bps.unwrap_or_else(|| {
+            let bps = (width * height) as f64
+                * fps as f64
+                * preset.bits_per_pixel()
+                * codec.bitrate_share();
+            (bps as u32).max(MIN_BITRATE_BPS)
+        });
+        // a single thread keeps up below 720p and leaves the cores to the yuv conversion
+        let threads = self.threads.unwrap_or_else(|| {
+            if width * height < 1280 * 720 {
+                1
+            } else {
+                thread::available_parallelism().map_or(1, |n| n.get().min(MAX_THREADS)) as u16
+            }
+        });
+        EncoderProfile {
+            codec,
+            preset,
+            bitrate_bps,
+            rate_control: self.rate_control.unwrap_or(RateControl::Bitrate),
+            keyframe_interval: self
+                .keyframe_interval
+                .unwrap_or(fps * KEYFRAME_SECONDS)
+                .max(1),
+            threads,
+            fps,
+        }
+    }
+}
+
+impl EncoderProfile {
+    pub fn h264_config(&self, width: u32, height: u32) -> EncoderConfig {
+        EncoderConfig::new(width, height)
+            .rate_control_mode(self.rate_control.mode())
+            .set_bitrate_bps(self.bitrate_bps)
+            .max_frame_rate(self.fps as f32)
+            .set_multiple_thread_idc(self.threads)
+            .enable_skip_frame(false)
+            .debug(false)
+    }
+
+    pub fn av1_config(&self, width: usize, height: usize) -> rav1e::EncoderConfig {
+        let mut config = rav1e::EncoderConfig {
+            width,
+            height,
+            time_base: Rational::new(1, self.fps as u64),
+            max_key_frame_interval: self.keyframe_interval as u64,
+            speed_settings: SpeedSettings::from_preset(self.preset.av1_speed()),
+            // tiles are what rav1e spreads over its threads
+            tiles: self.threads as usize,
+            // no frames held back to be shown later, about three times as fast for a few
+            // percent in size
+            low_latency: true,
+            ..Default::default()
+        };
+        config.min_key_frame_interval = config
+            .min_key_frame_interval
+            .min(config.max_key_frame_interval);
+        match self.rate_control {
+            RateControl::Quality | RateControl::Off => {
+                config.quantizer = self.preset.av1_quantizer()
+            }
+            _ => config.bitrate = self.bitrate_bps.min(i32::MAX as u32) as i32,
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitrate_follows_size_rate_preset_and_codec() {
+        use QualityPreset::*;
+        use VideoCodec::*;
+        let table = [
+            // width, height, fps, preset, codec, bitrate, keyframe interval
+            (640, 480, 30, Standard, H264, 737_280, 60),
+            (1280, 720, 30, Standard, H264, 2_211_840, 60),
+            (1280, 720, 60, Standard, H264, 4_423_680, 120),
+            (1920, 1080, 30, High, H264, 8_709_120, 60),
+            (1920, 1080, 30, Standard, Av1, 2_488_320, 60),
+            (1920, 1080, 24, Archival, H264, 12_441_600, 48),
+            // floored at the least a picture still holds together with
+            (320, 240, 15, Low, H264, MIN_BITRATE_BPS, 30),
+        ];
+        for (width, height, fps, preset, codec, bitrate_bps, keyframe_interval) in table {
+            let settings = EncoderSettings {
+                codec: Some(codec),
+                preset: Some(preset),
+                ..EncoderSettings::default()
+            };
+            let profile = settings.resolve(width, height, fps);
+            assert_eq!(
+                (profile.bitrate_bps, profile.keyframe_interval),
+                (bitrate_bps, keyframe_interval),
+                "{}x{} at {} fps",
+                width,
+                height,
+                fps
+            );
+            assert_eq!(profile.fps, fps);
+        }
+    }
+
+    #[test]
+    fn defaults_are_h264_standard_every_two_seconds() {
+        let profile = EncoderSettings::default().resolve(640, 480, 30);
+        assert_eq!(profile.codec, VideoCodec::H264);
+        assert_eq!(profile.preset, QualityPreset::Standard);
+        assert_eq!(profile.keyframe_interval, 30 * KEYFRAME_SECONDS);
+        // below 720p one thread keeps up
+        assert_eq!(profile.threads, 1);
+    }
+
+    #[test]
+    fn what_was_asked_for_is_kept() {
+        let settings = EncoderSettings {
+            bitrate_bps: Some(5_000_000),
+            rate_control: Some(RateControl::Quality),
+            keyframe_interval: Some(90),
+            threads: Some(3),
+            ..EncoderSettings::default()
+        };
+        let profile = settings.resolve(320, 240, 15);
+        assert_eq!(profile.bitrate_bps, 5_000_000);
+        assert_eq!(profile.rate_control, RateControl::Quality);
+        assert_eq!(profile.keyframe_interval, 90);
+        assert_eq!(profile.threads, 3);
+
+        // every frame a keyframe at the least
+        let settings = EncoderSettings {
+            keyframe_interval: Some(0),
+            ..EncoderSettings::default()
+        };
+        assert_eq!(settings.resolve(320, 240, 15).keyframe_interval, 1);
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    any::Any,
+    fmt::Display,
+    panic::{self, AssertUnwindSafe},
+};
+
+use kanal::Sender;
+use log::error;
+use thiserror::Error;
+
+use super::{channel::UiEvent, session::SessionState};
+
+#[derive(Debug, Error)]
+pub enum DomainError {
+    #[error("camera: {0}")]
+    Camera(String),
+    #[error("audio: {0}")]
+    Audio(String),
+    #[error("encoding: {0}")]
+    Encoding(String),
+    #[error("muxing: {0}")]
+    Muxing(String),
+    #[error("io: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("{thread} thread panicked: {message}")]
+    Panic { thread: String, message: String },
+    #[error("{action} is not allowed while {}", .state.to_str())]
+    InvalidState { state: SessionState, action: String },
+    #[error("the recording was cancelled")]
+    Cancelled,
+    #[error("{free_mb} MB left on the disk, a take needs {needed_mb} MB")]
+    DiskLow { free_mb: u64, needed_mb: u64 },
+}
+
+impl DomainError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            DomainError::Camera(_) => "camera_error",
+            DomainError::Audio(_) => "audio_error",
+            DomainError::Encoding(_) => "encoding_error",
+            DomainError::Muxing(_) => "muxing_error",
+            DomainError::Io(_) => "io_error",
+            DomainError::Panic { .. } => "panic",
+            DomainError::InvalidState { .. } => "busy",
+            DomainError::Cancelled => "cancelled",
+            DomainError::DiskLow { .. } => "disk_low",
+        }
+    }
+
+    pub fn camera(e: impl Display) -> Self {
+        DomainError::Camera(e.to_string())
+    }
+
+    pub fn audio(e: impl Display) -> Self {
+        DomainError::Audio(e.to_string())
+    }
+
+    pub fn encoding(e: impl Display) -> Self {
+        DomainError::Encoding(e.to_string())
+    }
+
+    pub fn muxing(e: impl Display) -> Self {
+        DomainError::Muxing(e.to_string())
+    }
+}
+
+impl From<image::ImageError> for DomainError {
+    fn from(e: image::ImageError) -> Self {
+        match e {
+            image::ImageError::IoError(e) => DomainError::Io(e),
+            e => DomainError::encoding(e),
+        }
+    }
+}
+
+// Runs the body of a background thread, turning a panic into an error so it can be
+// reported instead of silently killing the thread.
+pub fn catch_panic<T, F>(thread: &str, f: F) -> Result<T, DomainError>
+where
+    F: FnOnce() -> Result<T, DomainError>,
+{
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => Err(DomainError::Panic {
+            thread: thread.to_string(),
+            message: panic_message(payload),
+        }),
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+// Background threads have no caller to return to, so failures go to the UI instead.
+pub fn report(ui_event: &Sender<UiEvent>, e: DomainError) {
+    error!("{}", e);
+    ui_event
+        .send(UiEvent::Error(e))
+        .unwrap_or_else(|e| error!("Failed to report error to the UI: {:?}", e));
+}
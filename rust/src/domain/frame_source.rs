@@ -0,0 +1,494 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use image::{codecs::jpeg::JpegEncoder, ColorType};
+use kanal::Sender;
+use log::{debug, error};
+use nokhwa::{
+    utils::{FrameFormat, Resolution},
+    Buffer, CallbackCamera,
+};
+
+use super::error::DomainError;
+
+pub const SYNTHETIC_CAMERA_NAME: &str = "Synthetic Test Pattern";
+
+// Anything that can feed `(Buffer, Instant)` pairs into `ChannelService.rendering`.
+// The camera service only drives it, so the texture / recording / encoding paths
+// work the same whether the frames come from a webcam or are generated.
+pub trait FrameSource: Send {
+    fn open_stream(&mut self) -> Result<(), DomainError>;
+    fn health_check(&mut self) -> (bool, String);
+    fn resolution(&self) -> Resolution;
+    // what the source negotiated, 0 if it doesn't say
+    fn frame_rate(&self) -> u32;
+    fn available_resolutions(&self) -> Vec<String>;
+}
+
+pub struct NokhwaFrameSource {
+    camera: CallbackCamera,
+    resolution: Resolution,
+    frame_rate: u32,
+    available_resolutions: Vec<String>,
+}
+
+impl NokhwaFrameSource {
+    pub fn new(
+        camera: CallbackCamera,
+        resolution: Resolution,
+        frame_rate: u32,
+        available_resolutions: Vec<String>,
+    ) -> Self {
+        Self {
+            camera,
+            resolution,
+            frame_rate,
+            available_resolutions,
+        }
+    }
+}
+
+impl FrameSource for NokhwaFrameSource {
+    fn open_stream(&mut self) -> Result<(), DomainError> {
+        self.camera.open_stream().map_err(DomainError::camera)
+    }
+
+    fn health_check(&mut self) -> (bool, String) {
+        match self.camera.poll_frame() {
+            Ok(_) => (true, "".to_string()),
+            Err(e) => (false, e.to_string()),
+        }
+    }
+
+    fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    fn frame_rate(&self) -> u32 {
+        self.frame_rate
+    }
+
+    fn available_resolutions(&self) -> Vec<String> {
+        self.available_resolutions.clone()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SyntheticConfig {
+    pub resolution: Resolution,
+    pub fps: u32,
+    pub frame_format: FrameFormat,
+}
+
+impl Default for SyntheticConfig {
+    fn default() -> Self {
+        Self {
+            resolution: Resolution::new(1280, 720),
+            fps: 24,
+            frame_format: FrameFormat::YUYV,
+        }
+    }
+}
+
+// Moving color bars with a frame counter, paced in real time like a webcam would be.
+pub struct SyntheticFrameSource {
+    config: SyntheticConfig,
+    rendering_sender: Sender<(Buffer, Instant)>,
+    running: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl SyntheticFrameSource {
+    pub fn new(config: SyntheticConfig, rendering_sender: Sender<(Buffer, Instant)>) -> Self {
+        Self {
+            config,
+            rendering_sender,
+            running: Arc::new(AtomicBool::new(false)),
+            worker: None,
+        }
+    }
+}
+
+impl FrameSource for SyntheticFrameSource {
+    fn open_stream(&mut self) -> Result<(), DomainError> {
+        if self.worker.is_some() {
+            return Ok(());
+        }
+        if self.config.fps == 0 {
+            return Err(DomainError::Camera(
+                "Synthetic camera fps must be greater than 0".to_string(),
+            ));
+        }
+        self.running.store(true, Ordering::Relaxed);
+
+        let config = self.config;
+        let running = self.running.clone();
+        let rendering_sender = self.rendering_sender.clone();
+        let worker = thread::Builder::new()
+            .name("synthetic_camera".to_string())
+            .spawn(move || run_synthetic_camera(config, rendering_sender, running))?;
+        self.worker = Some(worker);
+        debug!("synthetic camera opened: {:?}", self.config);
+        Ok(())
+    }
+
+    fn health_check(&mut self) -> (bool, String) {
+        match self.worker.as_ref() {
+            Some(worker) if !worker.is_finished() => (true, "".to_string()),
+            Some(_) => (false, "Synthetic camera stopped".to_string()),
+            None => (false, "Synthetic camera is not streaming".to_string()),
+        }
+    }
+
+    fn resolution(&self) -> Resolution {
+        self.config.resolution
+    }
+
+    fn frame_rate(&self) -> u32 {
+        self.config.fps
+    }
+
+    fn available_resolutions(&self) -> Vec<String> {
+        let mut resolutions: Vec<String> = vec!["1920x1080", "1280x720", "640x480"]
+            .into_iter()
+            .map(|r| r.to_string())
+            .collect();
+        let current = self.config.resolution.to_string();
+        if !resolutions.contains(&current) {
+            resolutions.push(current);
+        }
+        resolutions
+    }
+}
+
+impl Drop for SyntheticFrameSource {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+        debug!("synthetic camera stopped");
+    }
+}
+
+fn run_synthetic_camera(
+    config: SyntheticConfig,
+    rendering_sender: Sender<(Buffer, Instant)>,
+    running: Arc<AtomicBool>,
+) {
+    let frame_interval = Duration::from_secs_f64(1.0 / config.fps as f64);
+    let mut next_tick = Instant::now();
+    let mut frame_index: u64 = 0;
+
+    while running.load(Ordering::Relaxed) {
+        if rendering_sender.is_closed() {
+            debug!("rendering channel closed, synthetic camera exits");
+            break;
+        }
+        match render_test_pattern(&config, frame_index) {
+            Ok(buf) => {
+                // same as the nokhwa callback, the latest frame wins
+                rendering_sender
+                    .try_send_realtime((buf, Instant::now()))
+                    .unwrap_or_else(|e| {
+                        error!("Error sending frame: {:?}", e);
+                        false
+                    });
+            }
+            Err(e) => error!("Error rendering test pattern: {:?}", e),
+        }
+        frame_index += 1;
+
+        next_tick += frame_interval;
+        let now = Instant::now();
+        if next_tick > now {
+            thread::sleep(next_tick - now);
+        } else {
+            // fell behind, don't try to catch up with a burst of frames
+            next_tick = now;
+        }
+    }
+}
+
+pub fn parse_frame_format(name: &str) -> Option<FrameFormat> {
+    match name.to_ascii_uppercase().as_str() {
+        "MJPEG" => Some(FrameFormat::MJPEG),
+        "YUYV" => Some(FrameFormat::YUYV),
+        "NV12" => Some(FrameFormat::NV12),
+        "GRAY" => Some(FrameFormat::GRAY),
+        "RAWRGB" => Some(FrameFormat::RAWRGB),
+        _ => None,
+    }
+}
+
+pub fn render_test_pattern(
+    config: &SyntheticConfig,
+    frame_index: u64,
+) -> Result<Buffer, DomainError> {
+    let width = config.resolution.width() as usize;
+    let height = config.resolution.height() as usize;
+    if width == 0 || height == 0 || width % 2 != 0 || height % 2 != 0 {
+        return Err(DomainError::Camera(format!(
+            "Unsupported synthetic resolution: {}",
+            config.resolution
+        )));
+    }
+    let rgb = test_pattern_rgb(width, height, frame_index);
+
+    let data = match config.frame_format {
+        FrameFormat::RAWRGB => rgb,
+        FrameFormat::GRAY => rgb
+            .chunks_exact(3)
+            .map(|p| luma(p[0], p[1], p[2]))
+            .collect(),
+        FrameFormat::YUYV => rgb_to_yuyv(&rgb),
+        FrameFormat::NV12 => rgb_to_nv12(&rgb, width, height),
+        FrameFormat::MJPEG => {
+            let mut jpeg = Vec::new();
+            JpegEncoder::new_with_quality(&mut jpeg, 80).encode(
+                &rgb,
+                width as u32,
+                height as u32,
+                ColorType::Rgb8,
+            )?;
+            jpeg
+        }
+    };
+
+    Ok(Buffer::new(config.resolution, &data, config.frame_format))
+}
+
+// SMPTE-ish bars: white, yellow, cyan, green, magenta, red, blue, black
+const BARS: [[u8; 3]; 8] = [
+    [235, 235, 235],
+    [235, 235, 16],
+    [16, 235, 235],
+    [16, 235, 16],
+    [235, 16, 235],
+    [235, 16, 16],
+    [16, 16, 235],
+    [16, 16, 16],
+];
+
+// 3x5 bitmaps, one bit per pixel, top row in the highest bits
+const DIGITS: [u16; 10] = [
+    0b111_101_101_101_111,
+    0b010_110_010_010_111,
+    0b111_001_111_100_111,
+    0b111_001_111_001_111,
+    0b101_101_111_001_001,
+    0b111_100_111_001_111,
+    0b111_100_111_101_111,
+    0b111_001_001_001_001,
+    0b111_101_111_101_111,
+    0b111_101_111_001_111,
+];
+
+fn test_pattern_rgb(width: usize, height: usize, frame_index: u64) -> Vec<u8> {
+    let mut rgb = vec![0u8; width * height * 3];
+    let bar_width = (width / BARS.len()).max(1);
+    // the bars scroll by a few pixels per frame so frozen or duplicated frames are visible
+    let offset = (frame_index as usize * 4) % width;
+
+    for y in 0..height {
+        for x in 0..width {
+            let bar = ((x + offset) / bar_width) % BARS.len();
+            let pos = (x + y * width) * 3;
+            rgb[pos..pos + 3].copy_from_slice(&BARS[bar]);
+        }
+    }
+
+    draw_counter(&mut rgb, width, height, frame_index);
+    rgb
+}
+
+fn draw_counter(rgb: &mut [u8], width: usize, height: usize, frame_index: u64) {
+    let scale = (height / 60).max(2);
+    let digits = frame_index.to_string();
+    let margin = scale * 2;
+    let box_width = digits.len() * 4 * scale + scale + margin;
+    let box_height = 7 * scale;
+
+    for y in margin.min(height)..(margin + box_height).min(height) {
+        for x in margin.min(width)..(margin + box_width).min(width) {
+            let pos = (x + y * width) * 3;
+            rgb[pos..pos + 3].copy_from_slice(&[0, 0, 0]);
+        }
+    }
+
+    for (i, digit) in digits.bytes().enumerate() {
+        let bitmap = DIGITS[(digit - b'0') as usize];
+        let origin_x = margin + scale + i * 4 * scale;
+        let origin_y = margin + scale;
+        for row in 0..5 {
+            for col in 0..3 {
+                if bitmap & (1 << (14 - (row * 3 + col))) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let x = origin_x + col * scale + dx;
+                        let y = origin_y + row * scale + dy;
+                        if x < width && y < height {
+                            let pos = (x + y * width) * 3;
+                            rgb[pos..pos + 3].copy_from_slice(&[255, 255, 255]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn luma(r: u8, g: u8, b: u8) -> u8 {
+    (0.2578125 * r as f32 + 0.50390625 * g as f32 + 0.09765625 * b as f32 + 16.0) as u8
+}
+
+fn chroma(r: f32, g: f32, b: f32) -> (u8, u8) {
+    let u = -0.1484375 * r + -0.2890625 * g + 0.4375 * b + 128.0;
+    let v = 0.4375 * r + -0.3671875 * g + -0.0703125 * b + 128.0;
+    (u as u8, v as u8)
+}
+
+fn rgb_to_yuyv(rgb: &[u8]) -> Vec<u8> {
+    let mut yuyv = Vec::with_capacity(rgb.len() / 3 * 2);
+    for pair in rgb.chunks_exact(6) {
+        let (p0, p1) = (&pair[0..3], &pair[3..6]);
+        let (u, v) = chroma(
+            (p0[0] as f32 + p1[0] as f32) / 2.0,
+            (p0[1] as f32 + p1[1] as f32) / 2.0,
+            (p0[2] as f32 + p1[2] as f32) / 2.0,
+        );
+        yuyv.extend_from_slice(&[luma(p0[0], p0[1], p0[2]), u, luma(p1[0], p1[1], p1[2]), v]);
+    }
+    yuyv
+}
+
+fn rgb_to_nv12(rgb: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut nv12 = vec![0u8; width * height * 3 / 2];
+    for (i, p) in rgb.chunks_exact(3).enumerate() {
+        nv12[i] = luma(p[0], p[1], p[2]);
+    }
+    let uv_base = width * height;
+    for j in 0..height / 2 {
+        for i in 0..width / 2 {
+            let pos = (i * 2 + j * 2 * width) * 3;
+            let (u, v) = chroma(rgb[pos] as f32, rgb[pos + 1] as f32, rgb[pos + 2] as f32);
+            nv12[uv_base + j * width + i * 2] = u;
+            nv12[uv_base + j * width + i * 2 + 1] = v;
+        }
+    }
+    nv12
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 48;
+    // what `luma` and `chroma` make of the white bar, a gray has no chroma
+    const WHITE_Y: u8 = 217;
+    const NO_CHROMA: u8 = 128;
+
+    fn render(frame_format: FrameFormat, frame_index: u64) -> Buffer {
+        let config = SyntheticConfig {
+            resolution: Resolution::new(WIDTH as u32, HEIGHT as u32),
+            frame_format,
+            ..SyntheticConfig::default()
+        };
+        render_test_pattern(&config, frame_index).unwrap()
+    }
+
+    fn pixel(rgb: &[u8], x: usize, y: usize) -> [u8; 3] {
+        let pos = (x + y * WIDTH) * 3;
+        [rgb[pos], rgb[pos + 1], rgb[pos + 2]]
+    }
+
+    #[test]
+    fn frame_sizes_match_the_format() {
+        let pixels = WIDTH * HEIGHT;
+        assert_eq!(render(FrameFormat::RAWRGB, 0).buffer().len(), pixels * 3);
+        assert_eq!(render(FrameFormat::GRAY, 0).buffer().len(), pixels);
+        assert_eq!(render(FrameFormat::YUYV, 0).buffer().len(), pixels * 2);
+        assert_eq!(render(FrameFormat::NV12, 0).buffer().len(), pixels * 3 / 2);
+        let jpeg = render(FrameFormat::MJPEG, 0);
+        assert_eq!(&jpeg.buffer()[..2], &[0xff, 0xd8]);
+    }
+
+    #[test]
+    fn odd_or_empty_resolutions_are_refused() {
+        for (width, height) in [(63, 48), (64, 47), (0, 48)] {
+            let config = SyntheticConfig {
+                resolution: Resolution::new(width, height),
+                ..SyntheticConfig::default()
+            };
+            assert!(render_test_pattern(&config, 0).is_err());
+        }
+    }
+
+    #[test]
+    fn bars_scroll_four_pixels_a_frame() {
+        let bar_width = WIDTH / BARS.len();
+        // below the counter
+        let y = HEIGHT - 1;
+        let first = test_pattern_rgb(WIDTH, HEIGHT, 0);
+        for (bar, color) in BARS.iter().enumerate() {
+            assert_eq!(pixel(&first, bar * bar_width, y), *color);
+        }
+        let second = test_pattern_rgb(WIDTH, HEIGHT, 1);
+        assert_eq!(pixel(&second, bar_width - 4, y), BARS[1]);
+        assert_eq!(pixel(&second, bar_width - 5, y), BARS[0]);
+    }
+
+    #[test]
+    fn counter_draws_white_digits_on_black() {
+        let rgb = test_pattern_rgb(WIDTH, HEIGHT, 0);
+        let scale = 2;
+        let margin = scale * 2;
+        // the box's corner, then the top left and the hole of the 0
+        assert_eq!(pixel(&rgb, margin, margin), [0, 0, 0]);
+        let origin = margin + scale;
+        assert_eq!(pixel(&rgb, origin, origin), [255, 255, 255]);
+        assert_eq!(pixel(&rgb, origin + scale, origin + scale), [0, 0, 0]);
+    }
+
+    #[test]
+    fn yuyv_shares_chroma_between_two_pixels() {
+        let white = [235, 235, 235, 235, 235, 235];
+        assert_eq!(
+            rgb_to_yuyv(&white),
+            [WHITE_Y, NO_CHROMA, WHITE_Y, NO_CHROMA]
+        );
+        // red and blue average out to a purple
+        let red_blue = [235, 16, 16, 16, 16, 235];
+        let yuyv = rgb_to_yuyv(&red_blue);
+        assert_eq!(yuyv[0], luma(235, 16, 16));
+        assert_eq!(yuyv[2], luma(16, 16, 235));
+        let (u, v) = chroma(125.5, 16.0, 125.5);
+        assert_eq!((yuyv[1], yuyv[3]), (u, v));
+    }
+
+    #[test]
+    fn nv12_has_full_luma_and_quarter_chroma() {
+        let rgb: Vec<u8> = [[235, 235, 235], [235, 16, 16], [16, 16, 235], [16, 16, 16]].concat();
+        let nv12 = rgb_to_nv12(&rgb, 2, 2);
+        assert_eq!(
+            nv12[..4],
+            [
+                WHITE_Y,
+                luma(235, 16, 16),
+                luma(16, 16, 235),
+                luma(16, 16, 16)
+            ]
+        );
+        // chroma is taken from the top left pixel of each 2x2 block
+        assert_eq!(nv12[4..], [NO_CHROMA, NO_CHROMA]);
+    }
+}
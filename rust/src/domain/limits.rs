@@ -0,0 +1,244 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use log::error;
+
+use super::error::DomainError;
+
+// how often a running take is held against its limits
+pub const LIMIT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const MB: u64 = 1024 * 1024;
+
+// Why a take stopped without `stop_recording`, sent to the UI as the code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    MaxDuration,
+    MaxSize,
+    DiskLow,
+}
+
+impl StopReason {
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            StopReason::MaxDuration => "max_duration",
+            StopReason::MaxSize => "max_size",
+            StopReason::DiskLow => "disk_low",
+        }
+    }
+}
+
+// What stops a take on its own. The take is stopped and saved as if `stop_recording` had been
+// called, so the limits are on what is recorded, the encoder still has to catch up after.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecordingLimits {
+    // recorded time, pauses excluded
+    pub max_duration: Option<Duration>,
+    // of the take's files, estimated from the bitrates
+    pub max_bytes: Option<u64>,
+    // on the volumes of the data and the scratch directory, a take doesn't start below it
+    pub min_free_bytes: Option<u64>,
+}
+
+impl RecordingLimits {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // Refuses to start a take on a volume that is low already.
+    pub fn check_free_space(&self, dirs: &[PathBuf]) -> Result<(), DomainError> {
+        let needed = match self.min_free_bytes {
+            Some(needed) => needed,
+            None => return Ok(()),
+        };
+        match least_free_space(dirs) {
+            Some(free) if free < needed => Err(DomainError::DiskLow {
+                free_mb: free / MB,
+                needed_mb: needed / MB,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    // The first limit a take `elapsed` long at `bits_per_second` is past, `free_bytes` is
+    // None when the volumes could not be asked.
+    pub fn reached(
+        &self,
+        elapsed: Duration,
+        bits_per_second: u64,
+        free_bytes: Option<u64>,
+    ) -> Option<StopReason> {
+        let estimated_bytes = elapsed.as_secs_f64() * bits_per_second as f64 / 8.0;
+        if self.max_duration.is_some_and(|max| elapsed >= max) {
+            Some(StopReason::MaxDuration)
+        } else if self
+            .max_bytes
+            .is_some_and(|max| estimated_bytes >= max as f64)
+        {
+            Some(StopReason::MaxSize)
+        } else if self
+            .min_free_bytes
+            .zip(free_bytes)
+            .is_some_and(|(min, free)| free < min)
+        {
+            Some(StopReason::DiskLow)
+        } else {
+            None
+        }
+    }
+}
+
+// The least space left on the volumes of `dirs`, those that can't be asked are left out.
+pub fn least_free_space(dirs: &[PathBuf]) -> Option<u64> {
+    dirs.iter()
+        .filter_map(|dir| {
+            // an empty prefix writes next to the executable
+            let dir = match dir.as_os_str().is_empty() {
+                true => Path::new("."),
+                false => dir.as_path(),
+            };
+            free_space(dir)
+                .map_err(|e| error!("Failed to get the free space of {:?}: {}", dir, e))
+                .ok()
+        })
+        .min()
+}
+
+#[cfg(windows)]
+pub fn free_space(dir: &Path) -> io::Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetDiskFreeSpaceExW(
+            directory: *const u16,
+            free_to_caller: *mut u64,
+            total: *mut u64,
+            total_free: *mut u64,
+        ) -> i32;
+    }
+
+    let wide: Vec<u16> = dir.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut free = 0u64;
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            wide.as_ptr(),
+            &mut free,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(free)
+}
+
+#[cfg(unix)]
+pub fn free_space(dir: &Path) -> io::Result<u64> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let dir = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(dir.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // the field types differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    let free = stat.f_bavail as u64 * stat.f_frsize as u64;
+    Ok(free)
+}
+
+#[cfg(not(any(windows, unix)))]
+pub fn free_space(_dir: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "free space is not known on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn limits() -> RecordingLimits {
+        RecordingLimits {
+            max_duration: Some(10 * MINUTE),
+            // a minute at 8 Mbit/s
+            max_bytes: Some(60_000_000),
+            min_free_bytes: Some(500 * MB),
+        }
+    }
+
+    #[test]
+    fn limits_are_reached_at_exactly_the_limit() {
+        let cases = [
+            // elapsed, bits per second, free bytes, reached
+            (MINUTE / 2, 8_000_000, Some(MB << 10), None),
+            (10 * MINUTE - Duration::from_millis(1), 0, None, None),
+            (10 * MINUTE, 0, None, Some(StopReason::MaxDuration)),
+            (11 * MINUTE, 0, None, Some(StopReason::MaxDuration)),
+            (MINUTE - Duration::from_millis(1), 8_000_000, None, None),
+            (MINUTE, 8_000_000, None, Some(StopReason::MaxSize)),
+            (MINUTE / 2, 16_000_000, None, Some(StopReason::MaxSize)),
+            (MINUTE / 2, 8_000_000, Some(500 * MB), None),
+            (
+                MINUTE / 2,
+                8_000_000,
+                Some(500 * MB - 1),
+                Some(StopReason::DiskLow),
+            ),
+            (MINUTE / 2, 8_000_000, Some(0), Some(StopReason::DiskLow)),
+            // past every limit, the duration goes first and then the size
+            (
+                10 * MINUTE,
+                8_000_000,
+                Some(0),
+                Some(StopReason::MaxDuration),
+            ),
+            (MINUTE, 8_000_000, Some(0), Some(StopReason::MaxSize)),
+        ];
+        for (elapsed, bits_per_second, free_bytes, reached) in cases {
+            assert_eq!(
+                limits().reached(elapsed, bits_per_second, free_bytes),
+                reached,
+                "{:?} at {} bit/s with {:?} bytes free",
+                elapsed,
+                bits_per_second,
+                free_bytes
+            );
+        }
+    }
+
+    #[test]
+    fn no_limits_are_never_reached() {
+        let limits = RecordingLimits::default();
+        assert!(limits.is_empty());
+        assert_eq!(limits.reached(100 * MINUTE, u32::MAX as u64, Some(0)), None);
+        assert!(limits.check_free_space(&[std::env::temp_dir()]).is_ok());
+    }
+
+    #[test]
+    fn take_does_not_start_on_a_low_volume() {
+        let dirs = [std::env::temp_dir()];
+        let needed = |min_free_bytes| RecordingLimits {
+            min_free_bytes: Some(min_free_bytes),
+            ..RecordingLimits::default()
+        };
+        assert!(needed(0).check_free_space(&dirs).is_ok());
+        match needed(u64::MAX).check_free_space(&dirs) {
+            Err(DomainError::DiskLow { needed_mb, .. }) => assert_eq!(needed_mb, u64::MAX / MB),
+            other => panic!("started on a full volume: {:?}", other),
+        }
+        // volumes that can't be asked don't hold a take back
+        let missing = [std::env::temp_dir()
+            .join("no such directory")
+            .join("at all")];
+        assert!(needed(u64::MAX).check_free_space(&missing).is_ok());
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    error::DomainError,
+    mp4::{read_mp4_metadata, write_mp4_metadata},
+    segments::{read_playlist, PLAYLIST_EXTENSION},
+};
+
+// seconds from 1904, where mp4 times count from, to 1970
+const MP4_EPOCH_OFFSET: i64 = 2_082_844_800;
+const SECONDS_PER_DAY: i64 = 86_400;
+
+// What the diary knows about an entry, kept in the file itself so a copy taken out of the
+// app still has it and the library can be rebuilt from the files alone.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryMetadata {
+    pub title: Option<String>,
+    // ISO 8601 with the time zone it was recorded in, see `parse_created`
+    pub created: Option<String>,
+    pub note: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl EntryMetadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // `created` as 'mvhd' counts time, in UTC seconds since 1904
+    pub fn mp4_time(&self) -> Option<u64> {
+        let created = parse_created(self.created.as_deref()?)?;
+        u64::try_from(created + MP4_EPOCH_OFFSET).ok()
+    }
+}
+
+// Seconds since 1970 of "2026-10-18T09:30:00+02:00", also with 'Z', "+0200" or a fraction of
+// a second, which is dropped. None without a time zone, the point is to keep it.
+pub fn parse_created(created: &str) -> Option<i64> {
+    let bytes = created.as_bytes();
+    let number = |at: usize, len: usize| -> Option<i64> {
+        let digits = created.get(at..at + len)?;
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    };
+    if bytes.len() < 20
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || !matches!(bytes[10], b'T' | b' ')
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+    {
+        return None;
+    }
+    let (year, month, day) = (number(0, 4)?, number(5, 2)?, number(8, 2)?);
+    let (hour, minute, second) = (number(11, 2)?, number(14, 2)?, number(17, 2)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    // 60 for a leap second
+    if second > 60 {
+        return None;
+    }
+
+    let mut zone = &created[19..];
+    if let Some(fraction) = zone.strip_prefix('.') {
+        zone = fraction.trim_start_matches(|c: char| c.is_ascii_digit());
+    }
+    let offset = match zone.as_bytes() {
+        [b'Z'] => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] | [sign @ (b'+' | b'-'), _, _, _, _] => {
+            let hours = number(created.len() - zone.len() + 1, 2)?;
+            let minutes = number(created.len() - 2, 2)?;
+            let offset = hours * 3600 + minutes * 60;
+            if *sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => return None,
+    };
+    let days = days_from_civil(year, month, day);
+    Some(days * SECONDS_PER_DAY + hour * 3600 + minute * 60 + second - offset)
+}
+
+// "2026-10-18T07:30:00Z" for seconds since 1970
+pub fn format_created(seconds: i64) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(SECONDS_PER_DAY));
+    let time = seconds.rem_euclid(SECONDS_PER_DAY);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+// For a take started without a creation time, the time zone is not known here.
+pub fn created_now() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64);
+    format_created(seconds)
+}
+
+// `seconds` since 1904 as 'mvhd' keeps it
+pub fn format_mp4_time(seconds: u64) -> String {
+    format_created(seconds as i64 - MP4_EPOCH_OFFSET)
+}
+
+// Replaces the metadata of a saved entry. Every segment of a segmented one gets it.
+pub fn write_metadata(entry_path: &Path, metadata: &EntryMetadata) -> Result<(), DomainError> {
+    for path in entry_files(entry_path)? {
+        write_mp4_metadata(&path, metadata)?;
+    }
+    Ok(())
+}
+
+// The metadata of a saved entry, of its first segment for a segmented one.
+pub fn read_metadata(entry_path: &Path) -> Result<EntryMetadata, DomainError> {
+    let path = entry_files(entry_path)?.into_iter().next().ok_or_else(|| {
+        DomainError::Muxing(format!("{} lists no segments", entry_path.display()))
+    })?;
+    read_mp4_metadata(BufReader::new(File::open(path)?))
+}
+
+// The mp4 files behind an entry, only those carry metadata.
+fn entry_files(entry_path: &Path) -> Result<Vec<PathBuf>, DomainError> {
+    let extension = entry_path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let files = match extension.as_str() {
+        "mp4" | "m4a" => vec![entry_path.to_path_buf()],
+        PLAYLIST_EXTENSION => read_playlist(entry_path)?,
+        _ => vec![],
+    };
+    // a playlist of mkv segments, or an mkv
+    let other = match files.is_empty() {
+        true => Some(entry_path),
+        false => files
+            .iter()
+            .map(PathBuf::as_path)
+            .find(|path| !is_mp4(path)),
+    };
+    match other {
+        Some(path) => Err(DomainError::Muxing(format!(
+            "only mp4 and m4a files carry metadata, not {}",
+            path.display()
+        ))),
+        None => Ok(files),
+    }
+}
+
+fn is_mp4(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        extension.eq_ignore_ascii_case("mp4") || extension.eq_ignore_ascii_case("m4a")
+    })
+}
+
+// Days since 1970 of a date in the proleptic Gregorian calendar, after Howard Hinnant's
+// `days_from_civil`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// The other way around, year, month and day of `days` since 1970.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        mp4::{scan_fragments, Mp4Layout, Mp4Writer},
+        test_support::{avc_config, is_keyframe, sample, temp_dir, FRAME},
+    };
+    use std::{fs, io::BufWriter, time::Duration};
+
+    // a second of video at 30 fps in a directory of its own
+    fn take(test: &str, layout: Mp4Layout, metadata: Option<EntryMetadata>) -> PathBuf {
+        let path = temp_dir(&format!("metadata_{}", test)).join("take.mp4");
+        let file = BufWriter::new(File::create(&path).unwrap());
+        let mut writer = Mp4Writer::new(file, 64, 48, layout).unwrap();
+        writer.set_video(avc_config()).unwrap();
+        if let Some(metadata) = metadata {
+            writer.set_metadata(metadata).unwrap();
+        }
+        for n in 0..30 {
+            writer
+                .write_video(&sample(n), is_keyframe(n), FRAME)
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        path
+    }
+
+    fn metadata() -> EntryMetadata {
+        EntryMetadata {
+            title: Some("Morgens am Fluss".to_string()),
+            created: Some("2026-10-18T09:30:00+02:00".to_string()),
+            note: Some("Nebel über dem Wasser.\nDie Enten waren schon wach.".to_string()),
+            tags: vec!["river".to_string(), "early morning".to_string()],
+        }
+    }
+
+    #[test]
+    fn metadata_set_while_writing_reads_back() {
+        for (test, layout) in [
+            ("written_progressive", Mp4Layout::Progressive),
+            (
+                "written_fragmented",
+                Mp4Layout::Fragmented {
+                    fragment: Duration::from_secs(1),
+                },
+            ),
+        ] {
+            let path = take(test, layout, Some(metadata()));
+            assert_eq!(read_metadata(&path).unwrap(), metadata(), "{}", test);
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn metadata_replaced_on_a_saved_entry_reads_back() {
+        for (test, layout) in [
+            ("replaced_progressive", Mp4Layout::Progressive),
+            (
+                "replaced_fragmented",
+                Mp4Layout::Fragmented {
+                    fragment: Duration::from_secs(1),
+                },
+            ),
+        ] {
+            let path = take(test, layout, None);
+            // without any, the creation time in 'mvhd' is 0 and not taken
+            assert_eq!(read_metadata(&path).unwrap(), EntryMetadata::default());
+
+            write_metadata(&path, &metadata()).unwrap();
+            assert_eq!(read_metadata(&path).unwrap(), metadata(), "{}", test);
+
+            // a longer note has to find room too, and a title can go again
+            let longer = EntryMetadata {
+                title: None,
+                note: Some("Nebel. ".repeat(1000)),
+                ..metadata()
+            };
+            write_metadata(&path, &longer).unwrap();
+            assert_eq!(read_metadata(&path).unwrap(), longer, "{}", test);
+            if let Mp4Layout::Fragmented { .. } = layout {
+                let scan = scan_fragments(BufReader::new(File::open(&path).unwrap())).unwrap();
+                assert!(scan.is_complete());
+                assert_eq!(scan.video_samples, 30);
+            }
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn matroska_entries_carry_no_metadata() {
+        let dir = temp_dir("metadata_matroska");
+        let path = dir.join("take.mkv");
+        assert!(matches!(read_metadata(&path), Err(DomainError::Muxing(_))));
+        assert!(matches!(
+            write_metadata(&path, &metadata()),
+            Err(DomainError::Muxing(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,838 @@
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+
+use log::error;
+
+use super::{
+    error::DomainError,
+    mp4::{annexb_frame, parse_avc_decoder_config, AacConfig, VideoConfig, VIDEO_TIMESCALE},
+};
+
+// Element ids, with their length marker as they're written.
+const EBML: u32 = 0x1a45dfa3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42f7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42f2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42f3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x18538067;
+const SEEK_HEAD: u32 = 0x114d9b74;
+const SEEK: u32 = 0x4dbb;
+const SEEK_ID: u32 = 0x53ab;
+const SEEK_POSITION: u32 = 0x53ac;
+const INFO: u32 = 0x1549a966;
+const TIMESTAMP_SCALE: u32 = 0x2ad7b1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4d80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654ae6b;
+const TRACK_ENTRY: u32 = 0xae;
+const TRACK_NUMBER: u32 = 0xd7;
+const TRACK_UID: u32 = 0x73c5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9c;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63a2;
+const CODEC_DELAY: u32 = 0x56aa;
+const VIDEO: u32 = 0xe0;
+const PIXEL_WIDTH: u32 = 0xb0;
+const PIXEL_HEIGHT: u32 = 0xba;
+const AUDIO: u32 = 0xe1;
+const SAMPLING_FREQUENCY: u32 = 0xb5;
+const CHANNELS: u32 = 0x9f;
+const CLUSTER: u32 = 0x1f43b675;
+const TIMESTAMP: u32 = 0xe7;
+const SIMPLE_BLOCK: u32 = 0xa3;
+const CUES: u32 = 0x1c53bb6b;
+const CUE_POINT: u32 = 0xbb;
+const CUE_TIME: u32 = 0xb3;
+const CUE_TRACK_POSITIONS: u32 = 0xb7;
+const CUE_TRACK: u32 = 0xf7;
+const CUE_CLUSTER_POSITION: u32 = 0xf1;
+const VOID: u32 = 0xec;
+
+const APP_NAME: &str = "avatar_vision";
+// Timestamps are in milliseconds.
+const TIMESTAMP_SCALE_NS: u64 = 1_000_000;
+const VIDEO_TRACK: u8 = 1;
+const AUDIO_TRACK: u8 = 2;
+// Room up front for the 'SeekHead' `finish` writes, three entries fit with space to spare.
+const SEEK_HEAD_SPACE: usize = 96;
+// What the 'Duration' of `finish` takes: a 2 byte id, a 1 byte size and a double.
+const DURATION_SPACE: usize = 11;
+// A segment or element size of all ones, still being written.
+const UNKNOWN_SIZE: u64 = 0x00ff_ffff_ffff_ffff;
+// A cluster starts at every keyframe, or once it spans this much, so a file that was cut off
+// loses little and block timestamps stay well within their 16 bits.
+const MAX_CLUSTER_MS: u64 = 5000;
+
+struct Block {
+    track: u8,
+    // in ms from the start
+    time: u64,
+    keyframe: bool,
+    data: Vec<u8>,
+}
+
+// Writes a Matroska file as samples come in, with the same tracks `Mp4Writer` writes: the
+// h264 or AV1 video in the samples of its mp4 sample entry and the aac audio. Blocks are collected into a cluster in memory
+// and the cluster goes to `out` as a whole, flushed, so whatever made it to disk plays should
+// the app go down. The segment's size, the duration, the cues and the seek head are filled in
+// by `finish`; a file that never got there is still valid without them.
+pub struct MkvWriter<W: Write + Seek> {
+    out: W,
+    position: u64,
+    // where the segment's size is and where its data starts, positions count from there
+    segment_size_at: u64,
+    segment_start: u64,
+    seek_head_at: u64,
+    info_at: u64,
+    duration_at: u64,
+    tracks_at: Option<u64>,
+    width: u32,
+    height: u32,
+    video_config: Option<VideoConfig>,
+    audio_config: Option<AacConfig>,
+    // in VIDEO_TIMESCALE units and in samples, ahead of the next block of each track
+    video_time: u64,
+    audio_time: u64,
+    blocks: Vec<Block>,
+    // the keyframe's time and the position of every cluster that starts with one
+    cues: Vec<(u64, u64)>,
+}
+
+impl<W: Write + Seek> MkvWriter<W> {
+    pub fn new(mut out: W, width: u32, height: u32) -> Result<Self, DomainError> {
+        let mut header = vec![];
+        write_element(&mut header, EBML, |b| {
+            put_uint(b, EBML_VERSION, 1);
+            put_uint(b, EBML_READ_VERSION, 1);
+            put_uint(b, EBML_MAX_ID_LENGTH, 4);
+            put_uint(b, EBML_MAX_SIZE_LENGTH, 8);
+            put_string(b, DOC_TYPE, "matroska");
+            // CodecDelay came with version 4, SimpleBlock needs a reader of version 2
+            put_uint(b, DOC_TYPE_VERSION, 4);
+            put_uint(b, DOC_TYPE_READ_VERSION, 2);
+        });
+        put_id(&mut header, SEGMENT);
+        let segment_size_at = header.len() as u64;
+        put_size_8(&mut header, UNKNOWN_SIZE);
+        let segment_start = header.len() as u64;
+
+        let seek_head_at = header.len() as u64;
+        put_void(&mut header, SEEK_HEAD_SPACE);
+        let mut info = vec![];
+        put_uint(&mut info, TIMESTAMP_SCALE, TIMESTAMP_SCALE_NS);
+        put_string(&mut info, MUXING_APP, APP_NAME);
+        put_string(&mut info, WRITING_APP, APP_NAME);
+        let duration_in_info = info.len();
+        put_void(&mut info, DURATION_SPACE);
+        let info_at = header.len() as u64;
+        put_id(&mut header, INFO);
+        put_size(&mut header, info.len() as u64);
+        let duration_at = (header.len() + duration_in_info) as u64;
+        header.extend_from_slice(&info);
+        out.write_all(&header)?;
+
+        Ok(Self {
+            out,
+            position: header.len() as u64,
+            segment_size_at,
+            segment_start,
+            seek_head_at,
+            info_at,
+            duration_at,
+            tracks_at: None,
+            width,
+            height,
+            video_config: None,
+            audio_config: None,
+            video_time: 0,
+            audio_time: 0,
+            blocks: vec![],
+            cues: vec![],
+        })
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.position + self.blocks.iter().map(|b| b.data.len() as u64).sum::<u64>()
+    }
+
+    // Like the audio, the video's codec has to be known before the first cluster.
+    pub fn set_video(&mut self, config: VideoConfig) -> Result<(), DomainError> {
+        if self.tracks_at.is_some() {
+            return Err(DomainError::Muxing(
+                "video set up after the first cluster".to_string(),
+            ));
+        }
+        self.video_config = Some(config);
+        Ok(())
+    }
+
+    // One encoded frame as `Mp4Writer::write_video` takes it. A keyframe starts a new cluster.
+    pub fn write_video(
+        &mut self,
+        sample: &[u8],
+        sync: bool,
+        duration: u32,
+    ) -> Result<(), DomainError> {
+        let time = self.video_time * 1000 / VIDEO_TIMESCALE as u64;
+        if sync && !self.blocks.is_empty() {
+            self.write_cluster()?;
+        }
+        self.push_block(Block {
+            track: VIDEO_TRACK,
+            time,
+            keyframe: sync,
+            data: sample.to_vec(),
+        })?;
+        self.video_time += duration as u64;
+        Ok(())
+    }
+
+    // The tracks go in front of the first cluster, so the audio has to be set up before.
+    pub fn set_audio(&mut self, config: AacConfig) -> Result<(), DomainError> {
+        if self.tracks_at.is_some() {
+            return Err(DomainError::Muxing(
+                "audio set up after the first cluster".to_string(),
+            ));
+        }
+        self.audio_config = Some(config);
+        Ok(())
+    }
+
+    // One AAC access unit, `duration` in samples. The encoder's priming is left in and
+    // the track's CodecDelay tells the player to skip it.
+    pub fn write_audio(&mut self, access_unit: &[u8], duration: u32) -> Result<(), DomainError> {
+        let sample_rate = match &self.audio_config {
+            Some(config) => config.sample_rate.max(1) as u64,
+            None => {
+                return Err(DomainError::Muxing(
+                    "audio written before the track was set up".to_string(),
+                ))
+            }
+        };
+        let time = self.audio_time * 1000 / sample_rate;
+        self.push_block(Block {
+            track: AUDIO_TRACK,
+            time,
+            keyframe: true,
+            data: access_unit.to_vec(),
+        })?;
+        self.audio_time += duration as u64;
+        Ok(())
+    }
+
+    fn push_block(&mut self, block: Block) -> Result<(), DomainError> {
+        let span = self
+            .blocks
+            .first()
+            .map_or(0, |first| block.time.saturating_sub(first.time));
+        if span >= MAX_CLUSTER_MS {
+            self.write_cluster()?;
+        }
+        self.blocks.push(block);
+        Ok(())
+    }
+
+    // Writes the collected blocks in time order as one cluster, the tracks in front of the
+    // first one. Audio encoded behind the video may come out a little before the cluster's
+    // timestamp, a block's timestamp is relative and signed.
+    fn write_cluster(&mut self) -> Result<(), DomainError> {
+        if self.blocks.is_empty() {
+            return Ok(());
+        }
+        if self.tracks_at.is_none() {
+            self.write_tracks()?;
+        }
+        self.blocks.sort_by_key(|block| block.time);
+        let cluster_time = self.blocks[0].time;
+        let cluster_at = self.position;
+        // players seek to the keyframe, the audio in front of it comes along
+        let keyframe = self
+            .blocks
+            .iter()
+            .find(|block| block.track == VIDEO_TRACK)
+            .filter(|block| block.keyframe)
+            .map(|block| block.time);
+        if let Some(time) = keyframe {
+            self.cues.push((time, cluster_at - self.segment_start));
+        }
+
+        let mut cluster = vec![];
+        write_element(&mut cluster, CLUSTER, |b| {
+            put_uint(b, TIMESTAMP, cluster_time);
+            for block in &self.blocks {
+                let relative = (block.time as i64 - cluster_time as i64)
+                    .clamp(i16::MIN as i64, i16::MAX as i64) as i16;
+                put_id(b, SIMPLE_BLOCK);
+                put_size(b, 4 + block.data.len() as u64);
+                b.push(0x80 | block.track);
+                b.extend_from_slice(&relative.to_be_bytes());
+                b.push(if block.keyframe { 0x80 } else { 0 });
+                b.extend_from_slice(&block.data);
+            }
+        });
+        self.out.write_all(&cluster)?;
+        self.out.flush()?;
+        self.position += cluster.len() as u64;
+        self.blocks.clear();
+        Ok(())
+    }
+
+    fn write_tracks(&mut self) -> Result<(), DomainError> {
+        let (codec_id, config) = match &self.video_config {
+            Some(config @ VideoConfig::Avc { .. }) => ("V_MPEG4/ISO/AVC", config.decoder_config()),
+            Some(config @ VideoConfig::Av1 { .. }) => ("V_AV1", config.decoder_config()),
+            None => {
+                return Err(DomainError::Muxing(
+                    "video written before the track was set up".to_string(),
+                ))
+            }
+        };
+        let mut tracks = vec![];
+        write_element(&mut tracks, TRACKS, |b| {
+            write_element(b, TRACK_ENTRY, |b| {
+                put_uint(b, TRACK_NUMBER, VIDEO_TRACK as u64);
+                put_uint(b, TRACK_UID, VIDEO_TRACK as u64);
+                put_uint(b, TRACK_TYPE, 1);
+                put_uint(b, FLAG_LACING, 0);
+                put_string(b, CODEC_ID, codec_id);
+                put_bytes(b, CODEC_PRIVATE, &config);
+                write_element(b, VIDEO, |b| {
+                    put_uint(b, PIXEL_WIDTH, self.width as u64);
+                    put_uint(b, PIXEL_HEIGHT, self.height as u64);
+                });
+            });
+            if let Some(audio) = &self.audio_config {
+                write_element(b, TRACK_ENTRY, |b| {
+                    put_uint(b, TRACK_NUMBER, AUDIO_TRACK as u64);
+                    put_uint(b, TRACK_UID, AUDIO_TRACK as u64);
+                    put_uint(b, TRACK_TYPE, 2);
+                    put_uint(b, FLAG_LACING, 0);
+                    put_string(b, CODEC_ID, "A_AAC");
+                    put_bytes(b, CODEC_PRIVATE, &audio.decoder_config);
+                    put_uint(
+                        b,
+                        CODEC_DELAY,
+                        audio.priming as u64 * 1_000_000_000 / audio.sample_rate.max(1) as u64,
+                    );
+                    write_element(b, AUDIO, |b| {
+                        put_float(b, SAMPLING_FREQUENCY, audio.sample_rate as f64);
+                        put_uint(b, CHANNELS, audio.channels as u64);
+                    });
+                });
+            }
+        });
+        self.out.write_all(&tracks)?;
+        self.tracks_at = Some(self.position);
+        self.position += tracks.len() as u64;
+        Ok(())
+    }
+
+    // Writes the last cluster and the cues, then goes back for the segment's size, the
+    // duration and the seek head, and hands back the output.
+    pub fn finish(mut self) -> Result<W, DomainError> {
+        self.write_cluster()?;
+        let tracks_at = match self.tracks_at {
+            Some(tracks_at) => tracks_at,
+            None => return Err(DomainError::Muxing("no video was written".to_string())),
+        };
+
+        let cues_at = self.position;
+        let mut cues = vec![];
+        write_element(&mut cues, CUES, |b| {
+            for &(time, position) in &self.cues {
+                write_element(b, CUE_POINT, |b| {
+                    put_uint(b, CUE_TIME, time);
+                    write_element(b, CUE_TRACK_POSITIONS, |b| {
+                        put_uint(b, CUE_TRACK, VIDEO_TRACK as u64);
+                        put_uint(b, CUE_CLUSTER_POSITION, position);
+                    });
+                });
+            }
+        });
+        self.out.write_all(&cues)?;
+        self.position += cues.len() as u64;
+
+        let mut segment_size = vec![];
+        put_size_8(&mut segment_size, self.position - self.segment_start);
+        self.out.seek(SeekFrom::Start(self.segment_size_at))?;
+        self.out.write_all(&segment_size)?;
+
+        self.out.seek(SeekFrom::Start(self.duration_at))?;
+        self.out.write_all(&duration_element(self.duration_ms()))?;
+
+        let mut seek_head = vec![];
+        write_element(&mut seek_head, SEEK_HEAD, |b| {
+            for (id, at) in [(INFO, self.info_at), (TRACKS, tracks_at), (CUES, cues_at)] {
+                write_element(b, SEEK, |b| {
+                    let mut seek_id = vec![];
+                    put_id(&mut seek_id, id);
+                    put_bytes(b, SEEK_ID, &seek_id);
+                    put_uint(b, SEEK_POSITION, at - self.segment_start);
+                });
+            }
+        });
+        if seek_head.len() + 2 <= SEEK_HEAD_SPACE {
+            let left = SEEK_HEAD_SPACE - seek_head.len();
+            put_void(&mut seek_head, left);
+            self.out.seek(SeekFrom::Start(self.seek_head_at))?;
+            self.out.write_all(&seek_head)?;
+        } else {
+            error!("the seek head doesn't fit, players will have to look for the cues");
+        }
+
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn duration_ms(&self) -> f64 {
+        let video = self.video_time as f64 * 1000.0 / VIDEO_TIMESCALE as f64;
+        let audio = self.audio_config.as_ref().map_or(0.0, |config| {
+            self.audio_time.saturating_sub(config.priming as u64) as f64 * 1000.0
+                / config.sample_rate.max(1) as f64
+        });
+        video.max(audio)
+    }
+}
+
+// The 'Duration' in ms that goes where `ClusterScan::duration_at` points, `DURATION_SPACE`
+// bytes long.
+pub fn duration_element(duration: f64) -> Vec<u8> {
+    let mut b = vec![];
+    put_float(&mut b, DURATION, duration);
+    b
+}
+
+// What `scan_clusters` found in a Matroska file.
+#[derive(Debug, Default)]
+pub struct ClusterScan {
+    pub clusters: usize,
+    pub video_blocks: usize,
+    pub audio_blocks: usize,
+    // timestamp of the last block of each track in ms
+    pub video_end: u64,
+    pub audio_end: u64,
+    // the file plays up to here, anything behind is an element that was cut off
+    pub playable_len: u64,
+    pub file_len: u64,
+    // the CodecPrivate of the video track
+    pub video_config: Option<Vec<u8>>,
+    // offset and size of the first video frame
+    pub first_video_block: Option<(u64, u32)>,
+    // the space `finish` writes the duration to, None once it did
+    pub duration_at: Option<u64>,
+}
+
+impl ClusterScan {
+    pub fn is_complete(&self) -> bool {
+        self.clusters > 0 && self.playable_len == self.file_len
+    }
+}
+
+struct ElementHeader {
+    id: u32,
+    // None for a size not known yet
+    size: Option<u64>,
+    header_size: u64,
+}
+
+// Reads a Matroska file back element by element at the top of the segment, counting the
+// blocks of every cluster. An element that was cut off ends the scan, so on a file that was
+// never finished `playable_len` is where the last whole cluster ends.
+pub fn scan_clusters<R: Read + Seek>(mut input: R) -> Result<ClusterScan, DomainError> {
+    let mut scan = ClusterScan {
+        file_len: input.seek(SeekFrom::End(0))?,
+        ..ClusterScan::default()
+    };
+    input.seek(SeekFrom::Start(0))?;
+    match read_element_header(&mut input)? {
+        Some(ElementHeader {
+            id: EBML,
+            size: Some(size),
+            ..
+        }) => {
+            input.seek(SeekFrom::Current(size as i64))?;
+        }
+        _ => return Err(DomainError::Muxing("not a Matroska file".to_string())),
+    }
+    let segment = match read_element_header(&mut input)? {
+        Some(header) if header.id == SEGMENT => header,
+        _ => return Err(DomainError::Muxing("the file has no segment".to_string())),
+    };
+    let mut at = input.stream_position()?;
+    let segment_end = segment
+        .size
+        .map_or(scan.file_len, |size| (at + size).min(scan.file_len));
+    scan.playable_len = at;
+
+    while at < segment_end {
+        let header = match read_element_header(&mut input)? {
+            Some(header) => header,
+            None => break,
+        };
+        let size = match header.size {
+            Some(size) => size,
+            None => break,
+        };
+        let body_at = at + header.header_size;
+        let end = body_at + size;
+        if end > segment_end {
+            break;
+        }
+        match header.id {
+            INFO => {
+                let body = read_body(&mut input, size)?;
+                let mut duration = None;
+                let mut void = None;
+                for (id, offset, child) in child_elements(&body)? {
+                    match id {
+                        DURATION => duration = Some(child),
+                        VOID if child.len() + 2 == DURATION_SPACE => {
+                            void = Some(body_at + offset as u64 - 2)
+                        }
+                        _ => {}
+                    }
+                }
+                scan.duration_at = if duration.is_none() { void } else { None };
+            }
+            TRACKS => {
+                let body = read_body(&mut input, size)?;
+                for (id, _, entry) in child_elements(&body)? {
+                    if id != TRACK_ENTRY {
+                        continue;
+                    }
+                    let mut number = 0;
+                    let mut private = None;
+                    for (id, _, field) in child_elements(entry)? {
+                        match id {
+                            TRACK_NUMBER => number = read_uint(field),
+                            CODEC_PRIVATE => private = Some(field.to_vec()),
+                            _ => {}
+                        }
+                    }
+                    if number == VIDEO_TRACK as u64 {
+                        scan.video_config = private;
+                    }
+                }
+            }
+            CLUSTER => {
+                let body = read_body(&mut input, size)?;
+                let mut cluster_time = 0;
+                for (id, offset, child) in child_elements(&body)? {
+                    match id {
+                        TIMESTAMP => cluster_time = read_uint(child),
+                        SIMPLE_BLOCK => {
+                            if child.len() < 4 || child[0] & 0x80 == 0 {
+                                return Err(DomainError::Muxing(format!(
+                                    "cluster at {}: a block without a track",
+                                    at
+                                )));
+                            }
+                            let relative = i16::from_be_bytes([child[1], child[2]]) as i64;
+                            let time = (cluster_time as i64 + relative).max(0) as u64;
+                            match child[0] & 0x7f {
+                                VIDEO_TRACK => {
+                                    if scan.first_video_block.is_none() {
+                                        let data_at = body_at + offset as u64 + 4;
+                                        scan.first_video_block =
+                                            Some((data_at, child.len() as u32 - 4));
+                                    }
+                                    scan.video_blocks += 1;
+                                    scan.video_end = scan.video_end.max(time);
+                                }
+                                _ => {
+                                    scan.audio_blocks += 1;
+                                    scan.audio_end = scan.audio_end.max(time);
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                scan.clusters += 1;
+            }
+            _ => {}
+        }
+        input.seek(SeekFrom::Start(end))?;
+        at = end;
+        scan.playable_len = end;
+    }
+    Ok(scan)
+}
+
+// The first video frame of a Matroska file in Annex B, the parameter sets in front.
+pub fn first_matroska_frame<R: Read + Seek>(
+    mut input: R,
+    scan: &ClusterScan,
+) -> Result<Vec<u8>, DomainError> {
+    let (offset, size) = scan
+        .first_video_block
+        .ok_or_else(|| DomainError::Muxing("the file has no video".to_string()))?;
+    let (sps, pps) = scan
+        .video_config
+        .as_deref()
+        .and_then(parse_avc_decoder_config)
+        .ok_or_else(|| DomainError::Muxing("no h264 parameter sets were saved".to_string()))?;
+    let mut sample = vec![0u8; size as usize];
+    input.seek(SeekFrom::Start(offset))?;
+    input.read_exact(&mut sample)?;
+    annexb_frame(&sps, &pps, &sample)
+}
+
+// The id and size at the reader's position, None at the end of the file, when the header
+// itself was cut off or when there's no header but garbage.
+fn read_element_header<R: Read>(input: &mut R) -> Result<Option<ElementHeader>, DomainError> {
+    let (id, id_len) = match read_vint(input, 4)? {
+        Some((_, raw, len)) => (raw as u32, len),
+        None => return Ok(None),
+    };
+    let (size, size_len) = match read_vint(input, 8)? {
+        Some((value, _, len)) => (value, len),
+        None => return Ok(None),
+    };
+    let all_ones = (1u64 << (7 * size_len)) - 1;
+    Ok(Some(ElementHeader {
+        id,
+        size: (size != all_ones).then_some(size),
+        header_size: (id_len + size_len) as u64,
+    }))
+}
+
+// A variable length integer of at most `max_len` bytes: its value, the bytes as they are with
+// the length marker, what ids are compared by, and its length.
+fn read_vint<R: Read>(
+    input: &mut R,
+    max_len: usize,
+) -> Result<Option<(u64, u64, usize)>, DomainError> {
+    let mut first = [0u8; 1];
+    if let Err(e) = input.read_exact(&mut first) {
+        return end_of_data(e);
+    }
+    let len = first[0].leading_zeros() as usize + 1;
+    if len > max_len {
+        return Ok(None);
+    }
+    let mut rest = [0u8; 7];
+    if let Err(e) = input.read_exact(&mut rest[..len - 1]) {
+        return end_of_data(e);
+    }
+    let raw = rest[..len - 1]
+        .iter()
+        .fold(first[0] as u64, |raw, &byte| (raw << 8) | byte as u64);
+    let value = raw & ((1u64 << (7 * len)) - 1);
+    Ok(Some((value, raw, len)))
+}
+
+fn end_of_data<T>(e: std::io::Error) -> Result<Option<T>, DomainError> {
+    if e.kind() == ErrorKind::UnexpectedEof {
+        Ok(None)
+    } else {
+        Err(e.into())
+    }
+}
+
+fn read_body<R: Read>(input: &mut R, size: u64) -> Result<Vec<u8>, DomainError> {
+    let mut body = vec![0u8; size as usize];
+    input.read_exact(&mut body)?;
+    Ok(body)
+}
+
+// Id, offset of the body in its parent and the body.
+type Child<'a> = (u32, usize, &'a [u8]);
+
+// Each element in `data`, which has to hold them whole.
+fn child_elements(data: &[u8]) -> Result<Vec<Child<'_>>, DomainError> {
+    let mut children = vec![];
+    let mut at = 0;
+    while at < data.len() {
+        let mut reader = &data[at..];
+        let header = read_element_header(&mut reader)?
+            .ok_or_else(|| DomainError::Muxing("element cut short".to_string()))?;
+        let body_at = at + header.header_size as usize;
+        let end = header
+            .size
+            .and_then(|size| body_at.checked_add(size as usize))
+            .filter(|&end| end <= data.len())
+            .ok_or_else(|| DomainError::Muxing("element cut short".to_string()))?;
+        children.push((header.id, body_at, &data[body_at..end]));
+        at = end;
+    }
+    Ok(children)
+}
+
+fn read_uint(data: &[u8]) -> u64 {
+    data.iter()
+        .fold(0, |value, &byte| (value << 8) | byte as u64)
+}
+
+fn put_id(b: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = (id.leading_zeros() / 8).min(3) as usize;
+    b.extend_from_slice(&bytes[skip..]);
+}
+
+// The shortest size that holds `size`, all ones is reserved for an unknown size.
+fn put_size(b: &mut Vec<u8>, size: u64) {
+    let len = (1..=8)
+        .find(|&len| size < (1u64 << (7 * len)) - 1)
+        .unwrap_or(8);
+    let marked = size | (1u64 << (7 * len));
+    b.extend_from_slice(&marked.to_be_bytes()[8 - len..]);
+}
+
+// Always 8 bytes, for a size patched later.
+fn put_size_8(b: &mut Vec<u8>, size: u64) {
+    b.extend_from_slice(&(size | (1u64 << 56)).to_be_bytes());
+}
+
+fn write_element(b: &mut Vec<u8>, id: u32, body: impl FnOnce(&mut Vec<u8>)) {
+    let mut data = vec![];
+    body(&mut data);
+    put_id(b, id);
+    put_size(b, data.len() as u64);
+    b.extend_from_slice(&data);
+}
+
+fn put_uint(b: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = ((value.leading_zeros() / 8) as usize).min(7);
+    put_bytes(b, id, &bytes[skip..]);
+}
+
+fn put_float(b: &mut Vec<u8>, id: u32, value: f64) {
+    put_bytes(b, id, &value.to_be_bytes());
+}
+
+fn put_string(b: &mut Vec<u8>, id: u32, value: &str) {
+    put_bytes(b, id, value.as_bytes());
+}
+
+fn put_bytes(b: &mut Vec<u8>, id: u32, value: &[u8]) {
+    put_id(b, id);
+    put_size(b, value.len() as u64);
+    b.extend_from_slice(value);
+}
+
+// A 'Void' element taking up `len` bytes, at least 2.
+fn put_void(b: &mut Vec<u8>, len: usize) {
+    put_id(b, VOID);
+    if len - 2 < 127 {
+        put_size(b, len as u64 - 2);
+        b.resize(b.len() + len - 2, 0);
+    } else {
+        put_size_8(b, len as u64 - 9);
+        b.resize(b.len() + len - 9, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        mp4::avc_decoder_config,
+        test_support::{aac_config, avc_config, is_keyframe, sample, AUDIO_FRAME, FRAME, PPS, SPS},
+    };
+    use std::io::Cursor;
+
+    // `frames` frames at 30 fps with a keyframe every second, a frame's worth of audio with each
+    fn matroska(frames: usize) -> Vec<u8> {
+        let mut writer = MkvWriter::new(Cursor::new(vec![]), 64, 48).unwrap();
+        writer.set_video(avc_config()).unwrap();
+        writer.set_audio(aac_config()).unwrap();
+        for n in 0..frames {
+            writer
+                .write_video(&sample(n), is_keyframe(n), FRAME)
+                .unwrap();
+            writer.write_audio(&[0x21; 12], AUDIO_FRAME).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    // The Timestamp of every cluster in the segment.
+    fn cluster_times(file: &[u8]) -> Vec<u64> {
+        let mut input = Cursor::new(file);
+        let ebml = read_element_header(&mut input).unwrap().unwrap();
+        input
+            .seek(SeekFrom::Current(ebml.size.unwrap() as i64))
+            .unwrap();
+        read_element_header(&mut input).unwrap().unwrap();
+        let mut times = vec![];
+        while let Some(header) = read_element_header(&mut input).unwrap() {
+            let body = read_body(&mut input, header.size.unwrap()).unwrap();
+            if header.id == CLUSTER {
+                let children = child_elements(&body).unwrap();
+                let (_, _, time) = children.iter().find(|(id, ..)| *id == TIMESTAMP).unwrap();
+                times.push(read_uint(time));
+            }
+        }
+        times
+    }
+
+    #[test]
+    fn ebml_header_names_a_matroska_file() {
+        let file = matroska(30);
+        let mut input = Cursor::new(&file);
+        let ebml = read_element_header(&mut input).unwrap().unwrap();
+        assert_eq!(ebml.id, EBML);
+        let body = read_body(&mut input, ebml.size.unwrap()).unwrap();
+        let children = child_elements(&body).unwrap();
+        let field = |id| children.iter().find(|child| child.0 == id).unwrap().2;
+        assert_eq!(field(DOC_TYPE), b"matroska");
+        assert_eq!(read_uint(field(DOC_TYPE_VERSION)), 4);
+        assert_eq!(read_uint(field(DOC_TYPE_READ_VERSION)), 2);
+        assert_eq!(
+            read_element_header(&mut input).unwrap().unwrap().id,
+            SEGMENT
+        );
+    }
+
+    #[test]
+    fn matroska_file_scans_back() {
+        let file = matroska(90);
+        let scan = scan_clusters(Cursor::new(&file)).unwrap();
+        assert!(scan.is_complete());
+        assert_eq!(scan.video_config, Some(avc_decoder_config(&SPS, &PPS)));
+        // a cluster for every keyframe
+        assert_eq!(scan.clusters, 3);
+        assert_eq!(cluster_times(&file), [0, 1000, 2000]);
+        assert_eq!(scan.video_blocks, 90);
+        assert_eq!(scan.audio_blocks, 90);
+        assert_eq!(scan.video_end, 89 * 1000 / 30);
+        assert_eq!(scan.audio_end, 89 * AUDIO_FRAME as u64 * 1000 / 48000);
+        // `finish` wrote the duration, there's no room left for one
+        assert_eq!(scan.duration_at, None);
+    }
+
+    #[test]
+    fn unfinished_file_plays_up_to_its_last_whole_cluster() {
+        let mut out = Cursor::new(vec![]);
+        let mut writer = MkvWriter::new(&mut out, 64, 48).unwrap();
+        writer.set_video(avc_config()).unwrap();
+        for n in 0..90 {
+            writer
+                .write_video(&sample(n), is_keyframe(n), FRAME)
+                .unwrap();
+        }
+        // the app went down, the last cluster never left memory
+        drop(writer);
+        let file = out.into_inner();
+
+        let scan = scan_clusters(Cursor::new(&file)).unwrap();
+        assert_eq!(scan.clusters, 2);
+        assert_eq!(scan.video_blocks, 60);
+        assert_eq!(cluster_times(&file), [0, 1000]);
+        assert_eq!(scan.playable_len, scan.file_len);
+        // the duration is still to be written
+        assert!(scan.duration_at.is_some());
+
+        // and the second cluster was cut off on its way to disk
+        let cut = scan_clusters(Cursor::new(&file[..file.len() - 3])).unwrap();
+        assert!(!cut.is_complete());
+        assert_eq!(cut.clusters, 1);
+        assert_eq!(cut.video_blocks, 30);
+    }
+}
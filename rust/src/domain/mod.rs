@@ -1,7 +1,27 @@
-
+pub mod aac;
 pub mod audio;
+pub mod audio_source;
+pub mod av_sync;
 pub mod camera;
+pub mod channel;
+pub mod encoder_profile;
+pub mod error;
+pub mod frame_source;
+pub mod limits;
+pub mod metadata;
+pub mod mkv;
+pub mod mp4;
+pub mod pipeline;
+pub mod progress;
 pub mod recording;
+pub mod recovery;
 pub mod resolution;
+pub mod scratch;
+pub mod segments;
+pub mod session;
+#[cfg(test)]
+mod test_support;
 pub mod textrue;
-pub mod channel;
\ No newline at end of file
+pub mod video_encoder;
+pub mod visual_track;
+pub mod wav;
@@ -0,0 +1,1998 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::Duration,
+};
+
+use log::{debug, error};
+
+use super::{
+    error::DomainError,
+    metadata::{format_mp4_time, EntryMetadata},
+};
+
+// Timescale of the video track, fine enough for any frame rate we record at.
+pub const VIDEO_TIMESCALE: u32 = 90000;
+const MOVIE_TIMESCALE: u32 = 1000;
+// Room in the 'free' box in front of 'mdat' for the parameter sets, see `save_parameter_sets`.
+const PARAMETER_SET_SPACE: usize = 256;
+// Anything longer is garbage at the end of an unfinished file, not a nal unit.
+const MAX_NAL_SIZE: u32 = 16 << 20;
+// Frames the audio in a progressive 'mdat', no nal unit has the forbidden bit set.
+const AUDIO_MARKER: u8 = 0x80;
+const VIDEO_TRACK_ID: u32 = 1;
+const AUDIO_TRACK_ID: u32 = 2;
+// Left behind the 'moov' of a fragmented file, `write_mp4_metadata` grows into it instead of
+// moving every fragment.
+const METADATA_SPACE: usize = 4096;
+// the iTunes items `write_udta` writes, tags go into a freeform item of their own
+const TITLE_ITEM: [u8; 4] = *b"\xa9nam";
+const CREATED_ITEM: [u8; 4] = *b"\xa9day";
+const NOTE_ITEM: [u8; 4] = *b"\xa9cmt";
+const FREEFORM_MEAN: &str = "com.apple.iTunes";
+const TAGS_NAME: &str = "keywords";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mp4Layout {
+    // one 'moov' behind all of the media, written by `finish`
+    Progressive,
+    // 'moov' up front and a 'moof' with its own 'mdat' every `fragment` of video, so a file
+    // that was cut off still plays up to its last whole fragment
+    Fragmented { fragment: Duration },
+}
+
+impl Mp4Layout {
+    pub const DEFAULT_FRAGMENT: Duration = Duration::from_secs(2);
+
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            Mp4Layout::Progressive => "progressive",
+            Mp4Layout::Fragmented { .. } => "fragmented",
+        }
+    }
+
+    pub fn parse(name: &str, fragment: Duration) -> Option<Self> {
+        match name {
+            "progressive" => Some(Mp4Layout::Progressive),
+            "fragmented" => Some(Mp4Layout::Fragmented { fragment }),
+            _ => None,
+        }
+    }
+}
+
+struct Sample {
+    offset: u64,
+    size: u32,
+    duration: u32,
+    sync: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct AacConfig {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bit_rate: u32,
+    // AudioSpecificConfig as the encoder reports it
+    pub decoder_config: Vec<u8>,
+    // samples the encoder adds in front, cut off with an edit list
+    pub priming: u32,
+}
+
+// What a player needs to set up the video track's decoder, the encoder has it once its first
+// frame is out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VideoConfig {
+    // samples in 'avc1' are length-prefixed nal units, `sps` is at least 4 bytes
+    Avc { sps: Vec<u8>, pps: Vec<u8> },
+    // samples in 'av01' are the OBUs of a temporal unit without its delimiter, `config` is
+    // the AV1CodecConfigurationRecord with the sequence header in it
+    Av1 { config: Vec<u8> },
+}
+
+impl VideoConfig {
+    // The body of 'avcC' or 'av1C' and the CodecPrivate of a Matroska track.
+    pub fn decoder_config(&self) -> Vec<u8> {
+        match self {
+            VideoConfig::Avc { sps, pps } => avc_decoder_config(sps, pps),
+            VideoConfig::Av1 { config } => config.clone(),
+        }
+    }
+}
+
+// The fragment being collected in the fragmented layout. Its samples are the tail of the
+// writer's sample lists, their media waits here until the fragment is written.
+struct Fragment {
+    length: u64,
+    sequence: u32,
+    // where the duration in 'mehd' goes, 0 until 'moov' is written
+    mehd_at: u64,
+    first_video: usize,
+    first_audio: usize,
+    video_data: Vec<u8>,
+    audio_data: Vec<u8>,
+}
+
+// Writes an mp4 as samples come in. In the progressive layout media goes straight to `out`,
+// only the sample tables are kept in memory, and the 'moov' box is written behind 'mdat' by
+// `finish`. In the fragmented layout a fragment's worth of media is held back at a time.
+// Without video it writes an m4a, always progressive, see `audio_only`.
+pub struct Mp4Writer<W: Write + Seek> {
+    out: W,
+    has_video: bool,
+    position: u64,
+    mdat_start: u64,
+    parameter_sets_at: u64,
+    width: u32,
+    height: u32,
+    video_config: Option<VideoConfig>,
+    video: Vec<Sample>,
+    audio_config: Option<AacConfig>,
+    audio: Vec<Sample>,
+    fragment: Option<Fragment>,
+    metadata: Option<EntryMetadata>,
+}
+
+impl<W: Write + Seek> Mp4Writer<W> {
+    pub fn new(out: W, width: u32, height: u32, layout: Mp4Layout) -> Result<Self, DomainError> {
+        Self::create(out, Some((width, height)), layout)
+    }
+
+    // A single aac track, what players expect of an .m4a. A crashed voice memo is encoded
+    // again from its pcm file, so there's no fragmented layout.
+    pub fn audio_only(out: W) -> Result<Self, DomainError> {
+        Self::create(out, None, Mp4Layout::Progressive)
+    }
+
+    fn create(
+        mut out: W,
+        video: Option<(u32, u32)>,
+        layout: Mp4Layout,
+    ) -> Result<Self, DomainError> {
+        let mut header = vec![];
+        write_box(&mut header, b"ftyp", |b| {
+            match (video, layout) {
+                (None, _) => b.extend_from_slice(b"M4A "),
+                (Some(_), Mp4Layout::Progressive) => b.extend_from_slice(b"isom"),
+                (Some(_), Mp4Layout::Fragmented { .. }) => b.extend_from_slice(b"iso6"),
+            }
+            put_u32(b, 0x200);
+            let brands: &[&[u8; 4]] = match video {
+                Some(_) => &[b"isom", b"iso2", b"iso6", b"avc1", b"mp41"],
+                None => &[b"M4A ", b"isom", b"iso2", b"mp41"],
+            };
+            for brand in brands {
+                b.extend_from_slice(*brand);
+            }
+        });
+        let (width, height) = video.unwrap_or((0, 0));
+        let parameter_sets_at = header.len() as u64 + 8;
+        write_box(&mut header, b"free", |b| {
+            b.resize(b.len() + PARAMETER_SET_SPACE, 0)
+        });
+        let mdat_start = header.len() as u64;
+        let fragment = match layout {
+            Mp4Layout::Progressive => {
+                // size 1 means a 64 bit size follows, patched once everything is written
+                put_u32(&mut header, 1);
+                header.extend_from_slice(b"mdat");
+                put_u64(&mut header, 0);
+                None
+            }
+            Mp4Layout::Fragmented { fragment } => Some(Fragment {
+                length: fragment.as_millis() as u64 * VIDEO_TIMESCALE as u64 / 1000,
+                sequence: 1,
+                mehd_at: 0,
+                first_video: 0,
+                first_audio: 0,
+                video_data: vec![],
+                audio_data: vec![],
+            }),
+        };
+        out.write_all(&header)?;
+
+        Ok(Self {
+            out,
+            has_video: video.is_some(),
+            position: header.len() as u64,
+            mdat_start,
+            parameter_sets_at,
+            width,
+            height,
+            video_config: None,
+            video: vec![],
+            audio_config: None,
+            audio: vec![],
+            fragment,
+            metadata: None,
+        })
+    }
+
+    pub fn layout(&self) -> Mp4Layout {
+        match &self.fragment {
+            None => Mp4Layout::Progressive,
+            Some(fragment) => Mp4Layout::Fragmented {
+                fragment: Duration::from_millis(fragment.length * 1000 / VIDEO_TIMESCALE as u64),
+            },
+        }
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.position
+            + self
+                .fragment
+                .as_ref()
+                .map_or(0, |f| (f.video_data.len() + f.audio_data.len()) as u64)
+    }
+
+    // The sample entry of the video track, 'avc1' or 'av01', set before the first frame.
+    pub fn set_video(&mut self, config: VideoConfig) -> Result<(), DomainError> {
+        if !self.has_video {
+            return Err(DomainError::Muxing(
+                "video written to an audio only file".to_string(),
+            ));
+        }
+        if self.video_config.is_some() {
+            return Err(DomainError::Muxing("video set up twice".to_string()));
+        }
+        self.video_config = Some(config);
+        self.save_parameter_sets()
+    }
+
+    // One encoded frame in the format of the track's sample entry, `sync` for a keyframe.
+    // `duration` is in VIDEO_TIMESCALE units.
+    pub fn write_video(
+        &mut self,
+        sample: &[u8],
+        sync: bool,
+        duration: u32,
+    ) -> Result<(), DomainError> {
+        if self.video_config.is_none() {
+            return Err(DomainError::Muxing(
+                "video written before the track was set up".to_string(),
+            ));
+        }
+        let full = self.fragment.as_ref().is_some_and(|fragment| {
+            media_duration(&self.video[fragment.first_video..]) >= fragment.length
+        });
+        if full {
+            self.write_fragment()?;
+        }
+        let offset = match &mut self.fragment {
+            None => {
+                self.out.write_all(sample)?;
+                self.position += sample.len() as u64;
+                self.position - sample.len() as u64
+            }
+            Some(fragment) => {
+                fragment.video_data.extend_from_slice(sample);
+                0
+            }
+        };
+        self.video.push(Sample {
+            offset,
+            size: sample.len() as u32,
+            duration,
+            sync,
+        });
+        Ok(())
+    }
+
+    // Fragmented files describe their tracks up front, so the audio has to be set up before
+    // the first fragment is written.
+    pub fn set_audio(&mut self, config: AacConfig) -> Result<(), DomainError> {
+        if self.fragment.as_ref().is_some_and(|f| f.mehd_at != 0) {
+            return Err(DomainError::Muxing(
+                "audio set up after the first fragment".to_string(),
+            ));
+        }
+        self.audio_config = Some(config);
+        Ok(())
+    }
+
+    // Goes into 'udta', so like the audio it has to be there before the first fragment.
+    pub fn set_metadata(&mut self, metadata: EntryMetadata) -> Result<(), DomainError> {
+        if self.fragment.as_ref().is_some_and(|f| f.mehd_at != 0) {
+            return Err(DomainError::Muxing(
+                "metadata set after the first fragment".to_string(),
+            ));
+        }
+        self.metadata = Some(metadata);
+        Ok(())
+    }
+
+    pub fn metadata(&self) -> Option<&EntryMetadata> {
+        self.metadata.as_ref()
+    }
+
+    // One AAC access unit, `duration` in samples.
+    pub fn write_audio(&mut self, access_unit: &[u8], duration: u32) -> Result<(), DomainError> {
+        if self.audio_config.is_none() {
+            return Err(DomainError::Muxing(
+                "audio written before the track was set up".to_string(),
+            ));
+        }
+        let offset = match &mut self.fragment {
+            // framed like a nal unit `Mp4Salvage` skips, players only go by the sample offset
+            None => {
+                self.out
+                    .write_all(&(access_unit.len() as u32 + 1).to_be_bytes())?;
+                self.out.write_all(&[AUDIO_MARKER])?;
+                self.out.write_all(access_unit)?;
+                self.position += 5 + access_unit.len() as u64;
+                self.position - access_unit.len() as u64
+            }
+            Some(fragment) => {
+                fragment.audio_data.extend_from_slice(access_unit);
+                0
+            }
+        };
+        self.audio.push(Sample {
+            offset,
+            size: access_unit.len() as u32,
+            duration,
+            sync: true,
+        });
+        Ok(())
+    }
+
+    // The parameter sets only make it into 'moov' at the very end, so a copy of the decoder
+    // config box goes into the 'free' box up front. `Mp4Salvage` reads it back when the app
+    // died before `finish`, players skip it.
+    fn save_parameter_sets(&mut self) -> Result<(), DomainError> {
+        let mut config_box = vec![];
+        if let Some(config) = &self.video_config {
+            write_decoder_config(&mut config_box, config);
+        }
+        if config_box.len() > PARAMETER_SET_SPACE {
+            error!("parameter sets don't fit, the take can't be recovered");
+            return Ok(());
+        }
+        self.out.seek(SeekFrom::Start(self.parameter_sets_at))?;
+        self.out.write_all(&config_box)?;
+        self.out.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+
+    fn video_config(&self) -> Result<&VideoConfig, DomainError> {
+        self.video_config
+            .as_ref()
+            .ok_or_else(|| DomainError::Muxing("no video was written".to_string()))
+    }
+
+    // Writes what was collected since the last fragment as a 'moof' and its 'mdat', 'moov'
+    // goes in front of the first one. Flushed, so it's on disk should the app go down.
+    fn write_fragment(&mut self) -> Result<(), DomainError> {
+        if self.fragment.as_ref().is_some_and(|f| f.mehd_at == 0) {
+            self.write_fragmented_moov()?;
+        }
+        let fragment = match &mut self.fragment {
+            Some(fragment) => fragment,
+            None => return Ok(()),
+        };
+        let video = &self.video[fragment.first_video..];
+        let audio = &self.audio[fragment.first_audio..];
+        if video.is_empty() && audio.is_empty() {
+            return Ok(());
+        }
+
+        let mut video_offset_at = None;
+        let mut audio_offset_at = None;
+        let mut moof = vec![];
+        write_box(&mut moof, b"moof", |b| {
+            write_full_box(b, b"mfhd", 0, 0, |b| put_u32(b, fragment.sequence));
+            if !video.is_empty() {
+                let decode_time = media_duration(&self.video[..fragment.first_video]);
+                video_offset_at = Some(write_traf(b, VIDEO_TRACK_ID, decode_time, video));
+            }
+            if !audio.is_empty() {
+                let decode_time = media_duration(&self.audio[..fragment.first_audio]);
+                audio_offset_at = Some(write_traf(b, AUDIO_TRACK_ID, decode_time, audio));
+            }
+        });
+        // sample data counts from the start of 'moof', behind it and the 'mdat' header
+        let data_offset = moof.len() as u32 + 8;
+        if let Some(at) = video_offset_at {
+            moof[at..at + 4].copy_from_slice(&data_offset.to_be_bytes());
+        }
+        if let Some(at) = audio_offset_at {
+            let offset = data_offset + fragment.video_data.len() as u32;
+            moof[at..at + 4].copy_from_slice(&offset.to_be_bytes());
+        }
+
+        let media = fragment.video_data.len() + fragment.audio_data.len();
+        let mut mdat_header = vec![];
+        put_u32(&mut mdat_header, 8 + media as u32);
+        mdat_header.extend_from_slice(b"mdat");
+        self.out.write_all(&moof)?;
+        self.out.write_all(&mdat_header)?;
+        self.out.write_all(&fragment.video_data)?;
+        self.out.write_all(&fragment.audio_data)?;
+        self.out.flush()?;
+        self.position += (moof.len() + mdat_header.len() + media) as u64;
+
+        fragment.sequence += 1;
+        fragment.first_video = self.video.len();
+        fragment.first_audio = self.audio.len();
+        fragment.video_data.clear();
+        fragment.audio_data.clear();
+        Ok(())
+    }
+
+    // Tracks without samples, those come in fragments. 'mehd' gets the duration at the end.
+    fn write_fragmented_moov(&mut self) -> Result<(), DomainError> {
+        let config = self.video_config()?;
+        let mut mehd_at = 0;
+        let mut moov = vec![];
+        write_box(&mut moov, b"moov", |b| {
+            let next_track_id = if self.audio_config.is_some() { 3 } else { 2 };
+            write_mvhd(b, 0, next_track_id, self.created());
+            write_video_trak(b, self.width, self.height, config, &[]);
+            if let Some(config) = &self.audio_config {
+                write_audio_trak(b, config, &[]);
+            }
+            write_box(b, b"mvex", |b| {
+                write_full_box(b, b"mehd", 1, 0, |b| {
+                    mehd_at = b.len();
+                    put_u64(b, 0);
+                });
+                write_trex(b, VIDEO_TRACK_ID);
+                if self.audio_config.is_some() {
+                    write_trex(b, AUDIO_TRACK_ID);
+                }
+            });
+            if let Some(metadata) = &self.metadata {
+                write_udta(b, metadata, &[]);
+            }
+        });
+        write_free(&mut moov, METADATA_SPACE);
+        self.out.write_all(&moov)?;
+        if let Some(fragment) = &mut self.fragment {
+            fragment.mehd_at = self.position + mehd_at as u64;
+        }
+        self.position += moov.len() as u64;
+        Ok(())
+    }
+
+    // for 'mvhd', 0 when unknown
+    fn created(&self) -> u64 {
+        self.metadata
+            .as_ref()
+            .and_then(EntryMetadata::mp4_time)
+            .unwrap_or(0)
+    }
+
+    fn movie_duration(&self) -> u64 {
+        let video_duration = movie_duration(&self.video, VIDEO_TIMESCALE);
+        let audio_duration = self
+            .audio_config
+            .as_ref()
+            .map_or(0, |config| movie_duration(&self.audio, config.sample_rate));
+        video_duration.max(audio_duration)
+    }
+
+    // Patches the size of 'mdat', writes 'moov' and hands back the output. A fragmented file
+    // gets its last fragment and the duration in 'mehd' instead.
+    pub fn finish(mut self) -> Result<W, DomainError> {
+        if self.fragment.is_some() {
+            self.write_fragment()?;
+            let duration = self.movie_duration();
+            let mehd_at = self.fragment.as_ref().map_or(0, |f| f.mehd_at);
+            self.out.seek(SeekFrom::Start(mehd_at))?;
+            self.out.write_all(&duration.to_be_bytes())?;
+            self.out.seek(SeekFrom::End(0))?;
+            self.out.flush()?;
+            return Ok(self.out);
+        }
+
+        // an m4a keeps the audio's track id, there's just no video in front of it
+        let video_config = match self.has_video {
+            true => Some(self.video_config()?),
+            false => None,
+        };
+        let mut moov = vec![];
+        write_box(&mut moov, b"moov", |b| {
+            let next_track_id = if self.audio_config.is_some() { 3 } else { 2 };
+            write_mvhd(b, self.movie_duration(), next_track_id, self.created());
+            if let Some(config) = video_config {
+                write_video_trak(b, self.width, self.height, config, &self.video);
+            }
+            if let Some(config) = &self.audio_config {
+                write_audio_trak(b, config, &self.audio);
+            }
+            if let Some(metadata) = &self.metadata {
+                write_udta(b, metadata, &[]);
+            }
+        });
+        self.out.write_all(&moov)?;
+
+        let mdat_size = self.position - self.mdat_start;
+        self.out.seek(SeekFrom::Start(self.mdat_start + 8))?;
+        self.out.write_all(&mdat_size.to_be_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+// Reads the h264 video back out of a progressive file `Mp4Writer` never got to finish. Frames
+// come out in Annex B like openh264 hands them out, up to the first one cut off, the audio
+// between them is skipped. AV1 takes can't be read back, a temporal unit has no length of its
+// own to find it by.
+pub struct Mp4Salvage<R: Read> {
+    input: R,
+    pub sps: Vec<u8>,
+    pub pps: Vec<u8>,
+    // read ahead, it starts the next frame
+    next_nal: Option<Vec<u8>>,
+}
+
+impl<R: Read + Seek> Mp4Salvage<R> {
+    pub fn open(mut input: R) -> Result<Self, DomainError> {
+        let mut parameter_sets = None;
+        loop {
+            let header = read_box_header(&mut input)?
+                .ok_or_else(|| DomainError::Muxing("the file has no media data".to_string()))?;
+            match (&header.kind, header.body_size()) {
+                // an unfinished 'mdat' has no size yet, it runs to the end of the file
+                (b"mdat", _) => break,
+                (b"free", Some(size)) => {
+                    let mut body = vec![0u8; size as usize];
+                    input.read_exact(&mut body)?;
+                    parameter_sets = parameter_sets.or_else(|| parse_avcc(&body));
+                }
+                (_, Some(size)) => {
+                    input.seek(SeekFrom::Current(size as i64))?;
+                }
+                (_, None) => {
+                    return Err(DomainError::Muxing(
+                        "the file has no media data".to_string(),
+                    ))
+                }
+            }
+        }
+        let (sps, pps) = parameter_sets
+            .ok_or_else(|| DomainError::Muxing("no h264 parameter sets were saved".to_string()))?;
+        Ok(Self {
+            input,
+            sps,
+            pps,
+            next_nal: None,
+        })
+    }
+
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, DomainError> {
+        let mut frame = vec![];
+        let mut has_slice = false;
+        loop {
+            let nal = match self.next_nal.take() {
+                Some(nal) => nal,
+                None => match self.read_nal()? {
+                    Some(nal) => nal,
+                    None => break,
+                },
+            };
+            // the audio in between
+            if nal[0] & AUDIO_MARKER != 0 {
+                continue;
+            }
+            let slice = matches!(nal[0] & 0x1f, 1 | 5);
+            // a slice with first_mb_in_slice 0 starts a picture, so does anything behind one
+            let starts_frame = if slice {
+                nal.len() > 1 && nal[1] & 0x80 != 0
+            } else {
+                true
+            };
+            if has_slice && starts_frame {
+                self.next_nal = Some(nal);
+                break;
+            }
+            has_slice |= slice;
+            frame.extend_from_slice(&[0, 0, 0, 1]);
+            frame.extend_from_slice(&nal);
+        }
+        // a frame without any slice got cut off before its picture
+        Ok(if has_slice { Some(frame) } else { None })
+    }
+
+    fn read_nal(&mut self) -> Result<Option<Vec<u8>>, DomainError> {
+        let mut size = [0u8; 4];
+        if let Err(e) = self.input.read_exact(&mut size) {
+            return end_of_data(e);
+        }
+        let size = u32::from_be_bytes(size);
+        if size == 0 || size > MAX_NAL_SIZE {
+            return Ok(None);
+        }
+        let mut nal = vec![0u8; size as usize];
+        if let Err(e) = self.input.read_exact(&mut nal) {
+            return end_of_data(e);
+        }
+        Ok(Some(nal))
+    }
+}
+
+// The file ends wherever the app stopped writing, a short read is the end of the data.
+fn end_of_data<T>(e: std::io::Error) -> Result<Option<T>, DomainError> {
+    if e.kind() == ErrorKind::UnexpectedEof {
+        Ok(None)
+    } else {
+        Err(e.into())
+    }
+}
+
+// The 'avcC' box `save_parameter_sets` leaves in the 'free' box, None while it's still zeroed.
+fn parse_avcc(body: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    if body.get(4..8)? != b"avcC" {
+        return None;
+    }
+    parse_avc_decoder_config(&body[8..])
+}
+
+// The sps and pps out of what `avc_decoder_config` writes, None for anything else.
+pub fn parse_avc_decoder_config(config: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    if config.first() != Some(&1) {
+        return None;
+    }
+    let sps_size = u16::from_be_bytes([*config.get(6)?, *config.get(7)?]) as usize;
+    let sps = config.get(8..8 + sps_size)?;
+    let pps_at = 8 + sps_size + 1;
+    let pps_size = u16::from_be_bytes([*config.get(pps_at)?, *config.get(pps_at + 1)?]) as usize;
+    let pps = config.get(pps_at + 2..pps_at + 2 + pps_size)?;
+    Some((sps.to_vec(), pps.to_vec()))
+}
+
+struct BoxHeader {
+    kind: [u8; 4],
+    // None runs to the end of the file
+    size: Option<u64>,
+    header_size: u64,
+}
+
+impl BoxHeader {
+    fn body_size(&self) -> Option<u64> {
+        self.size.map(|size| size.saturating_sub(self.header_size))
+    }
+}
+
+// The header of the box at the reader's position, None at the end of the file or when the
+// header itself was cut off. A size of 0 runs to the end of the file, and so does the 'mdat'
+// of a progressive file whose 64 bit size `finish` never got to patch.
+fn read_box_header<R: Read>(input: &mut R) -> Result<Option<BoxHeader>, DomainError> {
+    let mut header = [0u8; 8];
+    if let Err(e) = input.read_exact(&mut header) {
+        return end_of_data(e);
+    }
+    let mut kind = [0u8; 4];
+    kind.copy_from_slice(&header[4..]);
+    let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+    if size == 1 {
+        let mut large_size = [0u8; 8];
+        if let Err(e) = input.read_exact(&mut large_size) {
+            return end_of_data(e);
+        }
+        let size = u64::from_be_bytes(large_size);
+        return Ok(Some(BoxHeader {
+            kind,
+            size: (size != 0).then_some(size),
+            header_size: 16,
+        }));
+    }
+    Ok(Some(BoxHeader {
+        kind,
+        size: (size != 0).then_some(size),
+        header_size: 8,
+    }))
+}
+
+// A box at the top level of a file.
+struct TopBox {
+    kind: [u8; 4],
+    start: u64,
+    size: u64,
+    header_size: u64,
+}
+
+// The boxes of a finished file one after the other, one that was cut off is an error.
+fn top_level_boxes<R: Read + Seek>(input: &mut R) -> Result<Vec<TopBox>, DomainError> {
+    let file_len = input.seek(SeekFrom::End(0))?;
+    input.seek(SeekFrom::Start(0))?;
+    let mut boxes = vec![];
+    let mut at = 0;
+    while let Some(header) = read_box_header(input)? {
+        let size = header.size.unwrap_or(file_len - at);
+        if size < header.header_size || at + size > file_len {
+            return Err(DomainError::Muxing(format!(
+                "the file is cut off at {} of {} bytes",
+                at, file_len
+            )));
+        }
+        boxes.push(TopBox {
+            kind: header.kind,
+            start: at,
+            size,
+            header_size: header.header_size,
+        });
+        at += size;
+        input.seek(SeekFrom::Start(at))?;
+    }
+    Ok(boxes)
+}
+
+fn read_moov<R: Read + Seek>(input: &mut R, boxes: &[TopBox]) -> Result<Vec<u8>, DomainError> {
+    let moov = boxes
+        .iter()
+        .find(|b| &b.kind == b"moov")
+        .ok_or_else(|| DomainError::Muxing("the file has no 'moov'".to_string()))?;
+    let mut body = vec![0u8; (moov.size - moov.header_size) as usize];
+    input.seek(SeekFrom::Start(moov.start + moov.header_size))?;
+    input.read_exact(&mut body)?;
+    Ok(body)
+}
+
+// What `write_udta` put into a file. Without a creation time of its own, the one in 'mvhd'
+// is taken, in UTC.
+pub fn read_mp4_metadata<R: Read + Seek>(mut input: R) -> Result<EntryMetadata, DomainError> {
+    let boxes = top_level_boxes(&mut input)?;
+    let moov = read_moov(&mut input, &boxes)?;
+    let mut metadata = EntryMetadata::default();
+    let mut created = 0;
+    for (kind, body) in child_boxes(&moov)? {
+        match &kind {
+            b"mvhd" => created = mvhd_created(body)?,
+            b"udta" => {
+                for (kind, body) in child_boxes(body)? {
+                    if &kind == b"meta" {
+                        read_meta(body, &mut metadata)?;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if metadata.created.is_none() && created != 0 {
+        metadata.created = Some(format_mp4_time(created));
+    }
+    Ok(metadata)
+}
+
+fn mvhd_created(body: &[u8]) -> Result<u64, DomainError> {
+    let mut fields = Fields::new(body);
+    let version = fields.u32()? >> 24;
+    Ok(match version {
+        1 => (fields.u32()? as u64) << 32 | fields.u32()? as u64,
+        _ => fields.u32()? as u64,
+    })
+}
+
+// The iTunes items of 'meta', which is a full box in mp4 files but not in QuickTime ones.
+fn read_meta(body: &[u8], metadata: &mut EntryMetadata) -> Result<(), DomainError> {
+    let body = match body.get(4..8) {
+        Some(kind) if kind == b"hdlr" => body,
+        _ => body.get(4..).unwrap_or_default(),
+    };
+    for (kind, body) in child_boxes(body)? {
+        if &kind != b"ilst" {
+            continue;
+        }
+        for (item, body) in child_boxes(body)? {
+            let mut name = None;
+            let mut values = vec![];
+            for (kind, body) in child_boxes(body)? {
+                // behind the type and the locale, and behind version and flags
+                match &kind {
+                    b"data" => values.push(text(body.get(8..).unwrap_or_default())),
+                    b"name" => name = Some(text(body.get(4..).unwrap_or_default())),
+                    _ => {}
+                }
+            }
+            match item {
+                TITLE_ITEM => metadata.title = values.into_iter().next(),
+                CREATED_ITEM => metadata.created = values.into_iter().next(),
+                NOTE_ITEM => metadata.note = values.into_iter().next(),
+                _ if &item == b"----" && name.as_deref() == Some(TAGS_NAME) => {
+                    metadata.tags = values
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+// Puts `metadata` into the 'moov' of a finished file in place of what was there. A 'moov' at
+// the end, as in a progressive file, is just written over. One in front of the media grows
+// into the 'free' box behind it if it fits. If not, a progressive file keeps its media where
+// the chunk offsets point and gets the 'moov' at its end; a fragmented file is copied with
+// more room, its fragments point at their samples from where they are, so they can move.
+pub fn write_mp4_metadata(path: &Path, metadata: &EntryMetadata) -> Result<(), DomainError> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let boxes = top_level_boxes(&mut file)?;
+    let moov = metadata_moov(&read_moov(&mut file, &boxes)?, metadata)?;
+    let at = boxes
+        .iter()
+        .position(|b| &b.kind == b"moov")
+        .unwrap_or_default();
+    let old = &boxes[at];
+    let behind = &boxes[at + 1..];
+    let free = behind.first().filter(|b| &b.kind == b"free");
+    let room = old.size + free.map_or(0, |free| free.size);
+    let len = moov.len() as u64;
+
+    if behind.iter().all(|b| &b.kind == b"free") {
+        file.seek(SeekFrom::Start(old.start))?;
+        file.write_all(&moov)?;
+        file.set_len(old.start + len)?;
+    } else if len == room || len + 8 <= room {
+        let mut moov = moov;
+        if len < room {
+            write_free(&mut moov, (room - len) as usize);
+        }
+        file.seek(SeekFrom::Start(old.start))?;
+        file.write_all(&moov)?;
+    } else if !behind.iter().any(|b| &b.kind == b"moof") {
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(&moov)?;
+        file.sync_all()?;
+        // the old one is skipped from now on
+        let mut header = vec![];
+        match old.header_size {
+            8 => {
+                put_u32(&mut header, old.size as u32);
+                header.extend_from_slice(b"free");
+            }
+            _ => {
+                put_u32(&mut header, 1);
+                header.extend_from_slice(b"free");
+                put_u64(&mut header, old.size);
+            }
+        }
+        file.seek(SeekFrom::Start(old.start))?;
+        file.write_all(&header)?;
+    } else {
+        let mut moov = moov;
+        write_free(&mut moov, METADATA_SPACE);
+        return copy_with_moov(file, path, old.start, old.start + room, &moov);
+    }
+    file.sync_all()?;
+    debug!("metadata written to {}", path.display());
+    Ok(())
+}
+
+// `file` with `moov` in place of what's from `start` to `end`, through a copy next to it.
+fn copy_with_moov(
+    mut file: File,
+    path: &Path,
+    start: u64,
+    end: u64,
+    moov: &[u8],
+) -> Result<(), DomainError> {
+    let part_path = path.with_extension("metadata.part");
+    let copied = (|| -> Result<(), DomainError> {
+        let mut part = BufWriter::new(File::create(&part_path)?);
+        file.seek(SeekFrom::Start(0))?;
+        io::copy(&mut Read::by_ref(&mut file).take(start), &mut part)?;
+        part.write_all(moov)?;
+        file.seek(SeekFrom::Start(end))?;
+        io::copy(&mut BufReader::new(&mut file), &mut part)?;
+        part.into_inner()
+            .map_err(|e| DomainError::Io(e.into_error()))?
+            .sync_all()?;
+        Ok(())
+    })();
+    // can't be replaced while open on Windows
+    drop(file);
+    match copied {
+        Ok(()) => {
+            fs::rename(&part_path, path)?;
+            debug!(
+                "metadata written to {}, the fragments moved",
+                path.display()
+            );
+            Ok(())
+        }
+        Err(e) => {
+            fs::remove_file(&part_path)
+                .unwrap_or_else(|e| error!("Failed to remove {}: {}", part_path.display(), e));
+            Err(e)
+        }
+    }
+}
+
+// A 'moov' with the boxes of `body` and `metadata` in its 'udta', the creation time in
+// 'mvhd' too. Whatever else was in 'udta' stays.
+fn metadata_moov(body: &[u8], metadata: &EntryMetadata) -> Result<Vec<u8>, DomainError> {
+    let children = child_boxes(body)?;
+    let mut keep = vec![];
+    for (_, body) in children.iter().filter(|(kind, _)| kind == b"udta") {
+        keep.extend(
+            child_boxes(body)?
+                .into_iter()
+                .filter(|(kind, _)| kind != b"meta"),
+        );
+    }
+    let mut moov = vec![];
+    write_box(&mut moov, b"moov", |b| {
+        for (kind, body) in &children {
+            match kind {
+                b"udta" => {}
+                b"mvhd" => write_box(b, kind, |b| {
+                    let at = b.len();
+                    b.extend_from_slice(body);
+                    if let Some(created) = metadata.mp4_time() {
+                        set_mvhd_created(&mut b[at..], created);
+                    }
+                }),
+                _ => write_box(b, kind, |b| b.extend_from_slice(body)),
+            }
+        }
+        write_udta(b, metadata, &keep);
+    });
+    Ok(moov)
+}
+
+// Both the creation and the modification time of an 'mvhd' body.
+fn set_mvhd_created(body: &mut [u8], created: u64) {
+    match body.first() {
+        Some(1) if body.len() >= 20 => {
+            body[4..12].copy_from_slice(&created.to_be_bytes());
+            body[12..20].copy_from_slice(&created.to_be_bytes());
+        }
+        Some(0) if body.len() >= 12 => {
+            let created = u32::try_from(created).unwrap_or(0);
+            body[4..8].copy_from_slice(&created.to_be_bytes());
+            body[8..12].copy_from_slice(&created.to_be_bytes());
+        }
+        _ => {}
+    }
+}
+
+// What `scan_fragments` found in a fragmented file.
+#[derive(Debug, Default)]
+pub struct FragmentScan {
+    pub fragments: usize,
+    pub video_samples: usize,
+    pub audio_samples: usize,
+    // in VIDEO_TIMESCALE units
+    pub video_duration: u64,
+    // in samples
+    pub audio_duration: u64,
+    // the file plays up to here, anything behind is a fragment that was cut off
+    pub playable_len: u64,
+    pub file_len: u64,
+    // offset and size of the first video sample
+    pub first_video_sample: Option<(u64, u32)>,
+    // where the duration in 'mehd' is
+    pub mehd_at: Option<u64>,
+}
+
+impl FragmentScan {
+    pub fn is_complete(&self) -> bool {
+        self.fragments > 0 && self.playable_len == self.file_len
+    }
+}
+
+// The samples one 'traf' puts in the 'mdat' behind its 'moof'.
+struct TrackRun {
+    track_id: u32,
+    // from the start of 'moof'
+    data_offset: u64,
+    sizes: Vec<u32>,
+    duration: u64,
+}
+
+// Reads a fragmented file back box by box, checking that fragments are numbered in order and
+// that the samples of each add up to its 'mdat'. A fragment that was cut off ends the scan,
+// so on a file that was never finished `playable_len` is where the last whole fragment ends.
+pub fn scan_fragments<R: Read + Seek>(mut input: R) -> Result<FragmentScan, DomainError> {
+    let mut scan = FragmentScan {
+        file_len: input.seek(SeekFrom::End(0))?,
+        ..FragmentScan::default()
+    };
+    input.seek(SeekFrom::Start(0))?;
+    let mut at = 0;
+    let mut moov = false;
+    let mut sequence = 0;
+    let mut moof: Option<(u64, Vec<TrackRun>)> = None;
+    while let Some(header) = read_box_header(&mut input)? {
+        let size = header.size.unwrap_or(scan.file_len - at);
+        let end = at + size;
+        if size < header.header_size || end > scan.file_len {
+            break;
+        }
+        match &header.kind {
+            b"moov" => {
+                moov = true;
+                let mut body = vec![0u8; (size - header.header_size) as usize];
+                input.read_exact(&mut body)?;
+                let mehd = match find_child(&body, b"mvex")? {
+                    Some((mvex_at, mvex)) => {
+                        find_child(mvex, b"mehd")?.map(|(mehd_at, _)| mvex_at + mehd_at)
+                    }
+                    None => None,
+                };
+                // behind the version and flags
+                scan.mehd_at = mehd.map(|i| at + header.header_size + i as u64 + 4);
+            }
+            b"moof" => {
+                if !moov {
+                    return Err(DomainError::Muxing("'moof' in front of 'moov'".to_string()));
+                }
+                let mut body = vec![0u8; (size - header.header_size) as usize];
+                input.read_exact(&mut body)?;
+                let (next, runs) = parse_moof(&body)?;
+                if next <= sequence {
+                    return Err(DomainError::Muxing(format!(
+                        "fragment {} follows fragment {}",
+                        next, sequence
+                    )));
+                }
+                sequence = next;
+                moof = Some((at, runs));
+            }
+            b"mdat" => {
+                let (moof_start, runs) = moof.take().ok_or_else(|| {
+                    DomainError::Muxing(
+                        "'mdat' without a 'moof', not a fragmented file".to_string(),
+                    )
+                })?;
+                let data_start = at + header.header_size;
+                let mut media = 0u64;
+                for run in &runs {
+                    let run_size: u64 = run.sizes.iter().map(|&s| s as u64).sum();
+                    let run_start = moof_start + run.data_offset;
+                    if run_start < data_start || run_start + run_size > end {
+                        return Err(DomainError::Muxing(format!(
+                            "fragment {}: track {} points outside its 'mdat'",
+                            sequence, run.track_id
+                        )));
+                    }
+                    media += run_size;
+                    match run.track_id {
+                        VIDEO_TRACK_ID => {
+                            if scan.first_video_sample.is_none() {
+                                scan.first_video_sample =
+                                    run.sizes.first().map(|&size| (run_start, size));
+                            }
+                            scan.video_samples += run.sizes.len();
+                            scan.video_duration += run.duration;
+                        }
+                        _ => {
+                            scan.audio_samples += run.sizes.len();
+                            scan.audio_duration += run.duration;
+                        }
+                    }
+                }
+                if media != end - data_start {
+                    return Err(DomainError::Muxing(format!(
+                        "fragment {}: {} bytes of samples in {} bytes of 'mdat'",
+                        sequence,
+                        media,
+                        end - data_start
+                    )));
+                }
+                scan.fragments += 1;
+                scan.playable_len = end;
+            }
+            _ => {}
+        }
+        input.seek(SeekFrom::Start(end))?;
+        at = end;
+    }
+    if !moov {
+        return Err(DomainError::Muxing("the file has no 'moov'".to_string()));
+    }
+    Ok(scan)
+}
+
+// The first video frame of a fragmented file in Annex B, the parameter sets in front.
+pub fn first_fragmented_frame<R: Read + Seek>(
+    mut input: R,
+    scan: &FragmentScan,
+) -> Result<Vec<u8>, DomainError> {
+    let (offset, size) = scan
+        .first_video_sample
+        .ok_or_else(|| DomainError::Muxing("the file has no video".to_string()))?;
+    input.seek(SeekFrom::Start(0))?;
+    // stops at the first 'mdat', the parameter sets are in front of it in either layout
+    let Mp4Salvage { sps, pps, .. } = Mp4Salvage::open(&mut input)?;
+    let mut sample = vec![0u8; size as usize];
+    input.seek(SeekFrom::Start(offset))?;
+    input.read_exact(&mut sample)?;
+    annexb_frame(&sps, &pps, &sample)
+}
+
+// An avc1 sample back in Annex B with the parameter sets in front, what a decoder wants for
+// a frame on its own.
+pub fn annexb_frame(sps: &[u8], pps: &[u8], sample: &[u8]) -> Result<Vec<u8>, DomainError> {
+    let mut frame = vec![];
+    for nal in [sps, pps] {
+        frame.extend_from_slice(&[0, 0, 0, 1]);
+        frame.extend_from_slice(nal);
+    }
+    let mut fields = Fields::new(sample);
+    while fields.remaining() > 0 {
+        let nal_size = fields.u32()? as usize;
+        frame.extend_from_slice(&[0, 0, 0, 1]);
+        frame.extend_from_slice(fields.bytes(nal_size)?);
+    }
+    Ok(frame)
+}
+
+// Returns the sequence number and the runs of every 'traf'.
+fn parse_moof(body: &[u8]) -> Result<(u32, Vec<TrackRun>), DomainError> {
+    let mut sequence = 0;
+    let mut runs = vec![];
+    for (kind, body) in child_boxes(body)? {
+        match &kind {
+            b"mfhd" => {
+                let mut fields = Fields::new(body);
+                fields.u32()?; // version and flags
+                sequence = fields.u32()?;
+            }
+            b"traf" => runs.push(parse_traf(body)?),
+            _ => {}
+        }
+    }
+    Ok((sequence, runs))
+}
+
+fn parse_traf(body: &[u8]) -> Result<TrackRun, DomainError> {
+    let mut run = TrackRun {
+        track_id: 0,
+        data_offset: 0,
+        sizes: vec![],
+        duration: 0,
+    };
+    let mut default_duration = 0;
+    for (kind, body) in child_boxes(body)? {
+        let mut fields = Fields::new(body);
+        let flags = fields.u32()? & 0xffffff;
+        match &kind {
+            b"tfhd" => {
+                run.track_id = fields.u32()?;
+                if flags & 0x1 != 0 {
+                    return Err(DomainError::Muxing(
+                        "explicit base data offsets are not supported".to_string(),
+                    ));
+                }
+                if flags & 0x2 != 0 {
+                    fields.u32()?; // sample description index
+                }
+                if flags & 0x8 != 0 {
+                    default_duration = fields.u32()?;
+                }
+            }
+            b"trun" => {
+                let count = fields.u32()?;
+                if flags & 0x1 != 0 {
+                    run.data_offset = fields.u32()? as i32 as u64;
+                }
+                if flags & 0x4 != 0 {
+                    fields.u32()?; // first sample flags
+                }
+                if flags & 0x200 == 0 {
+                    return Err(DomainError::Muxing(
+                        "'trun' without sample sizes".to_string(),
+                    ));
+                }
+                for _ in 0..count {
+                    let duration = if flags & 0x100 != 0 {
+                        fields.u32()?
+                    } else {
+                        default_duration
+                    };
+                    run.duration += duration as u64;
+                    run.sizes.push(fields.u32()?);
+                    if flags & 0x400 != 0 {
+                        fields.u32()?;
+                    }
+                    if flags & 0x800 != 0 {
+                        fields.u32()?;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(run)
+}
+
+// Kind and body of a box.
+type ChildBox<'a> = ([u8; 4], &'a [u8]);
+
+// Kind and body of each box in `data`, only 32 bit sizes as in what `Mp4Writer` writes.
+fn child_boxes(data: &[u8]) -> Result<Vec<ChildBox<'_>>, DomainError> {
+    let mut boxes = vec![];
+    let mut fields = Fields::new(data);
+    while fields.remaining() > 0 {
+        let size = fields.u32()? as usize;
+        let mut kind = [0u8; 4];
+        kind.copy_from_slice(fields.bytes(4)?);
+        if size < 8 {
+            return Err(DomainError::Muxing(format!("box of size {}", size)));
+        }
+        boxes.push((kind, fields.bytes(size - 8)?));
+    }
+    Ok(boxes)
+}
+
+// Where the body of the first `kind` box in `data` starts, and the body.
+fn find_child<'a>(
+    data: &'a [u8],
+    kind: &[u8; 4],
+) -> Result<Option<(usize, &'a [u8])>, DomainError> {
+    let mut at = 0;
+    for (child, body) in child_boxes(data)? {
+        if &child == kind {
+            return Ok(Some((at + 8, body)));
+        }
+        at += 8 + body.len();
+    }
+    Ok(None)
+}
+
+// Big endian fields off a box body, running past its end is an error.
+struct Fields<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, at: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.at
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], DomainError> {
+        if n > self.remaining() {
+            return Err(DomainError::Muxing("box cut short".to_string()));
+        }
+        self.at += n;
+        Ok(&self.data[self.at - n..self.at])
+    }
+
+    fn u32(&mut self) -> Result<u32, DomainError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+// One frame as it goes into a file, see `avc_sample`.
+pub struct AvcSample<'a> {
+    // length-prefixed nal units
+    pub sample: Vec<u8>,
+    // an IDR picture
+    pub sync: bool,
+    pub sps: Option<&'a [u8]>,
+    pub pps: Option<&'a [u8]>,
+}
+
+// An Annex B frame the way mp4 and Matroska store it: the parameter sets go to the track's
+// decoder config, access unit delimiters are dropped and every other unit is prefixed with
+// its length.
+pub fn avc_sample(frame: &[u8]) -> AvcSample<'_> {
+    let mut avc = AvcSample {
+        sample: vec![],
+        sync: false,
+        sps: None,
+        pps: None,
+    };
+    for nal in annexb_nal_units(frame) {
+        match nal[0] & 0x1f {
+            7 => {
+                avc.sps.get_or_insert(nal);
+            }
+            8 => {
+                avc.pps.get_or_insert(nal);
+            }
+            // access unit delimiter, not allowed in avc1 samples
+            9 => {}
+            nal_type => {
+                avc.sync |= nal_type == 5;
+                avc.sample
+                    .extend_from_slice(&(nal.len() as u32).to_be_bytes());
+                avc.sample.extend_from_slice(nal);
+            }
+        }
+    }
+    avc
+}
+
+// Splits on 3 and 4 byte start codes, empty units are skipped.
+pub fn annexb_nal_units(data: &[u8]) -> Vec<&[u8]> {
+    let mut starts = vec![];
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            starts.push((i, i + 3));
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    let mut units = vec![];
+    for (n, &(_, start)) in starts.iter().enumerate() {
+        let mut end = starts.get(n + 1).map_or(data.len(), |&(next, _)| next);
+        // the leading zero of a 4 byte start code belongs to the next one
+        while end > start && data[end - 1] == 0 {
+            end -= 1;
+        }
+        if end > start {
+            units.push(&data[start..end]);
+        }
+    }
+    units
+}
+
+fn movie_duration(samples: &[Sample], timescale: u32) -> u64 {
+    media_duration(samples) * MOVIE_TIMESCALE as u64 / timescale as u64
+}
+
+fn media_duration(samples: &[Sample]) -> u64 {
+    samples.iter().map(|s| s.duration as u64).sum()
+}
+
+fn put_u16(b: &mut Vec<u8>, v: u16) {
+    b.extend_from_slice(&v.to_be_bytes());
+}
+
+fn put_u32(b: &mut Vec<u8>, v: u32) {
+    b.extend_from_slice(&v.to_be_bytes());
+}
+
+fn put_u64(b: &mut Vec<u8>, v: u64) {
+    b.extend_from_slice(&v.to_be_bytes());
+}
+
+fn write_box(b: &mut Vec<u8>, kind: &[u8; 4], body: impl FnOnce(&mut Vec<u8>)) {
+    let start = b.len();
+    put_u32(b, 0);
+    b.extend_from_slice(kind);
+    body(b);
+    let size = (b.len() - start) as u32;
+    b[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn write_full_box(
+    b: &mut Vec<u8>,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    body: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(b, kind, |b| {
+        put_u32(b, (version as u32) << 24 | flags);
+        body(b);
+    });
+}
+
+fn write_matrix(b: &mut Vec<u8>) {
+    for v in [0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x40000000] {
+        put_u32(b, v);
+    }
+}
+
+fn write_mvhd(b: &mut Vec<u8>, duration: u64, next_track_id: u32, created: u64) {
+    write_full_box(b, b"mvhd", 1, 0, |b| {
+        put_u64(b, created); // creation time
+        put_u64(b, created); // modification time
+        put_u32(b, MOVIE_TIMESCALE);
+        put_u64(b, duration);
+        put_u32(b, 0x10000); // rate 1.0
+        put_u16(b, 0x100); // volume 1.0
+        b.extend_from_slice(&[0; 10]);
+        write_matrix(b);
+        b.extend_from_slice(&[0; 24]);
+        put_u32(b, next_track_id);
+    });
+}
+
+fn write_tkhd(b: &mut Vec<u8>, track_id: u32, duration: u64, audio: bool, width: u32, height: u32) {
+    // enabled, in movie, in preview
+    write_full_box(b, b"tkhd", 1, 0x7, |b| {
+        put_u64(b, 0);
+        put_u64(b, 0);
+        put_u32(b, track_id);
+        put_u32(b, 0);
+        put_u64(b, duration);
+        b.extend_from_slice(&[0; 8]);
+        put_u16(b, 0); // layer
+        put_u16(b, if audio { 1 } else { 0 }); // alternate group
+        put_u16(b, if audio { 0x100 } else { 0 });
+        put_u16(b, 0);
+        write_matrix(b);
+        put_u32(b, width << 16);
+        put_u32(b, height << 16);
+    });
+}
+
+fn write_mdhd(b: &mut Vec<u8>, timescale: u32, duration: u64) {
+    write_full_box(b, b"mdhd", 1, 0, |b| {
+        put_u64(b, 0);
+        put_u64(b, 0);
+        put_u32(b, timescale);
+        put_u64(b, duration);
+        put_u16(b, 0x55c4); // 'und'
+        put_u16(b, 0);
+    });
+}
+
+fn write_hdlr(b: &mut Vec<u8>, handler: &[u8; 4], name: &str) {
+    write_full_box(b, b"hdlr", 0, 0, |b| {
+        put_u32(b, 0);
+        b.extend_from_slice(handler);
+        b.extend_from_slice(&[0; 12]);
+        b.extend_from_slice(name.as_bytes());
+        b.push(0);
+    });
+}
+
+fn write_dinf(b: &mut Vec<u8>) {
+    write_box(b, b"dinf", |b| {
+        write_full_box(b, b"dref", 0, 0, |b| {
+            put_u32(b, 1);
+            // media is in this file
+            write_full_box(b, b"url ", 0, 1, |_| {});
+        });
+    });
+}
+
+// Every sample is its own chunk, which keeps 'stsc' to a single entry.
+fn write_sample_tables(b: &mut Vec<u8>, samples: &[Sample], sync_table: bool) {
+    write_full_box(b, b"stts", 0, 0, |b| {
+        let mut runs: Vec<(u32, u32)> = vec![];
+        for sample in samples {
+            match runs.last_mut() {
+                Some((count, duration)) if *duration == sample.duration => *count += 1,
+                _ => runs.push((1, sample.duration)),
+            }
+        }
+        put_u32(b, runs.len() as u32);
+        for (count, duration) in runs {
+            put_u32(b, count);
+            put_u32(b, duration);
+        }
+    });
+    // tracks of a fragmented file have no samples here, every one is a sync sample then
+    if sync_table && !samples.is_empty() {
+        write_full_box(b, b"stss", 0, 0, |b| {
+            let sync: Vec<u32> = samples
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| s.sync)
+                .map(|(i, _)| i as u32 + 1)
+                .collect();
+            put_u32(b, sync.len() as u32);
+            for index in sync {
+                put_u32(b, index);
+            }
+        });
+    }
+    write_full_box(b, b"stsc", 0, 0, |b| {
+        if samples.is_empty() {
+            put_u32(b, 0);
+            return;
+        }
+        put_u32(b, 1);
+        put_u32(b, 1); // first chunk
+        put_u32(b, 1); // samples per chunk
+        put_u32(b, 1); // sample description
+    });
+    write_full_box(b, b"stsz", 0, 0, |b| {
+        put_u32(b, 0);
+        put_u32(b, samples.len() as u32);
+        for sample in samples {
+            put_u32(b, sample.size);
+        }
+    });
+    write_full_box(b, b"co64", 0, 0, |b| {
+        put_u32(b, samples.len() as u32);
+        for sample in samples {
+            put_u64(b, sample.offset);
+        }
+    });
+}
+
+fn write_video_trak(
+    b: &mut Vec<u8>,
+    width: u32,
+    height: u32,
+    config: &VideoConfig,
+    samples: &[Sample],
+) {
+    write_box(b, b"trak", |b| {
+        write_tkhd(
+            b,
+            1,
+            movie_duration(samples, VIDEO_TIMESCALE),
+            false,
+            width,
+            height,
+        );
+        write_box(b, b"mdia", |b| {
+            write_mdhd(b, VIDEO_TIMESCALE, media_duration(samples));
+            write_hdlr(b, b"vide", "VideoHandler");
+            write_box(b, b"minf", |b| {
+                write_full_box(b, b"vmhd", 0, 1, |b| b.extend_from_slice(&[0; 8]));
+                write_dinf(b);
+                write_box(b, b"stbl", |b| {
+                    write_full_box(b, b"stsd", 0, 0, |b| {
+                        put_u32(b, 1);
+                        write_visual_sample_entry(b, width, height, config);
+                    });
+                    write_sample_tables(b, samples, true);
+                });
+            });
+        });
+    });
+}
+
+fn write_visual_sample_entry(b: &mut Vec<u8>, width: u32, height: u32, config: &VideoConfig) {
+    let kind = match config {
+        VideoConfig::Avc { .. } => b"avc1",
+        VideoConfig::Av1 { .. } => b"av01",
+    };
+    write_box(b, kind, |b| {
+        b.extend_from_slice(&[0; 6]);
+        put_u16(b, 1); // data reference index
+        b.extend_from_slice(&[0; 16]);
+        put_u16(b, width as u16);
+        put_u16(b, height as u16);
+        put_u32(b, 0x480000); // 72 dpi
+        put_u32(b, 0x480000);
+        put_u32(b, 0);
+        put_u16(b, 1); // frame count
+        let mut compressor = [0u8; 32];
+        let name = b"diary";
+        compressor[0] = name.len() as u8;
+        compressor[1..=name.len()].copy_from_slice(name);
+        b.extend_from_slice(&compressor);
+        put_u16(b, 0x18); // depth
+        put_u16(b, 0xffff);
+        write_decoder_config(b, config);
+    });
+}
+
+fn write_decoder_config(b: &mut Vec<u8>, config: &VideoConfig) {
+    let kind = match config {
+        VideoConfig::Avc { .. } => b"avcC",
+        VideoConfig::Av1 { .. } => b"av1C",
+    };
+    write_box(b, kind, |b| b.extend_from_slice(&config.decoder_config()));
+}
+
+// AVCDecoderConfigurationRecord, the body of 'avcC' and the CodecPrivate of a Matroska track.
+// `sps` is at least 4 bytes.
+pub fn avc_decoder_config(sps: &[u8], pps: &[u8]) -> Vec<u8> {
+    let mut b = vec![1];
+    b.extend_from_slice(&sps[1..4]); // profile, compatibility, level
+    b.push(0xff); // 4 byte lengths
+    b.push(0xe1); // one sps
+    put_u16(&mut b, sps.len() as u16);
+    b.extend_from_slice(sps);
+    b.push(1);
+    put_u16(&mut b, pps.len() as u16);
+    b.extend_from_slice(pps);
+    if matches!(sps[1], 100 | 110 | 122 | 144) {
+        // the high profiles carry chroma format and bit depths, 4:2:0 8 bit here
+        b.extend_from_slice(&[0xfd, 0xf8, 0xf8, 0]);
+    }
+    b
+}
+
+fn write_audio_trak(b: &mut Vec<u8>, config: &AacConfig, samples: &[Sample]) {
+    write_box(b, b"trak", |b| {
+        let duration = media_duration(samples).saturating_sub(config.priming as u64);
+        write_tkhd(
+            b,
+            2,
+            duration * MOVIE_TIMESCALE as u64 / config.sample_rate as u64,
+            true,
+            0,
+            0,
+        );
+        if config.priming > 0 {
+            write_box(b, b"edts", |b| {
+                write_full_box(b, b"elst", 1, 0, |b| {
+                    put_u32(b, 1);
+                    put_u64(
+                        b,
+                        duration * MOVIE_TIMESCALE as u64 / config.sample_rate as u64,
+                    );
+                    put_u64(b, config.priming as u64);
+                    put_u32(b, 0x10000); // rate 1.0
+                });
+            });
+        }
+        write_box(b, b"mdia", |b| {
+            write_mdhd(b, config.sample_rate, media_duration(samples));
+            write_hdlr(b, b"soun", "SoundHandler");
+            write_box(b, b"minf", |b| {
+                write_full_box(b, b"smhd", 0, 0, |b| put_u32(b, 0));
+                write_dinf(b);
+                write_box(b, b"stbl", |b| {
+                    write_full_box(b, b"stsd", 0, 0, |b| {
+                        put_u32(b, 1);
+                        write_mp4a(b, config);
+                    });
+                    write_sample_tables(b, samples, false);
+                });
+            });
+        });
+    });
+}
+
+fn write_mp4a(b: &mut Vec<u8>, config: &AacConfig) {
+    write_box(b, b"mp4a", |b| {
+        b.extend_from_slice(&[0; 6]);
+        put_u16(b, 1); // data reference index
+        b.extend_from_slice(&[0; 8]);
+        put_u16(b, config.channels);
+        put_u16(b, 16);
+        put_u32(b, 0);
+        put_u32(b, config.sample_rate.min(0xffff) << 16);
+        write_full_box(b, b"esds", 0, 0, |b| {
+            let mut decoder_specific = vec![];
+            write_descriptor(&mut decoder_specific, 0x05, &config.decoder_config);
+
+            let mut decoder_config = vec![0x40, 0x15]; // aac, audio stream
+            decoder_config.extend_from_slice(&[0; 3]); // buffer size
+            put_u32(&mut decoder_config, config.bit_rate);
+            put_u32(&mut decoder_config, config.bit_rate);
+            decoder_config.extend_from_slice(&decoder_specific);
+
+            let mut es = vec![0, 2, 0]; // ES_ID, flags
+            write_descriptor(&mut es, 0x04, &decoder_config);
+            write_descriptor(&mut es, 0x06, &[0x02]);
+
+            write_descriptor(b, 0x03, &es);
+        });
+    });
+}
+
+// Defaults for the samples of a track in fragments, every 'trun' spells them out anyway.
+fn write_trex(b: &mut Vec<u8>, track_id: u32) {
+    write_full_box(b, b"trex", 0, 0, |b| {
+        put_u32(b, track_id);
+        put_u32(b, 1); // sample description
+        put_u32(b, 0);
+        put_u32(b, 0);
+        put_u32(b, 0);
+    });
+}
+
+// Returns where the data offset of the 'trun' is, patched once the size of 'moof' is known.
+fn write_traf(b: &mut Vec<u8>, track_id: u32, decode_time: u64, samples: &[Sample]) -> usize {
+    let mut data_offset_at = 0;
+    write_box(b, b"traf", |b| {
+        // default-base-is-moof, data offsets count from the start of 'moof'
+        write_full_box(b, b"tfhd", 0, 0x020000, |b| put_u32(b, track_id));
+        write_full_box(b, b"tfdt", 1, 0, |b| put_u64(b, decode_time));
+        // data offset, then duration, size and flags of every sample
+        write_full_box(b, b"trun", 0, 0x000701, |b| {
+            put_u32(b, samples.len() as u32);
+            data_offset_at = b.len();
+            put_u32(b, 0);
+            for sample in samples {
+                put_u32(b, sample.duration);
+                put_u32(b, sample.size);
+                // depends on nothing, or on others and is no sync sample
+                put_u32(b, if sample.sync { 0x02000000 } else { 0x01010000 });
+            }
+        });
+    });
+    data_offset_at
+}
+
+// iTunes items in 'udta', what players and file browsers show, after `keep`, the other boxes
+// of the 'udta' that was there before. The tags go into a freeform item, one 'data' each.
+fn write_udta(b: &mut Vec<u8>, metadata: &EntryMetadata, keep: &[ChildBox]) {
+    if metadata.is_empty() && keep.is_empty() {
+        return;
+    }
+    write_box(b, b"udta", |b| {
+        for (kind, body) in keep {
+            write_box(b, kind, |b| b.extend_from_slice(body));
+        }
+        if metadata.is_empty() {
+            return;
+        }
+        write_full_box(b, b"meta", 0, 0, |b| {
+            write_hdlr(b, b"mdir", "");
+            write_box(b, b"ilst", |b| {
+                let items = [
+                    (TITLE_ITEM, &metadata.title),
+                    (CREATED_ITEM, &metadata.created),
+                    (NOTE_ITEM, &metadata.note),
+                ];
+                for (item, value) in items {
+                    if let Some(value) = value {
+                        write_box(b, &item, |b| write_data(b, value));
+                    }
+                }
+                if !metadata.tags.is_empty() {
+                    write_box(b, b"----", |b| {
+                        write_full_box(b, b"mean", 0, 0, |b| {
+                            b.extend_from_slice(FREEFORM_MEAN.as_bytes())
+                        });
+                        write_full_box(b, b"name", 0, 0, |b| {
+                            b.extend_from_slice(TAGS_NAME.as_bytes())
+                        });
+                        for tag in &metadata.tags {
+                            write_data(b, tag);
+                        }
+                    });
+                }
+            });
+        });
+    });
+}
+
+// The value of an item, type 1 is utf-8 text.
+fn write_data(b: &mut Vec<u8>, value: &str) {
+    write_box(b, b"data", |b| {
+        put_u32(b, 1);
+        put_u32(b, 0); // locale
+        b.extend_from_slice(value.as_bytes());
+    });
+}
+
+// `size` bytes players skip, header included
+fn write_free(b: &mut Vec<u8>, size: usize) {
+    write_box(b, b"free", |b| b.resize(b.len() + size - 8, 0));
+}
+
+// Descriptor sizes use the 4 byte form, which every reader accepts.
+fn write_descriptor(b: &mut Vec<u8>, tag: u8, body: &[u8]) {
+    b.push(tag);
+    let len = body.len() as u32;
+    b.extend_from_slice(&[
+        0x80 | (len >> 21 & 0x7f) as u8,
+        0x80 | (len >> 14 & 0x7f) as u8,
+        0x80 | (len >> 7 & 0x7f) as u8,
+        (len & 0x7f) as u8,
+    ]);
+    b.extend_from_slice(body);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::test_support::{
+        aac_config, avc_config, is_keyframe, sample, AUDIO_FRAME, FRAME, PPS, SPS,
+    };
+    use std::io::Cursor;
+
+    // `frames` frames at 30 fps in fragments of a second, a frame's worth of audio with each
+    fn fragmented(frames: usize) -> Vec<u8> {
+        let layout = Mp4Layout::Fragmented {
+            fragment: Duration::from_secs(1),
+        };
+        let mut writer = Mp4Writer::new(Cursor::new(vec![]), 64, 48, layout).unwrap();
+        writer.set_video(avc_config()).unwrap();
+        writer.set_audio(aac_config()).unwrap();
+        for n in 0..frames {
+            writer
+                .write_video(&sample(n), is_keyframe(n), FRAME)
+                .unwrap();
+            writer.write_audio(&[0x21; 12], AUDIO_FRAME).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    // where each fragment ends, behind its 'mdat'
+    fn fragment_ends(file: &[u8]) -> Vec<u64> {
+        let mut input = Cursor::new(file);
+        let mut ends = vec![];
+        let mut at = 0;
+        while let Some(header) = read_box_header(&mut input).unwrap() {
+            at += header.size.unwrap();
+            if &header.kind == b"mdat" {
+                ends.push(at);
+            }
+            input.seek(SeekFrom::Start(at)).unwrap();
+        }
+        ends
+    }
+
+    #[test]
+    fn fragmented_file_scans_back() {
+        let file = fragmented(90);
+        let scan = scan_fragments(Cursor::new(&file)).unwrap();
+        assert!(scan.is_complete());
+        assert_eq!(scan.fragments, 3);
+        assert_eq!(scan.video_samples, 90);
+        assert_eq!(scan.audio_samples, 90);
+        assert_eq!(scan.video_duration, 90 * FRAME as u64);
+        assert_eq!(scan.audio_duration, 90 * AUDIO_FRAME as u64);
+
+        let (offset, size) = scan.first_video_sample.unwrap();
+        assert_eq!(&file[offset as usize..][..size as usize], &sample(0)[..]);
+        // `finish` wrote the duration of the take there, in movie time
+        let at = scan.mehd_at.unwrap() as usize;
+        assert_eq!(
+            u64::from_be_bytes(file[at..at + 8].try_into().unwrap()),
+            3000
+        );
+    }
+
+    #[test]
+    fn truncated_file_keeps_its_whole_fragments() {
+        let file = fragmented(120);
+        let ends = fragment_ends(&file);
+        assert_eq!(ends.len(), 4);
+        for (n, &end) in ends.iter().enumerate() {
+            let next = ends.get(n + 1).copied().unwrap_or(end + 1);
+            // right behind fragment n, into the next 'moof' and just short of its end
+            for cut in [end, end + 1, next - 1] {
+                let cut = cut.min(file.len() as u64) as usize;
+                let scan = scan_fragments(Cursor::new(&file[..cut])).unwrap();
+                assert_eq!(scan.fragments, n + 1, "cut at {} of {}", cut, file.len());
+                assert_eq!(scan.playable_len, end);
+                assert_eq!(scan.video_samples, 30 * (n + 1));
+            }
+        }
+    }
+
+    #[test]
+    fn cut_off_file_plays_up_to_its_last_whole_fragment() {
+        let file = fragmented(90);
+
+        // the last fragment lost its end
+        let cut = scan_fragments(Cursor::new(&file[..file.len() - 10])).unwrap();
+        assert!(!cut.is_complete());
+        assert_eq!(cut.fragments, 2);
+        assert_eq!(cut.video_samples, 60);
+        assert!(cut.playable_len < cut.file_len);
+
+        // which leaves a file that is whole up to there
+        let playable = &file[..cut.playable_len as usize];
+        let scan = scan_fragments(Cursor::new(playable)).unwrap();
+        assert!(scan.is_complete());
+        assert_eq!(scan.fragments, 2);
+
+        // cut in the middle of the second fragment's 'moof'
+        let second = scan_fragments(Cursor::new(&playable[..playable.len() - 1])).unwrap();
+        let moof_cut = &file[..second.playable_len as usize + 20];
+        let scan = scan_fragments(Cursor::new(moof_cut)).unwrap();
+        assert_eq!(scan.fragments, 1);
+        assert_eq!(scan.playable_len, second.playable_len);
+        assert_eq!(scan.video_samples, 30);
+    }
+
+    #[test]
+    fn unfinished_progressive_file_gives_back_its_frames() {
+        let mut writer =
+            Mp4Writer::new(Cursor::new(vec![]), 64, 48, Mp4Layout::Progressive).unwrap();
+        writer.set_video(avc_config()).unwrap();
+        writer.set_audio(aac_config()).unwrap();
+        for n in 0..46 {
+            writer
+                .write_video(&sample(n), is_keyframe(n), FRAME)
+                .unwrap();
+            writer.write_audio(&[0x21; 12], AUDIO_FRAME).unwrap();
+        }
+        // the app died in the middle of the last frame's audio
+        let file = writer.out.into_inner();
+        let cut = &file[..file.len() - 4];
+
+        let mut salvaged = Mp4Salvage::open(Cursor::new(cut)).unwrap();
+        assert_eq!((&salvaged.sps[..], &salvaged.pps[..]), (&SPS[..], &PPS[..]));
+        let mut frames = vec![];
+        while let Some(frame) = salvaged.next_frame().unwrap() {
+            frames.push(frame);
+        }
+        assert_eq!(frames.len(), 46);
+        for (n, frame) in frames.iter().enumerate() {
+            assert_eq!(frame[..4], [0, 0, 0, 1]);
+            assert_eq!(frame[4..], sample(n)[4..]);
+        }
+    }
+
+    // the body of the box at the end of `path` below `data`
+    fn child<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> &'a [u8] {
+        path.iter().fold(data, |data, kind| {
+            find_child(data, kind).unwrap().unwrap().1
+        })
+    }
+
+    // the entries of a full box's table, `width` fields of 32 bits each
+    fn table(body: &[u8], skip: usize, width: usize) -> Vec<Vec<u32>> {
+        let mut fields = Fields::new(body);
+        for _ in 0..1 + skip {
+            fields.u32().unwrap();
+        }
+        let count = fields.u32().unwrap();
+        (0..count)
+            .map(|_| (0..width).map(|_| fields.u32().unwrap()).collect())
+            .collect()
+    }
+
+    fn access_unit(n: usize) -> Vec<u8> {
+        vec![0x21 ^ n as u8; 10 + n % 3]
+    }
+
+    #[test]
+    fn progressive_file_has_its_samples_in_moov() {
+        let mut writer =
+            Mp4Writer::new(Cursor::new(vec![]), 64, 48, Mp4Layout::Progressive).unwrap();
+        writer.set_video(avc_config()).unwrap();
+        writer
+            .set_audio(AacConfig {
+                priming: 1024,
+                ..aac_config()
+            })
+            .unwrap();
+        for n in 0..45 {
+            // the last frame is held for two
+            let duration = if n == 44 { 2 * FRAME } else { FRAME };
+            writer
+                .write_video(&sample(n), is_keyframe(n), duration)
+                .unwrap();
+            writer.write_audio(&access_unit(n), 1024).unwrap();
+        }
+        let file = writer.finish().unwrap().into_inner();
+
+        let mut input = Cursor::new(&file);
+        let boxes = top_level_boxes(&mut input).unwrap();
+        let kinds: Vec<&[u8; 4]> = boxes.iter().map(|b| &b.kind).collect();
+        assert_eq!(kinds, [b"ftyp", b"free", b"mdat", b"moov"]);
+        // the 64 bit size of 'mdat' was patched, each access unit has its marker in front
+        let media: usize = (0..45)
+            .map(|n| sample(n).len() + 5 + access_unit(n).len())
+            .sum();
+        assert_eq!(boxes[2].size, 16 + media as u64);
+
+        let moov = read_moov(&mut input, &boxes).unwrap();
+        let mvhd = child(&moov, &[b"mvhd"]);
+        let duration = u64::from_be_bytes(mvhd[24..32].try_into().unwrap());
+        assert_eq!(duration, 46 * FRAME as u64 * 1000 / VIDEO_TIMESCALE as u64);
+
+        let traks: Vec<&[u8]> = child_boxes(&moov)
+            .unwrap()
+            .into_iter()
+            .filter(|(kind, _)| kind == b"trak")
+            .map(|(_, body)| body)
+            .collect();
+        assert_eq!(traks.len(), 2);
+        let stbl = |trak| child(trak, &[b"mdia", b"minf", b"stbl"]);
+
+        let video = stbl(traks[0]);
+        let sizes = table(child(video, &[b"stsz"]), 1, 1);
+        let offsets = table(child(video, &[b"co64"]), 0, 2);
+        for n in 0..45 {
+            let offset = (offsets[n][0] as usize) << 32 | offsets[n][1] as usize;
+            let size = sizes[n][0] as usize;
+            assert_eq!(&file[offset..offset + size], &sample(n)[..]);
+        }
+        assert_eq!(offsets.len(), 45);
+        assert_eq!(
+            table(child(video, &[b"stts"]), 0, 2),
+            [vec![44, FRAME], vec![1, 2 * FRAME]]
+        );
+        assert_eq!(table(child(video, &[b"stss"]), 0, 1), [vec![1], vec![31]]);
+
+        let audio = stbl(traks[1]);
+        let sizes = table(child(audio, &[b"stsz"]), 1, 1);
+        let offsets = table(child(audio, &[b"co64"]), 0, 2);
+        assert_eq!(offsets.len(), 45);
+        for n in 0..45 {
+            let offset = (offsets[n][0] as usize) << 32 | offsets[n][1] as usize;
+            let size = sizes[n][0] as usize;
+            assert_eq!(&file[offset..offset + size], &access_unit(n)[..]);
+        }
+        assert_eq!(table(child(audio, &[b"stts"]), 0, 2), [vec![45, 1024]]);
+        // the priming samples are edited out
+        let elst = table(child(traks[1], &[b"edts", b"elst"]), 0, 5);
+        let played = (45 - 1) * 1024 * 1000 / 48000;
+        assert_eq!(elst, [vec![0, played, 0, 1024, 0x10000]]);
+    }
+}
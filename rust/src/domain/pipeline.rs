@@ -0,0 +1,545 @@
+use std::{
+    fs::File,
+    io::Read,
+    ops::Not,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use image::{DynamicImage, ImageBuffer, Rgba};
+use kanal::{ReceiveErrorTimeout, Receiver, Sender};
+use log::{debug, error, info};
+use nokhwa::Buffer;
+
+use crate::{
+    message_channel::audio_message_channel::Pcm,
+    tools::{
+        image_processing::{decode_to_rgb, rgba_to_yuv},
+        ordqueue::{new, OrdQueue},
+    },
+};
+
+use super::{
+    av_sync::PCM_CLOSE_TIMEOUT,
+    encoder_profile::{EncoderProfile, EncoderSettings},
+    error::{catch_panic, DomainError},
+    metadata::EntryMetadata,
+    mp4::Mp4Layout,
+    progress::ProgressTracker,
+    recording::{
+        create_m4a, create_mkv, create_mp4, create_segmented, encode_video, finish_take, Container,
+    },
+    scratch::{move_file, ScratchDir},
+    segments::{remove_segments, Segmenting},
+    session::{SessionState, SessionStateHandle},
+    visual_track::{
+        encode_visual_track, VisualTrack, BACKGROUND, FOREGROUND, VISUAL_FPS, VISUAL_HEIGHT,
+        VISUAL_WIDTH,
+    },
+    wav::write_wav,
+};
+
+// what a take can be recorded at
+pub const FRAME_RATES: [u32; 4] = [15, 24, 30, 60];
+pub const DEFAULT_FPS: u32 = 24;
+pub const THUMBNAIL_DIR_NAME: &str = "thumbnails";
+// the size `save_thumbnail` brings every thumbnail to
+const THUMBNAIL_WIDTH: usize = 320;
+const THUMBNAIL_HEIGHT: usize = 180;
+
+pub struct EncodingJob {
+    pub file_path_prefix: String,
+    pub file_name: String,
+    pub width: usize,
+    pub height: usize,
+    pub encoding_receiver: Receiver<(Buffer, Instant)>,
+    // format and pcm file of the take's audio, encoded alongside the video
+    pub audio: Pcm,
+    pub container: Container,
+    pub layout: Mp4Layout,
+    // None keeps the take in one file
+    pub segmenting: Option<Segmenting>,
+    pub wav_master: bool,
+    pub encoder: EncoderProfile,
+    // written into the take, mp4 only
+    pub metadata: EntryMetadata,
+    pub state: SessionStateHandle,
+    // set by `cancel_recording`, nothing gets written once it is
+    pub cancelled: Arc<AtomicBool>,
+    // removed once the entry is saved, left behind when anything fails
+    pub scratch: ScratchDir,
+}
+
+// A take of the microphone alone, no camera involved.
+pub struct VoiceMemoJob {
+    pub file_path_prefix: String,
+    pub file_name: String,
+    pub audio: Pcm,
+    // drawn once the take is over and saved as an mp4 with it, None for an m4a
+    pub visual: Option<VisualTrack>,
+    pub wav_master: bool,
+    pub metadata: EntryMetadata,
+    pub state: SessionStateHandle,
+    pub cancelled: Arc<AtomicBool>,
+    pub scratch: ScratchDir,
+}
+
+// Passes frames on to the encoder with their capture time, which the muxer turns into
+// per-sample durations. Frames closer together than the frame interval, less some jitter,
+// are dropped; a slow camera just gets longer frames.
+// Frames are shifted back by the time spent paused so a pause leaves no gap to fill.
+// Closes the encoding channel once recording has stopped and the queue is flushed, or
+// right away when cancelled. The recording channel is left open then, the preview feeds it.
+pub fn spawn_batching(
+    recording_receiver: Receiver<(Buffer, Instant)>,
+    encoding_sender: Sender<(Buffer, Instant)>,
+    paused_total: Arc<Mutex<Duration>>,
+    fps: u32,
+    cancelled: Arc<AtomicBool>,
+    state: SessionStateHandle,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let result = catch_panic("batching", || {
+            let frame_interval = Duration::from_secs(1) / fps;
+            let min_interval = frame_interval - frame_interval / 4;
+            let mut last_sent: Option<Instant> = None;
+            let mut dropped = 0;
+            loop {
+                match recording_receiver.recv_timeout(Duration::from_millis(400)) {
+                    Ok((buffer, time)) => {
+                        let paused = *paused_total.lock().unwrap();
+                        let time = time.checked_sub(paused).unwrap_or(time);
+                        let too_soon = last_sent.map_or(false, |last_sent| {
+                            time.saturating_duration_since(last_sent) < min_interval
+                        });
+                        if too_soon {
+                            dropped += 1;
+                        } else {
+                            if encoding_sender.send((buffer, time)).is_err() {
+                                debug!("encoding channel closed, stop batching");
+                                recording_receiver.close();
+                                break;
+                            }
+                            last_sent = Some(time);
+                        }
+                    }
+                    Err(ReceiveErrorTimeout::Timeout) => {}
+                    Err(_) => break,
+                }
+                if cancelled.load(Ordering::Relaxed) {
+                    debug!("recording cancelled, stop batching");
+                    break;
+                }
+                // nothing comes in while paused, but the take is not over yet
+                let taking = matches!(state.get(), SessionState::Recording | SessionState::Paused);
+                if taking.not() && recording_receiver.is_empty() {
+                    recording_receiver.close();
+                    break;
+                }
+            }
+            debug!("{} frames dropped above {} fps", dropped, fps);
+            Ok(())
+        });
+        encoding_sender.close();
+        if let Err(e) = result {
+            // nobody is left to drain the recording channel
+            recording_receiver.close();
+            state.fail(e);
+        }
+    })
+}
+
+// Decodes and encodes frames until the encoding channel is closed, then writes the mp4 and
+// the thumbnail. A segmented take has moved all but its last segment out already, the entry
+// is the playlist of them. `on_finished` runs on the encoding thread once everything is on disk, or
+// with the error that stopped it, panics included.
+pub fn spawn_encoding<F>(job: EncodingJob, on_finished: F) -> JoinHandle<()>
+where
+    F: FnOnce(Result<(), DomainError>) + Send + 'static,
+{
+    let EncodingJob {
+        file_path_prefix,
+        file_name,
+        width,
+        height,
+        encoding_receiver,
+        audio,
+        container,
+        layout,
+        segmenting,
+        wav_master,
+        encoder,
+        metadata,
+        state,
+        cancelled,
+        scratch,
+    } = job;
+    let started = std::time::Instant::now();
+
+    let mut video_path = PathBuf::from(&file_path_prefix);
+    video_path.push(&file_name);
+    let part_path = scratch.video_path();
+
+    thread::spawn(move || {
+        let result = catch_panic("encoding", || {
+            let mut thumbnail_rgba: Vec<u8> = vec![];
+            let mut count = 0;
+
+            // This maintains order of frames while they are being encoded in multiple threads pool
+            // the data added to this queue will be consumed through the 'iter'.
+            let (queue, iter) = new();
+            let queue: Arc<OrdQueue<(Vec<u8>, Instant)>> = Arc::new(queue);
+
+            let mut worker_count = 2;
+            let mut pool = tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(worker_count)
+                .build()?;
+            // pools that were outgrown, dropping one would drop the frames still queued on it
+            // and leave a gap in the queue the encoder waits on forever
+            let mut outgrown = vec![];
+
+            let mut take = match (container, segmenting) {
+                (_, Some(segmenting)) => create_segmented(
+                    &scratch,
+                    &video_path,
+                    width,
+                    height,
+                    container,
+                    layout,
+                    &audio,
+                    segmenting,
+                )?,
+                (Container::Mp4, None) => create_mp4(&part_path, width, height, layout, &audio)?,
+                (Container::Matroska, None) => create_mkv(&part_path, width, height, &audio)?,
+            };
+            take.set_metadata(metadata)?;
+            let mut encoded = Ok(0);
+            let progress = ProgressTracker::new(state.clone());
+
+            rayon::scope(|s| {
+                s.spawn(|_| {
+                    while let Ok((buf, time)) = encoding_receiver.recv() {
+                        if cancelled.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        //pulling first frame to get the thumbnail
+                        if count == 0 {
+                            match decode_to_rgb(
+                                buf.buffer(),
+                                &buf.source_frame_format(),
+                                true,
+                                width as u32,
+                                height as u32,
+                            ) {
+                                Ok(rgba) => thumbnail_rgba.extend_from_slice(&rgba[..]),
+                                Err(e) => error!("Failed to decode thumbnail: {:?}", e),
+                            }
+                        }
+
+                        let queue = queue.clone();
+
+                        pool.spawn(async move {
+                            // an empty frame tells the encoder to stretch the previous one
+                            let yuv = match decode_to_rgb(
+                                buf.buffer(),
+                                &buf.source_frame_format(),
+                                true,
+                                width as u32,
+                                height as u32,
+                            ) {
+                                Ok(rgba) => rgba_to_yuv(&rgba[..], width, height),
+                                Err(e) => {
+                                    error!("Failed to decode frame {}: {:?}", count, e);
+                                    vec![]
+                                }
+                            };
+                            queue.push(count, (yuv, time)).unwrap_or_else(|e| {
+                                error!("queue push failed: {:?}", e);
+                            });
+                            // debug!("encoding to h264 send {}", count);
+                        });
+                        // debug!("encoded {} frames", count);
+                        count += 1;
+                        progress.frame_queued();
+                        // when threads for display when off, increase the thread count for encoding
+                        if state.get() == SessionState::Encoding {
+                            if worker_count == 2 {
+                                worker_count = 8;
+                                match tokio::runtime::Builder::new_multi_thread()
+                                    .worker_threads(worker_count)
+                                    .build()
+                                {
+                                    Ok(bigger_pool) => {
+                                        outgrown.push(std::mem::replace(&mut pool, bigger_pool))
+                                    }
+                                    Err(e) => error!("Failed to grow the encoding pool: {:?}", e),
+                                }
+                            }
+                        }
+                    }
+
+                    drop(queue);
+
+                    debug!("terminate receiving frames on recording");
+                });
+                s.spawn(|_| {
+                    //keep encoding to h264. this will be terminated when the queue is empty
+                    encoded = encode_video(
+                        iter, &mut take, width, height, &encoder, &cancelled, &progress,
+                    );
+                    debug!("terminate encoding frames on recording");
+                });
+            });
+
+            if cancelled.load(Ordering::Relaxed) {
+                // whatever is left in the pool only pushes to a queue nobody reads anymore
+                // the session removes the scratch directory once this thread is done
+                pool.shutdown_background();
+                outgrown
+                    .into_iter()
+                    .for_each(tokio::runtime::Runtime::shutdown_background);
+                if segmenting.is_some() {
+                    remove_segments(&video_path);
+                }
+                return Err(DomainError::Cancelled);
+            }
+
+            // every frame made it through the queue, so they are idle by now
+            pool.shutdown_timeout(std::time::Duration::from_secs(1));
+            for pool in outgrown {
+                pool.shutdown_timeout(std::time::Duration::from_secs(1));
+            }
+            let encoded = encoded?;
+            debug!(
+                "encoded {} of {} frames, time elapsed {}",
+                encoded,
+                count,
+                started.elapsed().as_secs()
+            );
+
+            state.transition(SessionState::Saving)?;
+            progress.muxing(encoded, take.bytes_written());
+            debug!("*********** saving... ***********");
+
+            // written first, so a failure leaves the whole take in the scratch directory
+            if wav_master {
+                write_wav(
+                    &scratch.pcm_path(),
+                    audio.sample_rate,
+                    audio.channels,
+                    &scratch.wav_path(),
+                )?;
+            }
+            // the video is on disk already, so is most of the audio
+            let video_path = finish_take(take, &part_path, &video_path)?;
+            if wav_master {
+                move_file(&scratch.wav_path(), &video_path.with_extension("wav"))?;
+            }
+
+            save_thumbnail(&file_path_prefix, &file_name, thumbnail_rgba, width, height)?;
+            scratch.remove();
+
+            debug!("*********** saved! ***********");
+            Ok(())
+        });
+        on_finished(result);
+    })
+}
+
+// Encodes the audio of a voice memo while the pcm file fills up, then writes the m4a and a
+// waveform of it as the thumbnail. With a visual track the audio waits for the video, which
+// can only be drawn once the loudest moment is known, and the entry is an mp4 with the
+// loudest frame as its thumbnail. `on_finished` as with `spawn_encoding`.
+pub fn spawn_voice_memo<F>(job: VoiceMemoJob, on_finished: F) -> JoinHandle<()>
+where
+    F: FnOnce(Result<(), DomainError>) + Send + 'static,
+{
+    let VoiceMemoJob {
+        file_path_prefix,
+        file_name,
+        audio,
+        visual,
+        wav_master,
+        metadata,
+        state,
+        cancelled,
+        scratch,
+    } = job;
+
+    let mut audio_path = PathBuf::from(&file_path_prefix);
+    audio_path.push(&file_name);
+    let part_path = scratch.video_path();
+
+    thread::spawn(move || {
+        let result = catch_panic("voice memo", || {
+            let mut take = match &visual {
+                None => create_m4a(&part_path, &audio)?,
+                Some(_) => create_mp4(
+                    &part_path,
+                    VISUAL_WIDTH,
+                    VISUAL_HEIGHT,
+                    Mp4Layout::Progressive,
+                    &Pcm {
+                        clock: None,
+                        ..audio.clone()
+                    },
+                )?,
+            };
+            take.set_metadata(metadata)?;
+            let progress = ProgressTracker::new(state.clone());
+            loop {
+                if cancelled.load(Ordering::Relaxed) {
+                    return Err(DomainError::Cancelled);
+                }
+                let taking = matches!(state.get(), SessionState::Recording | SessionState::Paused);
+                if visual.is_none() {
+                    take.write_audio()?;
+                }
+                if taking.not() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(400));
+            }
+            // `stop_recording` closes the pcm file right after the take leaves Recording
+            let closed = audio
+                .clock
+                .as_ref()
+                .is_none_or(|clock| clock.wait_closed(PCM_CLOSE_TIMEOUT));
+            if !closed {
+                error!("the pcm file was never closed, the memo may lose its last samples");
+            }
+
+            let (frames, (rgba, width, height)) = match &visual {
+                None => (0, waveform_thumbnail(&scratch.pcm_path(), audio.channels)?),
+                Some(visual) => {
+                    let profile =
+                        EncoderSettings::default().resolve(VISUAL_WIDTH, VISUAL_HEIGHT, VISUAL_FPS);
+                    let (frames, rgba) = encode_visual_track(
+                        visual, &audio, &mut take, &profile, &cancelled, &progress,
+                    )?;
+                    (frames, (rgba, VISUAL_WIDTH, VISUAL_HEIGHT))
+                }
+            };
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(DomainError::Cancelled);
+            }
+
+            state.transition(SessionState::Saving)?;
+            progress.muxing(frames, take.bytes_written());
+            debug!("*********** saving voice memo... ***********");
+
+            if wav_master {
+                write_wav(
+                    &scratch.pcm_path(),
+                    audio.sample_rate,
+                    audio.channels,
+                    &scratch.wav_path(),
+                )?;
+            }
+            let audio_path = finish_take(take, &part_path, &audio_path)?;
+            if wav_master {
+                move_file(&scratch.wav_path(), &audio_path.with_extension("wav"))?;
+            }
+
+            save_thumbnail(&file_path_prefix, &file_name, rgba, width, height)?;
+            scratch.remove();
+
+            debug!("*********** saved! ***********");
+            Ok(())
+        });
+        on_finished(result);
+    })
+}
+
+// The placeholder thumbnail of an entry without video: the loudest sample of every column,
+// scaled to the loudest of the take so quiet talk still shows. Silence is a flat line.
+pub fn waveform_thumbnail(
+    pcm_path: &Path,
+    channels: u16,
+) -> Result<(Vec<u8>, usize, usize), DomainError> {
+    let channels = channels.max(1) as u64;
+    let frames = pcm_path.metadata()?.len() / 2 / channels;
+    let per_column = (frames / THUMBNAIL_WIDTH as u64).max(1);
+    let mut peaks = vec![0u16; THUMBNAIL_WIDTH];
+
+    let mut pcm = File::open(pcm_path)?;
+    let mut bytes = vec![0u8; 64 * 1024];
+    let mut sample = 0u64;
+    let mut filled = 0;
+    loop {
+        let read = pcm.read(&mut bytes[filled..])?;
+        if read == 0 {
+            break;
+        }
+        filled += read;
+        let whole = filled / 2 * 2;
+        for le in bytes[..whole].chunks_exact(2) {
+            let column = (sample / channels / per_column).min(THUMBNAIL_WIDTH as u64 - 1);
+            let level = i16::from_le_bytes([le[0], le[1]]).unsigned_abs();
+            let peak = &mut peaks[column as usize];
+            *peak = (*peak).max(level);
+            sample += 1;
+        }
+        bytes.copy_within(whole..filled, 0);
+        filled -= whole;
+    }
+
+    let loudest = peaks.iter().copied().max().unwrap_or(0).max(1) as f64;
+    let middle = THUMBNAIL_HEIGHT / 2;
+    let reach = (middle - 16) as f64;
+    let mut rgba = Vec::with_capacity(THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 4);
+    for y in 0..THUMBNAIL_HEIGHT {
+        for peak in &peaks {
+            let half = (*peak as f64 / loudest * reach).round() as usize;
+            let pixel = match y.abs_diff(middle) <= half {
+                true => FOREGROUND,
+                false => BACKGROUND,
+            };
+            rgba.extend_from_slice(&pixel);
+        }
+    }
+    Ok((rgba, THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT))
+}
+
+pub fn save_thumbnail(
+    file_path_prefix: &str,
+    file_name: &str,
+    thumbnail_rgba: Vec<u8>,
+    width: usize,
+    height: usize,
+) -> Result<(), DomainError> {
+    if thumbnail_rgba.len() == 0 {
+        return Ok(());
+    }
+    // create an ImageBuffer from the RGBA data
+    let imgbuf = ImageBuffer::<Rgba<u8>, _>::from_raw(width as u32, height as u32, thumbnail_rgba)
+        .ok_or_else(|| {
+            DomainError::Encoding(format!("thumbnail does not match {}x{}", width, height))
+        })?;
+    // Convert the image buffer to a dynamic image
+    let image = DynamicImage::ImageRgba8(imgbuf);
+
+    // Resize the dynamic image
+    let resized_image = image.resize(
+        THUMBNAIL_WIDTH as u32,
+        THUMBNAIL_HEIGHT as u32,
+        image::imageops::FilterType::Lanczos3,
+    );
+
+    // Convert the resized dynamic image back to an image buffer
+    let resized_imgbuf = resized_image.into_rgba8();
+
+    let mut thumbnail_path = PathBuf::from(&file_path_prefix);
+    thumbnail_path.push(THUMBNAIL_DIR_NAME);
+    thumbnail_path.push(&file_name);
+    thumbnail_path.set_extension("png");
+
+    resized_imgbuf.save(thumbnail_path)?;
+    info!("thumbnail saved");
+    Ok(())
+}
@@ -0,0 +1,233 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use super::session::{SessionState, SessionStateHandle};
+
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressPhase {
+    Encoding,
+    Muxing,
+}
+
+impl ProgressPhase {
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            ProgressPhase::Encoding => "Encoding",
+            ProgressPhase::Muxing => "Muxing",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EncodingProgress {
+    pub phase: ProgressPhase,
+    pub frames_queued: usize,
+    pub frames_encoded: usize,
+    pub bytes_written: u64,
+    // over the last interval, not the whole take
+    pub fps: f64,
+    // only known once the recording has stopped and no more frames are coming in
+    pub eta: Option<Duration>,
+}
+
+// Counts frames going into and coming out of the encoder and publishes progress at most
+// once a second while the session is encoding, so long entries don't look hung.
+pub struct ProgressTracker {
+    // None when nobody is watching, as while recovering
+    state: Option<SessionStateHandle>,
+    queued: AtomicUsize,
+    // when and at which frame the last progress went out
+    last_published: Mutex<(Instant, usize)>,
+}
+
+impl ProgressTracker {
+    pub fn new(state: SessionStateHandle) -> Self {
+        Self::started_at(Some(state), Instant::now())
+    }
+
+    // Counts without publishing, for work outside a session like recovery.
+    pub fn silent() -> Self {
+        Self::started_at(None, Instant::now())
+    }
+
+    fn started_at(state: Option<SessionStateHandle>, start: Instant) -> Self {
+        Self {
+            state,
+            queued: AtomicUsize::new(0),
+            last_published: Mutex::new((start, 0)),
+        }
+    }
+
+    pub fn frame_queued(&self) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    // Called by the encoder after every frame.
+    pub fn frame_encoded(&self, frames_encoded: usize, bytes_written: u64) {
+        self.frame_encoded_at(Instant::now(), frames_encoded, bytes_written)
+    }
+
+    fn frame_encoded_at(&self, now: Instant, frames_encoded: usize, bytes_written: u64) {
+        let state = match &self.state {
+            Some(state) => state,
+            None => return,
+        };
+        let mut last_published = self.last_published.lock().unwrap();
+        let (published_at, published_frames) = *last_published;
+        let elapsed = now.saturating_duration_since(published_at);
+        if elapsed < PUBLISH_INTERVAL {
+            return;
+        }
+        *last_published = (now, frames_encoded);
+        // while recording the encoder just keeps up with the camera, nothing to report
+        if state.get() != SessionState::Encoding {
+            return;
+        }
+
+        let frames_queued = self.queued();
+        let fps = frames_encoded.saturating_sub(published_frames) as f64 / elapsed.as_secs_f64();
+        let eta = (fps > 0.0).then(|| {
+            Duration::from_secs_f64(frames_queued.saturating_sub(frames_encoded) as f64 / fps)
+        });
+        state.publish_progress(EncodingProgress {
+            phase: ProgressPhase::Encoding,
+            frames_queued,
+            frames_encoded,
+            bytes_written,
+            fps,
+            eta,
+        });
+    }
+
+    pub fn muxing(&self, frames_encoded: usize, bytes_written: u64) {
+        let state = match &self.state {
+            Some(state) => state,
+            None => return,
+        };
+        state.publish_progress(EncodingProgress {
+            phase: ProgressPhase::Muxing,
+            frames_queued: self.queued(),
+            frames_encoded,
+            bytes_written,
+            fps: 0.0,
+            eta: None,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kanal::Receiver;
+
+    use super::*;
+    use crate::domain::channel::UiEvent;
+
+    fn tracker(start: Instant) -> (ProgressTracker, SessionStateHandle, Receiver<UiEvent>) {
+        let (sender, receiver) = kanal::unbounded();
+        let state = SessionStateHandle::new(sender);
+        (
+            ProgressTracker::started_at(Some(state.clone()), start),
+            state,
+            receiver,
+        )
+    }
+
+    fn published(ui_event: &Receiver<UiEvent>) -> Vec<EncodingProgress> {
+        let mut progress = Vec::new();
+        while let Ok(Some(event)) = ui_event.try_recv() {
+            if let UiEvent::Progress(p) = event {
+                progress.push(p);
+            }
+        }
+        progress
+    }
+
+    fn encoding(state: &SessionStateHandle) {
+        for next in [
+            SessionState::Previewing,
+            SessionState::Recording,
+            SessionState::Encoding,
+        ] {
+            state.transition(next).unwrap();
+        }
+    }
+
+    #[test]
+    fn publishes_at_most_once_a_second() {
+        let start = Instant::now();
+        let (tracker, state, ui_event) = tracker(start);
+        encoding(&state);
+        let at = |millis| start + Duration::from_millis(millis);
+
+        tracker.frame_encoded_at(at(500), 10, 0);
+        assert!(published(&ui_event).is_empty());
+        tracker.frame_encoded_at(at(1000), 30, 0);
+        tracker.frame_encoded_at(at(1500), 45, 0);
+        tracker.frame_encoded_at(at(1999), 59, 0);
+        assert_eq!(published(&ui_event).len(), 1);
+        tracker.frame_encoded_at(at(2000), 60, 0);
+        let progress = published(&ui_event);
+        assert_eq!(progress.len(), 1);
+        assert_eq!(progress[0].frames_encoded, 60);
+        assert_eq!(progress[0].fps, 30.0);
+    }
+
+    #[test]
+    fn publishes_only_while_encoding() {
+        let start = Instant::now();
+        let (tracker, state, ui_event) = tracker(start);
+        let second = |n| start + Duration::from_secs(n);
+        state.transition(SessionState::Previewing).unwrap();
+        state.transition(SessionState::Recording).unwrap();
+
+        tracker.frame_encoded_at(second(1), 30, 0);
+        assert!(published(&ui_event).is_empty());
+        state.transition(SessionState::Encoding).unwrap();
+        tracker.frame_encoded_at(second(2), 60, 0);
+        assert_eq!(published(&ui_event).len(), 1);
+        state.transition(SessionState::Saving).unwrap();
+        tracker.frame_encoded_at(second(3), 90, 0);
+        assert!(published(&ui_event).is_empty());
+
+        // nobody is watching a silent tracker
+        let silent = ProgressTracker::started_at(None, start);
+        silent.frame_encoded_at(second(1), 30, 0);
+        assert!(published(&ui_event).is_empty());
+    }
+
+    #[test]
+    fn eta_is_what_is_left_at_the_current_rate() {
+        let start = Instant::now();
+        let (tracker, state, ui_event) = tracker(start);
+        encoding(&state);
+        for _ in 0..300 {
+            tracker.frame_queued();
+        }
+        let second = |n| start + Duration::from_secs(n);
+
+        tracker.frame_encoded_at(second(2), 100, 4096);
+        let progress = published(&ui_event).remove(0);
+        assert_eq!(progress.phase, ProgressPhase::Encoding);
+        assert_eq!(progress.frames_queued, 300);
+        assert_eq!(progress.bytes_written, 4096);
+        assert_eq!(progress.fps, 50.0);
+        assert_eq!(progress.eta, Some(Duration::from_secs(4)));
+
+        // a stalled encoder has no rate to go by
+        tracker.frame_encoded_at(second(3), 100, 4096);
+        let progress = published(&ui_event).remove(0);
+        assert_eq!(progress.fps, 0.0);
+        assert_eq!(progress.eta, None);
+    }
+}
@@ -1,202 +1,969 @@
-use log::debug;
-use minimp4::Mp4Muxer;
-use openh264::{
-    encoder::{Encoder, EncoderConfig, RateControlMode},
-    Error,
-};
+use log::{debug, error, info};
 use std::{
-    fs::File,
-    io::{self, Cursor, Read, Seek, SeekFrom, Write},
-    path::Path,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read},
+    path::{Path, PathBuf},
     sync::{atomic::AtomicBool, Arc, Mutex},
+    time::{Duration, Instant},
 };
 
-use crate::{
-    message_channel::audio_message_channel::Pcm, tools::image_processing::YUVBuf,
-    tools::ordqueue::OrdQueueIter,
+use crate::message_channel::audio_message_channel::Pcm;
+
+use super::{
+    aac::AacEncoder,
+    av_sync::{Resampler, PCM_CLOSE_TIMEOUT},
+    encoder_profile::EncoderProfile,
+    error::DomainError,
+    metadata::EntryMetadata,
+    mkv::{scan_clusters, MkvWriter},
+    mp4::{scan_fragments, AacConfig, Mp4Layout, Mp4Writer, VideoConfig, VIDEO_TIMESCALE},
+    progress::ProgressTracker,
+    scratch::{move_file, ScratchDir},
+    segments::{segment_dir, segment_file_name, write_playlist, Segment, Segmenting},
+    video_encoder::{video_encoder, EncodedFrame, VideoEncoder},
 };
 
+// `recording` is what the texture and audio threads look at, it is off while paused.
 pub struct RecordingService {
     pub started: std::time::Instant,
     pub recording: Arc<AtomicBool>,
     pub time_elapsed: f64,
-    pub writing_state: Arc<Mutex<WritingState>>,
+    paused_at: Option<Instant>,
+    // shared with the batching thread, which shifts frames back by it to close the gaps
+    pub paused_total: Arc<Mutex<Duration>>,
+}
+
+impl RecordingService {
+    pub fn new(recording: Arc<AtomicBool>) -> Self {
+        Self {
+            started: std::time::Instant::now(),
+            recording,
+            time_elapsed: 0.0,
+            paused_at: None,
+            paused_total: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    pub fn start(&mut self) {
+        self.started = std::time::Instant::now();
+        self.time_elapsed = 0.0;
+        self.paused_at = None;
+        *self.paused_total.lock().unwrap() = Duration::ZERO;
+        self.recording
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn pause(&mut self) {
+        self.recording
+            .store(false, std::sync::atomic::Ordering::Relaxed);
+        self.paused_at = Some(Instant::now());
+        self.time_elapsed = self.elapsed().as_secs_f64();
+    }
+
+    pub fn resume(&mut self) {
+        // the total has to be right before any new frame comes in
+        if let Some(paused_at) = self.paused_at.take() {
+            *self.paused_total.lock().unwrap() += paused_at.elapsed();
+        }
+        self.recording
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn stop(&mut self) {
+        self.recording
+            .store(false, std::sync::atomic::Ordering::Relaxed);
+        if let Some(paused_at) = self.paused_at.take() {
+            *self.paused_total.lock().unwrap() += paused_at.elapsed();
+        }
+        self.time_elapsed = self.elapsed().as_secs_f64();
+    }
+
+    // recorded time, pauses excluded
+    pub fn elapsed(&self) -> Duration {
+        let now = self.paused_at.unwrap_or_else(Instant::now);
+        now.duration_since(self.started)
+            .saturating_sub(*self.paused_total.lock().unwrap())
+    }
+}
+
+pub type Mp4File = Mp4Writer<BufWriter<File>>;
+pub type MkvFile = MkvWriter<BufWriter<File>>;
+
+fn ticks(offset: Duration) -> u64 {
+    (offset.as_secs_f64() * VIDEO_TIMESCALE as f64).round() as u64
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum WritingState {
-    Encoding,
-    Saving,
-    Idle,
+// The file format of a take with video. Voice memos are always mp4, or m4a.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Mp4,
+    // plays up to its last whole cluster when cut off, without any recovery
+    Matroska,
 }
 
-impl WritingState {
+impl Container {
     pub fn to_str(&self) -> &'static str {
         match self {
-            WritingState::Encoding => "Encoding",
-            WritingState::Saving => "Saving",
-            WritingState::Idle => "Idle",
+            Container::Mp4 => "mp4",
+            Container::Matroska => "matroska",
         }
     }
-    pub fn from_str(s: &str) -> Self {
-        match s {
-            "Encoding" => WritingState::Encoding,
-            "Saving" => WritingState::Saving,
-            "Idle" => WritingState::Idle,
-            _ => WritingState::Idle,
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "mp4" => Some(Container::Mp4),
+            "matroska" => Some(Container::Matroska),
+            _ => None,
         }
     }
-}
 
-impl PartialEq for WritingState {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (WritingState::Encoding, WritingState::Encoding) => true,
-            (WritingState::Saving, WritingState::Saving) => true,
-            (WritingState::Idle, WritingState::Idle) => true,
-            _ => false,
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Container::Mp4 => "mp4",
+            Container::Matroska => "mkv",
         }
     }
 }
 
-impl RecordingService {
-    pub fn new(recording: Arc<AtomicBool>) -> Self {
-        Self {
-            started: std::time::Instant::now(),
-            recording,
-            time_elapsed: 0.0,
-            writing_state: Arc::new(Mutex::new(WritingState::Idle)),
+// The writer of a take, both take the same video samples and aac access units.
+enum Muxer {
+    Mp4(Mp4File),
+    Matroska(MkvFile),
+}
+
+impl Muxer {
+    fn set_video(&mut self, config: VideoConfig) -> Result<(), DomainError> {
+        match self {
+            Muxer::Mp4(mp4) => mp4.set_video(config),
+            Muxer::Matroska(mkv) => mkv.set_video(config),
         }
     }
 
-    pub fn start(&mut self) {
-        self.recording
-            .store(true, std::sync::atomic::Ordering::Relaxed);
-        self.started = std::time::Instant::now();
-        self.time_elapsed = 0.0;
+    fn write_video(&mut self, sample: &[u8], sync: bool, duration: u32) -> Result<(), DomainError> {
+        match self {
+            Muxer::Mp4(mp4) => mp4.write_video(sample, sync, duration),
+            Muxer::Matroska(mkv) => mkv.write_video(sample, sync, duration),
+        }
     }
 
-    pub fn stop(&mut self) {
-        self.recording
-            .store(false, std::sync::atomic::Ordering::Relaxed);
-        self.time_elapsed = self.started.elapsed().as_secs_f64();
+    fn set_audio(&mut self, config: AacConfig) -> Result<(), DomainError> {
+        match self {
+            Muxer::Mp4(mp4) => mp4.set_audio(config),
+            Muxer::Matroska(mkv) => mkv.set_audio(config),
+        }
+    }
+
+    fn write_audio(&mut self, access_unit: &[u8], duration: u32) -> Result<(), DomainError> {
+        match self {
+            Muxer::Mp4(mp4) => mp4.write_audio(access_unit, duration),
+            Muxer::Matroska(mkv) => mkv.write_audio(access_unit, duration),
+        }
+    }
+
+    fn set_metadata(&mut self, metadata: EntryMetadata) -> Result<(), DomainError> {
+        match self {
+            Muxer::Mp4(mp4) => mp4.set_metadata(metadata),
+            // Matroska takes go without, the app keeps it in its database for them
+            Muxer::Matroska(_) if metadata.is_empty() => Ok(()),
+            Muxer::Matroska(_) => Err(DomainError::Muxing(
+                "a Matroska take carries no metadata".to_string(),
+            )),
+        }
+    }
+
+    fn bytes_written(&self) -> u64 {
+        match self {
+            Muxer::Mp4(mp4) => mp4.bytes_written(),
+            Muxer::Matroska(mkv) => mkv.bytes_written(),
+        }
+    }
+
+    // another file of the same kind, with the same metadata
+    fn create(&self, path: &Path, width: usize, height: usize) -> Result<Self, DomainError> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match self {
+            Muxer::Mp4(mp4) => {
+                let mut next = Mp4Writer::new(file, width as u32, height as u32, mp4.layout())?;
+                if let Some(metadata) = mp4.metadata() {
+                    next.set_metadata(metadata.clone())?;
+                }
+                Muxer::Mp4(next)
+            }
+            Muxer::Matroska(_) => {
+                Muxer::Matroska(MkvWriter::new(file, width as u32, height as u32)?)
+            }
+        })
     }
 
-    pub fn set_writing_state(&mut self, state: WritingState) {
-        let mut state_ = self.writing_state.lock().unwrap();
-        *state_ = state;
+    fn finish(self) -> Result<BufWriter<File>, DomainError> {
+        match self {
+            Muxer::Mp4(mp4) => mp4.finish(),
+            Muxer::Matroska(mkv) => mkv.finish(),
+        }
     }
 }
 
-fn encoder(width: u32, height: u32) -> Result<Encoder, Error> {
-    let config = EncoderConfig::new(width, height)
-        .rate_control_mode(RateControlMode::Timestamp)
-        .enable_skip_frame(false)
-        .set_bitrate_bps(360000)
-        .debug(false);
+// The file of a take. The audio follows the video into it as the pcm file fills up, so
+// fragments and clusters carry both and little is left to encode once the video is done.
+pub struct TakeFile {
+    muxer: Muxer,
+    audio: PcmEncoder,
+    // the extension of the entry
+    extension: &'static str,
+    // None for a take in one file
+    segments: Option<Segments>,
+}
 
-    Encoder::with_config(config)
+impl TakeFile {
+    fn create(
+        muxer: Muxer,
+        audio: PcmEncoder,
+        extension: &'static str,
+    ) -> Result<Self, DomainError> {
+        let mut take = Self {
+            muxer,
+            audio,
+            extension,
+            segments: None,
+        };
+        take.muxer.set_audio(take.audio.config().clone())?;
+        Ok(take)
+    }
+
+    // The encoder's codec config, before the first frame.
+    pub fn set_video(&mut self, config: VideoConfig) -> Result<(), DomainError> {
+        if let Some(segments) = self.segments.as_mut() {
+            segments.video = Some(config.clone());
+        }
+        self.muxer.set_video(config)
+    }
+
+    pub fn write_video(
+        &mut self,
+        sample: &[u8],
+        sync: bool,
+        duration: u32,
+    ) -> Result<(), DomainError> {
+        self.muxer.write_video(sample, sync, duration)?;
+        if let Some(segments) = self.segments.as_mut() {
+            segments.written += duration as u64;
+        }
+        self.encode_audio(false)
+    }
+
+    // What goes into the file's 'udta', before anything is written. Every segment gets it.
+    pub fn set_metadata(&mut self, metadata: EntryMetadata) -> Result<(), DomainError> {
+        self.muxer.set_metadata(metadata)
+    }
+
+    // Whatever the audio thread has written by now, for a take without video.
+    pub fn write_audio(&mut self) -> Result<(), DomainError> {
+        self.encode_audio(false)
+    }
+
+    // Capture time of the first frame, the audio is lined up against it.
+    pub fn start_video(&mut self, at: Instant) {
+        self.audio.start_video(at);
+    }
+
+    // of every segment so far
+    pub fn bytes_written(&self) -> u64 {
+        let segments = self.segments.as_ref();
+        let closing = segments
+            .and_then(|segments| segments.closing.as_ref())
+            .map_or(0, |closing| closing.muxer.bytes_written());
+        segments.map_or(0, |segments| segments.bytes_done) + closing + self.muxer.bytes_written()
+    }
+
+    // The segment the take is in, counted from 1. A take in one file is always in the first.
+    pub fn segment(&self) -> usize {
+        self.segments.as_ref().map_or(1, |segments| segments.index)
+    }
+
+    // A keyframe at `at` ticks would start the next segment.
+    pub fn segment_due(&self, at: u64) -> bool {
+        self.segments.as_ref().is_some_and(|segments| {
+            let elapsed = at.saturating_sub(segments.started);
+            segments.segmenting.due(elapsed, self.muxer.bytes_written())
+        })
+    }
+
+    // Starts the next segment with the keyframe at `at` ticks, the video written so far ends
+    // there. The segment before stays open until the audio has caught up with its end.
+    pub fn next_segment(&mut self, at: u64) -> Result<(), DomainError> {
+        let segments = match self.segments.as_mut() {
+            Some(segments) => segments,
+            None => return Ok(()),
+        };
+        if segments.closing.is_some() {
+            // the audio is a whole segment behind, it goes on in the next one
+            error!("segment {} never got its audio", segments.index - 1);
+            segments.close(&mut self.audio)?;
+        }
+        let video = segments.video.clone().ok_or_else(|| {
+            DomainError::Muxing("a segment can't start before the video".to_string())
+        })?;
+        let part_path = segments.scratch.segment_path(segments.index + 1);
+        let mut muxer = self
+            .muxer
+            .create(&part_path, segments.width, segments.height)?;
+        muxer.set_audio(self.audio.config().clone())?;
+        muxer.set_video(video)?;
+        debug!("segment {} starts at {} ticks", segments.index + 1, at);
+
+        segments.closing = Some(ClosingSegment {
+            muxer: std::mem::replace(&mut self.muxer, muxer),
+            index: segments.index,
+            started: segments.started,
+            end: at,
+        });
+        segments.index += 1;
+        segments.started = at;
+        segments.written = at;
+        self.encode_audio(false)
+    }
+
+    // With segments the audio never goes past the video written so far, a segment has to
+    // end on both at once. `finishing` reads up to the end of the pcm file.
+    fn encode_audio(&mut self, finishing: bool) -> Result<(), DomainError> {
+        let segments = match self.segments.as_mut() {
+            Some(segments) => segments,
+            None => {
+                return self
+                    .audio
+                    .encode_available(&mut self.muxer, None, finishing)
+            }
+        };
+        if let Some(closing) = segments.closing.as_mut() {
+            let end = self.audio.frames_at(closing.end);
+            self.audio
+                .encode_available(&mut closing.muxer, Some(end), finishing)?;
+            if self.audio.produced() < end && !finishing {
+                return Ok(());
+            }
+            segments.close(&mut self.audio)?;
+        }
+        let written = self.audio.frames_at(segments.written);
+        self.audio
+            .encode_available(&mut self.muxer, Some(written), finishing)
+    }
 }
 
-pub fn encode_to_h264(
-    mut yuv_iter: OrdQueueIter<Vec<u8>>,
-    buffer_file_name: &str,
+// Where the files of a take split by `segmenting` go: `<file_name>_001.mp4` and on in a
+// directory named like the entry, which is a playlist of them.
+struct Segments {
+    segmenting: Segmenting,
+    scratch: ScratchDir,
+    entry_path: PathBuf,
+    file_name: String,
     width: usize,
     height: usize,
-) {
-    debug!("encoding to h264");
-    let mut inner_count = 0;
-
-    let mut encoder = encoder(width as u32, height as u32).unwrap();
+    // every segment starts with it, the encoder puts it out once
+    video: Option<VideoConfig>,
+    // of the current segment, counted from 1
+    index: usize,
+    // ticks of its first frame, and of the end of the video written so far
+    started: u64,
+    written: u64,
+    closing: Option<ClosingSegment>,
+    finished: Vec<Segment>,
+    bytes_done: u64,
+}
 
-    let started = std::time::Instant::now();
-    let mut timer = std::time::Instant::now();
+// A segment whose video is complete, waiting for the rest of its audio.
+struct ClosingSegment {
+    muxer: Muxer,
+    index: usize,
+    started: u64,
+    end: u64,
+}
 
-    // if 'buffer_file_name' exists, delete it
-    if Path::new(buffer_file_name).exists() {
-        std::fs::remove_file(buffer_file_name).unwrap();
+impl Segments {
+    // Finishes the closing segment with the audio it got and moves it to the data directory.
+    fn close(&mut self, audio: &mut PcmEncoder) -> Result<(), DomainError> {
+        let ClosingSegment {
+            mut muxer,
+            index,
+            started,
+            end,
+        } = match self.closing.take() {
+            Some(closing) => closing,
+            None => return Ok(()),
+        };
+        audio.end_segment(&mut muxer)?;
+        self.bytes_done += muxer.bytes_written();
+        self.add(muxer, index, (end - started) as f64, audio.input_position())
     }
-    let file = File::create(buffer_file_name).unwrap();
-    let mut buffered_file = io::BufWriter::new(file);
-    while let Some(el) = yuv_iter.next() {
-        inner_count += 1;
-        if timer.elapsed().as_secs() > 3 {
-            debug!("encoding...");
-            timer = std::time::Instant::now();
-        }
-        let yuv = YUVBuf {
-            yuv: el,
-            width,
-            height,
+
+    // Moves segment `index` of `ticks` into the segment directory and notes it down.
+    fn add(
+        &mut self,
+        muxer: Muxer,
+        index: usize,
+        ticks: f64,
+        pcm_end: u64,
+    ) -> Result<(), DomainError> {
+        let extension = match muxer {
+            Muxer::Mp4(_) => Container::Mp4.extension(),
+            Muxer::Matroska(_) => Container::Matroska.extension(),
+        };
+        let dir = segment_dir(&self.entry_path);
+        fs::create_dir_all(&dir)?;
+        let segment = Segment {
+            file_name: segment_file_name(&self.file_name, index, extension),
+            seconds: ticks / VIDEO_TIMESCALE as f64,
+            pcm_end,
         };
+        finish_file(
+            muxer,
+            &self.scratch.segment_path(index),
+            &dir.join(&segment.file_name),
+        )?;
+        self.scratch.add_segment(&segment)?;
+        debug!("segment {} saved, {:.1}s", index, segment.seconds);
+        self.finished.push(segment);
+        Ok(())
+    }
+}
+
+// The file is written in the scratch directory of the take and moved to the data directory
+// by `finish_take` once it's complete, so the app never lists a half written entry.
+// `audio.path` is the pcm file of the take, it has to exist already.
+pub fn create_mp4<P: AsRef<Path>>(
+    part_path: P,
+    width: usize,
+    height: usize,
+    layout: Mp4Layout,
+    audio: &Pcm,
+) -> Result<TakeFile, DomainError> {
+    let audio = PcmEncoder::open(audio)?;
+    let file = File::create(part_path)?;
+    let mp4 = Mp4Writer::new(BufWriter::new(file), width as u32, height as u32, layout)?;
+    TakeFile::create(Muxer::Mp4(mp4), audio, Container::Mp4.extension())
+}
 
-        let bitstream = encoder.encode(&yuv).unwrap();
-        for l in 0..bitstream.num_layers() {
-            let layer = bitstream.layer(l).unwrap();
-            for n in 0..layer.nal_count() {
-                let nal = layer.nal_unit(n).unwrap();
+// `create_mp4` for a Matroska file.
+pub fn create_mkv<P: AsRef<Path>>(
+    part_path: P,
+    width: usize,
+    height: usize,
+    audio: &Pcm,
+) -> Result<TakeFile, DomainError> {
+    let audio = PcmEncoder::open(audio)?;
+    let file = File::create(part_path)?;
+    let mkv = MkvWriter::new(BufWriter::new(file), width as u32, height as u32)?;
+    TakeFile::create(Muxer::Matroska(mkv), audio, Container::Matroska.extension())
+}
 
-                buffered_file.write_all(nal).unwrap();
-            }
+// `create_mp4` or `create_mkv` for a take split into segments by `segmenting`. They are
+// written in `scratch` and moved next to `file_path` one by one as they are complete.
+#[allow(clippy::too_many_arguments)]
+pub fn create_segmented<P: AsRef<Path>>(
+    scratch: &ScratchDir,
+    file_path: P,
+    width: usize,
+    height: usize,
+    container: Container,
+    layout: Mp4Layout,
+    audio: &Pcm,
+    segmenting: Segmenting,
+) -> Result<TakeFile, DomainError> {
+    let part_path = scratch.segment_path(1);
+    let mut take = match container {
+        Container::Mp4 => create_mp4(&part_path, width, height, layout, audio)?,
+        Container::Matroska => create_mkv(&part_path, width, height, audio)?,
+    };
+    let file_path = file_path.as_ref();
+    take.segments = Some(Segments {
+        segmenting,
+        scratch: scratch.clone(),
+        entry_path: file_path.to_path_buf(),
+        file_name: file_path
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+        width,
+        height,
+        video: None,
+        index: 1,
+        started: 0,
+        written: 0,
+        closing: None,
+        finished: vec![],
+        bytes_done: 0,
+    });
+    Ok(take)
+}
+
+// A voice memo, written and moved like `create_mp4` does. There's no video to line the
+// audio up with, the pcm file is taken as it is.
+pub fn create_m4a<P: AsRef<Path>>(part_path: P, audio: &Pcm) -> Result<TakeFile, DomainError> {
+    let audio = PcmEncoder::open(&Pcm {
+        clock: None,
+        ..audio.clone()
+    })?;
+    let file = File::create(part_path)?;
+    let mp4 = Mp4Writer::audio_only(BufWriter::new(file))?;
+    TakeFile::create(Muxer::Mp4(mp4), audio, "m4a")
+}
+
+// Encodes frames straight into `take` as they come out of the queue, with the encoder for
+// `profile.codec`. A frame lasts until the capture time of the next one, so each is written
+// once its successor shows up; the last one gets the nominal frame duration. A segmented
+// take gets a keyframe once a segment is due and moves on to the next one with it.
+// Stops early, dropping `yuv_iter`, once `cancelled` is set.
+// Returns the number of frames encoded.
+pub fn encode_video<I: Iterator<Item = (Vec<u8>, Instant)>>(
+    mut yuv_iter: I,
+    take: &mut TakeFile,
+    width: usize,
+    height: usize,
+    profile: &EncoderProfile,
+    cancelled: &AtomicBool,
+    progress: &ProgressTracker,
+) -> Result<usize, DomainError> {
+    let codec = profile.codec.to_str();
+    debug!("encoding to {}", codec);
+    let mut inner_count = 0;
+
+    let mut encoder = video_encoder(width, height, profile)?;
+    let mut writer = FrameWriter::default();
+    // ticks are counted from the first frame, rounding doesn't add up over a long take
+    let mut first: Option<Instant> = None;
+    // the segment a keyframe was asked for already, it may take a few frames to come out
+    let mut forced = None;
+
+    let started = std::time::Instant::now();
+    while let Some((el, time)) = yuv_iter.next() {
+        if cancelled.load(std::sync::atomic::Ordering::Relaxed) {
+            debug!("encoding {} cancelled, count {}", codec, inner_count);
+            return Ok(inner_count);
         }
-        buffered_file.flush().unwrap();
+        inner_count += 1;
+        // a frame that failed to decode arrives empty, the previous one lasts until the next
+        if el.is_empty() {
+            error!("skipping frame {}, it did not decode", inner_count);
+            continue;
+        }
+        let first = *first.get_or_insert_with(|| {
+            take.start_video(time);
+            time
+        });
+        let at = ticks(time.saturating_duration_since(first));
+        if forced != Some(take.segment()) && take.segment_due(at) {
+            encoder.force_keyframe();
+            forced = Some(take.segment());
+        }
+        let frames = encoder.encode(el, at)?;
+        writer.write(take, encoder.as_ref(), frames)?;
+        progress.frame_encoded(inner_count, take.bytes_written());
     }
+    let frames = encoder.flush()?;
+    writer.write(take, encoder.as_ref(), frames)?;
+    writer.finish(
+        take,
+        encoder.as_ref(),
+        (VIDEO_TIMESCALE / profile.fps) as u64,
+    )?;
 
     debug!(
-        "encoding h264 done: {:?}, count {}",
+        "encoding {} done: {:?}, count {}, {} bytes",
+        codec,
         started.elapsed(),
         inner_count,
+        take.bytes_written(),
     );
+    Ok(inner_count)
 }
 
-pub fn to_mp4<P: AsRef<Path>>(
-    buf_h264: &[u8],
-    file_path: P,
-    frame_rate: u32,
-    audio: Pcm,
-    width: u32,
-    height: u32,
-) -> Result<(), std::io::Error> {
-    let mut video_buffer = Cursor::new(Vec::new());
-    let mut mp4muxer = Mp4Muxer::new(&mut video_buffer);
-    mp4muxer.init_video(width as i32, height as i32, false, "diary");
-    mp4muxer.init_audio(
-        audio.bit_rate.try_into().unwrap(),
-        audio.sample_rate,
-        audio.channels.into(),
-    );
+// Holds each encoded frame back until the next one tells how long it lasts.
+#[derive(Default)]
+struct FrameWriter {
+    pending: Option<EncodedFrame>,
+    video_set: bool,
+}
 
-    debug!(
-        "audio :: sample_rate: {}, channles: {}, bit_rate: {},",
-        &audio.sample_rate, &audio.channels, &audio.bit_rate
-    );
+impl FrameWriter {
+    fn write(
+        &mut self,
+        take: &mut TakeFile,
+        encoder: &dyn VideoEncoder,
+        frames: Vec<EncodedFrame>,
+    ) -> Result<(), DomainError> {
+        for frame in frames {
+            if let Some(pending) = self.pending.take() {
+                let duration = frame.timestamp.saturating_sub(pending.timestamp);
+                self.write_frame(take, encoder, pending, duration)?;
+            }
+            self.pending = Some(frame);
+        }
+        Ok(())
+    }
+
+    fn write_frame(
+        &mut self,
+        take: &mut TakeFile,
+        encoder: &dyn VideoEncoder,
+        frame: EncodedFrame,
+        duration: u64,
+    ) -> Result<(), DomainError> {
+        if !self.video_set {
+            let config = encoder.codec_config().ok_or_else(|| {
+                DomainError::Encoding("the first frame came without a codec config".to_string())
+            })?;
+            take.set_video(config)?;
+            self.video_set = true;
+        }
+        if frame.keyframe && take.segment_due(frame.timestamp) {
+            take.next_segment(frame.timestamp)?;
+        }
+        let duration = duration.clamp(1, u32::MAX as u64) as u32;
+        take.write_video(&frame.data, frame.keyframe, duration)
+    }
+
+    fn finish(
+        mut self,
+        take: &mut TakeFile,
+        encoder: &dyn VideoEncoder,
+        duration: u64,
+    ) -> Result<(), DomainError> {
+        match self.pending.take() {
+            Some(frame) => self.write_frame(take, encoder, frame, duration),
+            None => Ok(()),
+        }
+    }
+}
+
+// Encodes what is left of the audio, finishes the file and moves it from `part_path` to
+// `file_path` with the extension of its format, which is returned. A segmented take writes
+// its last segments and the playlist instead, its parts are where `create_segmented` put them.
+pub fn finish_take<P: AsRef<Path>, Q: AsRef<Path>>(
+    mut take: TakeFile,
+    part_path: P,
+    file_path: Q,
+) -> Result<PathBuf, DomainError> {
+    let mut segments = match take.segments.take() {
+        Some(segments) => segments,
+        None => {
+            let TakeFile {
+                mut muxer,
+                audio,
+                extension,
+                ..
+            } = take;
+            audio.finish(&mut muxer)?;
+            let file_path = file_path.as_ref().with_extension(extension);
+            finish_file(muxer, part_path.as_ref(), &file_path)?;
+            return Ok(file_path);
+        }
+    };
+    if let Some(closing) = segments.closing.as_mut() {
+        let end = take.audio.frames_at(closing.end);
+        take.audio
+            .encode_available(&mut closing.muxer, Some(end), true)?;
+        segments.close(&mut take.audio)?;
+    }
+    // the last one takes the rest of the audio, however long it is
+    let TakeFile {
+        mut muxer, audio, ..
+    } = take;
+    let pcm_end = audio.finish(&mut muxer)?;
+    let (index, ticks) = (segments.index, segments.written - segments.started);
+    segments.add(muxer, index, ticks as f64, pcm_end)?;
+    write_playlist(&segments.entry_path, &segments.finished)
+}
 
-    debug!("frame_rate: {}", frame_rate);
-
-    // read data from file temp.pcm
-    let audio_data = {
-        let mut file = File::open("temp.pcm").unwrap();
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer).unwrap();
-        let mut data = Vec::<u8>::new();
-        let mut index = 0;
-    
-        while index < buffer.len() {
-            let value = u16::from_le_bytes([buffer[index], buffer[index + 1]]);
-            data.extend(&value.to_le_bytes());
-            index += 2;
-        }
-        data
+// Finishes `muxer` and moves its file from `part_path` to `file_path`. A fragmented mp4 or a
+// Matroska file is read back first to make sure every fragment or cluster is whole.
+fn finish_file(muxer: Muxer, part_path: &Path, file_path: &Path) -> Result<(), DomainError> {
+    let fragmented = match &muxer {
+        Muxer::Mp4(mp4) => matches!(mp4.layout(), Mp4Layout::Fragmented { .. }),
+        Muxer::Matroska(_) => false,
     };
+    let matroska = matches!(muxer, Muxer::Matroska(_));
+    let file = muxer.finish()?;
+    file.into_inner()
+        .map_err(|e| DomainError::Io(e.into_error()))?
+        .sync_all()?;
+
+    if fragmented {
+        let scan = scan_fragments(BufReader::new(File::open(part_path)?))?;
+        if !scan.is_complete() {
+            return Err(DomainError::Muxing(format!(
+                "only {} of {} bytes are in whole fragments",
+                scan.playable_len, scan.file_len
+            )));
+        }
+        debug!(
+            "{} fragments, {} video and {} audio samples",
+            scan.fragments, scan.video_samples, scan.audio_samples
+        );
+    }
+    if matroska {
+        let scan = scan_clusters(BufReader::new(File::open(part_path)?))?;
+        if !scan.is_complete() {
+            return Err(DomainError::Muxing(format!(
+                "only {} of {} bytes are in whole elements",
+                scan.playable_len, scan.file_len
+            )));
+        }
+        debug!(
+            "{} clusters, {} video and {} audio blocks",
+            scan.clusters, scan.video_blocks, scan.audio_blocks
+        );
+    }
+
+    move_file(part_path, file_path)
+}
+
+// Encodes the pcm file of a take to aac while the audio thread is still appending to it,
+// one whole aac frame at a time. With a clock the samples are resampled onto the video's
+// timeline, see `Resampler`.
+struct PcmEncoder {
+    aac: AacEncoder,
+    pcm: File,
+    bytes: Vec<u8>,
+    resampler: Resampler,
+    // samples per channel out of the encoder, which starts over with every segment at
+    // `segment_start` frames out of the resampler
+    encoded: u64,
+    segment_start: u64,
+}
+
+impl PcmEncoder {
+    fn open(audio: &Pcm) -> Result<Self, DomainError> {
+        let bit_rate = audio
+            .bit_rate
+            .try_into()
+            .map_err(|_| DomainError::Muxing(format!("invalid bit rate {}", audio.bit_rate)))?;
+        debug!(
+            "audio :: sample_rate: {}, channles: {}, bit_rate: {},",
+            &audio.sample_rate, &audio.channels, &audio.bit_rate
+        );
+        let aac = AacEncoder::new(audio.sample_rate, audio.channels, bit_rate)?;
+        let pcm_path = audio
+            .path
+            .as_ref()
+            .ok_or_else(|| DomainError::Muxing("the take has no pcm file".to_string()))?;
+        let resampler = Resampler::new(
+            audio.sample_rate,
+            audio.channels,
+            audio.clock.clone(),
+            aac.frame_samples(),
+        );
+        Ok(Self {
+            aac,
+            pcm: File::open(pcm_path)?,
+            bytes: vec![],
+            resampler,
+            encoded: 0,
+            segment_start: 0,
+        })
+    }
+
+    fn config(&self) -> &AacConfig {
+        self.aac.config()
+    }
+
+    // Capture time of the first video frame, the audio is lined up against it.
+    fn start_video(&mut self, at: Instant) {
+        self.resampler.start_video(at);
+    }
+
+    fn produced(&self) -> u64 {
+        self.resampler.produced()
+    }
 
-    mp4muxer.write_video_with_audio(buf_h264, frame_rate, &audio_data);
+    // Every whole frame that can be resampled by now, up to `until` frames into the output,
+    // the rest waits for the next call. `finishing` takes the input up to its end.
+    fn encode_available(
+        &mut self,
+        muxer: &mut Muxer,
+        until: Option<u64>,
+        finishing: bool,
+    ) -> Result<(), DomainError> {
+        if self.resampler.waiting_for_video() && !finishing {
+            return Ok(());
+        }
+        self.read_available()?;
+        while let Some(frame) = self.resampler.next_frame(finishing, until) {
+            self.encode_frame(muxer, frame)?;
+        }
+        Ok(())
+    }
 
-    mp4muxer.close();
+    // The output frame `ticks` into the video.
+    fn frames_at(&self, ticks: u64) -> u64 {
+        ticks * self.config().sample_rate as u64 / VIDEO_TIMESCALE as u64
+    }
 
-    video_buffer.seek(SeekFrom::Start(0)).unwrap();
-    let mut video_bytes = Vec::new();
-    video_buffer.read_to_end(&mut video_bytes).unwrap();
+    // the input frame the next output frame is taken from
+    fn input_position(&self) -> u64 {
+        self.resampler.input_position()
+    }
+
+    // Ends the segment's audio in `muxer` with the frames produced so far, the next segment
+    // starts on an encoder of its own.
+    fn end_segment(&mut self, muxer: &mut Muxer) -> Result<(), DomainError> {
+        self.flush(muxer)?;
+        let config = self.config().clone();
+        self.aac = AacEncoder::new(config.sample_rate, config.channels, config.bit_rate)?;
+        self.encoded = 0;
+        self.segment_start = self.produced();
+        Ok(())
+    }
 
-    let file_path = file_path.as_ref().with_extension("mp4");
-    std::fs::write(file_path, &video_bytes)
+    // The rest of the file. Returns the input frame it ended at.
+    fn finish(mut self, muxer: &mut Muxer) -> Result<u64, DomainError> {
+        // the take is stopped before its audio stream is, the end may still be on its way
+        if !self.resampler.wait_for_input(PCM_CLOSE_TIMEOUT) {
+            error!("the pcm file was never closed, the take may lose its last samples");
+        }
+        self.encode_available(muxer, None, true)?;
+        self.flush(muxer)?;
+        if let Some((offset, drift_ppm)) = self.resampler.clock_report() {
+            info!(
+                "audio started {:.1} ms after the video, drift {:.0} ppm",
+                offset * 1000.0,
+                drift_ppm
+            );
+        }
+        Ok(self.input_position())
+    }
+
+    // The encoder lags behind by `priming` samples, silence pushes the tail out.
+    fn flush(&mut self, muxer: &mut Muxer) -> Result<(), DomainError> {
+        let wanted = self.produced() - self.segment_start + self.config().priming as u64;
+        let mut frame = self.resampler.partial_frame();
+        let mut flushing = 0;
+        while self.encoded < wanted && flushing < 8 {
+            frame.resize(self.aac.frame_samples(), 0);
+            flushing += 1;
+            self.encode_frame(muxer, std::mem::take(&mut frame))?;
+        }
+        Ok(())
+    }
+
+    fn read_available(&mut self) -> Result<(), DomainError> {
+        let mut chunk = [0u8; 64 * 1024];
+        loop {
+            let read = read_full(&mut self.pcm, &mut chunk)?;
+            if read == 0 {
+                break;
+            }
+            self.bytes.extend_from_slice(&chunk[..read]);
+            let whole = self.bytes.len() / 2 * 2;
+            self.resampler.push(
+                self.bytes[..whole]
+                    .chunks_exact(2)
+                    .map(|le| i16::from_le_bytes([le[0], le[1]])),
+            );
+            self.bytes.drain(..whole);
+        }
+        Ok(())
+    }
+
+    fn encode_frame(&mut self, muxer: &mut Muxer, frame: Vec<i16>) -> Result<(), DomainError> {
+        let frame_length = self.aac.frame_length();
+        if let Some(access_unit) = self.aac.encode(&frame)? {
+            muxer.write_audio(access_unit, frame_length)?;
+            self.encoded += frame_length as u64;
+        }
+        Ok(())
+    }
+}
+
+// Like `read_exact`, but a short read at the end of the file is not an error.
+pub fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        segments::read_playlist,
+        test_support::{avc_config, is_keyframe, sample, temp_dir, AUDIO_FRAME, FRAME},
+    };
+    use std::io::Cursor;
+
+    #[test]
+    fn long_take_moves_on_to_a_segment_at_a_keyframe() {
+        let root = temp_dir("recording_segments");
+        let scratch = ScratchDir::create(&root).unwrap();
+        // two and a half seconds at 30 fps, with the audio of it at 48 kHz
+        let frames = 75;
+        let pcm: Vec<u8> = (0..frames * AUDIO_FRAME as usize)
+            .flat_map(|n| ((n % 100) as i16).to_le_bytes())
+            .collect();
+        fs::write(scratch.pcm_path(), pcm).unwrap();
+        let audio = Pcm {
+            data: Arc::new(Mutex::new(vec![])),
+            sample_rate: 48000,
+            channels: 1,
+            bit_rate: 64000,
+            path: Some(scratch.pcm_path()),
+            clock: None,
+        };
+        let entry = root.join("take.mp4");
+        let mut take = create_segmented(
+            &scratch,
+            &entry,
+            64,
+            48,
+            Container::Mp4,
+            Mp4Layout::Fragmented {
+                fragment: Duration::from_secs(1),
+            },
+            &audio,
+            Segmenting::new(Some(Duration::from_secs(1)), None).unwrap(),
+        )
+        .unwrap();
+        take.set_video(avc_config()).unwrap();
+        // as `FrameWriter` does, a segment is due after every second
+        for n in 0..frames {
+            let at = n as u64 * FRAME as u64;
+            if is_keyframe(n) && take.segment_due(at) {
+                take.next_segment(at).unwrap();
+            }
+            take.write_video(&sample(n), is_keyframe(n), FRAME).unwrap();
+        }
+        let playlist = finish_take(take, scratch.video_path(), &entry).unwrap();
+
+        let files = read_playlist(&playlist).unwrap();
+        assert_eq!(files.len(), 3);
+        let mut video_duration = 0;
+        let mut video_samples = vec![];
+        for file in &files {
+            let data = fs::read(file).unwrap();
+            let scan = scan_fragments(Cursor::new(&data)).unwrap();
+            assert!(scan.is_complete());
+            // the length-prefixed nal unit of an IDR picture
+            let (offset, _) = scan.first_video_sample.unwrap();
+            assert_eq!(data[offset as usize + 4] & 0x1f, 5, "{}", file.display());
+            // the audio reaches the end of the segment's video
+            let video_seconds = scan.video_duration as f64 / VIDEO_TIMESCALE as f64;
+            assert!(scan.audio_duration as f64 >= video_seconds * 48000.0);
+            video_duration += scan.video_duration;
+            video_samples.push(scan.video_samples);
+        }
+        assert_eq!(video_samples, [30, 30, 15]);
+        assert_eq!(video_duration, frames as u64 * FRAME as u64);
+        let seconds: f64 = scratch
+            .read_segments()
+            .unwrap()
+            .iter()
+            .map(|segment| segment.seconds)
+            .sum();
+        assert!((seconds - 2.5).abs() < 1e-9, "{}", seconds);
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
@@ -0,0 +1,652 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::atomic::AtomicBool,
+};
+
+use log::{error, info};
+use openh264::decoder::Decoder;
+
+use crate::message_channel::audio_message_channel::Pcm;
+
+use super::{
+    encoder_profile::{EncoderSettings, VideoCodec},
+    error::DomainError,
+    mkv::{duration_element, first_matroska_frame, scan_clusters},
+    mp4::{
+        annexb_frame, avc_sample, first_fragmented_frame, scan_fragments, Mp4Layout, Mp4Salvage,
+        VideoConfig, VIDEO_TIMESCALE,
+    },
+    pipeline::{save_thumbnail, waveform_thumbnail},
+    progress::ProgressTracker,
+    recording::{create_m4a, create_mp4, finish_take, Container},
+    scratch::{move_file, ScratchDir, TakeManifest},
+    segments::{segment_dir, segment_file_name, write_playlist, Segment, PLAYLIST_EXTENSION},
+    visual_track::{encode_visual_track, VISUAL_FPS, VISUAL_HEIGHT, VISUAL_WIDTH},
+    wav::write_wav,
+};
+
+// What came out of one scratch directory left behind by a crash or a failed save.
+#[derive(Debug, Clone)]
+pub struct RecoveryReport {
+    // name of the scratch directory
+    pub session: String,
+    // the entry written, None if nothing could be salvaged
+    pub file_name: Option<String>,
+    pub frames: usize,
+    pub video_seconds: f64,
+    pub audio_seconds: f64,
+    pub thumbnail: bool,
+    // the audio as a WAV, asked for by the take or kept when no frame survived
+    pub wav_file: Option<String>,
+    // with an entry, what was lost of it
+    pub error: Option<String>,
+}
+
+impl RecoveryReport {
+    fn new(session: String) -> Self {
+        Self {
+            session,
+            file_name: None,
+            frames: 0,
+            video_seconds: 0.0,
+            audio_seconds: 0.0,
+            thumbnail: false,
+            wav_file: None,
+            error: None,
+        }
+    }
+}
+
+// Scratch directories under `app_data_root` except `current`, the one of the running take.
+pub fn find_orphaned(app_data_root: &Path, current: Option<&ScratchDir>) -> Vec<ScratchDir> {
+    ScratchDir::find_all(app_data_root)
+        .into_iter()
+        .filter(|dir| current.map_or(true, |current| current.path() != dir.path()))
+        .collect()
+}
+
+// Puts the take in `scratch` back together as an entry: every complete frame of the unfinished
+// mp4, as much audio as made it to disk and a thumbnail off the first frame. The directory is
+// removed afterwards, unless frames were found and saving them failed, so a later attempt
+// can have another go.
+pub fn recover(scratch: &ScratchDir) -> RecoveryReport {
+    let mut report = RecoveryReport::new(scratch.name());
+    match salvage(scratch, &mut report) {
+        Ok(()) => {
+            info!(
+                "recovered {}: {} frames, {:.1}s of audio",
+                report.session, report.frames, report.audio_seconds
+            );
+            scratch.remove();
+        }
+        Err(e) => {
+            error!("Failed to recover {}: {}", report.session, e);
+            report.error = Some(e.to_string());
+            if report.frames == 0 {
+                // without video the audio is still worth having, the scratch directory
+                // stays if that couldn't be saved either
+                let kept = scratch
+                    .read_manifest()
+                    .and_then(|manifest| keep_audio(scratch, &manifest));
+                match kept {
+                    Ok(wav_file) => {
+                        report.wav_file = wav_file;
+                        scratch.remove();
+                    }
+                    Err(e) => error!("Failed to keep the audio of {}: {}", report.session, e),
+                }
+            }
+        }
+    }
+    report
+}
+
+// rgba and its size
+type Thumbnail = (Vec<u8>, usize, usize);
+
+fn salvage(scratch: &ScratchDir, report: &mut RecoveryReport) -> Result<(), DomainError> {
+    let manifest = scratch.read_manifest()?;
+    let mut video_path = PathBuf::from(&manifest.file_path_prefix);
+    video_path.push(&manifest.file_name);
+    let extension = match () {
+        _ if manifest.voice_memo && manifest.visual.is_none() => "m4a",
+        _ if manifest.segmenting.is_some() => PLAYLIST_EXTENSION,
+        _ => manifest.container.extension(),
+    };
+    let video_path = video_path.with_extension(extension);
+    if video_path.exists() {
+        // the app went down between saving the entry and cleaning up
+        return Err(DomainError::Muxing(format!(
+            "{} was saved already",
+            manifest.file_name
+        )));
+    }
+
+    let thumbnail = match () {
+        _ if manifest.voice_memo => salvage_voice_memo(scratch, &manifest, &video_path, report)?,
+        _ if manifest.segmenting.is_some() => {
+            salvage_segments(scratch, &manifest, &video_path, report)?
+        }
+        _ => salvage_file(
+            scratch,
+            &manifest,
+            &scratch.video_path(),
+            &scratch.pcm_path(),
+            &video_path,
+            report,
+        )?,
+    };
+    report.file_name = Some(manifest.file_name.clone());
+    if manifest.wav_master {
+        match keep_audio(scratch, &manifest) {
+            Ok(wav_file) => report.wav_file = wav_file,
+            Err(e) => error!("Failed to save the WAV master: {}", e),
+        }
+    }
+
+    // the entry is saved, a missing thumbnail doesn't undo that
+    if let Some((rgba, width, height)) = thumbnail {
+        match save_thumbnail(
+            &manifest.file_path_prefix,
+            &manifest.file_name,
+            rgba,
+            width,
+            height,
+        ) {
+            Ok(()) => report.thumbnail = true,
+            Err(e) => error!("Failed to save the thumbnail: {}", e),
+        }
+    }
+    Ok(())
+}
+
+// The unfinished file of a take at `part_path`, moved to `video_path`. `pcm_path` is its
+// audio, only needed when the frames have to be muxed again.
+fn salvage_file(
+    scratch: &ScratchDir,
+    manifest: &TakeManifest,
+    part_path: &Path,
+    pcm_path: &Path,
+    video_path: &Path,
+    report: &mut RecoveryReport,
+) -> Result<Option<Thumbnail>, DomainError> {
+    match manifest.layout {
+        _ if manifest.container == Container::Matroska => {
+            salvage_matroska(manifest, part_path, video_path, report)
+        }
+        Mp4Layout::Progressive => {
+            salvage_progressive(scratch, manifest, part_path, pcm_path, video_path, report)
+        }
+        Mp4Layout::Fragmented { .. } => salvage_fragmented(manifest, part_path, video_path, report),
+    }
+}
+
+// The segments in the data directory stay as they are, those still in `scratch` are salvaged
+// one after the other like a take of their own and the playlist is written with all of them.
+// Muxed again, a segment gets the pcm file from where the one before it ended.
+fn salvage_segments(
+    scratch: &ScratchDir,
+    manifest: &TakeManifest,
+    entry_path: &Path,
+    report: &mut RecoveryReport,
+) -> Result<Option<Thumbnail>, DomainError> {
+    let mut segments = scratch.read_segments()?;
+    let dir = segment_dir(entry_path);
+    fs::create_dir_all(&dir)?;
+    let remux = manifest.container == Container::Mp4
+        && manifest.layout == Mp4Layout::Progressive
+        && manifest.codec == VideoCodec::H264;
+    let mut pcm_start = segments.last().map_or(0, |segment| segment.pcm_end);
+    let mut thumbnail = None;
+    let mut failed = None;
+
+    let mut index = segments.len() + 1;
+    while scratch.segment_path(index).exists() {
+        let part_path = scratch.segment_path(index);
+        let file_name =
+            segment_file_name(&manifest.file_name, index, manifest.container.extension());
+        let mut salvaged = RecoveryReport::new(report.session.clone());
+        let result = (|| {
+            if remux {
+                // a segment that was still waiting for its audio ends where its frames do
+                let frames = match scratch.segment_path(index + 1).exists() {
+                    true => Some(progressive_frames(&part_path)? as u64),
+                    false => None,
+                };
+                let pcm_frames = frames.map(|frames| {
+                    frames * manifest.sample_rate as u64 / manifest.fps.max(1) as u64
+                });
+                copy_pcm(
+                    &scratch.pcm_path(),
+                    manifest.channels,
+                    pcm_start,
+                    pcm_frames,
+                    &scratch.segment_pcm_path(),
+                )?;
+            }
+            salvage_file(
+                scratch,
+                manifest,
+                &part_path,
+                &scratch.segment_pcm_path(),
+                &dir.join(&file_name),
+                &mut salvaged,
+            )
+        })();
+        match result {
+            Ok(first_frame) => {
+                thumbnail = thumbnail.or(first_frame);
+                pcm_start += (salvaged.video_seconds * manifest.sample_rate as f64) as u64;
+                segments.push(Segment {
+                    file_name,
+                    seconds: salvaged.video_seconds,
+                    pcm_end: pcm_start,
+                });
+                report.frames += salvaged.frames;
+                report.video_seconds += salvaged.video_seconds;
+                report.audio_seconds += salvaged.audio_seconds;
+            }
+            // the segments around it are still worth having
+            Err(e) => {
+                error!("Failed to recover segment {}: {}", index, e);
+                report.error = Some(format!("segment {}: {}", index, e));
+                failed = Some(e);
+            }
+        }
+        index += 1;
+    }
+    if segments.is_empty() {
+        return Err(failed.unwrap_or_else(|| {
+            DomainError::Muxing("not a single segment made it to disk".to_string())
+        }));
+    }
+    write_playlist(entry_path, &segments)?;
+    Ok(thumbnail)
+}
+
+// The frames of the unfinished progressive mp4 at `part_path`.
+fn progressive_frames(part_path: &Path) -> Result<usize, DomainError> {
+    let mut salvaged = Mp4Salvage::open(BufReader::new(File::open(part_path)?))?;
+    let mut frames = 0;
+    while salvaged.next_frame()?.is_some() {
+        frames += 1;
+    }
+    Ok(frames)
+}
+
+// Copies `frames` of the pcm file at `pcm_path` from frame `from` on, or all of the rest, to
+// `to`. Nothing left is an empty file.
+fn copy_pcm(
+    pcm_path: &Path,
+    channels: u16,
+    from: u64,
+    frames: Option<u64>,
+    to: &Path,
+) -> Result<(), DomainError> {
+    let frame_bytes = 2 * channels.max(1) as u64;
+    let mut out = BufWriter::new(File::create(to)?);
+    if pcm_path.exists() {
+        let mut pcm = File::open(pcm_path)?;
+        pcm.seek(SeekFrom::Start(from * frame_bytes))?;
+        let limit = frames.map_or(u64::MAX, |frames| frames * frame_bytes);
+        io::copy(&mut BufReader::new(pcm).take(limit), &mut out)?;
+    }
+    out.into_inner()
+        .map_err(|e| DomainError::Io(e.into_error()))?
+        .sync_all()?;
+    Ok(())
+}
+
+// Without 'moov' the frames have to be muxed all over again, with the audio from the pcm file.
+// Only an h264 take can be read back that way.
+fn salvage_progressive(
+    scratch: &ScratchDir,
+    manifest: &TakeManifest,
+    part_path: &Path,
+    pcm_path: &Path,
+    video_path: &Path,
+    report: &mut RecoveryReport,
+) -> Result<Option<Thumbnail>, DomainError> {
+    if manifest.codec != VideoCodec::H264 {
+        return Err(DomainError::Muxing(format!(
+            "the frames of an unfinished {} mp4 can't be read back",
+            manifest.codec.to_str()
+        )));
+    }
+    let mut salvaged = Mp4Salvage::open(BufReader::new(File::open(part_path)?))?;
+
+    if !pcm_path.exists() {
+        // nothing was flushed yet, the entry gets a silent track
+        File::create(pcm_path)?;
+    }
+    let pcm_bytes = pcm_path.metadata()?.len();
+    if manifest.sample_rate > 0 && manifest.channels > 0 {
+        report.audio_seconds =
+            pcm_bytes as f64 / 2.0 / manifest.channels as f64 / manifest.sample_rate as f64;
+    }
+    let audio = Pcm {
+        sample_rate: manifest.sample_rate,
+        channels: manifest.channels,
+        bit_rate: manifest.bit_rate,
+        path: Some(pcm_path.to_path_buf()),
+        ..Pcm::new()
+    };
+
+    let recovered_path = scratch.recovered_video_path();
+    let mut take = create_mp4(
+        &recovered_path,
+        manifest.width,
+        manifest.height,
+        Mp4Layout::Progressive,
+        &audio,
+    )?;
+    take.set_video(VideoConfig::Avc {
+        sps: salvaged.sps.clone(),
+        pps: salvaged.pps.clone(),
+    })?;
+    let frame_duration = VIDEO_TIMESCALE / manifest.fps.max(1);
+    let mut thumbnail = None;
+    while let Some(frame) = salvaged.next_frame()? {
+        let avc = avc_sample(&frame);
+        if report.frames == 0 {
+            thumbnail = annexb_frame(&salvaged.sps, &salvaged.pps, &avc.sample)
+                .and_then(|frame| decode_frame(&frame))
+                .map_err(|e| error!("Failed to decode the thumbnail: {}", e))
+                .ok();
+        }
+        take.write_video(&avc.sample, avc.sync, frame_duration)?;
+        report.frames += 1;
+    }
+    if report.frames == 0 {
+        return Err(DomainError::Muxing(
+            "not a single frame made it to disk".to_string(),
+        ));
+    }
+    report.video_seconds = report.frames as f64 / manifest.fps.max(1) as f64;
+
+    finish_take(take, &recovered_path, video_path)?;
+    Ok(thumbnail)
+}
+
+// A fragmented file plays as it is up to its last whole fragment, what's behind it is cut
+// off. The audio in the fragments is all there is, the pcm file is left alone.
+fn salvage_fragmented(
+    manifest: &TakeManifest,
+    part_path: &Path,
+    video_path: &Path,
+    report: &mut RecoveryReport,
+) -> Result<Option<Thumbnail>, DomainError> {
+    let scan = scan_fragments(BufReader::new(File::open(part_path)?))?;
+    if scan.video_samples == 0 {
+        return Err(DomainError::Muxing(
+            "not a single fragment made it to disk".to_string(),
+        ));
+    }
+    report.frames = scan.video_samples;
+    report.video_seconds = scan.video_duration as f64 / VIDEO_TIMESCALE as f64;
+    if manifest.sample_rate > 0 {
+        report.audio_seconds = scan.audio_duration as f64 / manifest.sample_rate as f64;
+    }
+
+    let thumbnail = match manifest.codec {
+        VideoCodec::H264 => first_fragmented_frame(BufReader::new(File::open(part_path)?), &scan)
+            .and_then(|frame| decode_frame(&frame))
+            .map_err(|e| error!("Failed to decode the thumbnail: {}", e))
+            .ok(),
+        // openh264 is all there is to decode with, the entry goes without
+        VideoCodec::Av1 => None,
+    };
+
+    let mut file = OpenOptions::new().write(true).open(part_path)?;
+    file.set_len(scan.playable_len)?;
+    // 'mehd' still says 0, `finish` never got to it
+    if let Some(mehd_at) = scan.mehd_at {
+        let duration = (report.video_seconds.max(report.audio_seconds) * 1000.0) as u64;
+        file.seek(SeekFrom::Start(mehd_at))?;
+        file.write_all(&duration.to_be_bytes())?;
+    }
+    file.sync_all()?;
+    drop(file);
+    move_file(part_path, video_path)?;
+    Ok(thumbnail)
+}
+
+// A Matroska file plays as it is up to its last whole cluster, what's behind it is cut off
+// and the duration `finish` never wrote goes in. Like a fragmented file, the audio in the
+// clusters is all there is.
+fn salvage_matroska(
+    manifest: &TakeManifest,
+    part_path: &Path,
+    video_path: &Path,
+    report: &mut RecoveryReport,
+) -> Result<Option<Thumbnail>, DomainError> {
+    let scan = scan_clusters(BufReader::new(File::open(part_path)?))?;
+    if scan.video_blocks == 0 {
+        return Err(DomainError::Muxing(
+            "not a single cluster made it to disk".to_string(),
+        ));
+    }
+    report.frames = scan.video_blocks;
+    // the last frame lasts about as long as any other
+    report.video_seconds = scan.video_end as f64 / 1000.0 + 1.0 / manifest.fps.max(1) as f64;
+    report.audio_seconds = scan.audio_end as f64 / 1000.0;
+
+    let thumbnail = match manifest.codec {
+        VideoCodec::H264 => first_matroska_frame(BufReader::new(File::open(part_path)?), &scan)
+            .and_then(|frame| decode_frame(&frame))
+            .map_err(|e| error!("Failed to decode the thumbnail: {}", e))
+            .ok(),
+        VideoCodec::Av1 => None,
+    };
+
+    let mut file = OpenOptions::new().write(true).open(part_path)?;
+    file.set_len(scan.playable_len)?;
+    if let Some(duration_at) = scan.duration_at {
+        let duration = report.video_seconds.max(report.audio_seconds) * 1000.0;
+        file.seek(SeekFrom::Start(duration_at))?;
+        file.write_all(&duration_element(duration))?;
+    }
+    file.sync_all()?;
+    drop(file);
+    move_file(part_path, video_path)?;
+    Ok(thumbnail)
+}
+
+// Writes the pcm file of the take as `<file_name>.wav` next to where the entry goes, None when
+// there's no audio to write. An existing file is left as it is.
+fn keep_audio(
+    scratch: &ScratchDir,
+    manifest: &TakeManifest,
+) -> Result<Option<String>, DomainError> {
+    let pcm_path = scratch.pcm_path();
+    let mut wav_path = PathBuf::from(&manifest.file_path_prefix);
+    wav_path.push(&manifest.file_name);
+    let wav_path = wav_path.with_extension("wav");
+    if !pcm_path.exists() || wav_path.exists() {
+        return Ok(None);
+    }
+    let frames = write_wav(
+        &pcm_path,
+        manifest.sample_rate,
+        manifest.channels,
+        &scratch.wav_path(),
+    )?;
+    if frames == 0 {
+        return Ok(None);
+    }
+    move_file(&scratch.wav_path(), &wav_path)?;
+    info!("kept {} frames of audio as {}", frames, wav_path.display());
+    Ok(Some(format!("{}.wav", manifest.file_name)))
+}
+
+// A voice memo is all in its pcm file, the m4a is simply encoded again. Its thumbnail is the
+// waveform, as when it's saved.
+fn salvage_voice_memo(
+    scratch: &ScratchDir,
+    manifest: &TakeManifest,
+    audio_path: &Path,
+    report: &mut RecoveryReport,
+) -> Result<Option<Thumbnail>, DomainError> {
+    let pcm_path = scratch.pcm_path();
+    let pcm_bytes = match pcm_path.exists() {
+        true => pcm_path.metadata()?.len(),
+        false => 0,
+    };
+    if pcm_bytes == 0 {
+        return Err(DomainError::Muxing(
+            "not a single sample made it to disk".to_string(),
+        ));
+    }
+    if manifest.sample_rate > 0 && manifest.channels > 0 {
+        report.audio_seconds =
+            pcm_bytes as f64 / 2.0 / manifest.channels as f64 / manifest.sample_rate as f64;
+    }
+    let audio = Pcm {
+        sample_rate: manifest.sample_rate,
+        channels: manifest.channels,
+        bit_rate: manifest.bit_rate,
+        path: Some(pcm_path.clone()),
+        ..Pcm::new()
+    };
+
+    let part_path = scratch.recovered_video_path();
+    let visual = match &manifest.visual {
+        Some(visual) => visual,
+        None => {
+            let take = create_m4a(&part_path, &audio)?;
+            finish_take(take, &part_path, audio_path)?;
+            return Ok(waveform_thumbnail(&pcm_path, manifest.channels)
+                .map_err(|e| error!("Failed to draw the waveform: {}", e))
+                .ok());
+        }
+    };
+
+    // the picture only depends on the audio, it's drawn again from the start
+    let mut take = create_mp4(
+        &part_path,
+        VISUAL_WIDTH,
+        VISUAL_HEIGHT,
+        Mp4Layout::Progressive,
+        &audio,
+    )?;
+    let profile = EncoderSettings::default().resolve(VISUAL_WIDTH, VISUAL_HEIGHT, VISUAL_FPS);
+    let (frames, rgba) = encode_visual_track(
+        visual,
+        &audio,
+        &mut take,
+        &profile,
+        &AtomicBool::new(false),
+        &ProgressTracker::silent(),
+    )?;
+    report.frames = frames;
+    report.video_seconds = frames as f64 / profile.fps as f64;
+    finish_take(take, &part_path, audio_path)?;
+    Ok(Some((rgba, VISUAL_WIDTH, VISUAL_HEIGHT)))
+}
+
+// The pipeline takes the thumbnail off the camera, here only the encoded frame is left.
+fn decode_frame(frame: &[u8]) -> Result<Thumbnail, DomainError> {
+    let mut decoder = Decoder::new().map_err(DomainError::encoding)?;
+    let yuv = decoder
+        .decode(frame)
+        .map_err(DomainError::encoding)?
+        .ok_or_else(|| DomainError::Encoding("the first frame did not decode".to_string()))?;
+    let (width, height) = yuv.dimension_rgb();
+    let mut rgb = vec![0u8; width * height * 3];
+    yuv.write_rgb8(&mut rgb);
+    let rgba = rgb
+        .chunks_exact(3)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+        .collect();
+    Ok((rgba, width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use crate::domain::{
+        mp4::{read_mp4_metadata, Mp4Writer},
+        test_support::{aac_config, avc_config, is_keyframe, sample, temp_dir, FRAME},
+    };
+
+    fn manifest(file_path_prefix: &Path) -> TakeManifest {
+        TakeManifest {
+            file_path_prefix: file_path_prefix.to_string_lossy().into_owned(),
+            file_name: "take".to_string(),
+            width: 64,
+            height: 48,
+            fps: 30,
+            sample_rate: 48000,
+            channels: 1,
+            bit_rate: 64000,
+            container: Container::Mp4,
+            codec: VideoCodec::H264,
+            layout: Mp4Layout::Progressive,
+            wav_master: false,
+            voice_memo: false,
+            visual: None,
+            segmenting: None,
+        }
+    }
+
+    #[test]
+    fn crashed_take_comes_back_as_an_entry() {
+        let root = temp_dir("recovery_crashed");
+        let entries = root.join("entries");
+        fs::create_dir_all(&entries).unwrap();
+        let scratch = ScratchDir::create(&root).unwrap();
+        scratch.write_manifest(&manifest(&entries)).unwrap();
+
+        // the app died while writing frame 46, its audio never came
+        let file = BufWriter::new(File::create(scratch.video_path()).unwrap());
+        let mut writer = Mp4Writer::new(file, 64, 48, Mp4Layout::Progressive).unwrap();
+        writer.set_video(avc_config()).unwrap();
+        writer.set_audio(aac_config()).unwrap();
+        for n in 0..46 {
+            writer
+                .write_video(&sample(n), is_keyframe(n), FRAME)
+                .unwrap();
+            if n < 45 {
+                writer.write_audio(&[0x21; 12], 1024).unwrap();
+            }
+        }
+        drop(writer);
+        let written = scratch.video_path().metadata().unwrap().len();
+        let part = OpenOptions::new()
+            .write(true)
+            .open(scratch.video_path())
+            .unwrap();
+        part.set_len(written - 2).unwrap();
+        // a second and a half of audio and half a sample
+        let pcm: Vec<u8> = (0..72000).flat_map(|_| 100i16.to_le_bytes()).collect();
+        fs::write(scratch.pcm_path(), [&pcm[..], &[0x64]].concat()).unwrap();
+
+        let report = recover(&scratch);
+        assert_eq!(report.error, None);
+        assert_eq!(report.file_name.as_deref(), Some("take"));
+        assert_eq!(report.frames, 45);
+        assert_eq!(report.video_seconds, 1.5);
+        assert!((report.audio_seconds - 1.5).abs() < 0.001);
+        assert!(!scratch.path().exists());
+
+        let entry = fs::read(entries.join("take.mp4")).unwrap();
+        let mut kinds = vec![];
+        let mut at = 0;
+        while at < entry.len() {
+            let size = u32::from_be_bytes(entry[at..at + 4].try_into().unwrap()) as usize;
+            kinds.push(&entry[at + 4..at + 8]);
+            // 'mdat' has a 64 bit size
+            at += match size {
+                1 => u64::from_be_bytes(entry[at + 8..at + 16].try_into().unwrap()) as usize,
+                size => size,
+            };
+        }
+        assert_eq!(kinds, [b"ftyp", b"free", b"mdat", b"moov"]);
+        assert!(read_mp4_metadata(Cursor::new(&entry)).is_ok());
+        fs::remove_dir_all(&root).ok();
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    fs,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, error};
+
+use super::{
+    encoder_profile::VideoCodec,
+    error::DomainError,
+    mp4::Mp4Layout,
+    recording::Container,
+    segments::{Segment, Segmenting},
+    visual_track::VisualTrack,
+};
+
+pub const SESSIONS_DIR_NAME: &str = "sessions";
+const PCM_FILE_NAME: &str = "audio.pcm";
+const VIDEO_FILE_NAME: &str = "video.mp4.part";
+const RECOVERED_FILE_NAME: &str = "recovered.mp4.part";
+const WAV_FILE_NAME: &str = "audio.wav.part";
+const MANIFEST_FILE_NAME: &str = "take.txt";
+const SEGMENTS_FILE_NAME: &str = "segments.txt";
+const SEGMENT_PCM_FILE_NAME: &str = "segment.pcm";
+
+// Where scratch directories go until Dart tells us the app-data directory.
+pub fn default_app_data_root() -> PathBuf {
+    std::env::temp_dir().join("avatar_vision")
+}
+
+// What recovering a take needs to know about it, written once its audio stream is open.
+// Plain `key=value` lines.
+#[derive(Debug, Clone)]
+pub struct TakeManifest {
+    pub file_path_prefix: String,
+    pub file_name: String,
+    pub width: usize,
+    pub height: usize,
+    pub fps: u32,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bit_rate: usize,
+    pub container: Container,
+    pub codec: VideoCodec,
+    // only for an mp4
+    pub layout: Mp4Layout,
+    // a WAV of the audio goes next to the mp4
+    pub wav_master: bool,
+    // a voice memo, the entry is an m4a and width, height and fps are 0
+    pub voice_memo: bool,
+    // drawn for a voice memo, which is an mp4 then
+    pub visual: Option<VisualTrack>,
+    // a take split into several files, the entry is a playlist of them
+    pub segmenting: Option<Segmenting>,
+}
+
+impl TakeManifest {
+    fn to_text(&self) -> String {
+        format!(
+            "file_path_prefix={}\nfile_name={}\nwidth={}\nheight={}\nfps={}\n\
+             sample_rate={}\nchannels={}\nbit_rate={}\ncontainer={}\ncodec={}\nlayout={}\n\
+             wav_master={}\nvoice_memo={}\nvisual={}\navatar_path={}\n\
+             segment_seconds={}\nsegment_bytes={}\n",
+            self.file_path_prefix,
+            self.file_name,
+            self.width,
+            self.height,
+            self.fps,
+            self.sample_rate,
+            self.channels,
+            self.bit_rate,
+            self.container.to_str(),
+            self.codec.to_str(),
+            self.layout.to_str(),
+            self.wav_master,
+            self.voice_memo,
+            self.visual
+                .as_ref()
+                .map_or("none", |visual| visual.to_str()),
+            match &self.visual {
+                Some(VisualTrack::Avatar(path)) => path.to_string_lossy(),
+                _ => "".into(),
+            },
+            // 0 for no limit
+            self.segmenting
+                .and_then(|segmenting| segmenting.length)
+                .map_or(0, |length| length.as_secs()),
+            self.segmenting
+                .and_then(|segmenting| segmenting.bytes)
+                .unwrap_or(0),
+        )
+    }
+
+    fn parse(text: &str) -> Result<Self, DomainError> {
+        let value = |key: &str| {
+            text.lines()
+                .filter_map(|line| line.split_once('='))
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v)
+                .ok_or_else(|| DomainError::Muxing(format!("take manifest has no {}", key)))
+        };
+        fn number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, DomainError> {
+            value
+                .parse()
+                .map_err(|_| DomainError::Muxing(format!("take manifest: bad {} {}", key, value)))
+        }
+        Ok(Self {
+            file_path_prefix: value("file_path_prefix")?.to_string(),
+            file_name: value("file_name")?.to_string(),
+            width: number("width", value("width")?)?,
+            height: number("height", value("height")?)?,
+            fps: number("fps", value("fps")?)?,
+            sample_rate: number("sample_rate", value("sample_rate")?)?,
+            channels: number("channels", value("channels")?)?,
+            bit_rate: number("bit_rate", value("bit_rate")?)?,
+            // takes from before Matroska files are mp4
+            container: match value("container") {
+                Ok(container) => Container::parse(container).ok_or_else(|| {
+                    DomainError::Muxing(format!("take manifest: bad container {}", container))
+                })?,
+                Err(_) => Container::Mp4,
+            },
+            // and h264
+            codec: match value("codec") {
+                Ok(codec) => VideoCodec::parse(codec).ok_or_else(|| {
+                    DomainError::Muxing(format!("take manifest: bad codec {}", codec))
+                })?,
+                Err(_) => VideoCodec::H264,
+            },
+            // takes from before fragmented files are progressive, and the fragment length
+            // doesn't matter for reading one back
+            layout: match value("layout") {
+                Ok(layout) => {
+                    Mp4Layout::parse(layout, Mp4Layout::DEFAULT_FRAGMENT).ok_or_else(|| {
+                        DomainError::Muxing(format!("take manifest: bad layout {}", layout))
+                    })?
+                }
+                Err(_) => Mp4Layout::Progressive,
+            },
+            wav_master: value("wav_master").map_or(false, |v| v == "true"),
+            voice_memo: value("voice_memo").map_or(false, |v| v == "true"),
+            visual: match value("visual") {
+                Ok("none") | Err(_) => None,
+                Ok(visual) => {
+                    let avatar_path = value("avatar_path")
+                        .ok()
+                        .filter(|path| !path.is_empty())
+                        .map(Path::new);
+                    Some(VisualTrack::parse(visual, avatar_path).ok_or_else(|| {
+                        DomainError::Muxing(format!("take manifest: bad visual {}", visual))
+                    })?)
+                }
+            },
+            // takes from before segments are in one file
+            segmenting: {
+                let limit = |key: &str| match value(key) {
+                    Ok(v) => number::<u64>(key, v).map(|limit| Some(limit).filter(|l| *l > 0)),
+                    Err(_) => Ok(None),
+                };
+                Segmenting::new(
+                    limit("segment_seconds")?.map(Duration::from_secs),
+                    limit("segment_bytes")?,
+                )
+            },
+        })
+    }
+}
+
+// The working files of one take, in a directory of their own under the app-data root so
+// takes never share a file and nothing is written to the working directory.
+#[derive(Debug, Clone)]
+pub struct ScratchDir {
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    pub fn create(app_data_root: &Path) -> Result<Self, DomainError> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        let path =
+            app_data_root
+                .join(SESSIONS_DIR_NAME)
+                .join(format!("{}_{}", millis, process::id()));
+        fs::create_dir_all(&path)?;
+        debug!("scratch directory {:?}", path);
+        Ok(Self { path })
+    }
+
+    // Every scratch directory under `app_data_root`, whichever process made it.
+    pub fn find_all(app_data_root: &Path) -> Vec<Self> {
+        let entries = match fs::read_dir(app_data_root.join(SESSIONS_DIR_NAME)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return vec![],
+            Err(e) => {
+                error!("Failed to list scratch directories: {}", e);
+                return vec![];
+            }
+        };
+        let mut dirs: Vec<Self> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .map(|path| Self { path })
+            .collect();
+        dirs.sort_by(|a, b| a.path.cmp(&b.path));
+        dirs
+    }
+
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn pcm_path(&self) -> PathBuf {
+        self.path.join(PCM_FILE_NAME)
+    }
+
+    // the mp4 while it's written, moved to the data directory once it's complete
+    pub fn video_path(&self) -> PathBuf {
+        self.path.join(VIDEO_FILE_NAME)
+    }
+
+    // where a crashed take is put back together before it's moved to the data directory
+    pub fn recovered_video_path(&self) -> PathBuf {
+        self.path.join(RECOVERED_FILE_NAME)
+    }
+
+    // the WAV master while it's written
+    pub fn wav_path(&self) -> PathBuf {
+        self.path.join(WAV_FILE_NAME)
+    }
+
+    // segment `index` of a split take while it's written, counted from 1
+    pub fn segment_path(&self, index: usize) -> PathBuf {
+        self.path.join(format!("segment_{:03}.part", index))
+    }
+
+    // the audio of one segment, cut out of the take's pcm file to recover it
+    pub fn segment_pcm_path(&self) -> PathBuf {
+        self.path.join(SEGMENT_PCM_FILE_NAME)
+    }
+
+    // Notes down a segment that was moved to the data directory, so recovery knows where the
+    // rest of the take starts.
+    pub fn add_segment(&self, segment: &Segment) -> Result<(), DomainError> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path.join(SEGMENTS_FILE_NAME))?;
+        file.write_all(segment.to_line().as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    pub fn read_segments(&self) -> Result<Vec<Segment>, DomainError> {
+        let text = match fs::read_to_string(self.path.join(SEGMENTS_FILE_NAME)) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        text.lines()
+            .map(|line| {
+                Segment::parse(line)
+                    .ok_or_else(|| DomainError::Muxing(format!("bad segment line {:?}", line)))
+            })
+            .collect()
+    }
+
+    pub fn write_manifest(&self, manifest: &TakeManifest) -> Result<(), DomainError> {
+        let mut file = fs::File::create(self.path.join(MANIFEST_FILE_NAME))?;
+        file.write_all(manifest.to_text().as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    pub fn read_manifest(&self) -> Result<TakeManifest, DomainError> {
+        let text = fs::read_to_string(self.path.join(MANIFEST_FILE_NAME))?;
+        TakeManifest::parse(&text)
+    }
+
+    pub fn remove(&self) {
+        match fs::remove_dir_all(&self.path) {
+            Ok(()) => debug!("scratch directory {:?} removed", self.path),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => error!("Failed to remove {:?}: {}", self.path, e),
+        }
+    }
+}
+
+// `rename` can't cross file systems, which the app-data root and the data directory may do.
+pub fn move_file(from: &Path, to: &Path) -> Result<(), DomainError> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::remove_file(from)?;
+    Ok(())
+}
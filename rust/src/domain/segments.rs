@@ -0,0 +1,193 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use log::error;
+
+use super::{error::DomainError, mp4::VIDEO_TIMESCALE};
+
+pub const PLAYLIST_EXTENSION: &str = "m3u8";
+
+// When a long take moves on to its next file. Whichever limit is reached first cuts, on the
+// next keyframe the encoder is made to put in there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segmenting {
+    pub length: Option<Duration>,
+    pub bytes: Option<u64>,
+}
+
+impl Segmenting {
+    // None without any limit, the take stays in one file
+    pub fn new(length: Option<Duration>, bytes: Option<u64>) -> Option<Self> {
+        match (length, bytes) {
+            (None, None) => None,
+            _ => Some(Self { length, bytes }),
+        }
+    }
+
+    // `elapsed` ticks and `bytes` into the current segment
+    pub fn due(&self, elapsed: u64, bytes: u64) -> bool {
+        let long = self
+            .length
+            .is_some_and(|length| elapsed as f64 >= length.as_secs_f64() * VIDEO_TIMESCALE as f64);
+        let big = self.bytes.is_some_and(|limit| bytes >= limit);
+        long || big
+    }
+}
+
+// A segment that made it to the data directory.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub file_name: String,
+    pub seconds: f64,
+    // the pcm frame the next segment's audio starts at
+    pub pcm_end: u64,
+}
+
+impl Segment {
+    // one line of the scratch directory's list, the file name goes last as it may have tabs
+    pub fn to_line(&self) -> String {
+        format!(
+            "{:.3}\t{}\t{}\n",
+            self.seconds, self.pcm_end, self.file_name
+        )
+    }
+
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(3, '\t');
+        Some(Self {
+            seconds: fields.next()?.parse().ok()?,
+            pcm_end: fields.next()?.parse().ok()?,
+            file_name: fields.next()?.to_string(),
+        })
+    }
+}
+
+// The directory the segments of the entry at `entry_path` go in, named like the entry.
+pub fn segment_dir(entry_path: &Path) -> PathBuf {
+    entry_path.with_extension("")
+}
+
+// `<file_name>_001.mp4` for the first segment
+pub fn segment_file_name(file_name: &str, index: usize, extension: &str) -> String {
+    format!("{}_{:03}.{}", file_name, index, extension)
+}
+
+// Writes `<entry_path>.m3u8` listing `segments` relative to it, which is the entry the app
+// shows and plays. Returns its path.
+pub fn write_playlist(entry_path: &Path, segments: &[Segment]) -> Result<PathBuf, DomainError> {
+    let playlist_path = entry_path.with_extension(PLAYLIST_EXTENSION);
+    let dir_name = segment_dir(entry_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| DomainError::Muxing(format!("no entry name in {}", entry_path.display())))?;
+    let mut text = String::from("#EXTM3U\n");
+    for segment in segments {
+        text.push_str(&format!(
+            "#EXTINF:{:.3},{}\n{}/{}\n",
+            segment.seconds, segment.file_name, dir_name, segment.file_name
+        ));
+    }
+    fs::write(&playlist_path, text)?;
+    Ok(playlist_path)
+}
+
+// The files `playlist_path` lists, in order, as `write_playlist` wrote it. Every line ends
+// in a newline there, a last one without was cut off and names no file.
+pub fn read_playlist(playlist_path: &Path) -> Result<Vec<PathBuf>, DomainError> {
+    let dir = playlist_path.parent().unwrap_or(Path::new(""));
+    Ok(fs::read_to_string(playlist_path)?
+        .split_inclusive('\n')
+        .filter(|line| line.ends_with('\n'))
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| dir.join(line))
+        .collect())
+}
+
+// Throws away the segments of a cancelled take, they were saved as soon as they were complete.
+pub fn remove_segments(entry_path: &Path) {
+    match fs::remove_dir_all(segment_dir(entry_path)) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => error!("Failed to remove the segments of {:?}: {}", entry_path, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::test_support::temp_dir;
+
+    // an entry in a directory of its own
+    fn entry_path(test: &str) -> PathBuf {
+        temp_dir(&format!("segments_{}", test)).join("take.mp4")
+    }
+
+    fn segments() -> Vec<Segment> {
+        (1..=3)
+            .map(|index| Segment {
+                file_name: segment_file_name("take", index, "mp4"),
+                seconds: 600.0,
+                pcm_end: index as u64 * 600 * 48000,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn playlist_reads_back() {
+        let entry_path = entry_path("playlist");
+        let playlist_path = write_playlist(&entry_path, &segments()).unwrap();
+        assert_eq!(playlist_path, entry_path.with_extension("m3u8"));
+
+        let dir = segment_dir(&entry_path);
+        let expected: Vec<_> = segments()
+            .iter()
+            .map(|segment| dir.join(&segment.file_name))
+            .collect();
+        assert_eq!(read_playlist(&playlist_path).unwrap(), expected);
+        fs::remove_dir_all(entry_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn truncated_playlist_lists_its_whole_lines() {
+        let entry_path = entry_path("truncated");
+        let playlist_path = write_playlist(&entry_path, &segments()).unwrap();
+        let text = fs::read_to_string(&playlist_path).unwrap();
+        let dir = segment_dir(&entry_path);
+
+        // cut in the middle of the last file name
+        fs::write(&playlist_path, &text[..text.len() - 4]).unwrap();
+        let files = read_playlist(&playlist_path).unwrap();
+        assert_eq!(files, [dir.join("take_001.mp4"), dir.join("take_002.mp4")]);
+
+        // and in the middle of the second segment's #EXTINF
+        let second = text.find("#EXTINF:600.000,take_002").unwrap();
+        fs::write(&playlist_path, &text[..second + 10]).unwrap();
+        assert_eq!(
+            read_playlist(&playlist_path).unwrap(),
+            [dir.join("take_001.mp4")]
+        );
+
+        // or right behind the header
+        fs::write(&playlist_path, "#EXTM3U\n").unwrap();
+        assert!(read_playlist(&playlist_path).unwrap().is_empty());
+        fs::remove_dir_all(entry_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn segment_lines_read_back() {
+        let segment = Segment {
+            file_name: "take\t1_001.mp4".to_string(),
+            seconds: 12.5,
+            pcm_end: 600_000,
+        };
+        let parsed = Segment::parse(segment.to_line().trim_end_matches('\n')).unwrap();
+        assert_eq!(parsed.file_name, segment.file_name);
+        assert_eq!(parsed.seconds, segment.seconds);
+        assert_eq!(parsed.pcm_end, segment.pcm_end);
+    }
+}
@@ -0,0 +1,962 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex, Weak,
+    },
+    thread::{self, JoinHandle},
+};
+
+use kanal::Sender;
+use log::{debug, error, info};
+use nokhwa::utils::Resolution;
+
+use crate::message_channel::audio_message_channel::Pcm;
+
+use super::{
+    audio::{open_audio_stream, AudioService, TakeAudio},
+    audio_source::AudioSource,
+    camera::{CameraSelection, CameraService},
+    channel::{ChannelService, UiEvent},
+    encoder_profile::{EncoderProfile, EncoderSettings, VideoCodec},
+    error::{report, DomainError},
+    limits::{least_free_space, RecordingLimits, StopReason, LIMIT_POLL_INTERVAL},
+    metadata::{created_now, EntryMetadata},
+    mp4::Mp4Layout,
+    pipeline::{
+        spawn_batching, spawn_encoding, spawn_voice_memo, EncodingJob, VoiceMemoJob, DEFAULT_FPS,
+        FRAME_RATES,
+    },
+    progress::EncodingProgress,
+    recording::{Container, RecordingService},
+    recovery::{find_orphaned, recover, RecoveryReport},
+    resolution::ResolutionService,
+    scratch::{default_app_data_root, ScratchDir, TakeManifest},
+    segments::Segmenting,
+    visual_track::VisualTrack,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    Idle,
+    Previewing,
+    Recording,
+    Paused,
+    Encoding,
+    Saving,
+    Failed,
+}
+
+impl SessionState {
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            SessionState::Idle => "Idle",
+            SessionState::Previewing => "Previewing",
+            SessionState::Recording => "Recording",
+            SessionState::Paused => "Paused",
+            SessionState::Encoding => "Encoding",
+            SessionState::Saving => "Saving",
+            SessionState::Failed => "Failed",
+        }
+    }
+
+    // Idle -> Previewing -> Recording -> Encoding -> Saving -> Idle, a take can go back and
+    // forth between Recording and Paused and be stopped from either. A voice memo starts
+    // from Idle too, `start_recording` only from Previewing. A cancelled take goes back to
+    // Previewing, a cancelled voice memo to Idle. Anything can fail, a failed session starts
+    // over from Idle or goes straight back to Previewing.
+    pub fn can_transition_to(&self, next: SessionState) -> bool {
+        use SessionState::*;
+        match (self, next) {
+            (_, Failed) => true,
+            (Idle, Previewing) | (Previewing, Idle) => true,
+            (Previewing, Recording) | (Idle, Recording) => true,
+            (Recording, Paused) | (Paused, Recording) => true,
+            (Recording, Encoding) | (Paused, Encoding) => true,
+            (Recording, Previewing) | (Paused, Previewing) | (Encoding, Previewing) => true,
+            (Encoding, Idle) => true,
+            (Recording, Idle) | (Paused, Idle) => true,
+            (Encoding, Saving) => true,
+            (Saving, Idle) => true,
+            (Failed, Idle) | (Failed, Previewing) => true,
+            _ => false,
+        }
+    }
+
+    // the camera and the audio stream are in use by a take
+    pub fn is_capturing(&self) -> bool {
+        matches!(
+            self,
+            SessionState::Recording
+                | SessionState::Paused
+                | SessionState::Encoding
+                | SessionState::Saving
+        )
+    }
+}
+
+// The current state and where to publish it. Cheap to clone into the pipeline threads.
+#[derive(Clone)]
+pub struct SessionStateHandle {
+    state: Arc<Mutex<SessionState>>,
+    ui_event: Sender<UiEvent>,
+}
+
+impl SessionStateHandle {
+    pub fn new(ui_event: Sender<UiEvent>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SessionState::Idle)),
+            ui_event,
+        }
+    }
+
+    pub fn get(&self) -> SessionState {
+        *self.state.lock().unwrap()
+    }
+
+    pub fn transition(&self, next: SessionState) -> Result<(), DomainError> {
+        let mut state = self.state.lock().unwrap();
+        self.move_to(&mut state, next)
+    }
+
+    // Like `transition`, but only from `from`, for moves that race with the pipeline threads.
+    pub fn transition_from(
+        &self,
+        from: SessionState,
+        next: SessionState,
+    ) -> Result<(), DomainError> {
+        let mut state = self.state.lock().unwrap();
+        if *state != from {
+            return Err(DomainError::InvalidState {
+                state: *state,
+                action: format!("moving to {}", next.to_str()),
+            });
+        }
+        self.move_to(&mut state, next)
+    }
+
+    // Reports `e` and moves to Failed, whatever the session was doing.
+    pub fn fail(&self, e: DomainError) {
+        report(&self.ui_event, e);
+        let mut state = self.state.lock().unwrap();
+        if *state != SessionState::Failed {
+            debug!("session {} -> Failed", state.to_str());
+            *state = SessionState::Failed;
+            self.publish(SessionState::Failed);
+        }
+    }
+
+    pub fn ensure_not_capturing(&self, action: &str) -> Result<SessionState, DomainError> {
+        let state = self.get();
+        if state.is_capturing() {
+            return Err(DomainError::InvalidState {
+                state,
+                action: action.to_string(),
+            });
+        }
+        Ok(state)
+    }
+
+    pub fn publish_progress(&self, progress: EncodingProgress) {
+        self.ui_event
+            .send(UiEvent::Progress(progress))
+            .unwrap_or_else(|e| error!("Failed to publish progress: {:?}", e));
+    }
+
+    pub fn publish_limit(&self, reason: StopReason) {
+        self.ui_event
+            .send(UiEvent::LimitReached(reason))
+            .unwrap_or_else(|e| error!("Failed to publish the stop reason: {:?}", e));
+    }
+
+    fn move_to(&self, state: &mut SessionState, next: SessionState) -> Result<(), DomainError> {
+        if !state.can_transition_to(next) {
+            return Err(DomainError::InvalidState {
+                state: *state,
+                action: format!("moving to {}", next.to_str()),
+            });
+        }
+        debug!("session {} -> {}", state.to_str(), next.to_str());
+        *state = next;
+        self.publish(next);
+        Ok(())
+    }
+
+    fn publish(&self, state: SessionState) {
+        self.ui_event
+            .send(UiEvent::SessionState(state))
+            .unwrap_or_else(|e| error!("Failed to publish session state: {:?}", e));
+    }
+}
+
+pub struct RecordingTarget {
+    pub file_path_prefix: String,
+    pub file_name: String,
+    pub resolution: Resolution,
+    pub container: Container,
+    // only for an mp4
+    pub layout: Mp4Layout,
+    // split into several files, the entry is `<file_name>.m3u8` then
+    pub segmenting: Option<Segmenting>,
+    pub encoder: EncoderSettings,
+    // one of `FRAME_RATES`, None for the camera's up to `DEFAULT_FPS`
+    pub fps: Option<u32>,
+    // also keep the audio as a WAV next to the video
+    pub wav_master: bool,
+    pub limits: RecordingLimits,
+    // the creation time defaults to the start, in UTC
+    pub metadata: EntryMetadata,
+}
+
+// A take of the microphone alone, written as `<file_name>.m4a`, or as `<file_name>.mp4`
+// with a visual track.
+pub struct VoiceMemoTarget {
+    pub file_path_prefix: String,
+    pub file_name: String,
+    pub visual: Option<VisualTrack>,
+    pub wav_master: bool,
+    pub limits: RecordingLimits,
+    pub metadata: EntryMetadata,
+}
+
+// Owns everything a take needs: camera, audio, the recording flag the texture and audio
+// threads look at, and the pipeline threads. The method channels only translate calls.
+pub struct CaptureSession {
+    // for the threads that call back into the session, like the limit watch
+    this: Weak<CaptureSession>,
+    pub state: SessionStateHandle,
+    pub channel_handler: Arc<Mutex<ChannelService>>,
+    pub camera: Mutex<CameraService>,
+    // what `start_preview` opened last, opened again for a take cancelled while encoding
+    preview: Mutex<Option<(CameraSelection, Option<Resolution>)>>,
+    // read on every frame and every audio callback, so they stay atomics
+    pub recording: Arc<AtomicBool>,
+    pub rendering: Arc<AtomicBool>,
+    // of the current or last take, batching, muxing and the preview are paced by it
+    pub frame_rate: Arc<AtomicU32>,
+    pub pcm: Arc<Mutex<Pcm>>,
+    audio: Mutex<Option<(AudioSource, AudioService)>>,
+    recording_service: Mutex<RecordingService>,
+    cancelled: Arc<AtomicBool>,
+    // the current take has no video, the camera was stopped for it
+    voice_memo: AtomicBool,
+    // batching and encoding threads of the current take, only the encoding one for a memo
+    pipeline: Mutex<Vec<JoinHandle<()>>>,
+    // winds a cancelled take down, anything that opens the camera or the audio waits for it
+    teardown: Mutex<Option<JoinHandle<()>>>,
+    app_data_root: Mutex<PathBuf>,
+    // the take's, kept locked while its directory is made so recovery never takes it for a crash
+    scratch: Mutex<Option<ScratchDir>>,
+    recovering: Mutex<()>,
+}
+
+impl CaptureSession {
+    pub fn new(
+        channel_handler: Arc<Mutex<ChannelService>>,
+        resolution_service: Arc<ResolutionService>,
+    ) -> Arc<Self> {
+        let ui_event = channel_handler.lock().unwrap().ui_event.0.clone();
+        let recording = Arc::new(AtomicBool::new(false));
+        Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            state: SessionStateHandle::new(ui_event),
+            camera: Mutex::new(CameraService::new(
+                channel_handler.clone(),
+                resolution_service,
+            )),
+            preview: Mutex::new(None),
+            channel_handler,
+            recording: recording.clone(),
+            rendering: Arc::new(AtomicBool::new(false)),
+            frame_rate: Arc::new(AtomicU32::new(DEFAULT_FPS)),
+            pcm: Arc::new(Mutex::new(Pcm::new())),
+            audio: Mutex::new(None),
+            recording_service: Mutex::new(RecordingService::new(recording)),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            voice_memo: AtomicBool::new(false),
+            pipeline: Mutex::new(vec![]),
+            teardown: Mutex::new(None),
+            app_data_root: Mutex::new(default_app_data_root()),
+            scratch: Mutex::new(None),
+            recovering: Mutex::new(()),
+        })
+    }
+
+    pub fn state(&self) -> SessionState {
+        self.state.get()
+    }
+
+    // Where the scratch directories of the next takes go, a take already running keeps its own.
+    pub fn set_app_data_root(&self, path: &Path) -> Result<(), DomainError> {
+        std::fs::create_dir_all(path)?;
+        *self.app_data_root.lock().unwrap() = path.to_path_buf();
+        Ok(())
+    }
+
+    pub fn app_data_root(&self) -> PathBuf {
+        self.app_data_root.lock().unwrap().clone()
+    }
+
+    // Also used to switch cameras or resolutions while already previewing.
+    pub fn start_preview(
+        &self,
+        selection: CameraSelection,
+        resolution: Option<Resolution>,
+    ) -> Result<(), DomainError> {
+        self.settle();
+        let state = self.state.ensure_not_capturing("opening the camera")?;
+        {
+            let mut camera = self.camera.lock().unwrap();
+            camera.infate_camera(selection.clone(), resolution)?;
+            camera.open_camera_stream()?;
+        }
+        *self.preview.lock().unwrap() = Some((selection, resolution));
+        if state != SessionState::Previewing {
+            self.state.transition(SessionState::Previewing)?;
+        }
+        Ok(())
+    }
+
+    pub fn stop_preview(&self) -> Result<(), DomainError> {
+        self.settle();
+        let state = self.state.ensure_not_capturing("stopping the camera")?;
+        self.camera.lock().unwrap().stop_camera_stream();
+        if state == SessionState::Previewing {
+            self.state.transition(SessionState::Idle)?;
+        }
+        Ok(())
+    }
+
+    pub fn open_audio(&self, source: AudioSource) -> Result<(), DomainError> {
+        self.settle();
+        self.state.ensure_not_capturing("opening an audio stream")?;
+        let mut audio = self.audio.lock().unwrap();
+        if let Some((_, audio_service)) = audio.take() {
+            audio_service.stop();
+        }
+        let audio_service = open_audio_stream(&source, self.recording.clone(), None)?;
+        audio_service.play()?;
+        *self.pcm.lock().unwrap() = audio_service.pcm.clone();
+        *audio = Some((source, audio_service));
+        Ok(())
+    }
+
+    pub fn close_audio(&self) -> Result<(), DomainError> {
+        self.settle();
+        self.state
+            .ensure_not_capturing("stopping the audio stream")?;
+        if let Some((_, audio_service)) = self.audio.lock().unwrap().take() {
+            audio_service.stop();
+        }
+        Ok(())
+    }
+
+    // Starts capturing and encoding in one go, the entry is written once `stop_recording`
+    // has been called and the encoder has caught up. Returns what the take is encoded with.
+    // Needs the preview, from Idle there's no camera to take frames from.
+    pub fn start_recording(&self, target: RecordingTarget) -> Result<EncoderProfile, DomainError> {
+        self.settle();
+        let from = self.state.get();
+        if from != SessionState::Previewing {
+            return Err(DomainError::InvalidState {
+                state: from,
+                action: "starting a recording".to_string(),
+            });
+        }
+        let fps = self.negotiate_frame_rate(target.fps)?;
+        target
+            .limits
+            .check_free_space(&self.limit_dirs(&target.file_path_prefix))?;
+        self.state
+            .transition_from(SessionState::Previewing, SessionState::Recording)?;
+        self.voice_memo.store(false, Ordering::Relaxed);
+        self.frame_rate.store(fps, Ordering::Relaxed);
+        let encoder = target.encoder.resolve(
+            target.resolution.width() as usize,
+            target.resolution.height() as usize,
+            fps,
+        );
+        if let Err(e) = self.spawn_pipeline(target, encoder) {
+            // the caller gets the error, only the state needs publishing
+            self.recording_service.lock().unwrap().stop();
+            if let Some(scratch) = self.scratch.lock().unwrap().take() {
+                scratch.remove();
+            }
+            self.state.transition(SessionState::Failed)?;
+            return Err(e);
+        }
+        info!(
+            "The recording got into the process, encoding with {:?}",
+            encoder
+        );
+        Ok(encoder)
+    }
+
+    // Records the microphone alone into an m4a, from Idle or from the preview. The preview is
+    // stopped, the texture thread would otherwise feed frames to a channel nobody reads.
+    // Pause, resume, stop and cancel work as for any take, a cancelled memo goes to Idle.
+    pub fn start_voice_memo(&self, target: VoiceMemoTarget) -> Result<(), DomainError> {
+        self.settle();
+        let from = self.state.get();
+        if !from.can_transition_to(SessionState::Recording) {
+            return Err(DomainError::InvalidState {
+                state: from,
+                action: "starting a voice memo".to_string(),
+            });
+        }
+        target
+            .limits
+            .check_free_space(&self.limit_dirs(&target.file_path_prefix))?;
+        self.camera.lock().unwrap().stop_camera_stream();
+        self.state.transition_from(from, SessionState::Recording)?;
+        self.voice_memo.store(true, Ordering::Relaxed);
+        if let Err(e) = self.spawn_voice_memo(target) {
+            self.recording_service.lock().unwrap().stop();
+            if let Some(scratch) = self.scratch.lock().unwrap().take() {
+                scratch.remove();
+            }
+            self.state.transition(SessionState::Failed)?;
+            return Err(e);
+        }
+        info!("The voice memo got into the process");
+        Ok(())
+    }
+
+    // Video frames and audio samples stop being captured, the camera keeps previewing.
+    // Returns the recorded length so far in seconds.
+    pub fn pause_recording(&self) -> Result<f64, DomainError> {
+        self.state.transition(SessionState::Paused)?;
+        let mut recording_service = self.recording_service.lock().unwrap();
+        recording_service.pause();
+        Ok(recording_service.time_elapsed)
+    }
+
+    pub fn resume_recording(&self) -> Result<(), DomainError> {
+        self.state.transition(SessionState::Recording)?;
+        self.recording_service.lock().unwrap().resume();
+        Ok(())
+    }
+
+    // Returns the recorded length in seconds, paused time excluded. The take is claimed for
+    // the encoder first, so of two stops racing, like the UI's and the limit watch's, only
+    // one goes on.
+    pub fn stop_recording(&self) -> Result<f64, DomainError> {
+        let from = self.state.get();
+        if !matches!(from, SessionState::Recording | SessionState::Paused) {
+            return Err(DomainError::InvalidState {
+                state: from,
+                action: "stopping the recording".to_string(),
+            });
+        }
+        self.state.transition_from(from, SessionState::Encoding)?;
+        let time_elapsed = {
+            let mut recording_service = self.recording_service.lock().unwrap();
+            recording_service.stop();
+            recording_service.time_elapsed
+        };
+        // closes the pcm file of the take, whatever is still buffered gets written first.
+        // The encoder waits for that before it reads the end of the file.
+        self.reopen_audio(None)
+            .unwrap_or_else(|e| error!("Failed to reopen the audio stream: {}", e));
+        debug!("**************************** audio data finalized ****************************");
+
+        // the encoder gets the cpu, the preview comes back once the entry is saved
+        self.camera.lock().unwrap().stop_camera_stream();
+        Ok(time_elapsed)
+    }
+
+    // Recorded length of the last take in seconds, once it has stopped.
+    pub fn time_elapsed(&self) -> f64 {
+        self.recording_service.lock().unwrap().time_elapsed
+    }
+
+    // Throws the take away while it is recorded or encoded. Nothing is written and the
+    // preview keeps running, or comes back for a take that was encoding. A take that is being
+    // saved already can't be cancelled. Returns right away, the pipeline threads wind down
+    // on a thread of their own, see `tear_down`.
+    pub fn cancel_recording(&self) -> Result<(), DomainError> {
+        let from = self.state.get();
+        let next = match from {
+            SessionState::Recording | SessionState::Paused | SessionState::Encoding
+                if self.voice_memo.load(Ordering::Relaxed) =>
+            {
+                SessionState::Idle
+            }
+            SessionState::Recording | SessionState::Paused => SessionState::Previewing,
+            // the camera was stopped for the encoder, it is opened again by `tear_down`
+            SessionState::Encoding => SessionState::Previewing,
+            state => {
+                return Err(DomainError::InvalidState {
+                    state,
+                    action: "cancelling the recording".to_string(),
+                })
+            }
+        };
+        // set first, the encoding thread checks it right before moving to Saving
+        self.cancelled.store(true, Ordering::Relaxed);
+        if let Err(e) = self.state.transition_from(from, next) {
+            self.cancelled.store(false, Ordering::Relaxed);
+            return Err(e);
+        }
+
+        self.recording_service.lock().unwrap().stop();
+        self.channel_handler.lock().unwrap().encoding.0.close();
+        let pipeline = std::mem::take(&mut *self.pipeline.lock().unwrap());
+        let reopen_preview = from == SessionState::Encoding && next == SessionState::Previewing;
+        let this = self.this.clone();
+        let teardown = thread::spawn(move || {
+            if let Some(session) = this.upgrade() {
+                session.tear_down(pipeline, reopen_preview);
+            }
+        });
+        *self.teardown.lock().unwrap() = Some(teardown);
+        info!("The recording was cancelled.");
+        Ok(())
+    }
+
+    // The rest of `cancel_recording`: the pcm file is closed, the pipeline threads are waited
+    // for, which the limit watch may keep up to `LIMIT_POLL_INTERVAL`, and the scratch
+    // directory goes.
+    fn tear_down(&self, pipeline: Vec<JoinHandle<()>>, reopen_preview: bool) {
+        self.reopen_audio(None)
+            .unwrap_or_else(|e| error!("Failed to reopen the audio stream: {}", e));
+        for handle in pipeline {
+            handle
+                .join()
+                .unwrap_or_else(|_| error!("A pipeline thread did not wind down"));
+        }
+        if let Some(scratch) = self.scratch.lock().unwrap().take() {
+            scratch.remove();
+        }
+        if reopen_preview {
+            if let Err(e) = self.reopen_preview() {
+                self.state.fail(e);
+            }
+        }
+        debug!("cancelled take wound down");
+    }
+
+    // Waits for the take cancelled last to wind down.
+    fn settle(&self) {
+        let teardown = self.teardown.lock().unwrap().take();
+        if let Some(teardown) = teardown {
+            teardown
+                .join()
+                .unwrap_or_else(|_| error!("The cancelled take did not wind down"));
+        }
+    }
+
+    // Opens the camera `start_preview` opened last again, after it was stopped for the encoder.
+    fn reopen_preview(&self) -> Result<(), DomainError> {
+        let (selection, resolution) = self
+            .preview
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| DomainError::Camera("No camera was opened before".to_string()))?;
+        let mut camera = self.camera.lock().unwrap();
+        camera.infate_camera(selection, resolution)?;
+        camera.open_camera_stream()
+    }
+
+    // Scratch directories of takes that never made it to the data directory: the app went
+    // down, or the take failed. The running take is left out.
+    pub fn orphaned_sessions(&self) -> Vec<ScratchDir> {
+        // a cancelled take's directory goes once its threads are done with it
+        let winding_down = self
+            .teardown
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|teardown| !teardown.is_finished());
+        let scratch = self.scratch.lock().unwrap();
+        let current = scratch
+            .as_ref()
+            .filter(|_| winding_down || self.state.get().is_capturing());
+        find_orphaned(&self.app_data_root(), current)
+    }
+
+    // Salvages every orphaned take into an entry, one after the other. Calls made meanwhile
+    // wait and find nothing left.
+    pub fn recover_sessions(&self) -> Vec<RecoveryReport> {
+        let _recovering = self.recovering.lock().unwrap();
+        self.orphaned_sessions().iter().map(recover).collect()
+    }
+
+    // Starts the audio stream over with the same source, writing to `pcm_path` if given.
+    fn reopen_audio(&self, pcm_path: Option<&Path>) -> Result<(), DomainError> {
+        let paused_total = self.recording_service.lock().unwrap().paused_total.clone();
+        let mut audio = self.audio.lock().unwrap();
+        let (source, audio_service) = audio
+            .take()
+            .ok_or_else(|| DomainError::Audio("No audio stream open".to_string()))?;
+        audio_service.stop();
+        let take = pcm_path.map(|pcm_path| TakeAudio {
+            pcm_path,
+            paused_total: paused_total.clone(),
+        });
+        let audio_service = open_audio_stream(&source, self.recording.clone(), take.as_ref())?;
+        audio_service.play()?;
+        *self.pcm.lock().unwrap() = audio_service.pcm.clone();
+        *audio = Some((source, audio_service));
+        Ok(())
+    }
+
+    // A take can't go faster than the camera, without a request it goes as fast as the
+    // camera up to `DEFAULT_FPS`.
+    fn negotiate_frame_rate(&self, requested: Option<u32>) -> Result<u32, DomainError> {
+        let camera_fps = self.camera.lock().unwrap().frame_rate();
+        match (requested, camera_fps) {
+            (Some(fps), _) if !FRAME_RATES.contains(&fps) => Err(DomainError::Camera(format!(
+                "{} fps is not one of {:?}",
+                fps, FRAME_RATES
+            ))),
+            (Some(fps), Some(camera_fps)) if fps > camera_fps => Err(DomainError::Camera(format!(
+                "the camera delivers {} fps, {} requested",
+                camera_fps, fps
+            ))),
+            (Some(fps), _) => Ok(fps),
+            (None, _) => {
+                let limit = camera_fps.unwrap_or(DEFAULT_FPS).min(DEFAULT_FPS);
+                Ok(FRAME_RATES
+                    .into_iter()
+                    .filter(|fps| *fps <= limit)
+                    .max()
+                    .unwrap_or(FRAME_RATES[0]))
+            }
+        }
+    }
+
+    fn spawn_pipeline(
+        &self,
+        target: RecordingTarget,
+        encoder: EncoderProfile,
+    ) -> Result<(), DomainError> {
+        let mut metadata = target.metadata;
+        // a Matroska take has nowhere to keep it
+        if target.container == Container::Mp4 {
+            metadata.created.get_or_insert_with(created_now);
+        }
+        let scratch = {
+            let mut current = self.scratch.lock().unwrap();
+            let scratch = ScratchDir::create(&self.app_data_root())?;
+            *current = Some(scratch.clone());
+            scratch
+        };
+        // the preview stream has no file, this one writes the take into the scratch directory
+        self.reopen_audio(Some(&scratch.pcm_path()))?;
+        let audio = self.pcm.lock().unwrap().clone();
+        scratch.write_manifest(&TakeManifest {
+            file_path_prefix: target.file_path_prefix.clone(),
+            file_name: target.file_name.clone(),
+            width: target.resolution.width() as usize,
+            height: target.resolution.height() as usize,
+            fps: encoder.fps,
+            sample_rate: audio.sample_rate,
+            channels: audio.channels,
+            bit_rate: audio.bit_rate,
+            container: target.container,
+            codec: encoder.codec,
+            layout: target.layout,
+            wav_master: target.wav_master,
+            voice_memo: false,
+            visual: None,
+            segmenting: target.segmenting,
+        })?;
+
+        let (encoding_sender, encoding_receiver, recording_receiver) = {
+            let mut channel_handler = self.channel_handler.lock().unwrap();
+            if channel_handler.encoding.1.is_closed() {
+                channel_handler.reset_encoding();
+            }
+            (
+                channel_handler.encoding.0.clone(),
+                channel_handler.encoding.1.clone(),
+                channel_handler.recording.1.clone(),
+            )
+        };
+
+        let paused_total = {
+            let mut recording_service = self.recording_service.lock().unwrap();
+            recording_service.start();
+            recording_service.paused_total.clone()
+        };
+
+        self.cancelled.store(false, Ordering::Relaxed);
+        let batching = spawn_batching(
+            recording_receiver,
+            encoding_sender,
+            paused_total,
+            encoder.fps,
+            self.cancelled.clone(),
+            self.state.clone(),
+        );
+
+        let bits_per_second = encoder.bitrate_bps as u64 + audio.bit_rate as u64;
+        let watch =
+            self.spawn_limit_watch(target.limits, bits_per_second, &target.file_path_prefix);
+        let job = EncodingJob {
+            file_path_prefix: target.file_path_prefix,
+            file_name: target.file_name,
+            width: target.resolution.width() as usize,
+            height: target.resolution.height() as usize,
+            encoding_receiver,
+            audio,
+            container: target.container,
+            layout: target.layout,
+            segmenting: target.segmenting,
+            wav_master: target.wav_master,
+            encoder,
+            metadata,
+            state: self.state.clone(),
+            cancelled: self.cancelled.clone(),
+            scratch,
+        };
+        let encoding = spawn_encoding(job, self.on_finished());
+        *self.pipeline.lock().unwrap() = [batching, encoding].into_iter().chain(watch).collect();
+        Ok(())
+    }
+
+    fn spawn_voice_memo(&self, target: VoiceMemoTarget) -> Result<(), DomainError> {
+        let mut metadata = target.metadata;
+        metadata.created.get_or_insert_with(created_now);
+        // the video is only drawn once the take is over, too late to find a broken avatar
+        if let Some(visual) = &target.visual {
+            visual.check()?;
+        }
+        let scratch = {
+            let mut current = self.scratch.lock().unwrap();
+            let scratch = ScratchDir::create(&self.app_data_root())?;
+            *current = Some(scratch.clone());
+            scratch
+        };
+        self.reopen_audio(Some(&scratch.pcm_path()))?;
+        let audio = self.pcm.lock().unwrap().clone();
+        scratch.write_manifest(&TakeManifest {
+            file_path_prefix: target.file_path_prefix.clone(),
+            file_name: target.file_name.clone(),
+            width: 0,
+            height: 0,
+            fps: 0,
+            sample_rate: audio.sample_rate,
+            channels: audio.channels,
+            bit_rate: audio.bit_rate,
+            container: Container::Mp4,
+            codec: VideoCodec::H264,
+            layout: Mp4Layout::Progressive,
+            wav_master: target.wav_master,
+            voice_memo: true,
+            visual: target.visual.clone(),
+            segmenting: None,
+        })?;
+
+        self.recording_service.lock().unwrap().start();
+        self.cancelled.store(false, Ordering::Relaxed);
+        // the visual track is small next to the audio
+        let watch = self.spawn_limit_watch(
+            target.limits,
+            audio.bit_rate as u64,
+            &target.file_path_prefix,
+        );
+        let job = VoiceMemoJob {
+            file_path_prefix: target.file_path_prefix,
+            file_name: target.file_name,
+            audio,
+            visual: target.visual,
+            wav_master: target.wav_master,
+            metadata,
+            state: self.state.clone(),
+            cancelled: self.cancelled.clone(),
+            scratch,
+        };
+        let encoding = spawn_voice_memo(job, self.on_finished());
+        *self.pipeline.lock().unwrap() = [encoding].into_iter().chain(watch).collect();
+        Ok(())
+    }
+
+    // The volumes a take fills, the data directory and the scratch directory.
+    fn limit_dirs(&self, file_path_prefix: &str) -> Vec<PathBuf> {
+        vec![PathBuf::from(file_path_prefix), self.app_data_root()]
+    }
+
+    // Holds the take that just started against `limits` until it leaves Recording and Paused,
+    // and stops it at the first one it reaches. None without any limit.
+    fn spawn_limit_watch(
+        &self,
+        limits: RecordingLimits,
+        bits_per_second: u64,
+        file_path_prefix: &str,
+    ) -> Option<JoinHandle<()>> {
+        if limits.is_empty() {
+            return None;
+        }
+        let dirs = self.limit_dirs(file_path_prefix);
+        let this = self.this.clone();
+        let started = self.recording_service.lock().unwrap().started;
+        Some(thread::spawn(move || loop {
+            thread::sleep(LIMIT_POLL_INTERVAL);
+            let session = match this.upgrade() {
+                Some(session) => session,
+                None => break,
+            };
+            let elapsed = {
+                let recording_service = session.recording_service.lock().unwrap();
+                // a later take is watched by its own thread
+                if recording_service.started != started {
+                    break;
+                }
+                recording_service.elapsed()
+            };
+            let taking = matches!(
+                session.state.get(),
+                SessionState::Recording | SessionState::Paused
+            );
+            if !taking {
+                break;
+            }
+            let free = limits.min_free_bytes.and_then(|_| least_free_space(&dirs));
+            if let Some(reason) = limits.reached(elapsed, bits_per_second, free) {
+                session.stop_at_limit(reason);
+                break;
+            }
+        }))
+    }
+
+    // Stops the take as `stop_recording` does, it's saved as usual, and tells the UI why.
+    // A take stopped or cancelled in the meantime is left alone.
+    fn stop_at_limit(&self, reason: StopReason) {
+        if self.stop_recording().is_ok() {
+            info!("The recording reached a limit: {}", reason.to_str());
+            self.state.publish_limit(reason);
+        }
+    }
+
+    // Where the take's encoding thread leaves the session once it's done.
+    fn on_finished(&self) -> impl FnOnce(Result<(), DomainError>) + Send + 'static {
+        let state = self.state.clone();
+        let cancelled = self.cancelled.clone();
+        move |result| match result {
+            Ok(()) => state
+                .transition(SessionState::Idle)
+                .unwrap_or_else(|e| error!("{}", e)),
+            // `cancel_recording` has moved the session on already
+            Err(e) if cancelled.load(Ordering::Relaxed) => debug!("take discarded: {}", e),
+            // a failed save must not leave the UI in 'Saving'
+            Err(e) => state.fail(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::test_support::temp_dir;
+    use std::time::{Duration, Instant};
+
+    fn target() -> RecordingTarget {
+        RecordingTarget {
+            file_path_prefix: std::env::temp_dir().to_string_lossy().into_owned(),
+            file_name: "take".to_string(),
+            resolution: Resolution::new(1280, 720),
+            container: Container::Mp4,
+            layout: Mp4Layout::Progressive,
+            segmenting: None,
+            encoder: EncoderSettings::default(),
+            fps: None,
+            wav_master: false,
+            limits: RecordingLimits::default(),
+            metadata: EntryMetadata::default(),
+        }
+    }
+
+    #[test]
+    fn recording_needs_the_preview() {
+        let session = CaptureSession::new(
+            Arc::new(Mutex::new(ChannelService::new())),
+            Arc::new(ResolutionService::new()),
+        );
+        // the camera was never opened
+        match session.start_recording(target()) {
+            Err(DomainError::InvalidState { state, .. }) => assert_eq!(state, SessionState::Idle),
+            other => panic!("started from Idle: {:?}", other.map(|_| ())),
+        }
+        assert_eq!(session.state(), SessionState::Idle);
+    }
+
+    #[test]
+    fn cancelled_takes_go_back_where_they_came_from() {
+        assert!(SessionState::Encoding.can_transition_to(SessionState::Previewing));
+        // voice memos have no preview to go back to
+        assert!(SessionState::Encoding.can_transition_to(SessionState::Idle));
+        assert!(!SessionState::Saving.can_transition_to(SessionState::Previewing));
+    }
+
+    #[test]
+    fn a_take_is_stopped_once() {
+        let channel_handler = Arc::new(Mutex::new(ChannelService::new()));
+        let ui_event = channel_handler.lock().unwrap().ui_event.1.clone();
+        let session = CaptureSession::new(channel_handler, Arc::new(ResolutionService::new()));
+        session.state.transition(SessionState::Previewing).unwrap();
+        session.state.transition(SessionState::Recording).unwrap();
+
+        session.stop_recording().unwrap();
+        // the limit watch came second
+        session.stop_at_limit(StopReason::MaxDuration);
+        match session.stop_recording() {
+            Err(DomainError::InvalidState { state, .. }) => {
+                assert_eq!(state, SessionState::Encoding)
+            }
+            other => panic!("stopped twice: {:?}", other),
+        }
+        let mut encoding = 0;
+        while let Ok(Some(event)) = ui_event.try_recv() {
+            match event {
+                UiEvent::SessionState(SessionState::Encoding) => encoding += 1,
+                UiEvent::LimitReached(reason) => panic!("stopped at {}", reason.to_str()),
+                _ => {}
+            }
+        }
+        assert_eq!(encoding, 1);
+    }
+
+    #[test]
+    fn cancelling_leaves_the_teardown_to_a_thread() {
+        let session = CaptureSession::new(
+            Arc::new(Mutex::new(ChannelService::new())),
+            Arc::new(ResolutionService::new()),
+        );
+        let root = temp_dir("session_cancel");
+        session.set_app_data_root(&root).unwrap();
+        let scratch = ScratchDir::create(&root).unwrap();
+        *session.scratch.lock().unwrap() = Some(scratch.clone());
+        session.state.transition(SessionState::Previewing).unwrap();
+        session.state.transition(SessionState::Recording).unwrap();
+        // a limit watch that is asleep
+        *session.pipeline.lock().unwrap() =
+            vec![thread::spawn(|| thread::sleep(Duration::from_millis(500)))];
+
+        let started = Instant::now();
+        session.cancel_recording().unwrap();
+        assert!(started.elapsed() < Duration::from_millis(250));
+        assert_eq!(session.state(), SessionState::Previewing);
+        // the directory is the cancelled take's until its threads are done with it
+        assert!(session.orphaned_sessions().is_empty());
+
+        session.settle();
+        assert!(!scratch.path().exists());
+        assert!(session.scratch.lock().unwrap().is_none());
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn cancelling_needs_a_take() {
+        let session = CaptureSession::new(
+            Arc::new(Mutex::new(ChannelService::new())),
+            Arc::new(ResolutionService::new()),
+        );
+        match session.cancel_recording() {
+            Err(DomainError::InvalidState { state, .. }) => assert_eq!(state, SessionState::Idle),
+            other => panic!("cancelled from Idle: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,47 @@
+// What the tests of the muxers and of the files of a take share.
+use std::{fs, path::PathBuf, process};
+
+use super::mp4::{AacConfig, VideoConfig, VIDEO_TIMESCALE};
+
+// a frame at 30 fps
+pub const FRAME: u32 = VIDEO_TIMESCALE / 30;
+// the aac that goes with it at 48 kHz
+pub const AUDIO_FRAME: u32 = 1600;
+pub const SPS: [u8; 4] = [0x67, 0x42, 0x00, 0x1e];
+pub const PPS: [u8; 4] = [0x68, 0xce, 0x3c, 0x80];
+
+pub fn avc_config() -> VideoConfig {
+    VideoConfig::Avc {
+        sps: SPS.to_vec(),
+        pps: PPS.to_vec(),
+    }
+}
+
+// 48 kHz mono without priming, so audio and video durations add up alike
+pub fn aac_config() -> AacConfig {
+    AacConfig {
+        sample_rate: 48000,
+        channels: 1,
+        bit_rate: 64000,
+        decoder_config: vec![0x11, 0x88],
+        priming: 0,
+    }
+}
+
+// Frame `n` at 30 fps as it goes into a file, a keyframe every second.
+pub fn sample(n: usize) -> Vec<u8> {
+    let nal_type = if is_keyframe(n) { 0x65 } else { 0x41 };
+    vec![0, 0, 0, 3, nal_type, 0x88, n as u8 | 0x80]
+}
+
+pub fn is_keyframe(n: usize) -> bool {
+    n.is_multiple_of(30)
+}
+
+// An empty directory for `name` under the temp directory, one per test process.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{}_{}", name, process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
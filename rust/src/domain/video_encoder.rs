@@ -0,0 +1,410 @@
+use std::{collections::HashMap, sync::Arc};
+
+use openh264::encoder::{Encoder, FrameType};
+use rav1e::{
+    data::{FrameParameters, FrameType as Av1FrameType, Packet},
+    prelude::FrameTypeOverride,
+    Config, Context, EncoderStatus,
+};
+
+use crate::tools::image_processing::YUVBuf;
+
+use super::{
+    encoder_profile::{EncoderProfile, VideoCodec},
+    error::DomainError,
+    mp4::{avc_sample, VideoConfig},
+};
+
+const OBU_SEQUENCE_HEADER: u8 = 1;
+const OBU_TEMPORAL_DELIMITER: u8 = 2;
+
+// A frame as it goes into the take, in the format of its track's sample entry.
+pub struct EncodedFrame {
+    pub data: Vec<u8>,
+    pub keyframe: bool,
+    // what was passed to `encode` with the picture
+    pub timestamp: u64,
+}
+
+// What encodes a take's video. It's fed i420 pictures of the size it was configured with
+// and hands out frames in the order they're shown, though not necessarily right away; the
+// rest comes out of `flush`.
+pub trait VideoEncoder {
+    fn configure(
+        width: usize,
+        height: usize,
+        profile: &EncoderProfile,
+    ) -> Result<Self, DomainError>
+    where
+        Self: Sized;
+
+    fn encode(&mut self, yuv: Vec<u8>, timestamp: u64) -> Result<Vec<EncodedFrame>, DomainError>;
+
+    fn flush(&mut self) -> Result<Vec<EncodedFrame>, DomainError>;
+
+    // The next picture is encoded as a keyframe, a segmented take is cut there.
+    fn force_keyframe(&mut self);
+
+    // The track's decoder config, known once the first frame is out.
+    fn codec_config(&self) -> Option<VideoConfig>;
+}
+
+// The encoder for `profile.codec`.
+pub fn video_encoder(
+    width: usize,
+    height: usize,
+    profile: &EncoderProfile,
+) -> Result<Box<dyn VideoEncoder>, DomainError> {
+    Ok(match profile.codec {
+        VideoCodec::H264 => Box::new(H264Encoder::configure(width, height, profile)?),
+        VideoCodec::Av1 => Box::new(Av1Encoder::configure(width, height, profile)?),
+    })
+}
+
+pub struct H264Encoder {
+    encoder: Encoder,
+    width: usize,
+    height: usize,
+    keyframe_interval: u32,
+    // openh264 only puts in a keyframe on a scene change, past the interval one is forced
+    since_keyframe: u32,
+    config: Option<VideoConfig>,
+}
+
+impl VideoEncoder for H264Encoder {
+    fn configure(
+        width: usize,
+        height: usize,
+        profile: &EncoderProfile,
+    ) -> Result<Self, DomainError> {
+        let encoder = Encoder::with_config(profile.h264_config(width as u32, height as u32))
+            .map_err(DomainError::encoding)?;
+        Ok(Self {
+            encoder,
+            width,
+            height,
+            keyframe_interval: profile.keyframe_interval,
+            since_keyframe: 0,
+            config: None,
+        })
+    }
+
+    // openh264 doesn't hold frames back, each comes out right away or not at all.
+    fn encode(&mut self, yuv: Vec<u8>, timestamp: u64) -> Result<Vec<EncodedFrame>, DomainError> {
+        let yuv = YUVBuf {
+            yuv,
+            width: self.width,
+            height: self.height,
+        };
+        if self.since_keyframe >= self.keyframe_interval {
+            self.encoder.force_intra_frame(true);
+        }
+        let bitstream = self.encoder.encode(&yuv).map_err(DomainError::encoding)?;
+        match bitstream.frame_type() {
+            FrameType::IDR | FrameType::I => self.since_keyframe = 1,
+            _ => self.since_keyframe += 1,
+        }
+        let mut frame = vec![];
+        for l in 0..bitstream.num_layers() {
+            let layer = bitstream
+                .layer(l)
+                .ok_or_else(|| DomainError::Encoding(format!("missing layer {}", l)))?;
+            for n in 0..layer.nal_count() {
+                let nal = layer
+                    .nal_unit(n)
+                    .ok_or_else(|| DomainError::Encoding(format!("missing nal unit {}", n)))?;
+                frame.extend_from_slice(nal);
+            }
+        }
+
+        let avc = avc_sample(&frame);
+        if self.config.is_none() {
+            if let (Some(sps), Some(pps)) = (avc.sps, avc.pps) {
+                if sps.len() >= 4 {
+                    self.config = Some(VideoConfig::Avc {
+                        sps: sps.to_vec(),
+                        pps: pps.to_vec(),
+                    });
+                }
+            }
+        }
+        if avc.sample.is_empty() {
+            // parameter sets only, nothing to show
+            return Ok(vec![]);
+        }
+        Ok(vec![EncodedFrame {
+            data: avc.sample,
+            keyframe: avc.sync,
+            timestamp,
+        }])
+    }
+
+    fn flush(&mut self) -> Result<Vec<EncodedFrame>, DomainError> {
+        Ok(vec![])
+    }
+
+    fn force_keyframe(&mut self) {
+        self.since_keyframe = self.keyframe_interval;
+    }
+
+    fn codec_config(&self) -> Option<VideoConfig> {
+        self.config.clone()
+    }
+}
+
+pub struct Av1Encoder {
+    context: Context<u8>,
+    width: usize,
+    height: usize,
+    // the start of the codec config record, the sequence header comes with the first keyframe
+    config_header: Vec<u8>,
+    config: Option<VideoConfig>,
+    // of the frames still in the encoder, by the number rav1e gives them
+    timestamps: HashMap<u64, u64>,
+    sent: u64,
+    force_keyframe: bool,
+}
+
+impl Av1Encoder {
+    // Whatever rav1e has finished so far.
+    fn receive(&mut self, frames: &mut Vec<EncodedFrame>) -> Result<(), DomainError> {
+        loop {
+            match self.context.receive_packet() {
+                Ok(packet) => frames.push(self.frame(packet)?),
+                Err(EncoderStatus::Encoded) => {}
+                Err(EncoderStatus::NeedMoreData) | Err(EncoderStatus::LimitReached) => {
+                    return Ok(())
+                }
+                Err(e) => return Err(DomainError::encoding(e)),
+            }
+        }
+    }
+
+    // A packet is one temporal unit, it shows exactly one frame. The delimiter in front has
+    // no place in a sample.
+    fn frame(&mut self, packet: Packet<u8>) -> Result<EncodedFrame, DomainError> {
+        let timestamp = self
+            .timestamps
+            .remove(&packet.input_frameno)
+            .ok_or_else(|| {
+                DomainError::Encoding(format!("frame {} was never sent", packet.input_frameno))
+            })?;
+        let mut data = Vec::with_capacity(packet.data.len());
+        for (obu_type, obu) in obus(&packet.data)? {
+            match obu_type {
+                OBU_TEMPORAL_DELIMITER => {}
+                OBU_SEQUENCE_HEADER if self.config.is_none() => {
+                    let mut config = self.config_header.clone();
+                    config.extend_from_slice(obu);
+                    self.config = Some(VideoConfig::Av1 { config });
+                    data.extend_from_slice(obu);
+                }
+                _ => data.extend_from_slice(obu),
+            }
+        }
+        Ok(EncodedFrame {
+            data,
+            keyframe: packet.frame_type == Av1FrameType::KEY,
+            timestamp,
+        })
+    }
+}
+
+impl VideoEncoder for Av1Encoder {
+    fn configure(
+        width: usize,
+        height: usize,
+        profile: &EncoderProfile,
+    ) -> Result<Self, DomainError> {
+        let context: Context<u8> = Config::new()
+            .with_encoder_config(profile.av1_config(width, height))
+            .with_threads(profile.threads as usize)
+            .new_context()
+            .map_err(DomainError::encoding)?;
+        Ok(Self {
+            config_header: context.container_sequence_header(),
+            context,
+            width,
+            height,
+            config: None,
+            timestamps: HashMap::new(),
+            sent: 0,
+            force_keyframe: false,
+        })
+    }
+
+    fn encode(&mut self, yuv: Vec<u8>, timestamp: u64) -> Result<Vec<EncodedFrame>, DomainError> {
+        let luma = self.width * self.height;
+        let chroma = luma / 4;
+        if yuv.len() < luma + 2 * chroma {
+            return Err(DomainError::Encoding(format!(
+                "a frame of {} bytes at {}x{}",
+                yuv.len(),
+                self.width,
+                self.height
+            )));
+        }
+        let mut picture = self.context.new_frame();
+        picture.planes[0].copy_from_raw_u8(&yuv[..luma], self.width, 1);
+        picture.planes[1].copy_from_raw_u8(&yuv[luma..luma + chroma], self.width / 2, 1);
+        picture.planes[2].copy_from_raw_u8(&yuv[luma + chroma..], self.width / 2, 1);
+        // rav1e only pads a frame nothing else holds on to, this one is kept for a retry
+        for plane in picture.planes.iter_mut() {
+            plane.pad(self.width, self.height);
+        }
+        let picture = Arc::new(picture);
+        let frame_type_override = match std::mem::take(&mut self.force_keyframe) {
+            true => FrameTypeOverride::Key,
+            false => FrameTypeOverride::No,
+        };
+
+        let mut frames = vec![];
+        // a full queue has to give up some frames before it takes another
+        loop {
+            let parameters = FrameParameters {
+                frame_type_override,
+                ..Default::default()
+            };
+            match self.context.send_frame((picture.clone(), parameters)) {
+                Ok(()) => break,
+                Err(EncoderStatus::EnoughData) => self.receive(&mut frames)?,
+                Err(e) => return Err(DomainError::encoding(e)),
+            }
+        }
+        self.timestamps.insert(self.sent, timestamp);
+        self.sent += 1;
+        self.receive(&mut frames)?;
+        Ok(frames)
+    }
+
+    fn flush(&mut self) -> Result<Vec<EncodedFrame>, DomainError> {
+        self.context.flush();
+        let mut frames = vec![];
+        self.receive(&mut frames)?;
+        Ok(frames)
+    }
+
+    fn force_keyframe(&mut self) {
+        self.force_keyframe = true;
+    }
+
+    fn codec_config(&self) -> Option<VideoConfig> {
+        self.config.clone()
+    }
+}
+
+// The type and the bytes of every OBU in `data`, rav1e writes each one with its size.
+fn obus(data: &[u8]) -> Result<Vec<(u8, &[u8])>, DomainError> {
+    let broken = || DomainError::Encoding("rav1e wrote a broken OBU".to_string());
+    let mut obus = vec![];
+    let mut at = 0;
+    while at < data.len() {
+        let header = data[at];
+        if header & 0x02 == 0 {
+            return Err(broken());
+        }
+        // the extension byte follows the header
+        let mut end = at + 1 + ((header >> 2) & 1) as usize;
+        let mut size = 0;
+        for i in 0..8 {
+            let byte = *data.get(end).ok_or_else(broken)?;
+            end += 1;
+            size |= ((byte & 0x7f) as usize) << (7 * i);
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        end += size;
+        obus.push(((header >> 3) & 0x0f, data.get(at..end).ok_or_else(broken)?));
+        at = end;
+    }
+    Ok(obus)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::encoder_profile::EncoderSettings;
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 48;
+
+    fn av1_encoder() -> Av1Encoder {
+        let profile = EncoderSettings {
+            codec: Some(VideoCodec::Av1),
+            ..EncoderSettings::default()
+        }
+        .resolve(WIDTH, HEIGHT, 30);
+        Av1Encoder::configure(WIDTH, HEIGHT, &profile).unwrap()
+    }
+
+    // i420 of a grey picture with a bar that moves with `n`
+    fn picture(n: usize) -> Vec<u8> {
+        let mut yuv = vec![128u8; WIDTH * HEIGHT * 3 / 2];
+        for y in 0..HEIGHT {
+            for x in (n * 4 % WIDTH..).take(8).filter(|x| *x < WIDTH) {
+                yuv[y * WIDTH + x] = 230;
+            }
+        }
+        yuv
+    }
+
+    #[test]
+    fn av1_starts_on_a_keyframe_with_its_sequence_header() {
+        let mut encoder = av1_encoder();
+        let mut frames = vec![];
+        for n in 0..6 {
+            if n == 4 {
+                encoder.force_keyframe();
+            }
+            frames.extend(encoder.encode(picture(n), n as u64 * 3000).unwrap());
+        }
+        frames.extend(encoder.flush().unwrap());
+
+        // in the order they're shown, each with its timestamp
+        let timestamps: Vec<u64> = frames.iter().map(|frame| frame.timestamp).collect();
+        assert_eq!(timestamps, [0, 3000, 6000, 9000, 12000, 15000]);
+        let keyframes: Vec<bool> = frames.iter().map(|frame| frame.keyframe).collect();
+        assert_eq!(keyframes, [true, false, false, false, true, false]);
+
+        // the first frame carries the sequence header, no frame its temporal delimiter
+        let types = |frame: &EncodedFrame| -> Vec<u8> {
+            obus(&frame.data).unwrap().iter().map(|(t, _)| *t).collect()
+        };
+        assert_eq!(types(&frames[0])[0], OBU_SEQUENCE_HEADER);
+        assert!(frames
+            .iter()
+            .all(|frame| !types(frame).contains(&OBU_TEMPORAL_DELIMITER)));
+        let sequence_header = obus(&frames[0].data).unwrap()[0].1;
+        match encoder.codec_config() {
+            Some(VideoConfig::Av1 { config }) => {
+                assert_eq!(config[0], 0x81);
+                assert!(config.ends_with(sequence_header));
+            }
+            _ => panic!("no av1 config after the first frame"),
+        }
+    }
+
+    #[test]
+    fn av1_takes_only_pictures_of_its_size() {
+        let mut encoder = av1_encoder();
+        let small = vec![0u8; WIDTH * HEIGHT];
+        assert!(matches!(
+            encoder.encode(small, 0),
+            Err(DomainError::Encoding(_))
+        ));
+    }
+
+    #[test]
+    fn obus_are_split_by_their_size() {
+        // a temporal delimiter, then a frame OBU of 3 bytes with an extension byte
+        let data = [0x12, 0x00, 0x36, 0x00, 0x03, 0xaa, 0xbb, 0xcc];
+        let split = obus(&data).unwrap();
+        assert_eq!(split.len(), 2);
+        assert_eq!(split[0], (OBU_TEMPORAL_DELIMITER, &data[..2]));
+        assert_eq!(split[1], (6, &data[2..]));
+        // cut off, or without a size field
+        assert!(obus(&data[..7]).is_err());
+        assert!(obus(&[0x10, 0x00]).is_err());
+    }
+}
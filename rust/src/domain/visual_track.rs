@@ -0,0 +1,327 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::atomic::AtomicBool,
+    time::{Duration, Instant},
+};
+
+use image::{imageops, Rgba, RgbaImage};
+
+use crate::{message_channel::audio_message_channel::Pcm, tools::image_processing::rgba_to_yuv};
+
+use super::{
+    audio::{amplitude, ACTIVE_AUDIO_AMPLITUDE},
+    encoder_profile::EncoderProfile,
+    error::DomainError,
+    progress::ProgressTracker,
+    recording::{encode_video, read_full, TakeFile},
+};
+
+// Small, the picture is simple and a memo shouldn't take long to save.
+pub const VISUAL_WIDTH: usize = 640;
+pub const VISUAL_HEIGHT: usize = 360;
+pub const VISUAL_FPS: u32 = 15;
+pub const BACKGROUND: [u8; 4] = [0x20, 0x24, 0x2c, 0xff];
+pub const FOREGROUND: [u8; 4] = [0x7d, 0xc4, 0xe4, 0xff];
+// share of the level kept from one frame to the next as the voice falls, so nothing flickers
+const RELEASE: f32 = 0.75;
+const BAR_WIDTH: usize = 8;
+const BAR_GAP: usize = 4;
+// the avatar at rest takes this much of the height and grows by `AVATAR_PULSE` at full voice,
+// in `AVATAR_STEPS` sizes scaled once up front
+const AVATAR_SIZE: f32 = 0.6;
+const AVATAR_PULSE: f32 = 0.15;
+const AVATAR_STEPS: usize = 16;
+
+// What stands in for the camera in an entry recorded without one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VisualTrack {
+    // the loudness of the last seconds as bars, scrolling right to left
+    Audiogram,
+    // a picture of the user's, growing with the voice
+    Avatar(PathBuf),
+}
+
+impl VisualTrack {
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            VisualTrack::Audiogram => "audiogram",
+            VisualTrack::Avatar(_) => "avatar",
+        }
+    }
+
+    // an avatar needs its picture
+    pub fn parse(name: &str, avatar_path: Option<&Path>) -> Option<Self> {
+        match (name, avatar_path) {
+            ("audiogram", _) => Some(VisualTrack::Audiogram),
+            ("avatar", Some(path)) => Some(VisualTrack::Avatar(path.to_path_buf())),
+            _ => None,
+        }
+    }
+
+    // Makes sure the picture loads before a take depends on it.
+    pub fn check(&self) -> Result<(), DomainError> {
+        Painter::new(self).map(|_| ())
+    }
+}
+
+// Encodes a picture of `audio` into `take`, one frame every 1/`profile.fps` for as long as
+// its pcm file lasts. Frames are drawn as the encoder asks for them and the audio follows
+// them into `take` like it follows the camera. Returns the number of frames and the frame of
+// the loudest moment for the thumbnail, `VISUAL_WIDTH` by `VISUAL_HEIGHT`.
+pub fn encode_visual_track(
+    visual: &VisualTrack,
+    audio: &Pcm,
+    take: &mut TakeFile,
+    profile: &EncoderProfile,
+    cancelled: &AtomicBool,
+    progress: &ProgressTracker,
+) -> Result<(usize, Vec<u8>), DomainError> {
+    let pcm_path = audio
+        .path
+        .as_ref()
+        .ok_or_else(|| DomainError::Encoding("the take has no pcm file".to_string()))?;
+    let levels = frame_levels(pcm_path, audio.sample_rate, audio.channels, profile.fps)?;
+    let painter = Painter::new(visual)?;
+    let loudest = (0..levels.len())
+        .max_by(|a, b| levels[*a].total_cmp(&levels[*b]))
+        .unwrap_or(0);
+    let thumbnail = painter.paint(&levels, loudest);
+
+    for _ in 0..levels.len() {
+        progress.frame_queued();
+    }
+    let origin = Instant::now();
+    let frames = (0..levels.len()).map(|index| {
+        let rgba = painter.paint(&levels, index);
+        let at = origin + Duration::from_secs_f64(index as f64 / profile.fps as f64);
+        (rgba_to_yuv(&rgba, VISUAL_WIDTH, VISUAL_HEIGHT), at)
+    });
+    let encoded = encode_video(
+        frames,
+        take,
+        VISUAL_WIDTH,
+        VISUAL_HEIGHT,
+        profile,
+        cancelled,
+        progress,
+    )?;
+    Ok((encoded, thumbnail))
+}
+
+// The loudness of every frame's worth of the pcm file from 0 to 1, against the loudest frame
+// or what the audio stream takes for a voice, whichever is higher, so a quiet take stays calm.
+// At least one frame, a take without audio still gets a picture.
+fn frame_levels(
+    pcm_path: &Path,
+    sample_rate: u32,
+    channels: u16,
+    fps: u32,
+) -> Result<Vec<f32>, DomainError> {
+    let per_frame = (sample_rate / fps.max(1)).max(1) as usize * channels.max(1) as usize;
+    let mut pcm = BufReader::new(File::open(pcm_path)?);
+    let mut bytes = vec![0u8; per_frame * 2];
+    let mut samples = Vec::with_capacity(per_frame);
+    let mut peaks = vec![];
+    loop {
+        let read = read_full(&mut pcm, &mut bytes)?;
+        if read < 2 {
+            break;
+        }
+        samples.clear();
+        samples.extend(
+            bytes[..read / 2 * 2]
+                .chunks_exact(2)
+                .map(|le| i16::from_le_bytes([le[0], le[1]])),
+        );
+        peaks.push(amplitude(&samples));
+        if read < bytes.len() {
+            break;
+        }
+    }
+    if peaks.is_empty() {
+        peaks.push(0.0);
+    }
+
+    let loudest = peaks.iter().copied().fold(ACTIVE_AUDIO_AMPLITUDE, f32::max);
+    let mut level = 0.0;
+    Ok(peaks
+        .into_iter()
+        .map(|peak| {
+            level = (peak / loudest).max(level * RELEASE);
+            level
+        })
+        .collect())
+}
+
+enum Painter {
+    Audiogram,
+    // the picture at each step of the pulse
+    Avatar(Vec<RgbaImage>),
+}
+
+impl Painter {
+    fn new(visual: &VisualTrack) -> Result<Self, DomainError> {
+        let path = match visual {
+            VisualTrack::Audiogram => return Ok(Painter::Audiogram),
+            VisualTrack::Avatar(path) => path,
+        };
+        let picture = image::open(path)?.into_rgba8();
+        let (width, height) = picture.dimensions();
+        if width == 0 || height == 0 {
+            return Err(DomainError::Encoding(format!(
+                "the avatar {} is empty",
+                path.display()
+            )));
+        }
+        let rest = VISUAL_HEIGHT as f32 * AVATAR_SIZE / width.max(height) as f32;
+        let steps = (0..=AVATAR_STEPS)
+            .map(|step| {
+                let scale = rest * (1.0 + AVATAR_PULSE * step as f32 / AVATAR_STEPS as f32);
+                imageops::resize(
+                    &picture,
+                    ((width as f32 * scale).round() as u32).max(1),
+                    ((height as f32 * scale).round() as u32).max(1),
+                    imageops::FilterType::Triangle,
+                )
+            })
+            .collect();
+        Ok(Painter::Avatar(steps))
+    }
+
+    // rgba of the frame at `index`
+    fn paint(&self, levels: &[f32], index: usize) -> Vec<u8> {
+        let mut canvas =
+            RgbaImage::from_pixel(VISUAL_WIDTH as u32, VISUAL_HEIGHT as u32, Rgba(BACKGROUND));
+        match self {
+            Painter::Audiogram => {
+                let bars = VISUAL_WIDTH / (BAR_WIDTH + BAR_GAP);
+                let middle = VISUAL_HEIGHT / 2;
+                let reach = (middle - 24) as f32;
+                for bar in 0..bars {
+                    // the newest level is the rightmost bar
+                    let level = match (index + bar + 1).checked_sub(bars) {
+                        Some(at) => levels[at],
+                        None => 0.0,
+                    };
+                    let half = ((level * reach).round() as usize).max(1);
+                    let left = bar * (BAR_WIDTH + BAR_GAP) + BAR_GAP / 2;
+                    for y in middle - half..=middle + half {
+                        for x in left..left + BAR_WIDTH {
+                            canvas.put_pixel(x as u32, y as u32, Rgba(FOREGROUND));
+                        }
+                    }
+                }
+            }
+            Painter::Avatar(steps) => {
+                let step = (levels[index] * AVATAR_STEPS as f32).round() as usize;
+                let picture = &steps[step.min(AVATAR_STEPS)];
+                let x = (VISUAL_WIDTH as i64 - picture.width() as i64) / 2;
+                let y = (VISUAL_HEIGHT as i64 - picture.height() as i64) / 2;
+                imageops::overlay(&mut canvas, picture, x, y);
+            }
+        }
+        canvas.into_raw()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{
+        encoder_profile::{EncoderSettings, VideoCodec},
+        mp4::Mp4Layout,
+        recording::{create_mp4, finish_take},
+        test_support::temp_dir,
+    };
+    use std::fs;
+
+    // `seconds` of a tone at 48 kHz mono, loud for the second half
+    fn pcm(dir: &Path, seconds: f64) -> Pcm {
+        let path = dir.join("take.pcm");
+        let frames = (seconds * 48000.0) as usize;
+        let pcm: Vec<u8> = (0..frames)
+            .map(|n| {
+                let level = if n < frames / 2 { 2000.0 } else { 20000.0 };
+                (level * (n as f32 / 10.0).sin()) as i16
+            })
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        fs::write(&path, pcm).unwrap();
+        Pcm {
+            sample_rate: 48000,
+            channels: 1,
+            bit_rate: 64000,
+            path: Some(path),
+            ..Pcm::new()
+        }
+    }
+
+    #[test]
+    fn a_frame_for_every_fps_th_of_the_audio() {
+        let dir = temp_dir("visual_levels");
+        let audio = pcm(&dir, 2.1);
+        let levels = frame_levels(audio.path.as_ref().unwrap(), 48000, 1, VISUAL_FPS).unwrap();
+        // the last tenth of a second gets a frame of its own
+        assert_eq!(levels.len(), 32);
+        assert!(levels.iter().all(|level| (0.0..=1.0).contains(level)));
+        assert!(levels[4] < 0.2);
+        assert!(levels[31] > 0.9);
+
+        // a take without audio still gets a picture
+        fs::write(audio.path.as_ref().unwrap(), []).unwrap();
+        let levels = frame_levels(audio.path.as_ref().unwrap(), 48000, 1, VISUAL_FPS).unwrap();
+        assert_eq!(levels, [0.0]);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn audiogram_fills_its_frame() {
+        let painter = Painter::new(&VisualTrack::Audiogram).unwrap();
+        let levels = [0.0, 0.5, 1.0];
+        let rgba = painter.paint(&levels, 2);
+        assert_eq!(rgba.len(), VISUAL_WIDTH * VISUAL_HEIGHT * 4);
+        let pixel = |x: usize, y: usize| &rgba[(y * VISUAL_WIDTH + x) * 4..][..4];
+        // the newest level is the rightmost bar, reaching up from the middle
+        let right = VISUAL_WIDTH / (BAR_WIDTH + BAR_GAP) * (BAR_WIDTH + BAR_GAP) - BAR_GAP;
+        assert_eq!(pixel(right - 1, 30), FOREGROUND);
+        assert_eq!(pixel(right - 1, 10), BACKGROUND);
+        assert_eq!(pixel(0, 0), BACKGROUND);
+    }
+
+    #[test]
+    fn visual_track_encodes_a_frame_per_level() {
+        let dir = temp_dir("visual_encode");
+        let audio = pcm(&dir, 0.2);
+        let part_path = dir.join("take.part");
+        let mut take = create_mp4(
+            &part_path,
+            VISUAL_WIDTH,
+            VISUAL_HEIGHT,
+            Mp4Layout::Progressive,
+            &audio,
+        )
+        .unwrap();
+        // rav1e is there wherever the tests run
+        let profile = EncoderSettings {
+            codec: Some(VideoCodec::Av1),
+            ..EncoderSettings::default()
+        }
+        .resolve(VISUAL_WIDTH, VISUAL_HEIGHT, VISUAL_FPS);
+        let (frames, thumbnail) = encode_visual_track(
+            &VisualTrack::Audiogram,
+            &audio,
+            &mut take,
+            &profile,
+            &AtomicBool::new(false),
+            &ProgressTracker::silent(),
+        )
+        .unwrap();
+        assert_eq!(frames, 3);
+        assert_eq!(thumbnail.len(), VISUAL_WIDTH * VISUAL_HEIGHT * 4);
+        let entry = finish_take(take, &part_path, dir.join("take")).unwrap();
+        assert!(entry.metadata().unwrap().len() > 0);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
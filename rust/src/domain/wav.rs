@@ -0,0 +1,94 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read},
+    path::Path,
+};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use super::error::DomainError;
+
+// Wraps the raw pcm of a take in a WAV header, the uncompressed original for editing the
+// audio on its own. A partial frame at the end, as a crash leaves behind, is dropped.
+// Returns the number of frames written.
+pub fn write_wav(
+    pcm_path: &Path,
+    sample_rate: u32,
+    channels: u16,
+    wav_path: &Path,
+) -> Result<u64, DomainError> {
+    if sample_rate == 0 || channels == 0 {
+        return Err(DomainError::Audio(format!(
+            "no WAV for {} channels at {} Hz",
+            channels, sample_rate
+        )));
+    }
+    let spec = WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let frames = pcm_path.metadata()?.len() / 2 / channels as u64;
+    let mut remaining = frames * 2 * channels as u64;
+    let mut pcm = BufReader::new(File::open(pcm_path)?);
+    let mut wav = WavWriter::new(BufWriter::new(File::create(wav_path)?), spec)
+        .map_err(DomainError::audio)?;
+    let mut bytes = [0u8; 64 * 1024];
+    while remaining > 0 {
+        let read = remaining.min(bytes.len() as u64) as usize;
+        pcm.read_exact(&mut bytes[..read])?;
+        remaining -= read as u64;
+        for le in bytes[..read].chunks_exact(2) {
+            wav.write_sample(i16::from_le_bytes([le[0], le[1]]))
+                .map_err(DomainError::audio)?;
+        }
+    }
+    wav.finalize().map_err(DomainError::audio)?;
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::test_support::temp_dir;
+    use hound::WavReader;
+    use std::fs;
+
+    #[test]
+    fn pcm_comes_back_out_of_the_wav() {
+        let dir = temp_dir("wav_round_trip");
+        let pcm_path = dir.join("take.pcm");
+        let wav_path = dir.join("take.wav");
+        // 1000 stereo frames and the first half of one more, cut off by a crash
+        let samples: Vec<i16> = (0..2000).map(|n| (n * 31 - 30000) as i16).collect();
+        let mut pcm: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        pcm.extend_from_slice(&[0x12, 0x34]);
+        fs::write(&pcm_path, &pcm).unwrap();
+
+        assert_eq!(write_wav(&pcm_path, 44100, 2, &wav_path).unwrap(), 1000);
+        let mut reader = WavReader::open(&wav_path).unwrap();
+        let spec = reader.spec();
+        assert_eq!((spec.channels, spec.sample_rate), (2, 44100));
+        assert_eq!(spec.bits_per_sample, 16);
+        assert_eq!(spec.sample_format, SampleFormat::Int);
+        assert_eq!(reader.duration(), 1000);
+        // 44 bytes of header and the whole frames
+        assert_eq!(wav_path.metadata().unwrap().len(), 44 + 4000);
+        let read: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap()).collect();
+        assert_eq!(read, samples);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn no_wav_without_channels_or_a_rate() {
+        let dir = temp_dir("wav_no_format");
+        let pcm_path = dir.join("take.pcm");
+        fs::write(&pcm_path, [0u8; 16]).unwrap();
+        for (sample_rate, channels) in [(0, 1), (48000, 0)] {
+            let result = write_wav(&pcm_path, sample_rate, channels, &dir.join("take.wav"));
+            assert!(matches!(result, Err(DomainError::Audio(_))));
+        }
+        fs::remove_dir_all(&dir).ok();
+    }
+}
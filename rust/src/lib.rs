@@ -1,6 +1,6 @@
 use std::{
     ffi::c_void,
-    sync::{atomic::AtomicBool, Arc, Mutex, Once},
+    sync::{Arc, Mutex, Once},
 };
 
 use domain::{channel::ChannelService, textrue};
@@ -15,16 +15,13 @@ use message_channel::{
     texture_message_channel::{self, TextureHandler},
 };
 
-use crate::{
-    domain::camera::CameraService, domain::recording::RecordingService,
-    domain::resolution::ResolutionService,
-};
+use crate::{domain::resolution::ResolutionService, domain::session::CaptureSession};
 use textrue::TextureService;
 use tools::log_::init_logging;
 
-mod domain;
-mod message_channel;
-mod tools;
+pub mod domain;
+pub mod message_channel;
+pub mod tools;
 
 static START: Once = Once::new();
 
@@ -64,42 +61,26 @@ fn init_message_channels(
     resolution_settings: Arc<ResolutionService>,
 ) {
     let channel_handler = Arc::new(Mutex::new(ChannelService::new()));
-    let recording = Arc::new(AtomicBool::new(false));
-    let rendering = Arc::new(AtomicBool::new(false));
-    let recording_info = Arc::new(Mutex::new(RecordingService::new(recording.clone())));
-    let audio = Arc::new(Mutex::new(audio_message_channel::Pcm {
-        data: Arc::new(Mutex::new(vec![])),
-        sample_rate: 0,
-        channels: 0,
-        bit_rate: 0,
-    }));
+    let session = CaptureSession::new(channel_handler.clone(), resolution_settings);
 
     texture_message_channel::init(TextureHandler {
         render_buffer,
-        channel_handler: channel_handler.clone(),
-        recording: recording.clone(),
+        channel_handler,
+        recording: session.recording.clone(),
     });
 
-    camera_message_channel::init(CameraHandler::new(
-        rendering.clone(),
-        Arc::new(Mutex::new(CameraService::new(
-            channel_handler.clone(),
-            resolution_settings,
-        ))),
-    ));
+    camera_message_channel::init(CameraHandler::new(session.clone()));
 
-    recording_message_channel::init(RecordingHandler::new(
-        audio.clone(),
-        recording_info.clone(),
-        channel_handler,
-    ));
+    recording_message_channel::init(RecordingHandler::new(session.clone()));
 
-    rendering_message_channel::init(RenderingHandler::new(texture, rendering));
+    rendering_message_channel::init(RenderingHandler::new(
+        texture,
+        session.rendering.clone(),
+        session.frame_rate.clone(),
+    ));
 
     audio_message_channel::init(AudioHandler {
-        audio_service: Arc::new(Mutex::new(None)),
-        pcm: audio,
-        recording,
+        session,
         current_device: Arc::new(Mutex::new(None)),
     });
 }
@@ -1,7 +1,7 @@
 use std::{
-    collections::HashMap,
     mem::ManuallyDrop,
-    sync::{atomic::AtomicBool, Arc, Mutex},
+    path::PathBuf,
+    sync::{Arc, Mutex},
     thread,
 };
 
@@ -10,19 +10,21 @@ use cpal::{
     traits::{DeviceTrait, HostTrait},
     Device, SupportedStreamConfigRange,
 };
-use irondash_message_channel::{
-    AsyncMethodHandler, MethodCall, PlatformError, PlatformResult, Value,
-};
+use irondash_message_channel::{AsyncMethodHandler, MethodCall, PlatformResult};
 use irondash_run_loop::RunLoop;
 use log::debug;
 
-use crate::domain::audio::{open_audio_stream, AudioService};
+use crate::domain::{
+    audio_source::{AudioSource, TONE_GENERATOR_NAME},
+    av_sync::AudioClock,
+    session::CaptureSession,
+};
+
+use super::protocol::{self, AudioDeviceArgs, FromArgs, ProtocolError};
 
 pub struct AudioHandler {
-    pub audio_service: Arc<Mutex<Option<AudioService>>>,
-    pub pcm: Arc<Mutex<Pcm>>,
+    pub session: Arc<CaptureSession>,
     pub current_device: Arc<Mutex<Option<String>>>,
-    pub recording: Arc<AtomicBool>,
 }
 #[derive(Debug, Clone)]
 pub struct Pcm {
@@ -30,6 +32,10 @@ pub struct Pcm {
     pub sample_rate: u32,
     pub channels: u16,
     pub bit_rate: usize,
+    // the file the samples of a take go to, None while only previewing
+    pub path: Option<PathBuf>,
+    // when the samples in `path` were captured
+    pub clock: Option<AudioClock>,
 }
 
 impl Pcm {
@@ -39,6 +45,8 @@ impl Pcm {
             sample_rate: 0,
             channels: 0,
             bit_rate: 0,
+            path: None,
+            clock: None,
         }
     }
 }
@@ -47,24 +55,25 @@ impl Pcm {
 impl AsyncMethodHandler for AudioHandler {
     async fn on_method_call(&self, call: MethodCall) -> PlatformResult {
         match call.method.as_str() {
+            "protocol_handshake" => protocol::handshake(&call),
+
             "open_audio_stream" => {
                 debug!(
                     "Received request {:?} on thread {:?}",
                     call,
                     thread::current().id()
                 );
-                let map: HashMap<String, String> = call.args.try_into().unwrap();
-                let device_name = map.get("device_name").unwrap().as_str();
-                let recording = self.recording.clone();
-
-                let audio_service = open_audio_stream(device_name, recording).unwrap();
-
-                let mut pcm = self.pcm.lock().unwrap();
-                *pcm = audio_service.pcm.clone();
-
-                audio_service.play().unwrap();
-
-                self.audio_service.lock().unwrap().replace(audio_service);
+                let args = AudioDeviceArgs::from_call(&call)?;
+
+                let source = AudioSource::from_name(&args.device_name);
+                if !source.is_generated() && !is_available_input(&args.device_name) {
+                    return Err(ProtocolError::DeviceNotFound(format!(
+                        "Audio device not found: {}",
+                        args.device_name
+                    ))
+                    .into());
+                }
+                self.session.open_audio(source)?;
                 PlatformResult::Ok("ok".into())
             }
             "stop_audio_stream" => {
@@ -73,9 +82,7 @@ impl AsyncMethodHandler for AudioHandler {
                     call,
                     thread::current().id()
                 );
-                if let Some(recorder) = self.audio_service.lock().unwrap().take() {
-                    recorder.stop();
-                }
+                self.session.close_audio()?;
                 return PlatformResult::Ok("ok".into());
             }
 
@@ -88,7 +95,7 @@ impl AsyncMethodHandler for AudioHandler {
                 let mut data = vec![];
 
                 if let Some(_) = self.current_device.lock().unwrap().as_ref() {
-                    let audio = self.pcm.lock().unwrap();
+                    let audio = self.session.pcm.lock().unwrap();
                     data = audio.data.lock().unwrap().drain(..).collect::<Vec<u8>>();
                 }
                 // debug!("data len: {}", data.len());
@@ -104,10 +111,14 @@ impl AsyncMethodHandler for AudioHandler {
                     thread::current().id()
                 );
 
-                let names = cpal_available_inputs()
+                let mut names = cpal_available_inputs()
                     .iter()
-                    .map(|d| d.name().unwrap())
+                    .filter_map(|d| d.name().ok())
                     .collect::<Vec<String>>();
+                // the tone generator lets the pcm path run without a microphone
+                if cfg!(debug_assertions) {
+                    names.push(TONE_GENERATOR_NAME.to_string());
+                }
 
                 PlatformResult::Ok(names.into())
             }
@@ -130,22 +141,22 @@ impl AsyncMethodHandler for AudioHandler {
                     call,
                     thread::current().id()
                 );
-                let map: HashMap<String, String> = call.args.try_into().unwrap();
-                let device_name = map.get("device_name").unwrap().as_str();
-
-                let inputs = cpal_available_inputs();
-                if let Some(_) = inputs.iter().find(|d| d.name().unwrap() == device_name) {
-                    let mut current_device = self.current_device.lock().unwrap();
-                    *current_device = Some(device_name.to_string());
+                let args = AudioDeviceArgs::from_call(&call)?;
+
+                let source = AudioSource::from_name(&args.device_name);
+                if !source.is_generated() && !is_available_input(&args.device_name) {
+                    return Err(ProtocolError::DeviceNotFound(format!(
+                        "Audio device not found: {}",
+                        args.device_name
+                    ))
+                    .into());
                 }
+                let mut current_device = self.current_device.lock().unwrap();
+                *current_device = Some(args.device_name);
 
                 PlatformResult::Ok("ok".into())
             }
-            _ => Err(PlatformError {
-                code: "invalid_method".into(),
-                message: Some(format!("Unknown Method: {}", call.method)),
-                detail: Value::Null,
-            }),
+            _ => protocol::invalid_method(&call),
         }
     }
 }
@@ -161,6 +172,12 @@ pub fn init(audio_handler: AudioHandler) {
     });
 }
 
+fn is_available_input(device_name: &str) -> bool {
+    cpal_available_inputs()
+        .iter()
+        .any(|d| d.name().map_or(false, |name| name == device_name))
+}
+
 pub fn cpal_available_inputs() -> Vec<Device> {
     let available_hosts = cpal::available_hosts();
     println!("Available hosts:\n  {:?}", available_hosts);
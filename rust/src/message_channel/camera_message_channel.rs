@@ -1,31 +1,29 @@
-use std::{
-    collections::HashMap,
-    mem::ManuallyDrop,
-    sync::{atomic::AtomicBool, Arc, Mutex},
-    thread,
-};
+use std::{mem::ManuallyDrop, sync::Arc, thread};
 
 use async_trait::async_trait;
-use irondash_message_channel::{
-    AsyncMethodHandler, MethodCall, PlatformError, PlatformResult, Value,
-};
+use irondash_message_channel::{AsyncMethodHandler, MethodCall, PlatformResult, Value};
 use irondash_run_loop::RunLoop;
 use log::debug;
-use nokhwa::{
-    query,
-    utils::{ApiBackend, CameraIndex},
+use nokhwa::{query, utils::ApiBackend};
+
+use crate::domain::{
+    camera::CameraSelection,
+    error::DomainError,
+    frame_source::{SyntheticConfig, SYNTHETIC_CAMERA_NAME},
+    session::CaptureSession,
 };
 
-use crate::domain::camera::CameraService;
+use super::protocol::{
+    self, FromArgs, OpenCameraStreamArgs, ProtocolError, SelectCameraDeviceArgs,
+};
 
 pub struct CameraHandler {
-    pub rendering: Arc<AtomicBool>,
-    pub camera_service: Arc<Mutex<CameraService>>,
+    pub session: Arc<CaptureSession>,
 }
 
 impl CameraHandler {
-    pub fn new(rendering: Arc<AtomicBool>, camera: Arc<Mutex<CameraService>>) -> Self {
-        Self { rendering, camera_service: camera }
+    pub fn new(session: Arc<CaptureSession>) -> Self {
+        Self { session }
     }
 }
 
@@ -33,33 +31,27 @@ impl CameraHandler {
 impl AsyncMethodHandler for CameraHandler {
     async fn on_method_call(&self, call: MethodCall) -> PlatformResult {
         match call.method.as_str() {
+            "protocol_handshake" => protocol::handshake(&call),
+
             "open_camera_stream" => {
                 debug!(
                     "Received request {:?} on thread {:?}",
                     call,
                     thread::current().id()
                 );
-                let map: HashMap<String, String> = call.args.try_into().unwrap();
-                let resolution = map.get("resolution");
-
-                let mut camera_service = self.camera_service.lock().unwrap();
-                let mut camera_index: Option<CameraIndex> = None;
+                let args = OpenCameraStreamArgs::from_call(&call)?;
 
-                {
-                    let camera_info = &mut camera_service.current_camera_info.lock().unwrap();
-                    camera_index.replace(camera_info.as_ref().unwrap().index().clone());
-                }
+                let selection = {
+                    let camera_service = self.session.camera.lock().unwrap();
+                    let selection = camera_service.current_camera_info.lock().unwrap().clone();
+                    selection
+                };
 
-                if camera_index.is_none() {
-                    return PlatformResult::Err(PlatformError {
-                        code: "method_failed".into(),
-                        message: Some(format!("open_camera_stream failed: {}", call.method)),
-                        detail: Value::Null,
-                    });
-                }
+                let selection = selection.ok_or_else(|| {
+                    ProtocolError::DeviceNotFound("No camera selected".to_string())
+                })?;
 
-                camera_service.infate_camera(camera_index.unwrap(), resolution);
-                camera_service.open_camera_stream();
+                self.session.start_preview(selection, args.resolution)?;
 
                 return PlatformResult::Ok("ok".into());
             }
@@ -70,13 +62,12 @@ impl AsyncMethodHandler for CameraHandler {
                     call,
                     thread::current().id()
                 );
-                let mut camera_service = self.camera_service.lock().unwrap();
-                camera_service.stop_camera_stream();
+                self.session.stop_preview()?;
                 Ok("ok".into())
             }
 
             "camera_health_check" => {
-                let mut camera_service = self.camera_service.lock().unwrap();
+                let mut camera_service = self.session.camera.lock().unwrap();
                 let (health_check, message) = camera_service.health_check();
                 if health_check {
                     return PlatformResult::Ok("ok".into());
@@ -90,22 +81,33 @@ impl AsyncMethodHandler for CameraHandler {
                     call,
                     thread::current().id()
                 );
-                let camera_service = self.camera_service.lock().unwrap();
+                let args = SelectCameraDeviceArgs::from_call(&call)?;
+                let camera_service = self.session.camera.lock().unwrap();
+
+                if args.device_name == SYNTHETIC_CAMERA_NAME {
+                    let mut config = SyntheticConfig::default();
+                    if let Some(fps) = args.fps {
+                        config.fps = fps;
+                    }
+                    if let Some(frame_format) = args.frame_format {
+                        config.frame_format = frame_format;
+                    }
+                    let mut current_camera_info = camera_service.current_camera_info.lock().unwrap();
+                    current_camera_info.replace(CameraSelection::Synthetic(config));
+                    return PlatformResult::Ok("ok".into());
+                }
 
-                let map: HashMap<String, String> = call.args.try_into().unwrap();
-                let camera_name = map.get("device_name").unwrap().as_str();
-                
-                let cameras = query(ApiBackend::Auto).unwrap();
-                if let Some(camera_info) = cameras.iter().find(|c| c.human_name() == camera_name) {
+                let cameras = query(ApiBackend::Auto).map_err(DomainError::camera)?;
+                if let Some(camera_info) = cameras.iter().find(|c| c.human_name() == args.device_name) {
                     let mut current_camera_info = camera_service.current_camera_info.lock().unwrap();
-                    current_camera_info.replace(camera_info.clone());
+                    current_camera_info.replace(CameraSelection::Device(camera_info.clone()));
                     return PlatformResult::Ok("ok".into());
                 }
-                PlatformResult::Err(PlatformError {
-                    code: "method_failed".into(),
-                    message: Some(format!("select_camera_device failed: {}", call.method)),
-                    detail: Value::Null,
-                })
+                Err(ProtocolError::DeviceNotFound(format!(
+                    "Camera not found: {}",
+                    args.device_name
+                ))
+                .into())
             }
 
             "available_cameras" => {
@@ -114,9 +116,13 @@ impl AsyncMethodHandler for CameraHandler {
                     call,
                     thread::current().id()
                 );
-                let cameras = query(ApiBackend::Auto).unwrap();
-                let camera_names: Vec<String> =
+                let cameras = query(ApiBackend::Auto).map_err(DomainError::camera)?;
+                let mut camera_names: Vec<String> =
                     cameras.iter().map(|c| c.human_name().clone()).collect();
+                // the test pattern lets the whole pipeline run without a webcam
+                if cfg!(debug_assertions) {
+                    camera_names.push(SYNTHETIC_CAMERA_NAME.to_string());
+                }
                 debug!("available_cameras: {:?}", camera_names);
                 Ok(camera_names.into())
             }
@@ -127,7 +133,7 @@ impl AsyncMethodHandler for CameraHandler {
                     call,
                     thread::current().id()
                 );
-                let camera_service = self.camera_service.lock().unwrap();
+                let camera_service = self.session.camera.lock().unwrap();
 
                 let mut resolution = camera_service.resolution_service.get_current_resolution();
                 let resolution = resolution.as_mut().to_string();
@@ -142,7 +148,7 @@ impl AsyncMethodHandler for CameraHandler {
                     thread::current().id()
                 );
 
-                let camera_service = self.camera_service.lock().unwrap();
+                let camera_service = self.session.camera.lock().unwrap();
 
                 let camera_info = &mut camera_service.current_camera_info.lock().unwrap();
                 match camera_info.as_ref() {
@@ -156,18 +162,14 @@ impl AsyncMethodHandler for CameraHandler {
                     call,
                     thread::current().id()
                 );
-                let camera_service = self.camera_service.lock().unwrap();
+                let camera_service = self.session.camera.lock().unwrap();
                 let list = &mut camera_service.resolution_service.get_available_resolutions();
                 let list = list.to_owned();
 
                 debug!("available_resolution: {:?}", list);
                 Ok(list.into())
             }
-            _ => Err(PlatformError {
-                code: "invalid_method".into(),
-                message: Some(format!("Unknown Method: {}", call.method)),
-                detail: Value::Null,
-            }),
+            _ => protocol::invalid_method(&call),
         }
     }
 }
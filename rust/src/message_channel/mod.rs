@@ -3,3 +3,4 @@ pub mod rendering_message_channel;
 pub mod audio_message_channel;
 pub mod texture_message_channel;
 pub mod camera_message_channel;
+pub mod protocol;
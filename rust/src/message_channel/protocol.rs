@@ -0,0 +1,670 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use irondash_message_channel::{MethodCall, PlatformError, PlatformResult, Value};
+use nokhwa::utils::{FrameFormat, Resolution};
+use thiserror::Error;
+
+use crate::domain::{
+    encoder_profile::{EncoderSettings, QualityPreset, RateControl, VideoCodec},
+    error::DomainError,
+    frame_source::parse_frame_format,
+    limits::RecordingLimits,
+    metadata::{parse_created, EntryMetadata},
+    mp4::Mp4Layout,
+    pipeline::FRAME_RATES,
+    recording::Container,
+    segments::Segmenting,
+    visual_track::VisualTrack,
+};
+
+// Bump whenever a method, an argument or a response changes shape.
+// Dart calls 'protocol_handshake' on start up and refuses to talk to a mismatched library.
+pub const PROTOCOL_VERSION: i64 = 18;
+
+#[derive(Debug, Error)]
+pub enum ProtocolError {
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error("{0}")]
+    DeviceNotFound(String),
+    #[error("Unknown Method: {0}")]
+    InvalidMethod(String),
+}
+
+impl ProtocolError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProtocolError::InvalidArgument(_) => "invalid_argument",
+            ProtocolError::DeviceNotFound(_) => "device_not_found",
+            ProtocolError::InvalidMethod(_) => "invalid_method",
+        }
+    }
+}
+
+impl From<ProtocolError> for PlatformError {
+    fn from(e: ProtocolError) -> Self {
+        PlatformError {
+            code: e.code().into(),
+            message: Some(e.to_string()),
+            detail: Value::Null,
+        }
+    }
+}
+
+// The call was fine but the camera, audio or encoder behind it failed.
+impl From<DomainError> for PlatformError {
+    fn from(e: DomainError) -> Self {
+        PlatformError {
+            code: e.code().into(),
+            message: Some(e.to_string()),
+            detail: Value::Null,
+        }
+    }
+}
+
+// Arguments as Dart sends them, a flat map of strings.
+pub struct Args(HashMap<String, String>);
+
+impl Args {
+    pub fn from_call(call: &MethodCall) -> Result<Self, ProtocolError> {
+        if matches!(call.args, Value::Null) {
+            return Ok(Args(HashMap::new()));
+        }
+        let map: HashMap<String, String> = call.args.clone().try_into().map_err(|_| {
+            ProtocolError::InvalidArgument(format!(
+                "{} expects a map of strings, got {:?}",
+                call.method, call.args
+            ))
+        })?;
+        Ok(Args(map))
+    }
+
+    pub fn optional(&self, key: &str) -> Option<&str> {
+        self.0
+            .get(key)
+            .map(|v| v.as_str())
+            .filter(|v| !v.is_empty())
+    }
+
+    pub fn required(&self, key: &str) -> Result<&str, ProtocolError> {
+        self.optional(key)
+            .ok_or_else(|| ProtocolError::InvalidArgument(format!("missing argument '{}'", key)))
+    }
+
+    pub fn optional_parsed<T: std::str::FromStr>(
+        &self,
+        key: &str,
+    ) -> Result<Option<T>, ProtocolError> {
+        self.optional(key)
+            .map(|v| {
+                v.parse::<T>().map_err(|_| {
+                    ProtocolError::InvalidArgument(format!("invalid value for '{}': {}", key, v))
+                })
+            })
+            .transpose()
+    }
+
+    pub fn optional_resolution(&self, key: &str) -> Result<Option<Resolution>, ProtocolError> {
+        self.optional(key).map(parse_resolution).transpose()
+    }
+
+    pub fn resolution(&self, key: &str) -> Result<Resolution, ProtocolError> {
+        parse_resolution(self.required(key)?)
+    }
+}
+
+// "WxH", both sides positive and even as the yuv420 conversion needs
+pub fn parse_resolution(value: &str) -> Result<Resolution, ProtocolError> {
+    let invalid = || ProtocolError::InvalidArgument(format!("invalid resolution: {}", value));
+    let (width, height) = value.split_once('x').ok_or_else(invalid)?;
+    let width = width.trim().parse::<u32>().map_err(|_| invalid())?;
+    let height = height.trim().parse::<u32>().map_err(|_| invalid())?;
+    if width == 0 || height == 0 || width % 2 != 0 || height % 2 != 0 {
+        return Err(invalid());
+    }
+    Ok(Resolution::new(width, height))
+}
+
+pub trait FromArgs: Sized {
+    fn from_args(args: &Args) -> Result<Self, ProtocolError>;
+
+    fn from_call(call: &MethodCall) -> Result<Self, ProtocolError> {
+        Self::from_args(&Args::from_call(call)?)
+    }
+}
+
+pub struct HandshakeArgs {
+    pub protocol_version: Option<i64>,
+}
+
+impl FromArgs for HandshakeArgs {
+    fn from_args(args: &Args) -> Result<Self, ProtocolError> {
+        Ok(Self {
+            protocol_version: args.optional_parsed("protocol_version")?,
+        })
+    }
+}
+
+pub struct HandshakeResponse {
+    pub protocol_version: i64,
+    pub library_version: &'static str,
+    pub compatible: bool,
+}
+
+impl From<HandshakeResponse> for Value {
+    fn from(response: HandshakeResponse) -> Self {
+        let mut map: HashMap<String, Value> = HashMap::new();
+        map.insert(
+            "protocol_version".into(),
+            Value::I64(response.protocol_version),
+        );
+        map.insert(
+            "library_version".into(),
+            Value::String(response.library_version.into()),
+        );
+        map.insert("compatible".into(), Value::Bool(response.compatible));
+        map.into()
+    }
+}
+
+pub fn handshake(call: &MethodCall) -> PlatformResult {
+    let args = HandshakeArgs::from_call(call)?;
+    let response = HandshakeResponse {
+        protocol_version: PROTOCOL_VERSION,
+        library_version: env!("CARGO_PKG_VERSION"),
+        compatible: args
+            .protocol_version
+            .map_or(true, |version| version == PROTOCOL_VERSION),
+    };
+    Ok(response.into())
+}
+
+pub fn invalid_method(call: &MethodCall) -> PlatformResult {
+    Err(ProtocolError::InvalidMethod(call.method.clone()).into())
+}
+
+// camera_channel
+
+pub struct OpenCameraStreamArgs {
+    pub resolution: Option<Resolution>,
+}
+
+impl FromArgs for OpenCameraStreamArgs {
+    fn from_args(args: &Args) -> Result<Self, ProtocolError> {
+        Ok(Self {
+            resolution: args.optional_resolution("resolution")?,
+        })
+    }
+}
+
+pub struct SelectCameraDeviceArgs {
+    pub device_name: String,
+    // only used by the synthetic camera
+    pub fps: Option<u32>,
+    pub frame_format: Option<FrameFormat>,
+}
+
+impl FromArgs for SelectCameraDeviceArgs {
+    fn from_args(args: &Args) -> Result<Self, ProtocolError> {
+        let frame_format = args
+            .optional("frame_format")
+            .map(|f| {
+                parse_frame_format(f).ok_or_else(|| {
+                    ProtocolError::InvalidArgument(format!("unknown frame format: {}", f))
+                })
+            })
+            .transpose()?;
+        let fps = args.optional_parsed::<u32>("fps")?;
+        if fps == Some(0) {
+            return Err(ProtocolError::InvalidArgument(
+                "fps must be greater than 0".into(),
+            ));
+        }
+        Ok(Self {
+            device_name: args.required("device_name")?.to_string(),
+            fps,
+            frame_format,
+        })
+    }
+}
+
+// audio_channel
+
+pub struct AudioDeviceArgs {
+    pub device_name: String,
+}
+
+impl FromArgs for AudioDeviceArgs {
+    fn from_args(args: &Args) -> Result<Self, ProtocolError> {
+        Ok(Self {
+            device_name: args.required("device_name")?.to_string(),
+        })
+    }
+}
+
+// recording_channel
+
+pub struct AppDataDirArgs {
+    pub path: PathBuf,
+}
+
+impl FromArgs for AppDataDirArgs {
+    fn from_args(args: &Args) -> Result<Self, ProtocolError> {
+        Ok(Self {
+            path: PathBuf::from(args.required("path")?),
+        })
+    }
+}
+
+pub struct StartRecordingArgs {
+    pub file_path_prefix: String,
+    pub file_name: String,
+    pub resolution: Resolution,
+    pub container: Container,
+    pub layout: Mp4Layout,
+    pub segmenting: Option<Segmenting>,
+    pub encoder: EncoderSettings,
+    pub fps: Option<u32>,
+    pub wav_master: bool,
+    pub limits: RecordingLimits,
+    pub metadata: EntryMetadata,
+}
+
+impl FromArgs for StartRecordingArgs {
+    fn from_args(args: &Args) -> Result<Self, ProtocolError> {
+        let file_name = file_name(args)?;
+        // 'layout' is "progressive" (default) or "fragmented", cut every 'fragment_seconds'
+        let fragment = match args.optional_parsed::<f64>("fragment_seconds")? {
+            Some(seconds) if seconds > 0.0 => duration("fragment_seconds", seconds)?,
+            Some(seconds) => {
+                return Err(ProtocolError::InvalidArgument(format!(
+                    "fragment_seconds must be positive: {}",
+                    seconds
+                )))
+            }
+            None => Mp4Layout::DEFAULT_FRAGMENT,
+        };
+        // 'fps' is one of 15, 24, 30 or 60, checked against the camera when the take starts
+        let fps = args.optional_parsed::<u32>("fps")?;
+        if let Some(fps) = fps.filter(|fps| !FRAME_RATES.contains(fps)) {
+            return Err(ProtocolError::InvalidArgument(format!(
+                "fps must be one of {:?}: {}",
+                FRAME_RATES, fps
+            )));
+        }
+        let layout = args.optional("layout").unwrap_or("progressive");
+        let layout = Mp4Layout::parse(layout, fragment)
+            .ok_or_else(|| ProtocolError::InvalidArgument(format!("unknown layout: {}", layout)))?;
+        // 'container' is "mp4" (default) or "matroska", the layout only applies to an mp4
+        let container = args.optional("container").unwrap_or("mp4");
+        let container = Container::parse(container).ok_or_else(|| {
+            ProtocolError::InvalidArgument(format!("unknown container: {}", container))
+        })?;
+        // 'segment_minutes' and 'segment_mb' split a long take into files of at most that
+        // long or that big, whichever comes first; neither keeps it in one
+        let length = match args.optional_parsed::<f64>("segment_minutes")? {
+            Some(minutes) if minutes > 0.0 => Some(duration("segment_minutes", minutes * 60.0)?),
+            Some(minutes) => {
+                return Err(ProtocolError::InvalidArgument(format!(
+                    "segment_minutes must be positive: {}",
+                    minutes
+                )))
+            }
+            None => None,
+        };
+        let bytes = match args.optional_parsed::<u64>("segment_mb")? {
+            Some(0) => {
+                return Err(ProtocolError::InvalidArgument(
+                    "segment_mb must be positive".into(),
+                ))
+            }
+            megabytes => megabytes
+                .map(|megabytes| to_bytes("segment_mb", megabytes))
+                .transpose()?,
+        };
+        let metadata = entry_metadata(args)?;
+        if container == Container::Matroska && !metadata.is_empty() {
+            return Err(ProtocolError::InvalidArgument(
+                "title, created, note and tags only go into an mp4".into(),
+            ));
+        }
+        Ok(Self {
+            // an empty prefix writes next to the executable, as before
+            file_path_prefix: args.optional("file_path_prefix").unwrap_or("").to_string(),
+            file_name,
+            resolution: args.resolution("resolution")?,
+            container,
+            layout,
+            segmenting: Segmenting::new(length, bytes),
+            encoder: encoder_settings(args)?,
+            fps,
+            // 'wav_master' also writes the audio as `<file_name>.wav`
+            wav_master: args.optional_parsed::<bool>("wav_master")?.unwrap_or(false),
+            limits: recording_limits(args)?,
+            metadata,
+        })
+    }
+}
+
+pub struct StartVoiceMemoArgs {
+    pub file_path_prefix: String,
+    pub file_name: String,
+    pub visual: Option<VisualTrack>,
+    pub wav_master: bool,
+    pub limits: RecordingLimits,
+    pub metadata: EntryMetadata,
+}
+
+impl FromArgs for StartVoiceMemoArgs {
+    fn from_args(args: &Args) -> Result<Self, ProtocolError> {
+        // 'visual' is "none" (default), "audiogram" or "avatar" with the png in 'avatar_path'
+        let visual = match args.optional("visual").unwrap_or("none") {
+            "none" => None,
+            name => {
+                let avatar_path = args.optional("avatar_path").map(Path::new);
+                let visual = VisualTrack::parse(name, avatar_path).ok_or_else(|| {
+                    ProtocolError::InvalidArgument(format!(
+                        "unknown visual or no avatar_path: {}",
+                        name
+                    ))
+                })?;
+                Some(visual)
+            }
+        };
+        Ok(Self {
+            file_path_prefix: args.optional("file_path_prefix").unwrap_or("").to_string(),
+            file_name: file_name(args)?,
+            visual,
+            wav_master: args.optional_parsed::<bool>("wav_master")?.unwrap_or(false),
+            limits: recording_limits(args)?,
+            metadata: entry_metadata(args)?,
+        })
+    }
+}
+
+// Puts the metadata into an entry that is already saved, an mp4, m4a or a playlist of them.
+pub struct WriteMetadataArgs {
+    pub path: PathBuf,
+    pub metadata: EntryMetadata,
+}
+
+impl FromArgs for WriteMetadataArgs {
+    fn from_args(args: &Args) -> Result<Self, ProtocolError> {
+        let path = PathBuf::from(args.required("path")?);
+        let matroska = path.extension().is_some_and(|extension| {
+            extension.eq_ignore_ascii_case(Container::Matroska.extension())
+        });
+        if matroska {
+            return Err(ProtocolError::InvalidArgument(format!(
+                "a Matroska entry carries no metadata: {}",
+                path.display()
+            )));
+        }
+        Ok(Self {
+            path,
+            metadata: entry_metadata(args)?,
+        })
+    }
+}
+
+pub struct ReadMetadataArgs {
+    pub path: PathBuf,
+}
+
+impl FromArgs for ReadMetadataArgs {
+    fn from_args(args: &Args) -> Result<Self, ProtocolError> {
+        Ok(Self {
+            path: PathBuf::from(args.required("path")?),
+        })
+    }
+}
+
+// 'file_name' of an entry, without extension and without a path
+fn file_name(args: &Args) -> Result<String, ProtocolError> {
+    let file_name = args.required("file_name")?;
+    if file_name.contains(['/', '\\']) {
+        return Err(ProtocolError::InvalidArgument(format!(
+            "file_name must not contain a path: {}",
+            file_name
+        )));
+    }
+    Ok(file_name.to_string())
+}
+
+// 'codec' (h264, av1), 'quality' (low, standard, high, archival), 'bitrate' in bps,
+// 'rate_control' (quality, bitrate, buffer, timestamp, off), 'keyframe_interval' in frames
+// and 'encoder_threads', all optional
+fn encoder_settings(args: &Args) -> Result<EncoderSettings, ProtocolError> {
+    let codec = args
+        .optional("codec")
+        .map(|v| {
+            VideoCodec::parse(v)
+                .ok_or_else(|| ProtocolError::InvalidArgument(format!("unknown codec: {}", v)))
+        })
+        .transpose()?;
+    let preset = args
+        .optional("quality")
+        .map(|v| {
+            QualityPreset::parse(v)
+                .ok_or_else(|| ProtocolError::InvalidArgument(format!("unknown quality: {}", v)))
+        })
+        .transpose()?;
+    let rate_control = args
+        .optional("rate_control")
+        .map(|v| {
+            RateControl::parse(v).ok_or_else(|| {
+                ProtocolError::InvalidArgument(format!("unknown rate control: {}", v))
+            })
+        })
+        .transpose()?;
+    let bitrate_bps = args.optional_parsed::<u32>("bitrate")?;
+    if bitrate_bps == Some(0) {
+        return Err(ProtocolError::InvalidArgument(
+            "bitrate must be positive".to_string(),
+        ));
+    }
+    let keyframe_interval = args.optional_parsed::<u32>("keyframe_interval")?;
+    if keyframe_interval == Some(0) {
+        return Err(ProtocolError::InvalidArgument(
+            "keyframe_interval must be positive".to_string(),
+        ));
+    }
+    Ok(EncoderSettings {
+        codec,
+        preset,
+        bitrate_bps,
+        rate_control,
+        keyframe_interval,
+        threads: args.optional_parsed::<u16>("encoder_threads")?,
+    })
+}
+
+// 'max_minutes' of recorded time, 'max_mb' of estimated output and 'min_free_mb' left on the
+// disk stop a take on their own, all optional; a take doesn't start below 'min_free_mb'
+fn recording_limits(args: &Args) -> Result<RecordingLimits, ProtocolError> {
+    let max_duration = match args.optional_parsed::<f64>("max_minutes")? {
+        Some(minutes) if minutes > 0.0 => Some(duration("max_minutes", minutes * 60.0)?),
+        Some(minutes) => {
+            return Err(ProtocolError::InvalidArgument(format!(
+                "max_minutes must be positive: {}",
+                minutes
+            )))
+        }
+        None => None,
+    };
+    let max_bytes = match args.optional_parsed::<u64>("max_mb")? {
+        Some(0) => {
+            return Err(ProtocolError::InvalidArgument(
+                "max_mb must be positive".into(),
+            ))
+        }
+        megabytes => megabytes
+            .map(|megabytes| to_bytes("max_mb", megabytes))
+            .transpose()?,
+    };
+    Ok(RecordingLimits {
+        max_duration,
+        max_bytes,
+        min_free_bytes: args
+            .optional_parsed::<u64>("min_free_mb")?
+            .map(|megabytes| to_bytes("min_free_mb", megabytes))
+            .transpose()?,
+    })
+}
+
+// `seconds` from the argument `key`, which is positive, as a Duration
+fn duration(key: &str, seconds: f64) -> Result<Duration, ProtocolError> {
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| ProtocolError::InvalidArgument(format!("{} is too large", key)))
+}
+
+// `megabytes` of the argument `key` in bytes, a number that big is no use as a limit anyway
+fn to_bytes(key: &str, megabytes: u64) -> Result<u64, ProtocolError> {
+    megabytes.checked_mul(1024 * 1024).ok_or_else(|| {
+        ProtocolError::InvalidArgument(format!("{} is too large: {}", key, megabytes))
+    })
+}
+
+// 'title', 'created' as ISO 8601 with its time zone ("2026-10-18T09:30:00+02:00"), 'note'
+// and 'tags' one per line, all optional; empty ones are left out
+fn entry_metadata(args: &Args) -> Result<EntryMetadata, ProtocolError> {
+    let text = |key: &str| {
+        args.optional(key)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+    let created = text("created");
+    if let Some(created) = created.as_deref().filter(|c| parse_created(c).is_none()) {
+        return Err(ProtocolError::InvalidArgument(format!(
+            "created must be ISO 8601 with a time zone: {}",
+            created
+        )));
+    }
+    Ok(EntryMetadata {
+        title: text("title"),
+        created,
+        note: text("note"),
+        tags: args
+            .optional("tags")
+            .unwrap_or("")
+            .lines()
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_string)
+            .collect(),
+    })
+}
+
+// texture_channel
+
+pub struct OpenTextureStreamArgs {
+    pub resolution: Resolution,
+}
+
+impl FromArgs for OpenTextureStreamArgs {
+    fn from_args(args: &Args) -> Result<Self, ProtocolError> {
+        Ok(Self {
+            resolution: args.resolution("resolution")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(pairs: &[(&str, &str)]) -> Args {
+        Args(
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn limits_convert_to_bytes_and_durations() {
+        let limits = recording_limits(&args(&[
+            ("max_minutes", "1.5"),
+            ("max_mb", "2"),
+            ("min_free_mb", "0"),
+        ]))
+        .unwrap();
+        assert_eq!(limits.max_duration, Some(Duration::from_secs(90)));
+        assert_eq!(limits.max_bytes, Some(2 * 1024 * 1024));
+        assert_eq!(limits.min_free_bytes, Some(0));
+    }
+
+    #[test]
+    fn limits_too_large_are_invalid_arguments() {
+        let max = u64::MAX.to_string();
+        for pair in [
+            ("max_mb", max.as_str()),
+            ("min_free_mb", max.as_str()),
+            ("max_minutes", "1e300"),
+            ("max_minutes", "inf"),
+        ] {
+            assert!(
+                matches!(
+                    recording_limits(&args(&[pair])),
+                    Err(ProtocolError::InvalidArgument(_))
+                ),
+                "{:?}",
+                pair
+            );
+        }
+    }
+
+    #[test]
+    fn segmenting_too_large_is_an_invalid_argument() {
+        let max = u64::MAX.to_string();
+        for pair in [
+            ("segment_mb", max.as_str()),
+            ("segment_minutes", "1e300"),
+            ("fragment_seconds", "1e300"),
+        ] {
+            let args = args(&[("file_name", "take"), ("resolution", "1280x720"), pair]);
+            assert!(
+                matches!(
+                    StartRecordingArgs::from_args(&args),
+                    Err(ProtocolError::InvalidArgument(_))
+                ),
+                "{:?}",
+                pair
+            );
+        }
+    }
+
+    #[test]
+    fn metadata_only_goes_into_an_mp4() {
+        let take = [("file_name", "take"), ("resolution", "1280x720")];
+        let matroska = [take.as_slice(), &[("container", "matroska")]].concat();
+        assert!(StartRecordingArgs::from_args(&args(&matroska)).is_ok());
+        for pair in [
+            ("title", "Morgens"),
+            ("created", "2026-10-18T09:30:00+02:00"),
+        ] {
+            let args = args(&[matroska.as_slice(), &[pair]].concat());
+            assert!(
+                matches!(
+                    StartRecordingArgs::from_args(&args),
+                    Err(ProtocolError::InvalidArgument(_))
+                ),
+                "{:?}",
+                pair
+            );
+        }
+        let mp4 = [take.as_slice(), &[("title", "Morgens")]].concat();
+        assert!(StartRecordingArgs::from_args(&args(&mp4)).is_ok());
+
+        for (path, ok) in [("take.mp4", true), ("take.m3u8", true), ("take.MKV", false)] {
+            let write =
+                WriteMetadataArgs::from_args(&args(&[("path", path), ("title", "Morgens")]));
+            assert_eq!(write.is_ok(), ok, "{}", path);
+        }
+    }
+}
@@ -1,23 +1,29 @@
 use std::{
     mem::ManuallyDrop,
-    sync::{atomic::AtomicBool, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicU32},
+        Arc,
+    },
     thread,
     time::Duration,
 };
 
 use async_trait::async_trait;
 use irondash_message_channel::{
-    AsyncMethodHandler, AsyncMethodInvoker, IsolateId, Late, MethodCall, PlatformError,
-    PlatformResult, Value,
+    AsyncMethodHandler, AsyncMethodInvoker, IsolateId, Late, MethodCall, PlatformResult, Value,
 };
 use irondash_run_loop::RunLoop;
 use irondash_texture::{PixelDataProvider, SendableTexture};
 
 use log::{debug};
 
+use super::protocol;
+
 pub struct RenderingHandler {
     pub texture: Arc<SendableTexture<Box<dyn PixelDataProvider>>>,
     pub rendering: Arc<AtomicBool>,
+    // the session's, the preview is refreshed as often as the take records
+    pub frame_rate: Arc<AtomicU32>,
     invoker: Late<AsyncMethodInvoker>,
 }
 
@@ -25,10 +31,12 @@ impl RenderingHandler {
     pub fn new(
         texture: Arc<SendableTexture<Box<dyn PixelDataProvider>>>,
         rendering: Arc<AtomicBool>,
+        frame_rate: Arc<AtomicU32>,
     ) -> Self {
         Self {
             texture,
             rendering,
+            frame_rate,
             invoker: Late::new(),
         }
     }
@@ -52,6 +60,8 @@ impl AsyncMethodHandler for RenderingHandler {
     }
     async fn on_method_call(&self, call: MethodCall) -> PlatformResult {
         match call.method.as_str() {
+            "protocol_handshake" => protocol::handshake(&call),
+
             "start_rendering" => {
                 debug!(
                     "Received request {:?} on thread {:?}",
@@ -67,12 +77,13 @@ impl AsyncMethodHandler for RenderingHandler {
 
                 let rendering: Arc<AtomicBool> = self.rendering.clone();
 
-                let frame_interval = Duration::from_millis(1000 / 24);
+                let frame_rate = self.frame_rate.clone();
 
                 // avoid blocking the method channel
                 thread::spawn(move || {
                     while rendering.load(std::sync::atomic::Ordering::Relaxed) {
-                        thread::sleep(frame_interval);
+                        let fps = frame_rate.load(std::sync::atomic::Ordering::Relaxed);
+                        thread::sleep(Duration::from_secs(1) / fps.max(1));
                         // this will display the texture on the screen from the pixel data we provide
                         texture_provider.mark_frame_available();
                     }
@@ -93,11 +104,7 @@ impl AsyncMethodHandler for RenderingHandler {
                 self.mark_rendering_state_on_ui(call.isolate);
                 Ok("ok".into())
             }
-            _ => Err(PlatformError {
-                code: "invalid_method".into(),
-                message: Some(format!("Unknown Method: {}", call.method)),
-                detail: Value::Null,
-            }),
+            _ => protocol::invalid_method(&call),
         }
     }
 }
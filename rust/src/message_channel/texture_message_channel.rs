@@ -1,5 +1,4 @@
 use std::{
-    collections::HashMap,
     mem::ManuallyDrop,
     sync::{
         atomic::{AtomicBool, AtomicUsize},
@@ -9,15 +8,16 @@ use std::{
 };
 
 use async_trait::async_trait;
-use irondash_message_channel::{
-    AsyncMethodHandler, MethodCall, PlatformError, PlatformResult, Value,
-};
+use irondash_message_channel::{AsyncMethodHandler, MethodCall, PlatformResult};
 use irondash_run_loop::RunLoop;
 
-use log::{debug};
+use log::{debug, error};
 use nokhwa::Buffer;
 
-use crate::{ tools::image_processing::decode_to_rgb, domain::channel::ChannelService,  };
+use crate::{ tools::image_processing::decode_to_rgb, domain::{channel::ChannelService, error::DomainError},  };
+
+use super::protocol::{self, FromArgs, OpenTextureStreamArgs};
+
 pub struct TextureHandler {
     pub render_buffer: Arc<Mutex<Vec<u8>>>,
     pub channel_handler: Arc<Mutex<ChannelService>>,
@@ -27,6 +27,8 @@ pub struct TextureHandler {
 impl AsyncMethodHandler for TextureHandler {
     async fn on_method_call(&self, call: MethodCall) -> PlatformResult {
         match call.method.as_str() {
+            "protocol_handshake" => protocol::handshake(&call),
+
             // decode images from webcam parrallelly using thread pool and sending it to display and encoder.
 
             "open_texture_stream" => {
@@ -35,11 +37,9 @@ impl AsyncMethodHandler for TextureHandler {
                     call,
                     thread::current().id()
                 );
-                let map: HashMap<String, String> = call.args.try_into().unwrap();
-                let resolution = map.get("resolution").unwrap().as_str();
-                let resolution = resolution.split("x").collect::<Vec<&str>>();
-                let width = resolution[0].parse::<u32>().unwrap();
-                let height = resolution[1].parse::<u32>().unwrap();
+                let args = OpenTextureStreamArgs::from_call(&call)?;
+                let width = args.resolution.width();
+                let height = args.resolution.height();
 
                 // display only the latest image. highest index on the moment.
                 let render_buffer_index: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
@@ -47,7 +47,7 @@ impl AsyncMethodHandler for TextureHandler {
                 let pool = tokio::runtime::Builder::new_multi_thread()
                     .worker_threads(8)
                     .build()
-                    .unwrap();
+                    .map_err(DomainError::from)?;
 
                 let receiver = self.channel_handler.lock().unwrap().rendering.1.clone();
                 let recording = self.recording.clone();
@@ -76,11 +76,7 @@ impl AsyncMethodHandler for TextureHandler {
                 Ok("ok".into())
             }
 
-            _ => Err(PlatformError {
-                code: "invalid_method".into(),
-                message: Some(format!("Unknown Method: {}", call.method)),
-                detail: Value::Null,
-            }),
+            _ => protocol::invalid_method(&call),
         }
     }
 }
@@ -112,14 +108,20 @@ fn decode(
     }
 
     // let time = std::time::Instant::now();
-    let decoded = decode_to_rgb(
+    let decoded = match decode_to_rgb(
         buf.buffer(),
         &buf.source_frame_format(),
         true,
         width,
         height,
-    )
-    .unwrap();
+    ) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            // a broken frame only costs one frame of preview
+            error!("drop frame :: {:?}", e);
+            return;
+        }
+    };
     // debug!("decode time {:?}", time.elapsed());
     
     let render_buffer_index_ = render_buffer_index.load(std::sync::atomic::Ordering::SeqCst);